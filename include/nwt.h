@@ -0,0 +1,116 @@
+/* Generated with cbindgen from src/ffi.rs (see cbindgen.toml). Do not edit. */
+
+#ifndef NWT_H
+#define NWT_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * success
+ */
+#define NWT_OK 0
+
+/**
+ * a null pointer or out-of-range index was passed in
+ */
+#define NWT_ERR_INVALID_ARGUMENT -1
+
+/**
+ * the library returned an error; see `nwt_last_error_message`
+ */
+#define NWT_ERR_LIBRARY -2
+
+/**
+ * the output buffer is too small; the message says how big it must be
+ */
+#define NWT_ERR_BUFFER_TOO_SMALL -3
+
+/**
+ * the library panicked
+ */
+#define NWT_ERR_PANIC -4
+
+/**
+ * An open weight file. Entries are laid out as `NwtPoint` arrays the first
+ * time they're asked for, and stay valid until `nwt_close`
+ */
+typedef struct NwtHandle NwtHandle;
+
+/**
+ * One gridpoint, packed into 20 bytes like the records of NWT format 6
+ */
+typedef struct NwtPoint {
+  uint32_t lat_idx;
+  uint32_t lon_idx;
+  float lat;
+  float lon;
+  float weight;
+} NwtPoint;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Opens an NWT file (or converts a NetCDF one, as `NextWeightFile::open`
+ * does). Returns null on failure
+ *
+ * # Safety
+ * `path` must be null or a NUL-terminated string
+ */
+NwtHandle *nwt_open(const char *path);
+
+/**
+ * Returns the number of polyids, or a negative error code
+ *
+ * # Safety
+ * `handle` must be null or a live handle from `nwt_open`
+ */
+int64_t nwt_num_polyids(const NwtHandle *handle);
+
+/**
+ * Copies the name of polyid `idx` into `buf` as a NUL-terminated string
+ *
+ * # Safety
+ * `handle` must be null or a live handle from `nwt_open`, and `buf` null or
+ * valid for `len` bytes
+ */
+int32_t nwt_get_polyid_name(const NwtHandle *handle, size_t idx, char *buf, size_t len);
+
+/**
+ * Points `*out_ptr` at polyid `idx`'s `*out_len` gridpoints. The array
+ * belongs to the handle and stays valid until `nwt_close`
+ *
+ * # Safety
+ * `handle` must be null or a live handle from `nwt_open`, and `out_ptr`
+ * and `out_len` null or valid for writes
+ */
+int32_t nwt_get_entry(const NwtHandle *handle,
+                      size_t idx,
+                      const NwtPoint **out_ptr,
+                      size_t *out_len);
+
+/**
+ * Frees a handle and every entry array it handed out
+ *
+ * # Safety
+ * `handle` must be null or a live handle from `nwt_open`, and is invalid
+ * afterwards
+ */
+void nwt_close(NwtHandle *handle);
+
+/**
+ * Returns the message of this thread's most recent error, or null if there
+ * hasn't been one. The string is valid until the next failing call
+ */
+const char *nwt_last_error_message(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* NWT_H */
@@ -0,0 +1,315 @@
+//! Adding polyids to an NWT file on disk without loading it.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{atomic, options, AttrVal, ChecksumWriter, Coordinates, LookupEntry, NextWeightFile, NwtError, NwtHeader, NwtReader, PolyidEntry};
+use crate::codec::{write_f32_le, write_u32_le};
+use crate::{encode_point, quantization_range, read_axes, read_header, write_axes, write_bounds, write_header};
+use crate::weight_sets::{read_weight_sets, write_weight_sets};
+use crate::{BOUNDS_VERSION, OLDEST_WRITABLE_VERSION, QUANTIZED_DTYPE, WEIGHT_SETS_VERSION};
+
+/// bytes of existing gridpoint data copied at a time
+const COPY_CHUNK: usize = 64 * 1024;
+
+/// Appends polyids to an existing NWT file without decoding its points.
+///
+/// `open` reads only the header, metadata and lookup table; `append` queues
+/// entries; `finish` writes the file out again with the new polyids at the
+/// end. The existing points are copied through byte for byte rather than
+/// parsed and re-encoded, so memory use doesn't grow with the file. They
+/// can't stay where they are: the metadata and lookup table precede them so
+/// files can be read from a stream, and both grow with every polyid.
+///
+/// The new file goes to a temporary file that replaces the original only
+/// once it is complete and synced, so an interrupted `finish` leaves the
+/// original untouched. The original's checksum is verified as it is copied.
+/// Files of format versions 6 and later can be appended to, uncompressed;
+/// the file keeps its version, weight dtype and quantization. New polyids
+/// get an empty entry in any weight sets besides the main one
+pub struct NwtAppender {
+    path: PathBuf,
+    header: NwtHeader,
+    /// the file's shared coordinates, with any cells the new points are the
+    /// first to use filled in
+    coordinates: Option<Coordinates>,
+    names: HashSet<String>,
+    appended: Vec<(String, PolyidEntry)>,
+}
+
+impl NwtAppender {
+    /// Reads the header, metadata and lookup table of the NWT file at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, NwtError> {
+        let path = path.as_ref().to_path_buf();
+        let header = read_header(&path)?;
+        if header.version < OLDEST_WRITABLE_VERSION {
+            return Err(NwtError::InvalidInput(format!(
+                "can't append to NWT format version {}; rewrite it with serialize_to_file first", header.version)));
+        }
+        if header.codec != options::CODEC_NONE {
+            return Err(NwtError::InvalidInput("can't append to a compressed NWT file".to_string()));
+        }
+        let names = header.json_data.polyids.iter().cloned().collect();
+        Ok(Self { path, coordinates: header.coordinates.clone(), header, names, appended: Vec::new() })
+    }
+
+    /// Queues a polyid to be added after the existing ones. Its name must be
+    /// new, its points must lie on the grid and, in files that store the
+    /// grid's coordinates once (format 7 on), have the lat/lon stored for
+    /// their cell. In a double-precision file its weights are widened to f64
+    pub fn append(&mut self, name: impl Into<String>, entry: PolyidEntry) -> Result<(), NwtError> {
+        let name = name.into();
+        if self.names.contains(&name) {
+            return Err(NwtError::InvalidInput(format!("polyid {} is already in the file", name)));
+        }
+        let (lat_len, lon_len) = (self.header.lat_len, self.header.lon_len);
+        if let Some(p) = entry.data.iter().find(|p| p.0 as u64 >= lat_len || p.1 as u64 >= lon_len) {
+            return Err(NwtError::InvalidInput(format!(
+                "polyid {} has a point at ({}, {}), outside the {}x{} grid", name, p.0, p.1, lat_len, lon_len)));
+        }
+        if self.header.quantization.is_some() {
+            if let Some(p) = entry.data.iter().find(|p| !p.4.is_finite()) {
+                return Err(NwtError::InvalidInput(format!("polyid {} has weight {}, which can't be quantized", name, p.4)));
+            }
+        }
+        if let Some(coords) = self.coordinates.as_mut() {
+            // checked in full before any cell is filled in, so a rejected
+            // entry leaves the coordinates as they were
+            let mut filled = coords.clone();
+            for p in entry.data.iter() {
+                let lat = &mut filled.lats[p.0 as usize];
+                let lon = &mut filled.lons[p.1 as usize];
+                if !fill_coordinate(lat, p.2) || !fill_coordinate(lon, p.3) {
+                    return Err(NwtError::InvalidInput(format!(
+                        "polyid {} has a point at ({}, {}) whose lat/lon don't match the grid's", name, p.0, p.1)));
+                }
+            }
+            *coords = filled;
+        }
+        self.names.insert(name.clone());
+        self.appended.push((name, entry));
+        Ok(())
+    }
+
+    /// Writes the file out with the queued polyids appended, replacing the
+    /// original. Fails without touching it if the original changed since
+    /// `open` or doesn't match its checksum
+    pub fn finish(self) -> Result<(), NwtError> {
+        if self.appended.is_empty() {
+            return Ok(());
+        }
+        let header = &self.header;
+        let compact = self.coordinates.is_some();
+        let point_size = header.point_size() as u64;
+
+        let mut json_data = header.json_data.clone();
+        let mut lookup_table = header.lookup_table.clone();
+        let mut ranges = header.quantization.clone();
+        let mut running_total: u64 = lookup_table.iter().map(|e| e.count).sum();
+        let old_points = running_total;
+        let mut max_error: f64 = 0.0;
+        for (name, entry) in self.appended.iter() {
+            json_data.add_polyid(name.clone());
+            lookup_table.push(LookupEntry::new(running_total, entry.data.len() as u64));
+            running_total += entry.data.len() as u64;
+            if let Some(ranges) = ranges.as_mut() {
+                let weights: Vec<f64> = entry.data.iter().map(|p| p.4 as f64).collect();
+                let (range, error) = quantization_range(&weights);
+                ranges.push(range);
+                max_error = max_error.max(error);
+            }
+        }
+        if ranges.is_some() {
+            let recorded = match json_data.get_global_attr_value("nwt_quantization_max_error") {
+                Some(AttrVal::F64(recorded)) => *recorded,
+                _ => 0.0,
+            };
+            json_data.set_global_attr("nwt_quantization_max_error", recorded.max(max_error));
+        }
+        let json = serde_json::to_string(&json_data)?;
+        let dtype = if ranges.is_some() { QUANTIZED_DTYPE } else { header.weight_dtype as u16 };
+        log::debug!("appending {} polyids to {} ({} existing points)", self.appended.len(), self.path.display(), old_points);
+
+        atomic::write_atomically(&self.path, true, |out| {
+            // read the original again from the top, so its checksum covers
+            // everything that gets copied
+            let mut source = NwtReader::verifying(BufReader::new(File::open(&self.path)?));
+            let current = NextWeightFile::read_preamble(&mut source)?;
+            if current.lookup_table != header.lookup_table || current.json_data != header.json_data {
+                return Err(NwtError::InvalidInput(format!("{} changed since it was opened", self.path.display())));
+            }
+
+            write_header(out, header.version, header.codec, dtype, &json, lookup_table.len() as u64,
+                header.lat_len, header.lon_len)?;
+            let mut body = ChecksumWriter::new(out);
+            body.write_all(json.as_bytes())?;
+            for entry in lookup_table.iter() {
+                entry.write(&mut body)?;
+            }
+            if let Some(coords) = &self.coordinates {
+                body.write_all(&[coords.are_axes as u8])?;
+                for &v in coords.lats.iter().chain(coords.lons.iter()) {
+                    write_f32_le(&mut body, v)?;
+                }
+            }
+            if header.version >= BOUNDS_VERSION {
+                write_bounds(&mut body, header.lat_bounds.as_deref(), header.lon_bounds.as_deref())?;
+            }
+            for &(min, max) in ranges.iter().flatten() {
+                write_f32_le(&mut body, min)?;
+                write_f32_le(&mut body, max)?;
+            }
+
+            let mut remaining = old_points.checked_mul(point_size)
+                .ok_or_else(|| NwtError::Corrupt(format!("point count {} is too large", old_points)))?;
+            while remaining > 0 {
+                let len = remaining.min(COPY_CHUNK as u64) as usize;
+                body.write_all(&source.read_bytes(len, "gridpoint data")?)?;
+                remaining -= len as u64;
+            }
+            let mut entry_buff = Vec::new();
+            for (idx, (_, entry)) in self.appended.iter().enumerate() {
+                entry_buff.clear();
+                let range = ranges.as_ref().map(|ranges| ranges[header.lookup_table.len() + idx]);
+                for p in entry.data.iter() {
+                    encode_point(&mut entry_buff, p, p.4 as f64, compact, header.weight_dtype, range)?;
+                }
+                body.write_all(&entry_buff)?;
+            }
+            if header.version >= WEIGHT_SETS_VERSION {
+                let coords = header.coordinates.as_ref().expect("format 10 files store their coordinates");
+                let (main_name, mut sets) = read_weight_sets(&mut source, header.num_polyids, coords)?;
+                for set in sets.iter_mut() {
+                    set.entries.resize_with(lookup_table.len(), PolyidEntry::new);
+                }
+                write_weight_sets(&mut body, &main_name, &sets)?;
+            }
+            if !compact {
+                let axes = read_axes(&mut source, header.lat_len, header.lon_len)?;
+                write_axes(&mut body, axes.as_ref().map(|(lats, lons)| (&lats[..], &lons[..])))?;
+            }
+            source.finish_checksum()?;
+
+            let (_, checksum) = body.finish();
+            write_u32_le(out, checksum)?;
+            Ok(())
+        })
+    }
+}
+
+/// sets a coordinate the grid doesn't have yet (NaN), or checks it matches
+/// the one it has
+fn fill_coordinate(stored: &mut f32, value: f32) -> bool {
+    if stored.is_nan() && !value.is_nan() {
+        *stored = value;
+        return true;
+    }
+    stored.to_bits() == value.to_bits()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{sample_file, temp_path};
+    use crate::{NextWeightFileBuilder, WeightDtype, WriteOptions, DEFAULT_WEIGHT_SET};
+
+    fn entry(points: Vec<crate::GridPoint>) -> PolyidEntry {
+        PolyidEntry { data: points }
+    }
+
+    #[test]
+    fn appended_file_matches_a_full_rewrite() {
+        let with_axes = NextWeightFileBuilder::new()
+            .dimensions(2, 3)
+            .axes(vec![-45.0, 45.0], vec![0.0, 90.0, 180.0])
+            .add_polyid("A", vec![(0, 0, -45.0, 0.0, 1.0)])
+            .build();
+        let with_bounds = NextWeightFileBuilder::new()
+            .dimensions(2, 3)
+            .bounds(vec![(-90.0, 0.0), (0.0, 90.0)], vec![(-45.0, 45.0), (45.0, 135.0), (135.0, 225.0)])
+            .add_polyid("A", vec![(0, 0, -45.0, 0.0, 1.0)])
+            .build();
+        let cases = [
+            (sample_file(), 9), (sample_file(), 8), (sample_file(), 6), (with_axes.clone(), 7), (with_axes, 6), (with_bounds, 9),
+        ];
+        for (nwt, version) in cases {
+            let path = temp_path("append.nwt");
+            let opts = WriteOptions { version, overwrite: true, ..Default::default() };
+            nwt.serialize_to_file_with(&path, &opts).unwrap();
+
+            let mut appender = NwtAppender::open(&path).unwrap();
+            appender.append("D", entry(vec![(1, 1, 45.0, 90.0, 0.5), (0, 2, -45.0, 180.0, 0.5)])).unwrap();
+            appender.append("E", PolyidEntry::new()).unwrap();
+            appender.finish().unwrap();
+
+            let mut expected = nwt.clone();
+            expected.add_polyid_entry("D".to_string(), entry(vec![(1, 1, 45.0, 90.0, 0.5), (0, 2, -45.0, 180.0, 0.5)]));
+            expected.add_polyid_entry("E".to_string(), PolyidEntry::new());
+            let mut buff = Vec::new();
+            expected.serialize_to_writer_with(&mut buff, &opts).unwrap();
+            assert_eq!(std::fs::read(&path).unwrap(), buff, "version {}", version);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn appending_keeps_dtype_and_quantization() {
+        let path = temp_path("append_f64.nwt");
+        let nwt = NextWeightFileBuilder::new()
+            .dimensions(2, 3)
+            .add_polyid_f64("A", vec![(0, 0, -45.0, 0.0, 1.0 / 3.0)])
+            .build();
+        nwt.serialize_to_file(&path).unwrap();
+        let mut appender = NwtAppender::open(&path).unwrap();
+        appender.append("B", entry(vec![(1, 1, 45.0, 90.0, 0.25)])).unwrap();
+        appender.finish().unwrap();
+        let loaded = NextWeightFile::from_nwt(&path).unwrap();
+        assert_eq!(loaded.weight_dtype(), WeightDtype::F64);
+        assert_eq!(loaded.get_entry_f64("A").unwrap(), vec![(0, 0, -45.0, 0.0, 1.0 / 3.0)]);
+        assert_eq!(loaded.get_entry_f64("B").unwrap(), vec![(1, 1, 45.0, 90.0, 0.25)]);
+
+        let quantized = WriteOptions { quantize: true, overwrite: true, ..Default::default() };
+        sample_file().serialize_to_file_with(&path, &quantized).unwrap();
+        let mut appender = NwtAppender::open(&path).unwrap();
+        appender.append("D", entry(vec![(1, 1, 45.0, 90.0, 0.2), (0, 2, -45.0, 180.0, 0.8)])).unwrap();
+        appender.finish().unwrap();
+        let loaded = NextWeightFile::from_nwt(&path).unwrap();
+        let weights: Vec<f32> = loaded.get_entry("D").unwrap().data.iter().map(|p| p.4).collect();
+        assert!((weights[0] - 0.2).abs() < 1e-5 && (weights[1] - 0.8).abs() < 1e-5);
+        assert!(loaded.json_data.get_global_attr_value("nwt_quantization_max_error").is_some());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn bad_appends_leave_the_file_alone() {
+        let path = temp_path("append_bad.nwt");
+        sample_file().serialize_to_file(&path).unwrap();
+        let original = std::fs::read(&path).unwrap();
+
+        let mut appender = NwtAppender::open(&path).unwrap();
+        assert!(matches!(appender.append("A", PolyidEntry::new()), Err(NwtError::InvalidInput(_))));
+        assert!(matches!(appender.append("D", entry(vec![(2, 0, 0.0, 0.0, 1.0)])), Err(NwtError::InvalidInput(_))));
+        assert!(matches!(appender.append("D", entry(vec![(0, 0, -45.0, 1.0, 1.0)])), Err(NwtError::InvalidInput(_))));
+        appender.append("D", entry(vec![(0, 0, -45.0, 0.0, 1.0)])).unwrap();
+
+        // a corrupted original fails its checksum while being copied
+        let mut corrupt = original.clone();
+        let last_point = corrupt.len() - 4 - (8 + DEFAULT_WEIGHT_SET.len() + 8) - 1;
+        corrupt[last_point] ^= 0xff;
+        std::fs::write(&path, &corrupt).unwrap();
+        assert!(matches!(appender.finish(), Err(NwtError::ChecksumMismatch { .. })));
+        assert_eq!(std::fs::read(&path).unwrap(), corrupt);
+
+        // as does one that changed since it was opened
+        std::fs::write(&path, &original).unwrap();
+        let mut appender = NwtAppender::open(&path).unwrap();
+        appender.append("D", PolyidEntry::new()).unwrap();
+        let mut changed = sample_file();
+        changed.remove_polyid("C").unwrap();
+        changed.serialize_to_file_with(&path, &WriteOptions { overwrite: true, ..Default::default() }).unwrap();
+        assert!(matches!(appender.finish(), Err(NwtError::InvalidInput(ref m)) if m.contains("changed since")));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+//! Applying the same weights to many fields, with the per-point work done
+//! once up front.
+
+use crate::{NextWeightFile, NwtError};
+
+/// Every point of a file as a flat cell index and weight, laid out back to
+/// back in `get_polyids()` order. Built by `NextWeightFile::applicator`,
+/// after which it no longer needs the file. Each `apply` gives exactly what
+/// `NextWeightFile::apply` would, without resolving any indices or
+/// allocating
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightApplicator {
+    /// `lat_idx * lon_len + lon_idx` of every point
+    cells: Vec<u32>,
+    weights: Vec<f32>,
+    /// where each polyid's points start in `cells` and `weights`, then where
+    /// the last one's end
+    starts: Vec<usize>,
+    field_len: usize,
+}
+
+impl WeightApplicator {
+    /// number of polyids, and so of values `apply` writes
+    pub fn len(&self) -> usize {
+        self.starts.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Applies the weights to a row-major `lat_len * lon_len` field, writing
+    /// one value per polyid to `out`
+    pub fn apply(&self, field: &[f32], out: &mut [f32]) -> Result<(), NwtError> {
+        if field.len() != self.field_len {
+            return Err(NwtError::FieldSizeMismatch { expected: self.field_len, got: field.len() });
+        }
+        if out.len() != self.len() {
+            return Err(NwtError::FieldSizeMismatch { expected: self.len(), got: out.len() });
+        }
+        for (value, bounds) in out.iter_mut().zip(self.starts.windows(2)) {
+            let (cells, weights) = (&self.cells[bounds[0]..bounds[1]], &self.weights[bounds[0]..bounds[1]]);
+            let mut total = 0.0f64;
+            for (&cell, &weight) in cells.iter().zip(weights) {
+                total += weight as f64 * field[cell as usize] as f64;
+            }
+            *value = total as f32;
+        }
+        Ok(())
+    }
+}
+
+impl NextWeightFile {
+    /// Resolves every point's cell once, for applying the weights to many
+    /// fields, e.g. each step of a long time series. Fails if a point lies
+    /// off the grid, or the grid has more cells than a u32 can index
+    pub fn applicator(&self) -> Result<WeightApplicator, NwtError> {
+        let field_len = self.field_len();
+        if u32::try_from(field_len).is_err() {
+            return Err(NwtError::GridTooLarge { lat_len: self.lat_len, lon_len: self.lon_len });
+        }
+        let points = self.polyid_gridpoints.points();
+        let cells = points.iter()
+            .map(|point| self.flat_index(point.0, point.1).map(|cell| cell as u32))
+            .collect::<Result<Vec<_>, _>>()?;
+        let weights = points.iter().map(|point| point.4).collect();
+        let mut starts = Vec::with_capacity(self.polyid_gridpoints.len() + 1);
+        starts.push(0);
+        for count in self.polyid_gridpoints.counts() {
+            starts.push(starts[starts.len() - 1] + count);
+        }
+        Ok(WeightApplicator { cells, weights, starts, field_len })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::sample_file;
+    use crate::{NextWeightFileBuilder, NwtError};
+
+    #[test]
+    fn applicator_matches_apply() {
+        let nwt = sample_file();
+        let applicator = nwt.applicator().unwrap();
+        assert_eq!(applicator.len(), 3);
+        let mut out = [0.0; 3];
+        for step in 0..4 {
+            let field: Vec<f32> = (0..6).map(|i| ((i + step * 6) as f32 * 0.37).sin()).collect();
+            applicator.apply(&field, &mut out).unwrap();
+            assert_eq!(out.to_vec(), nwt.apply(&field).unwrap());
+        }
+
+        assert!(matches!(applicator.apply(&[1.0; 5], &mut out), Err(NwtError::FieldSizeMismatch { expected: 6, got: 5 })));
+        assert!(matches!(applicator.apply(&[1.0; 6], &mut [0.0; 2]),
+            Err(NwtError::FieldSizeMismatch { expected: 3, got: 2 })));
+    }
+
+    #[test]
+    fn applicator_handles_empty_polyids_and_bad_points() {
+        let nwt = NextWeightFileBuilder::new()
+            .dimensions(2, 2)
+            .add_polyid("none", vec![])
+            .add_polyid("one", vec![(1, 1, 0.0, 0.0, 2.0)])
+            .build();
+        let mut out = [f32::NAN; 2];
+        nwt.applicator().unwrap().apply(&[1.0, 2.0, 3.0, 4.0], &mut out).unwrap();
+        assert_eq!(out, [0.0, 8.0]);
+
+        let off_grid = NextWeightFileBuilder::new().dimensions(2, 2).add_polyid("a", vec![(2, 0, 0.0, 0.0, 1.0)]).build();
+        assert!(matches!(off_grid.applicator(), Err(NwtError::Corrupt(_))));
+    }
+}
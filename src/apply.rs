@@ -0,0 +1,475 @@
+//! Applying weights to gridded data.
+//!
+//! Every aggregation here accumulates in `f64`, whatever the output type, so
+//! summing many small weights over a large region doesn't lose precision the
+//! way a plain `f32` running sum does. The `f32` variants only round once, when
+//! the finished sum is stored.
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::{GridPoint, NextWeightFile, NwtError};
+
+/// Weighted statistics of a field over one polyid's cells, from
+/// `NextWeightFile::apply_stats`. Only cells with a valid value and a
+/// non-zero weight count, with the weights renormalized to sum to one over
+/// them
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolyidFieldStats {
+    /// `sum(w * x)`, the same value `apply_with_missing` gives
+    pub mean: f32,
+    /// `sum(w * (x - mean)^2)`, the population variance
+    pub variance: f32,
+    pub std: f32,
+    pub min: f32,
+    pub max: f32,
+    /// the weighted percentiles asked for, in the order asked. Each is the
+    /// smallest value whose cells, together with every smaller value's,
+    /// carry at least that share of the weight
+    pub percentiles: Vec<f32>,
+}
+
+impl NextWeightFile {
+    /// Applies the weights to a 2D field, returning one aggregated value per
+    /// polyid in `get_polyids()` order. `field` is a row-major
+    /// `lat_len * lon_len` array, and each polyid's value is
+    /// `sum(weight * field[lat_idx * lon_len + lon_idx])`
+    pub fn apply(&self, field: &[f32]) -> Result<Vec<f32>, NwtError> {
+        self.check_field_len(field.len())?;
+        self.polyid_gridpoints.iter()
+            .map(|entry| self.weighted_sum(entry, field).map(|v| v as f32))
+            .collect()
+    }
+
+    /// Same as `apply`, but returns the `f64` sums without rounding them back
+    /// down to `f32`
+    pub fn apply_f64(&self, field: &[f32]) -> Result<Vec<f64>, NwtError> {
+        self.check_field_len(field.len())?;
+        self.polyid_gridpoints.iter()
+            .map(|entry| self.weighted_sum(entry, field))
+            .collect()
+    }
+
+    /// Applies the weights to every time step of a row-major `[time, lat, lon]`
+    /// buffer, returning a row-major `[time, polyid]` result. Each polyid's
+    /// flat indices are resolved once and reused across all time steps
+    pub fn apply_3d(&self, data: &[f32], ntime: usize) -> Result<Vec<f32>, NwtError> {
+        self.check_data_len(data.len(), ntime)?;
+        let columns = self.polyid_gridpoints.iter()
+            .map(|entry| self.apply_entry_3d(entry, data, ntime))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(interleave_columns(&columns, ntime))
+    }
+
+    /// Parallel version of `apply`, splitting the work across polyids. The
+    /// output is identical to `apply`
+    #[cfg(feature = "rayon")]
+    pub fn apply_par(&self, field: &[f32]) -> Result<Vec<f32>, NwtError> {
+        self.check_field_len(field.len())?;
+        self.polyid_gridpoints.par_iter()
+            .map(|entry| self.weighted_sum(entry, field).map(|v| v as f32))
+            .collect()
+    }
+
+    /// Parallel version of `apply_3d`, splitting the work across polyids. The
+    /// output is identical to `apply_3d`
+    #[cfg(feature = "rayon")]
+    pub fn apply_3d_par(&self, data: &[f32], ntime: usize) -> Result<Vec<f32>, NwtError> {
+        self.check_data_len(data.len(), ntime)?;
+        let columns = self.polyid_gridpoints.par_iter()
+            .map(|entry| self.apply_entry_3d(entry, data, ntime))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(interleave_columns(&columns, ntime))
+    }
+
+    /// makes sure a `[time, lat, lon]` buffer holds exactly `ntime` fields
+    fn check_data_len(&self, len: usize, ntime: usize) -> Result<(), NwtError> {
+        let expected = ntime.checked_mul(self.field_len())
+            .ok_or(NwtError::FieldSizeMismatch { expected: usize::MAX, got: len })?;
+        if len != expected {
+            return Err(NwtError::FieldSizeMismatch { expected, got: len });
+        }
+        Ok(())
+    }
+
+    /// aggregates one polyid over every time step of a `[time, lat, lon]` buffer
+    fn apply_entry_3d(&self, entry: &[GridPoint], data: &[f32], ntime: usize) -> Result<Vec<f32>, NwtError> {
+        let field_len = self.field_len();
+        let indices = entry.iter()
+            .map(|point| self.flat_index(point.0, point.1))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut column = Vec::with_capacity(ntime);
+        for t in 0..ntime {
+            let field = &data[t * field_len..(t + 1) * field_len];
+            let mut total = 0.0f64;
+            for (point, &idx) in entry.iter().zip(indices.iter()) {
+                total += point.4 as f64 * field[idx] as f64;
+            }
+            column.push(total as f32);
+        }
+        Ok(column)
+    }
+
+    /// Applies the weights to a 2D field that contains missing values. Cells
+    /// equal to `missing` (or NaN, which is always treated as missing) are
+    /// skipped and the remaining weights are renormalized, so each value is a
+    /// weighted mean over the valid cells. A polyid is `None` when none of its
+    /// cells are valid, or when the valid cells carry less than `min_coverage`
+    /// of its total weight
+    pub fn apply_with_missing(&self, field: &[f32], missing: f32, min_coverage: f32) -> Result<Vec<Option<f32>>, NwtError> {
+        self.check_field_len(field.len())?;
+        let is_missing = |v: f32| v.is_nan() || v == missing;
+
+        let mut out = Vec::with_capacity(self.polyid_gridpoints.len());
+        for entry in self.polyid_gridpoints.iter() {
+            let mut total_weight = 0.0f64;
+            let mut valid_weight = 0.0f64;
+            let mut valid_sum = 0.0f64;
+            for point in entry.iter() {
+                let value = field[self.flat_index(point.0, point.1)?];
+                total_weight += point.4 as f64;
+                if !is_missing(value) {
+                    valid_weight += point.4 as f64;
+                    valid_sum += point.4 as f64 * value as f64;
+                }
+            }
+
+            if valid_weight == 0.0 || valid_weight / total_weight < min_coverage as f64 {
+                out.push(None);
+            } else {
+                out.push(Some((valid_sum / valid_weight) as f32));
+            }
+        }
+        Ok(out)
+    }
+
+    /// Weighted mean, variance, extremes and `percentiles` (each from 0 to
+    /// 100) of a 2D field over every polyid, in `get_polyids()` order. NaN
+    /// cells are skipped as in `apply_with_missing`, and a polyid is `None`
+    /// when none of its cells are valid
+    pub fn apply_stats(&self, field: &[f32], percentiles: &[f32]) -> Result<Vec<Option<PolyidFieldStats>>, NwtError> {
+        self.apply_stats_with_missing(field, f32::NAN, 0.0, percentiles)
+    }
+
+    /// Same as `apply_stats`, skipping cells equal to `missing` as well, and
+    /// giving `None` for polyids whose valid cells carry less than
+    /// `min_coverage` of their total weight
+    pub fn apply_stats_with_missing(
+        &self,
+        field: &[f32],
+        missing: f32,
+        min_coverage: f32,
+        percentiles: &[f32],
+    ) -> Result<Vec<Option<PolyidFieldStats>>, NwtError> {
+        self.check_field_len(field.len())?;
+        let is_missing = |v: f32| v.is_nan() || v == missing;
+
+        let mut out = Vec::with_capacity(self.polyid_gridpoints.len());
+        // each polyid's valid (value, weight) pairs, reused between them
+        let mut cells: Vec<(f32, f64)> = Vec::new();
+        for entry in self.polyid_gridpoints.iter() {
+            cells.clear();
+            let mut total_weight = 0.0f64;
+            for point in entry.iter() {
+                let value = field[self.flat_index(point.0, point.1)?];
+                total_weight += point.4 as f64;
+                if !is_missing(value) && point.4 != 0.0 {
+                    cells.push((value, point.4 as f64));
+                }
+            }
+            let valid_weight: f64 = cells.iter().map(|&(_, w)| w).sum();
+            if valid_weight == 0.0 || valid_weight / total_weight < min_coverage as f64 {
+                out.push(None);
+                continue;
+            }
+
+            let mean = cells.iter().map(|&(x, w)| w * x as f64).sum::<f64>() / valid_weight;
+            let variance = cells.iter().map(|&(x, w)| w * (x as f64 - mean).powi(2)).sum::<f64>() / valid_weight;
+            cells.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let quantiles = percentiles.iter().map(|&p| {
+                let target = (p as f64 / 100.0).clamp(0.0, 1.0) * valid_weight;
+                let mut cumulative = 0.0f64;
+                let at = cells.iter().position(|&(_, w)| {
+                    cumulative += w;
+                    cumulative >= target
+                });
+                // rounding can leave the running sum just short of the total
+                cells[at.unwrap_or(cells.len() - 1)].0
+            }).collect();
+            out.push(Some(PolyidFieldStats {
+                mean: mean as f32,
+                variance: variance as f32,
+                std: variance.sqrt() as f32,
+                min: cells[0].0,
+                max: cells[cells.len() - 1].0,
+                percentiles: quantiles,
+            }));
+        }
+        Ok(out)
+    }
+
+    /// number of cells in one lat x lon field
+    pub(crate) fn field_len(&self) -> usize {
+        self.lat_len as usize * self.lon_len as usize
+    }
+
+    /// makes sure a field has exactly one value per grid cell
+    pub(crate) fn check_field_len(&self, len: usize) -> Result<(), NwtError> {
+        let expected = self.field_len();
+        if len != expected {
+            return Err(NwtError::FieldSizeMismatch { expected, got: len });
+        }
+        Ok(())
+    }
+
+    /// the flat index of a point within a field
+    pub(crate) fn flat_index(&self, lat_idx: u32, lon_idx: u32) -> Result<usize, NwtError> {
+        if lat_idx as u64 >= self.lat_len || lon_idx as u64 >= self.lon_len {
+            return Err(NwtError::Corrupt(format!(
+                "point ({}, {}) lies outside the {}x{} grid", lat_idx, lon_idx, self.lat_len, self.lon_len)));
+        }
+        Ok(lat_idx as usize * self.lon_len as usize + lon_idx as usize)
+    }
+
+    fn weighted_sum(&self, entry: &[GridPoint], field: &[f32]) -> Result<f64, NwtError> {
+        let mut total = 0.0f64;
+        for point in entry.iter() {
+            total += point.4 as f64 * field[self.flat_index(point.0, point.1)?] as f64;
+        }
+        Ok(total)
+    }
+}
+
+#[cfg(feature = "ndarray")]
+impl NextWeightFile {
+    /// Applies the weights to a `(lat, lon)` array. Works with any memory
+    /// layout, including transposed or strided views
+    pub fn apply_array(&self, field: ndarray::ArrayView2<f32>) -> Result<ndarray::Array1<f32>, NwtError> {
+        let (nlat, nlon) = field.dim();
+        self.check_array_shape(nlat, nlon)?;
+
+        let mut out = ndarray::Array1::zeros(self.polyid_gridpoints.len());
+        for (value, entry) in out.iter_mut().zip(self.polyid_gridpoints.iter()) {
+            let mut total = 0.0f64;
+            for point in entry.iter() {
+                total += point.4 as f64 * *self.array_cell(&field, point.0, point.1)? as f64;
+            }
+            *value = total as f32;
+        }
+        Ok(out)
+    }
+
+    /// Applies the weights to every time step of a `(time, lat, lon)` array,
+    /// returning a `(time, polyid)` array. Works with any memory layout
+    pub fn apply_array3(&self, data: ndarray::ArrayView3<f32>) -> Result<ndarray::Array2<f32>, NwtError> {
+        let (ntime, nlat, nlon) = data.dim();
+        self.check_array_shape(nlat, nlon)?;
+
+        let mut out = ndarray::Array2::zeros((ntime, self.polyid_gridpoints.len()));
+        for (t, field) in data.outer_iter().enumerate() {
+            for (polyid, entry) in self.polyid_gridpoints.iter().enumerate() {
+                let mut total = 0.0f64;
+                for point in entry.iter() {
+                    total += point.4 as f64 * *self.array_cell(&field, point.0, point.1)? as f64;
+                }
+                out[[t, polyid]] = total as f32;
+            }
+        }
+        Ok(out)
+    }
+
+    fn check_array_shape(&self, nlat: usize, nlon: usize) -> Result<(), NwtError> {
+        let expected = (self.lat_len as usize, self.lon_len as usize);
+        if (nlat, nlon) != expected {
+            return Err(NwtError::ShapeMismatch { expected, got: (nlat, nlon) });
+        }
+        Ok(())
+    }
+
+    fn array_cell<'a>(&self, field: &'a ndarray::ArrayView2<f32>, lat_idx: u32, lon_idx: u32) -> Result<&'a f32, NwtError> {
+        field.get([lat_idx as usize, lon_idx as usize]).ok_or_else(|| NwtError::Corrupt(format!(
+            "point ({}, {}) lies outside the {}x{} grid", lat_idx, lon_idx, self.lat_len, self.lon_len)))
+    }
+}
+
+/// turns per-polyid time series into a row-major `[time, polyid]` array
+fn interleave_columns(columns: &[Vec<f32>], ntime: usize) -> Vec<f32> {
+    let num_polyids = columns.len();
+    let mut out = vec![0.0f32; ntime * num_polyids];
+    for (polyid, column) in columns.iter().enumerate() {
+        for (t, value) in column.iter().enumerate() {
+            out[t * num_polyids + polyid] = *value;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::sample_file;
+    use crate::{NextWeightFileBuilder, NwtError};
+
+    #[test]
+    fn apply_hand_computed() {
+        let nwt = sample_file();
+        // 2 x 3 grid, row-major
+        let field = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let result = nwt.apply(&field).unwrap();
+        // A: 0.25*1 + 0.75*2, B: 1.0*5, C: 0.5*4 + 0.5*6 + 0.0*3
+        assert_eq!(result, vec![1.75, 5.0, 5.0]);
+    }
+
+    #[test]
+    fn apply_rejects_wrong_field_size() {
+        let nwt = sample_file();
+        let err = nwt.apply(&[1.0; 5]).unwrap_err();
+        assert!(matches!(err, NwtError::FieldSizeMismatch { expected: 6, got: 5 }));
+    }
+
+    #[test]
+    fn apply_3d_matches_per_step_apply() {
+        let nwt = sample_file();
+        let step_a = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let step_b = [0.0, -1.0, 2.0, 10.0, 0.5, 4.0];
+        let data: Vec<f32> = step_a.iter().chain(step_b.iter()).copied().collect();
+
+        let result = nwt.apply_3d(&data, 2).unwrap();
+        let mut expected = nwt.apply(&step_a).unwrap();
+        expected.extend(nwt.apply(&step_b).unwrap());
+        assert_eq!(result, expected);
+
+        assert!(matches!(nwt.apply_3d(&data, 3), Err(NwtError::FieldSizeMismatch { expected: 18, got: 12 })));
+        assert_eq!(nwt.apply_3d(&[], 0).unwrap(), Vec::<f32>::new());
+    }
+
+    #[test]
+    fn apply_with_missing_renormalizes() {
+        let nwt = sample_file();
+        let missing = -999.0;
+        // A loses its 0.25 cell, B loses its only cell, C loses a 0.5 cell
+        let field = [missing, 2.0, 3.0, f32::NAN, missing, 6.0];
+
+        let result = nwt.apply_with_missing(&field, missing, 0.0).unwrap();
+        assert_eq!(result, vec![Some(2.0), None, Some(6.0)]);
+
+        // A keeps 75% of its weight and C keeps 50%
+        let result = nwt.apply_with_missing(&field, missing, 0.6).unwrap();
+        assert_eq!(result, vec![Some(2.0), None, None]);
+
+        // NaN works as the missing marker too
+        let field = [1.0, 2.0, 3.0, f32::NAN, 5.0, 6.0];
+        let result = nwt.apply_with_missing(&field, f32::NAN, 0.0).unwrap();
+        assert_eq!(result, vec![Some(1.75), Some(5.0), Some(6.0)]);
+    }
+
+    #[test]
+    fn apply_stats_hand_computed() {
+        // one 3-cell region, and one whose only cell is missing
+        let nwt = NextWeightFileBuilder::new()
+            .dimensions(1, 4)
+            .add_polyid("region", vec![(0, 0, 0.0, 0.0, 0.2), (0, 1, 0.0, 1.0, 0.3), (0, 2, 0.0, 2.0, 0.5)])
+            .add_polyid("empty", vec![(0, 3, 0.0, 3.0, 1.0)])
+            .build();
+        let field = [1.0, 4.0, 2.0, f32::NAN];
+        let stats = nwt.apply_stats(&field, &[10.0, 25.0, 90.0]).unwrap();
+        let region = stats[0].as_ref().unwrap();
+        // mean 0.2*1 + 0.3*4 + 0.5*2 = 2.4, variance
+        // 0.2*1.4^2 + 0.3*1.6^2 + 0.5*0.4^2 = 1.24. Sorted, the cumulative
+        // weights are 1: 0.2, 2: 0.7, 4: 1.0
+        assert!((region.mean - 2.4).abs() < 1e-6, "{:?}", region);
+        assert!((region.variance - 1.24).abs() < 1e-6, "{:?}", region);
+        assert!((region.std - 1.24f32.sqrt()).abs() < 1e-6, "{:?}", region);
+        assert_eq!((region.min, region.max), (1.0, 4.0));
+        assert_eq!(region.percentiles, vec![1.0, 2.0, 4.0]);
+        assert_eq!(stats[1], None);
+        assert_eq!(region.mean, nwt.apply_with_missing(&field, f32::NAN, 0.0).unwrap()[0].unwrap());
+
+        // the missing cell's weight is left out: 1 and 2 weigh 2/7 and 5/7
+        let missing = -999.0;
+        let stats = nwt.apply_stats_with_missing(&[1.0, missing, 2.0, 3.0], missing, 0.0, &[25.0, 50.0]).unwrap();
+        let region = stats[0].as_ref().unwrap();
+        assert!((region.mean - 12.0 / 7.0).abs() < 1e-6, "{:?}", region);
+        assert!((region.variance - 10.0 / 49.0).abs() < 1e-6, "{:?}", region);
+        assert_eq!(region.percentiles, vec![1.0, 2.0]);
+        assert_eq!(stats[1].as_ref().unwrap().percentiles, vec![3.0, 3.0]);
+        // which falls short of 80% coverage
+        assert_eq!(nwt.apply_stats_with_missing(&[1.0, missing, 2.0, 3.0], missing, 0.8, &[]).unwrap()[0], None);
+        assert!(matches!(nwt.apply_stats(&[1.0; 3], &[]), Err(NwtError::FieldSizeMismatch { expected: 4, got: 3 })));
+    }
+
+    #[test]
+    fn f64_accumulation_matches_kahan_reference() {
+        // one region made of 100k cells with tiny weights
+        let (lat_len, lon_len) = (400u32, 250u32);
+        let points: Vec<_> = (0..lat_len)
+            .flat_map(|i| (0..lon_len).map(move |j| (i, j, 0.0, 0.0, 1e-7f32)))
+            .collect();
+        let field: Vec<f32> = (0..lat_len * lon_len).map(|i| 1.0 + (i % 7) as f32 * 0.1).collect();
+        let nwt = NextWeightFileBuilder::new()
+            .dimensions(lat_len as u64, lon_len as u64)
+            .add_polyid("tiny", points.clone())
+            .build();
+
+        // compensated sum of the exact f64 products
+        let (mut reference, mut compensation) = (0.0f64, 0.0f64);
+        for (point, value) in points.iter().zip(field.iter()) {
+            let y = point.4 as f64 * *value as f64 - compensation;
+            let t = reference + y;
+            compensation = (t - reference) - y;
+            reference = t;
+        }
+        let naive_f32: f32 = points.iter().zip(field.iter()).map(|(p, v)| p.4 * v).sum();
+
+        let result = nwt.apply_f64(&field).unwrap()[0];
+        assert!((result - reference).abs() < 1e-12, "{} vs {}", result, reference);
+        assert!((naive_f32 as f64 - reference).abs() > 1e-9, "naive f32 sum was unexpectedly exact");
+        assert_eq!(nwt.apply(&field).unwrap()[0], reference as f32);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_apply_matches_serial() {
+        let nwt = sample_file();
+        let data: Vec<f32> = (0..6 * 4).map(|i| (i as f32 * 0.37).sin()).collect();
+        assert_eq!(nwt.apply_par(&data[..6]).unwrap(), nwt.apply(&data[..6]).unwrap());
+        assert_eq!(nwt.apply_3d_par(&data, 4).unwrap(), nwt.apply_3d(&data, 4).unwrap());
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn apply_array_handles_non_standard_layouts() {
+        let nwt = sample_file();
+        let flat = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let expected = nwt.apply(&flat).unwrap();
+
+        let field = ndarray::Array2::from_shape_vec((2, 3), flat.to_vec()).unwrap();
+        assert_eq!(nwt.apply_array(field.view()).unwrap().to_vec(), expected);
+
+        // the same field stored (lon, lat) and viewed transposed
+        let transposed = field.t().to_owned();
+        assert_eq!(nwt.apply_array(transposed.t()).unwrap().to_vec(), expected);
+
+        // a strided view picking every other row out of a bigger array
+        let padded = ndarray::Array2::from_shape_fn((4, 3), |(i, j)| if i % 2 == 0 { field[[i / 2, j]] } else { -1.0 });
+        assert_eq!(nwt.apply_array(padded.slice(ndarray::s![..;2, ..])).unwrap().to_vec(), expected);
+
+        assert!(matches!(nwt.apply_array(transposed.view()),
+            Err(NwtError::ShapeMismatch { expected: (2, 3), got: (3, 2) })));
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn apply_array3_matches_apply_3d() {
+        let nwt = sample_file();
+        let data: Vec<f32> = (0..6 * 3).map(|i| i as f32 * 0.5).collect();
+        let expected = nwt.apply_3d(&data, 3).unwrap();
+
+        // store as (lat, lon, time) and permute into a (time, lat, lon) view
+        let cube = ndarray::Array3::from_shape_vec((3, 2, 3), data).unwrap();
+        let permuted = cube.view().permuted_axes([1, 2, 0]).to_owned();
+        let result = nwt.apply_array3(permuted.view().permuted_axes([2, 0, 1])).unwrap();
+        assert_eq!(result.dim(), (3, 3));
+        assert_eq!(result.iter().copied().collect::<Vec<_>>(), expected);
+    }
+}
@@ -0,0 +1,244 @@
+//! Storage for a file's gridpoints: one buffer holding every polyid's
+//! points back to back, and the range of it each polyid owns.
+
+use std::mem::size_of;
+use std::ops::{Index, IndexMut, Range};
+use std::sync::OnceLock;
+
+use crate::{GridPoint, LookupEntry, PolyidEntry};
+
+/// Every polyid's points in a single allocation, in `get_polyids()` order.
+/// The ranges always tile the buffer, so no points lie between or after
+/// them
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PointArena {
+    points: Vec<GridPoint>,
+    ranges: Vec<Range<usize>>,
+    /// the entries as `PolyidEntry`s, for the accessors that hand them out
+    /// that way. Built on first use and dropped by any change
+    view: OnceLock<Vec<PolyidEntry>>,
+}
+
+impl PointArena {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// an empty arena with room for `polyids` entries of `points` points in
+    /// all
+    pub(crate) fn with_capacity(polyids: usize, points: usize) -> Self {
+        Self { points: Vec::with_capacity(points), ranges: Vec::with_capacity(polyids), view: OnceLock::new() }
+    }
+
+    /// number of entries
+    pub(crate) fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// every entry's points, one after the other
+    pub(crate) fn points(&self) -> &[GridPoint] {
+        &self.points
+    }
+
+    pub(crate) fn points_mut(&mut self) -> &mut [GridPoint] {
+        self.view = OnceLock::new();
+        &mut self.points
+    }
+
+    pub(crate) fn get(&self, idx: usize) -> Option<&[GridPoint]> {
+        self.ranges.get(idx).map(|range| &self.points[range.clone()])
+    }
+
+    /// each entry's points, in order
+    pub(crate) fn iter(&self) -> impl DoubleEndedIterator<Item = &[GridPoint]> + ExactSizeIterator + '_ {
+        self.ranges.iter().map(|range| &self.points[range.clone()])
+    }
+
+    #[cfg(feature = "rayon")]
+    pub(crate) fn par_iter(&self) -> impl rayon::prelude::IndexedParallelIterator<Item = &[GridPoint]> + '_ {
+        use rayon::prelude::*;
+        self.ranges.par_iter().map(|range| &self.points[range.clone()])
+    }
+
+    /// each entry's number of points
+    pub(crate) fn counts(&self) -> impl ExactSizeIterator<Item = usize> + Clone + '_ {
+        self.ranges.iter().map(|range| range.len())
+    }
+
+    /// the lookup table of the entries as they are now
+    pub(crate) fn lookup_table(&self) -> Vec<LookupEntry> {
+        self.ranges.iter().map(|range| LookupEntry::new(range.start as u64, range.len() as u64)).collect()
+    }
+
+    /// appends an entry
+    pub(crate) fn push(&mut self, points: impl IntoIterator<Item = GridPoint>) {
+        let start = self.points.len();
+        self.points.extend(points);
+        self.ranges.push(start..self.points.len());
+        self.view = OnceLock::new();
+    }
+
+    /// appends an entry whose points `fill` adds to the end of the buffer,
+    /// or nothing if it fails
+    pub(crate) fn push_with<T, E>(&mut self, fill: impl FnOnce(&mut Vec<GridPoint>) -> Result<T, E>) -> Result<T, E> {
+        let start = self.points.len();
+        match fill(&mut self.points) {
+            Ok(value) => {
+                self.ranges.push(start..self.points.len());
+                self.view = OnceLock::new();
+                Ok(value)
+            }
+            Err(e) => {
+                self.points.truncate(start);
+                Err(e)
+            }
+        }
+    }
+
+    /// appends empty entries until there are `len`
+    pub(crate) fn pad_to(&mut self, len: usize) {
+        while self.ranges.len() < len {
+            self.push([]);
+        }
+    }
+
+    /// replaces the points of entry `idx`, moving the ones after it
+    pub(crate) fn replace(&mut self, idx: usize, points: impl IntoIterator<Item = GridPoint>) {
+        let range = self.ranges[idx].clone();
+        let old_len = self.points.len();
+        self.points.splice(range.clone(), points);
+        let end = range.end + self.points.len() - old_len;
+        self.ranges[idx] = range.start..end;
+        self.shift_from(idx + 1, end as isize - range.end as isize);
+        self.view = OnceLock::new();
+    }
+
+    /// removes entry `idx`, returning its points
+    pub(crate) fn remove(&mut self, idx: usize) -> Vec<GridPoint> {
+        let range = self.ranges.remove(idx);
+        let removed: Vec<GridPoint> = self.points.drain(range.clone()).collect();
+        self.shift_from(idx, -(range.len() as isize));
+        self.view = OnceLock::new();
+        removed
+    }
+
+    /// keeps the points of entry `idx` whose `keep` flag is set, returning
+    /// how many were dropped
+    pub(crate) fn retain_in(&mut self, idx: usize, keep: &[bool]) -> usize {
+        let range = self.ranges[idx].clone();
+        let mut flags = keep.iter();
+        let kept: Vec<GridPoint> = self.points[range.clone()].iter().copied().filter(|_| *flags.next().unwrap()).collect();
+        let dropped = range.len() - kept.len();
+        if dropped > 0 {
+            self.replace(idx, kept);
+        }
+        dropped
+    }
+
+    /// the entries whose `dropped` flag isn't set, keeping only their points
+    pub(crate) fn without(&self, dropped: &[bool]) -> Self {
+        let mut kept = Self::new();
+        for (points, _) in self.iter().zip(dropped).filter(|(_, &gone)| !gone) {
+            kept.push(points.iter().copied());
+        }
+        kept
+    }
+
+    /// the entries as `PolyidEntry`s, built the first time they're asked for
+    pub(crate) fn entries(&self) -> &Vec<PolyidEntry> {
+        self.view.get_or_init(|| self.iter().map(|points| PolyidEntry { data: points.to_vec() }).collect())
+    }
+
+    /// heap bytes held, counting the full capacity of the buffer and of the
+    /// `PolyidEntry` view if it was built
+    pub(crate) fn heap_size(&self) -> usize {
+        let view = self.view.get().map_or(0, |entries| {
+            entries.capacity() * size_of::<PolyidEntry>()
+                + entries.iter().map(|e| e.data.capacity() * size_of::<GridPoint>()).sum::<usize>()
+        });
+        self.points.capacity() * size_of::<GridPoint>() + self.ranges.capacity() * size_of::<Range<usize>>() + view
+    }
+
+    /// moves the ranges of entry `idx` and every one after it by `by` points
+    fn shift_from(&mut self, idx: usize, by: isize) {
+        for range in self.ranges.iter_mut().skip(idx) {
+            *range = range.start.wrapping_add_signed(by)..range.end.wrapping_add_signed(by);
+        }
+    }
+}
+
+impl FromIterator<PolyidEntry> for PointArena {
+    fn from_iter<I: IntoIterator<Item = PolyidEntry>>(entries: I) -> Self {
+        let mut arena = Self::new();
+        for entry in entries {
+            arena.push(entry.data);
+        }
+        arena
+    }
+}
+
+impl Index<usize> for PointArena {
+    type Output = [GridPoint];
+
+    fn index(&self, idx: usize) -> &[GridPoint] {
+        &self.points[self.ranges[idx].clone()]
+    }
+}
+
+impl IndexMut<usize> for PointArena {
+    fn index_mut(&mut self, idx: usize) -> &mut [GridPoint] {
+        self.view = OnceLock::new();
+        &mut self.points[self.ranges[idx].clone()]
+    }
+}
+
+/// Equal when every entry has the same points, however the buffers were
+/// grown
+impl PartialEq for PointArena {
+    fn eq(&self, other: &Self) -> bool {
+        self.ranges == other.ranges && self.points == other.points
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(lat_idx: u32) -> GridPoint {
+        (lat_idx, 0, 0.0, 0.0, 1.0)
+    }
+
+    #[test]
+    fn edits_keep_the_ranges_tiling_the_buffer() {
+        let mut arena = PointArena::new();
+        arena.push([point(0), point(1)]);
+        arena.push([point(2)]);
+        arena.push([point(3), point(4), point(5)]);
+        assert_eq!(arena.lookup_table(), vec![(0, 2), (2, 1), (3, 3)]);
+        assert_eq!(&arena[2], &[point(3), point(4), point(5)]);
+
+        arena.replace(1, [point(6), point(7), point(8)]);
+        assert_eq!(arena.lookup_table(), vec![(0, 2), (2, 3), (5, 3)]);
+        assert_eq!(arena.remove(0), vec![point(0), point(1)]);
+        assert_eq!(arena.lookup_table(), vec![(0, 3), (3, 3)]);
+        assert_eq!(arena.retain_in(1, &[true, false, true]), 1);
+        assert_eq!(arena.points(), &[point(6), point(7), point(8), point(3), point(5)]);
+        assert_eq!(arena.without(&[true, false]).points(), &[point(3), point(5)]);
+
+        // a failed fill leaves nothing behind
+        assert!(arena.push_with(|points| { points.push(point(9)); Err::<(), _>(()) }).is_err());
+        assert_eq!(arena.len(), 2);
+        assert_eq!(arena.points().len(), 5);
+    }
+
+    #[test]
+    fn the_entry_view_follows_changes() {
+        let mut arena: PointArena = [PolyidEntry { data: vec![point(0)] }, PolyidEntry::new()].into_iter().collect();
+        assert_eq!(arena.entries()[0].data, vec![point(0)]);
+        arena[0][0].4 = 0.5;
+        assert_eq!(arena.entries()[0].data[0].4, 0.5);
+        arena.pad_to(3);
+        assert_eq!(arena.entries().len(), 3);
+        assert_eq!(arena, arena.without(&[false; 3]));
+    }
+}
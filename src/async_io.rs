@@ -0,0 +1,79 @@
+//! Async counterparts of `open` and `serialize_to_file`, using `tokio::fs`.
+//! The bytes move asynchronously; parsing and serializing go through the
+//! same in-memory code as `from_bytes` and `to_bytes`.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use tokio::io::AsyncReadExt;
+
+use crate::atomic::write_atomically;
+use crate::{is_nwt_magic, NextWeightFile, NwtError};
+
+impl NextWeightFile {
+    /// Async `open`. An NWT file is read with `tokio::fs` and parsed in
+    /// memory, verifying its checksum. A NetCDF file is converted (and
+    /// cached next to the source, as `open` does) on tokio's blocking pool,
+    /// since the NetCDF library only does blocking I/O
+    pub async fn open_async(path: impl AsRef<Path>) -> Result<Self, NwtError> {
+        let mut magic = [0u8; 4];
+        let read_len = tokio::fs::File::open(path.as_ref()).await?.read(&mut magic).await?;
+        if is_nwt_magic(&magic[..read_len]) {
+            let bytes = tokio::fs::read(path.as_ref()).await?;
+            // let other tasks run between the read and the parse
+            tokio::task::yield_now().await;
+            return Self::from_bytes(&bytes);
+        }
+        #[cfg(feature = "netcdf")]
+        {
+            let path = path.as_ref().to_path_buf();
+            tokio::task::spawn_blocking(move || Self::convert_and_cache(&path, &Default::default(), |_| {})).await
+                .map_err(|e| NwtError::Io(std::io::Error::other(e)))?
+        }
+        #[cfg(not(feature = "netcdf"))]
+        { Err(NwtError::InvalidMagic) }
+    }
+
+    /// Async `serialize_to_file`: serializes in memory, then writes the
+    /// bytes on tokio's blocking pool (as `tokio::fs` does), through a
+    /// temporary file renamed into place. Like `serialize_to_file`, an
+    /// existing file is an `AlreadyExists` error
+    pub async fn serialize_to_file_async(&self, path: impl AsRef<Path>) -> Result<PathBuf, NwtError> {
+        let bytes = self.to_bytes()?;
+        let path = path.as_ref().to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            write_atomically(&path, false, |w| Ok(w.write_all(&bytes)?))?;
+            Ok(path)
+        }).await.map_err(|e| NwtError::Io(std::io::Error::other(e)))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{sample_file, temp_path};
+    use crate::{NextWeightFile, NwtError};
+
+    fn block_on<F: std::future::Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(f)
+    }
+
+    #[test]
+    fn async_round_trip_matches_sync() {
+        let path = temp_path("async.nwt");
+        let nwt = sample_file();
+        block_on(nwt.serialize_to_file_async(&path)).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), nwt.to_bytes().unwrap());
+        let reopened = block_on(NextWeightFile::open_async(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reopened, nwt);
+
+        let existing = temp_path("async_existing.nwt");
+        std::fs::write(&existing, b"keep").unwrap();
+        let refused = block_on(nwt.serialize_to_file_async(&existing));
+        std::fs::remove_file(&existing).unwrap();
+        assert!(matches!(refused, Err(NwtError::AlreadyExists(_))));
+
+        let err = block_on(NextWeightFile::open_async(temp_path("async_missing.nwt"))).unwrap_err();
+        assert!(matches!(err, NwtError::Io(_)));
+    }
+}
@@ -0,0 +1,196 @@
+//! Replacing files so that readers see either the old contents or the new,
+//! never a partial write.
+
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::NwtError;
+
+/// Writes `path` by running `write` against a temporary file in the same
+/// directory, syncing it to disk and renaming it over `path`. If anything
+/// fails, the temporary file is removed and `path` is left as it was.
+/// Without `overwrite`, an existing `path` is an `AlreadyExists` error,
+/// checked before anything is written and again, atomically, when the
+/// temporary file is moved into place
+pub(crate) fn write_atomically(
+    path: &Path,
+    overwrite: bool,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), NwtError>,
+) -> Result<(), NwtError> {
+    if !overwrite && fs::symlink_metadata(path).is_ok() {
+        return Err(NwtError::AlreadyExists(path.to_path_buf()));
+    }
+    let tmp = temp_path_for(path);
+    let result = (|| {
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        write(&mut out)?;
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        if overwrite { replace(&tmp, path) } else { place_new(&tmp, path) }
+    })();
+    if result.is_err() {
+        // it may never have been created
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// `<path>.tmp-<random>`, next to `path` so the rename stays on one filesystem
+fn temp_path_for(path: &Path) -> PathBuf {
+    // RandomState is seeded randomly, which is all the uniqueness this needs
+    let suffix = RandomState::new().build_hasher().finish();
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(format!(".tmp-{:016x}", suffix));
+    path.with_file_name(name)
+}
+
+/// moves `tmp` over `target`, falling back to `copy_over` when the
+/// filesystem refuses the rename as crossing devices, as some network and
+/// union filesystems do even within one directory
+fn replace(tmp: &Path, target: &Path) -> Result<(), NwtError> {
+    match fs::rename(tmp, target) {
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => return copy_over(tmp, target),
+        result => result?,
+    }
+    sync_parent(target);
+    Ok(())
+}
+
+/// copies `tmp` into a second temporary file in the directory `target`
+/// really lives in, and renames that over `target`, so readers still never
+/// see it half-written. `tmp` is then removed if it can be
+fn copy_over(tmp: &Path, target: &Path) -> Result<(), NwtError> {
+    let dir = match target.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) => fs::canonicalize(dir)?,
+        None => std::env::current_dir()?,
+    };
+    let resolved = dir.join(target.file_name().unwrap_or_default());
+    let local = temp_path_for(&resolved);
+    let copied = (|| {
+        fs::copy(tmp, &local)?;
+        File::open(&local)?.sync_all()?;
+        fs::rename(&local, &resolved)
+    })();
+    if let Err(e) = copied {
+        let _ = fs::remove_file(&local);
+        return Err(e.into());
+    }
+    let _ = fs::remove_file(tmp);
+    sync_parent(&resolved);
+    Ok(())
+}
+
+/// moves `tmp` to `target`, failing with `AlreadyExists` if something is
+/// already there, however recently it appeared. A rename would replace it,
+/// so this links `tmp` in instead, or copies it into a file created with
+/// `create_new` where the filesystem has no hard links
+fn place_new(tmp: &Path, target: &Path) -> Result<(), NwtError> {
+    match fs::hard_link(tmp, target) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => return Err(NwtError::AlreadyExists(target.to_path_buf())),
+        Err(_) => {
+            let mut out = match OpenOptions::new().write(true).create_new(true).open(target) {
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => return Err(NwtError::AlreadyExists(target.to_path_buf())),
+                out => out?,
+            };
+            let copied = io::copy(&mut File::open(tmp)?, &mut out).and_then(|_| out.sync_all());
+            if let Err(e) = copied {
+                // the file is ours, and only partly written
+                let _ = fs::remove_file(target);
+                return Err(e.into());
+            }
+        }
+    }
+    // `target` is complete, so a leftover temporary file mustn't fail the
+    // write, or a retry would find `target` there and refuse
+    let _ = fs::remove_file(tmp);
+    sync_parent(target);
+    Ok(())
+}
+
+/// makes a rename or link into `target`'s directory durable. Directories
+/// can't be opened on every platform, so this is best-effort
+fn sync_parent(target: &Path) {
+    #[cfg(unix)]
+    if let Some(dir) = target.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        let _ = File::open(dir).and_then(|dir| dir.sync_all());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::tests::temp_path;
+
+    fn leftovers(dir: &Path) -> Vec<String> {
+        fs::read_dir(dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.contains(".tmp-"))
+            .collect()
+    }
+
+    #[test]
+    fn interrupted_write_leaves_target_intact() {
+        let dir = temp_path("atomic");
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("weights.nwt");
+        fs::write(&target, b"original").unwrap();
+
+        let err = write_atomically(&target, true, |w| {
+            w.write_all(b"half of the new")?;
+            Err(NwtError::InvalidInput("killed midway".to_string()))
+        });
+        assert!(matches!(err, Err(NwtError::InvalidInput(_))));
+        assert_eq!(fs::read(&target).unwrap(), b"original");
+        assert!(leftovers(&dir).is_empty());
+
+        write_atomically(&target, true, |w| Ok(w.write_all(b"replaced")?)).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"replaced");
+        assert!(leftovers(&dir).is_empty());
+
+        let refused = write_atomically(&target, false, |_| unreachable!());
+        assert!(matches!(refused, Err(NwtError::AlreadyExists(ref p)) if p == &target));
+        assert_eq!(fs::read(&target).unwrap(), b"replaced");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn copy_fallback_replaces_through_a_second_temp_file() {
+        let dir = temp_path("atomic-copy");
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("weights.nwt");
+        fs::write(&target, b"original").unwrap();
+        let tmp = temp_path_for(&target);
+        fs::write(&tmp, b"copied").unwrap();
+
+        copy_over(&tmp, &target).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"copied");
+        assert!(leftovers(&dir).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn target_created_mid_write_is_not_overwritten() {
+        let dir = temp_path("atomic-race");
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("weights.nwt");
+
+        let raced = write_atomically(&target, false, |w| {
+            fs::write(&target, b"theirs")?;
+            Ok(w.write_all(b"ours")?)
+        });
+        assert!(matches!(raced, Err(NwtError::AlreadyExists(ref p)) if p == &target));
+        assert_eq!(fs::read(&target).unwrap(), b"theirs");
+        assert!(leftovers(&dir).is_empty());
+
+        fs::remove_file(&target).unwrap();
+        write_atomically(&target, false, |w| Ok(w.write_all(b"ours")?)).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"ours");
+        assert!(leftovers(&dir).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,246 @@
+//! Typed attribute values.
+
+use std::fmt;
+
+#[cfg(feature = "netcdf")]
+use netcdf::AttributeValue;
+use serde::{Deserialize, Serialize};
+
+/// The value of a global or variable attribute, keeping the type it had in
+/// the source file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AttrVal {
+    Str(String),
+    Strs(Vec<String>),
+    F64(#[serde(with = "json_float")] f64),
+    F64s(#[serde(with = "json_floats")] Vec<f64>),
+    I64(i64),
+    I64s(Vec<i64>),
+    U64(u64),
+    U64s(Vec<u64>),
+}
+
+impl AttrVal {
+    /// Returns the string if this is a string attribute
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            AttrVal::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the strings of a string or string list attribute
+    pub fn as_strs(&self) -> Option<Vec<&str>> {
+        match self {
+            AttrVal::Str(s) => Some(vec![s.as_str()]),
+            AttrVal::Strs(v) => Some(v.iter().map(String::as_str).collect()),
+            _ => None,
+        }
+    }
+
+    /// Returns a single numeric value as `f64`
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            AttrVal::F64(v) => Some(*v),
+            AttrVal::I64(v) => Some(*v as f64),
+            AttrVal::U64(v) => Some(*v as f64),
+            _ => None,
+        }
+    }
+}
+
+/// Strings are shown as-is, everything else the way attribute values were
+/// stringified before they were typed
+impl fmt::Display for AttrVal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttrVal::Str(v) => write!(f, "{}", v),
+            AttrVal::Strs(v) => write!(f, "{:?}", v),
+            AttrVal::F64(v) => write!(f, "{}", v),
+            AttrVal::F64s(v) => write!(f, "{:?}", v),
+            AttrVal::I64(v) => write!(f, "{}", v),
+            AttrVal::I64s(v) => write!(f, "{:?}", v),
+            AttrVal::U64(v) => write!(f, "{}", v),
+            AttrVal::U64s(v) => write!(f, "{:?}", v),
+        }
+    }
+}
+
+impl From<String> for AttrVal {
+    fn from(v: String) -> Self {
+        AttrVal::Str(v)
+    }
+}
+
+impl From<&str> for AttrVal {
+    fn from(v: &str) -> Self {
+        AttrVal::Str(v.to_string())
+    }
+}
+
+impl From<Vec<String>> for AttrVal {
+    fn from(v: Vec<String>) -> Self {
+        AttrVal::Strs(v)
+    }
+}
+
+impl From<f64> for AttrVal {
+    fn from(v: f64) -> Self {
+        AttrVal::F64(v)
+    }
+}
+
+impl From<i64> for AttrVal {
+    fn from(v: i64) -> Self {
+        AttrVal::I64(v)
+    }
+}
+
+impl From<Vec<f64>> for AttrVal {
+    fn from(v: Vec<f64>) -> Self {
+        AttrVal::F64s(v)
+    }
+}
+
+#[cfg(feature = "netcdf")]
+impl From<AttributeValue> for AttrVal {
+    fn from(v: AttributeValue) -> Self {
+        fn widen<T: Into<f64>>(v: Vec<T>) -> Vec<f64> {
+            v.into_iter().map(Into::into).collect()
+        }
+        fn signed<T: Into<i64>>(v: Vec<T>) -> Vec<i64> {
+            v.into_iter().map(Into::into).collect()
+        }
+        fn unsigned<T: Into<u64>>(v: Vec<T>) -> Vec<u64> {
+            v.into_iter().map(Into::into).collect()
+        }
+        match v {
+            AttributeValue::Str(a) => AttrVal::Str(a),
+            AttributeValue::Strs(a) => AttrVal::Strs(a),
+            AttributeValue::Double(a) => AttrVal::F64(a),
+            AttributeValue::Doubles(a) => AttrVal::F64s(a),
+            AttributeValue::Float(a) => AttrVal::F64(a.into()),
+            AttributeValue::Floats(a) => AttrVal::F64s(widen(a)),
+            AttributeValue::Int(a) => AttrVal::I64(a.into()),
+            AttributeValue::Ints(a) => AttrVal::I64s(signed(a)),
+            AttributeValue::Longlong(a) => AttrVal::I64(a),
+            AttributeValue::Longlongs(a) => AttrVal::I64s(a),
+            AttributeValue::Schar(a) => AttrVal::I64(a.into()),
+            AttributeValue::Schars(a) => AttrVal::I64s(signed(a)),
+            AttributeValue::Short(a) => AttrVal::I64(a.into()),
+            AttributeValue::Shorts(a) => AttrVal::I64s(signed(a)),
+            AttributeValue::Uchar(a) => AttrVal::U64(a.into()),
+            AttributeValue::Uchars(a) => AttrVal::U64s(unsigned(a)),
+            AttributeValue::Uint(a) => AttrVal::U64(a.into()),
+            AttributeValue::Uints(a) => AttrVal::U64s(unsigned(a)),
+            AttributeValue::Ulonglong(a) => AttrVal::U64(a),
+            AttributeValue::Ulonglongs(a) => AttrVal::U64s(a),
+            AttributeValue::Ushort(a) => AttrVal::U64(a.into()),
+            AttributeValue::Ushorts(a) => AttrVal::U64s(unsigned(a)),
+        }
+    }
+}
+
+/// JSON has no NaN or infinity, and serde_json would write them as `null`.
+/// Non-finite values go out as the strings "NaN", "inf" and "-inf" instead,
+/// which is common for fill values
+mod json_float {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    pub(super) enum Repr {
+        Num(f64),
+        Special(String),
+    }
+
+    pub(super) fn to_repr(v: f64) -> Repr {
+        if v.is_nan() {
+            Repr::Special("NaN".to_string())
+        } else if v.is_infinite() {
+            Repr::Special(if v > 0.0 { "inf" } else { "-inf" }.to_string())
+        } else {
+            Repr::Num(v)
+        }
+    }
+
+    pub(super) fn from_repr<E: serde::de::Error>(r: Repr) -> Result<f64, E> {
+        match r {
+            Repr::Num(v) => Ok(v),
+            Repr::Special(s) => match s.as_str() {
+                "NaN" => Ok(f64::NAN),
+                "inf" => Ok(f64::INFINITY),
+                "-inf" => Ok(f64::NEG_INFINITY),
+                other => Err(E::custom(format!("invalid float {:?}", other))),
+            },
+        }
+    }
+
+    pub fn serialize<S: Serializer>(v: &f64, s: S) -> Result<S::Ok, S::Error> {
+        to_repr(*v).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<f64, D::Error> {
+        from_repr(Repr::deserialize(d)?)
+    }
+}
+
+/// `json_float` for a list of values
+mod json_floats {
+    use super::json_float::{from_repr, to_repr, Repr};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &[f64], s: S) -> Result<S::Ok, S::Error> {
+        v.iter().map(|&x| to_repr(x)).collect::<Vec<_>>().serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<f64>, D::Error> {
+        Vec::<Repr>::deserialize(d)?.into_iter().map(from_repr).collect()
+    }
+}
+
+/// The NetCDF value to write back for an attribute. Integers are written at
+/// 64 bits, so they come back as the same `AttrVal` but not necessarily
+/// their original NetCDF type
+#[cfg(feature = "netcdf")]
+impl From<&AttrVal> for AttributeValue {
+    fn from(v: &AttrVal) -> Self {
+        match v.clone() {
+            AttrVal::Str(a) => AttributeValue::Str(a),
+            AttrVal::Strs(a) => AttributeValue::Strs(a),
+            AttrVal::F64(a) => AttributeValue::Double(a),
+            AttrVal::F64s(a) => AttributeValue::Doubles(a),
+            AttrVal::I64(a) => AttributeValue::Longlong(a),
+            AttrVal::I64s(a) => AttributeValue::Longlongs(a),
+            AttrVal::U64(a) => AttributeValue::Ulonglong(a),
+            AttrVal::U64s(a) => AttributeValue::Ulonglongs(a),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_values_round_trip_through_json() {
+        let values = vec![
+            AttrVal::from("degrees_north"),
+            AttrVal::from(vec!["a.nc".to_string(), "b.nc".to_string()]),
+            AttrVal::F64(0.5),
+            AttrVal::F64(f64::NEG_INFINITY),
+            AttrVal::F64s(vec![-1.0, 1e20, f64::INFINITY]),
+            AttrVal::I64(-3),
+            AttrVal::I64s(vec![1, 2]),
+            AttrVal::U64(u64::MAX),
+            AttrVal::U64s(vec![]),
+        ];
+        let json = serde_json::to_string(&values).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<AttrVal>>(&json).unwrap(), values);
+
+        let nan = serde_json::to_string(&AttrVal::F64(f64::NAN)).unwrap();
+        assert_eq!(nan, r#"{"F64":"NaN"}"#);
+        assert!(serde_json::from_str::<AttrVal>(&nan).unwrap().as_f64().unwrap().is_nan());
+        assert_eq!(AttrVal::F64s(vec![1.5, 2.0]).to_string(), "[1.5, 2.0]");
+    }
+}
@@ -0,0 +1,89 @@
+//! `nwt`: inspect and convert NWT weight files from the command line.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use nextgen_weightfile::{NextWeightFile, NwtError, WriteOptions};
+
+#[derive(Parser)]
+#[command(name = "nwt", version, about = "Inspect and convert NWT weight files")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert a NetCDF weight file to NWT
+    Convert {
+        netcdf: PathBuf,
+        output: PathBuf,
+        /// Replace `output` if it already exists
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Print the dimensions, polyid count and attributes of a weight file
+    Info { file: PathBuf },
+    /// Print every polyid, one per line
+    ListPolyids { file: PathBuf },
+    /// Print one polyid's points as CSV
+    Dump {
+        file: PathBuf,
+        #[arg(long)]
+        polyid: String,
+    },
+    /// Check a weight file for problems, exiting non-zero if any are found
+    Validate { file: PathBuf },
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse().command) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("nwt: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(command: Command) -> Result<ExitCode, NwtError> {
+    match command {
+        Command::Convert { netcdf, output, overwrite } => {
+            let nwt = NextWeightFile::from_weight_file(&netcdf)?;
+            nwt.serialize_to_file_with(&output, &WriteOptions { overwrite, ..Default::default() })?;
+            println!("wrote {} polyids to {}", nwt.len(), output.display());
+        }
+        Command::Info { file } => {
+            let nwt = NextWeightFile::open(&file)?;
+            println!("{}", nwt);
+            for (key, value) in nwt.get_global_attrs() {
+                println!("  {} = {}", key, value);
+            }
+        }
+        Command::ListPolyids { file } => {
+            for polyid in NextWeightFile::open(&file)?.get_polyids() {
+                println!("{}", polyid);
+            }
+        }
+        Command::Dump { file, polyid } => {
+            let nwt = NextWeightFile::open(&file)?;
+            if nwt.polyid_index(&polyid).is_none() {
+                return Err(NwtError::PolyidNotFound(polyid));
+            }
+            nwt.to_csv_filtered(std::io::stdout().lock(), &[&polyid])?;
+        }
+        Command::Validate { file } => {
+            let report = NextWeightFile::open(&file)?.validate();
+            for issue in report.issues.iter() {
+                println!("{}", issue);
+            }
+            if !report.is_ok() {
+                println!("{} problem(s) found", report.issues.len());
+                return Ok(ExitCode::FAILURE);
+            }
+            println!("ok");
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
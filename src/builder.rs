@@ -0,0 +1,140 @@
+use std::sync::OnceLock;
+
+use crate::{AttrVal, Axes, Bounds, GridPoint, GridPointF64, JsonData, NextWeightFile, PointArena, PolyidEntry, DEFAULT_WEIGHT_SET};
+
+/// Builds a `NextWeightFile` from weights computed in memory rather than read
+/// from a file
+///
+/// ```
+/// use nextgen_weightfile::NextWeightFileBuilder;
+///
+/// let nwt = NextWeightFileBuilder::new()
+///     .dimensions(2, 2)
+///     .global_attr("title", "my weights")
+///     .add_polyid("region_a", vec![(0, 0, -45.0, 0.0, 0.5), (0, 1, -45.0, 90.0, 0.5)])
+///     .build();
+/// assert_eq!(nwt.len(), 1);
+/// ```
+#[derive(Debug, Default)]
+pub struct NextWeightFileBuilder {
+    json_data: JsonData,
+    lat_len: u64,
+    lon_len: u64,
+    polyid_gridpoints: Vec<PolyidEntry>,
+    /// double-precision weights, once a polyid has been added with them
+    f64_weights: Option<Vec<Vec<f64>>>,
+    axes: Option<Axes>,
+    lat_bounds: Option<Bounds>,
+    lon_bounds: Option<Bounds>,
+}
+
+impl NextWeightFileBuilder {
+    /// creates an empty builder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// sets the latitude and longitude dimensions of the grid
+    pub fn dimensions(mut self, lat_len: u64, lon_len: u64) -> Self {
+        self.lat_len = lat_len;
+        self.lon_len = lon_len;
+        self
+    }
+
+    /// records the source grid's latitude and longitude axes, which must have
+    /// the lengths given to `dimensions` for the file to be serialized
+    pub fn axes(mut self, lats: Vec<f32>, lons: Vec<f32>) -> Self {
+        self.axes = Some((lats, lons));
+        self
+    }
+
+    /// records the (lower, upper) bounds of the source grid's cells along
+    /// each axis, which must have the lengths given to `dimensions` for the
+    /// file to be serialized
+    pub fn bounds(mut self, lat_bounds: Vec<(f32, f32)>, lon_bounds: Vec<(f32, f32)>) -> Self {
+        self.lat_bounds = Some(lat_bounds);
+        self.lon_bounds = Some(lon_bounds);
+        self
+    }
+
+    /// adds a global attribute
+    pub fn global_attr(mut self, key: impl Into<String>, value: impl Into<AttrVal>) -> Self {
+        self.json_data.add_global_attr(key, value);
+        self
+    }
+
+    /// adds an attribute to a variable, adding the variable if needed
+    pub fn variable_attr(mut self, var: impl Into<String>, key: impl Into<String>, value: impl Into<AttrVal>) -> Self {
+        self.json_data.add_variable_attr(var, key, value);
+        self
+    }
+
+    /// adds a polyid along with its (lat_idx, lon_idx, lat, lon, weight) points
+    pub fn add_polyid(mut self, name: impl Into<String>, points: Vec<GridPoint>) -> Self {
+        self.json_data.add_polyid(name.into());
+        if let Some(weights) = self.f64_weights.as_mut() {
+            weights.push(points.iter().map(|p| p.4 as f64).collect());
+        }
+        self.polyid_gridpoints.push(PolyidEntry { data: points });
+        self
+    }
+
+    /// adds a polyid whose weights are double precision, which makes the
+    /// whole file store its weights as f64
+    pub fn add_polyid_f64(mut self, name: impl Into<String>, points: Vec<GridPointF64>) -> Self {
+        let gridpoints = &self.polyid_gridpoints;
+        let weights = self.f64_weights.get_or_insert_with(|| {
+            gridpoints.iter().map(|e| e.data.iter().map(|p| p.4 as f64).collect()).collect()
+        });
+        weights.push(points.iter().map(|p| p.4).collect());
+        self.json_data.add_polyid(name.into());
+        self.polyid_gridpoints.push(PolyidEntry { data: points.iter().map(|p| (p.0, p.1, p.2, p.3, p.4 as f32)).collect() });
+        self
+    }
+
+    /// finishes the weight file, computing its lookup table
+    pub fn build(self) -> NextWeightFile {
+        let polyid_gridpoints: PointArena = self.polyid_gridpoints.into_iter().collect();
+        let lookup_table = polyid_gridpoints.lookup_table();
+        let (lat_axis, lon_axis) = self.axes.unzip();
+        NextWeightFile {
+            json_data: self.json_data,
+            lat_len: self.lat_len,
+            lon_len: self.lon_len,
+            polyid_gridpoints,
+            lookup_table,
+            lat_axis,
+            lon_axis,
+            lat_bounds: self.lat_bounds,
+            lon_bounds: self.lon_bounds,
+            weight_set_name: DEFAULT_WEIGHT_SET.to_string(),
+            weight_sets: Vec::new(),
+            f64_weights: self.f64_weights,
+            polyid_lookup: OnceLock::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_file_round_trips_through_nwt() {
+        let built = NextWeightFileBuilder::new()
+            .dimensions(3, 4)
+            .global_attr("title", "built by hand")
+            .variable_attr("regridweights", "units", "1")
+            .add_polyid("north", vec![(2, 0, 30.0, 0.0, 0.5), (2, 1, 30.0, 90.0, 0.5)])
+            .add_polyid("empty", Vec::new())
+            .add_polyid("south", vec![(0, 3, -30.0, 270.0, 1.0)])
+            .build();
+        assert_eq!(built.get_lookup_table(), &vec![(0, 2), (2, 0), (2, 1)]);
+
+        let mut buffer: Vec<u8> = Vec::new();
+        built.serialize_to_writer(&mut buffer).unwrap();
+        let fresh = NextWeightFile::from_reader(&buffer[..]).unwrap();
+        assert_eq!(built, fresh);
+        assert_eq!(fresh.get_entry("south").unwrap().data, vec![(0, 3, -30.0, 270.0, 1.0)]);
+    }
+}
@@ -0,0 +1,85 @@
+//! Byte order. NWT is a little-endian format: every integer and float in a
+//! file is stored least significant byte first, whatever the host. Every
+//! multi-byte value read or written goes through here, so nothing else
+//! depends on the host's byte order.
+
+use std::io::Write;
+use std::mem::size_of;
+
+macro_rules! le_codec {
+    ($($ty:ty => $read:ident, $write:ident;)*) => {$(
+        #[doc = concat!("reads a little-endian `", stringify!($ty), "` from the start of `bytes`, which must hold one")]
+        pub(crate) fn $read(bytes: &[u8]) -> $ty {
+            <$ty>::from_le_bytes(bytes[..size_of::<$ty>()].try_into().unwrap())
+        }
+
+        #[doc = concat!("writes `value` as a little-endian `", stringify!($ty), "`")]
+        pub(crate) fn $write<W: Write + ?Sized>(w: &mut W, value: $ty) -> std::io::Result<()> {
+            w.write_all(&value.to_le_bytes())
+        }
+    )*};
+}
+
+le_codec! {
+    u16 => read_u16_le, write_u16_le;
+    u32 => read_u32_le, write_u32_le;
+    u64 => read_u64_le, write_u64_le;
+    f32 => read_f32_le, write_f32_le;
+    f64 => read_f64_le, write_f64_le;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{NextWeightFile, NextWeightFileBuilder};
+
+    #[test]
+    fn values_are_least_significant_byte_first() {
+        let mut buf = Vec::new();
+        write_u16_le(&mut buf, 0x0102).unwrap();
+        write_u32_le(&mut buf, 0x0304_0506).unwrap();
+        write_u64_le(&mut buf, 0x0708_090a_0b0c_0d0e).unwrap();
+        write_f32_le(&mut buf, 1.0).unwrap();
+        write_f64_le(&mut buf, -2.0).unwrap();
+        assert_eq!(buf, [
+            0x02, 0x01,
+            0x06, 0x05, 0x04, 0x03,
+            0x0e, 0x0d, 0x0c, 0x0b, 0x0a, 0x09, 0x08, 0x07,
+            0x00, 0x00, 0x80, 0x3f,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0,
+        ]);
+        assert_eq!(read_u16_le(&buf), 0x0102);
+        assert_eq!(read_u32_le(&buf[2..]), 0x0304_0506);
+        assert_eq!(read_u64_le(&buf[6..]), 0x0708_090a_0b0c_0d0e);
+        assert_eq!(read_f32_le(&buf[14..]), 1.0);
+        assert_eq!(read_f64_le(&buf[18..]), -2.0);
+    }
+
+    /// the bytes of a hex fixture, which may spread them over several lines
+    /// and comment them with `#`
+    fn parse_hex(fixture: &str) -> Vec<u8> {
+        let digits: String = fixture.lines()
+            .map(|line| line.split('#').next().unwrap())
+            .flat_map(|line| line.chars().filter(|c| !c.is_whitespace()))
+            .collect();
+        (0..digits.len()).step_by(2).map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn tiny_file_matches_golden_bytes() {
+        let nwt = NextWeightFileBuilder::new()
+            .dimensions(2, 2)
+            .axes(vec![-45.0, 45.0], vec![0.0, 90.0])
+            .global_attr("title", "golden")
+            .add_polyid("a", vec![(0, 0, -45.0, 0.0, 0.25), (1, 1, 45.0, 90.0, 0.75)])
+            .add_polyid("b", vec![(1, 0, 45.0, 0.0, 1.0)])
+            .build();
+        let golden = parse_hex(include_str!("../tests/fixtures/golden_v10.hex"));
+        let bytes = nwt.to_bytes().unwrap();
+        if let Some(at) = bytes.iter().zip(golden.iter()).position(|(a, b)| a != b) {
+            panic!("byte {} is {:#04x}, the golden file has {:#04x}", at, bytes[at], golden[at]);
+        }
+        assert_eq!(bytes.len(), golden.len());
+        assert_eq!(NextWeightFile::from_bytes(&golden).unwrap(), nwt);
+    }
+}
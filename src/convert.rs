@@ -0,0 +1,1137 @@
+//! Converting NetCDF weight files to NWT. Needs the `netcdf` feature, which
+//! is on by default.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::{grid_cells, has_nwt_magic, provenance, AttrVal, ConvertOptions, JsonData, NextWeightFile, NwtError, OpenOptions, PointArena, PolyidEntry, WriteOptions};
+use crate::DEFAULT_WEIGHT_SET;
+
+/// How far a NetCDF conversion has got, as passed to the callback of
+/// `from_weight_file_with_progress`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// index of the polyid that was just converted
+    pub polyid: usize,
+    /// number of polyids in the file
+    pub total_polyids: usize,
+    /// gridpoints kept so far, over all converted polyids
+    pub points: u64,
+}
+
+/// A converted file and what `from_weight_file_reporting` had to leave out
+/// of it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversionResult {
+    pub file: NextWeightFile,
+    /// one message per attribute skipped because its type (a compound,
+    /// enum, opaque or variable-length type) has no JSON form, naming it
+    pub warnings: Vec<String>,
+}
+
+impl NextWeightFile {
+    /// opens a NetCDF weight file and converts it to
+    pub fn from_weight_file(path: impl AsRef<Path>) -> Result<Self, NwtError> {
+        Self::from_weight_file_with(path, &ConvertOptions::default())
+    }
+
+    /// Same as `from_weight_file`, with control over how the conversion is
+    /// done
+    pub fn from_weight_file_with(path: impl AsRef<Path>, opts: &ConvertOptions) -> Result<Self, NwtError> {
+        Self::convert(path.as_ref(), opts, |_| {}).map(|result| result.file)
+    }
+
+    /// Same as `from_weight_file_with`, also returning the warnings the
+    /// conversion logged about attributes it skipped
+    pub fn from_weight_file_reporting(path: impl AsRef<Path>, opts: &ConvertOptions) -> Result<ConversionResult, NwtError> {
+        Self::convert(path.as_ref(), opts, |_| {})
+    }
+
+    /// Same as `from_weight_file_with`, calling `progress` after each polyid
+    /// is converted. The callback runs on the converting thread; if it
+    /// panics, the panic propagates out of this call and the conversion is
+    /// abandoned. Nothing has been written anywhere at that point
+    pub fn from_weight_file_with_progress(
+        path: impl AsRef<Path>,
+        opts: &ConvertOptions,
+        progress: impl FnMut(Progress),
+    ) -> Result<Self, NwtError> {
+        Self::convert(path.as_ref(), opts, progress).map(|result| result.file)
+    }
+
+    /// the conversion itself. Generic over the callback, so the no-op one
+    /// `from_weight_file_with` passes compiles away
+    fn convert(path: &Path, opts: &ConvertOptions, mut progress: impl FnMut(Progress)) -> Result<ConversionResult, NwtError> {
+        // open the weight file
+        let weight_netcdf = netcdf::open(path)?;
+        // explicit names come first, CF attributes are the fallback for
+        // unusually named coordinates
+        let resolved = opts.clone().with_cf_coordinates(&weight_netcdf);
+        let opts = &resolved;
+        log::debug!("converting {} using {}", path.display(), opts.names_summary());
+
+        // now we get all of the attributes
+        let (mut json_data, warnings) = read_netcdf_attrs(&weight_netcdf)?;
+        log::debug!("read {} global attributes and the attributes of {} variables",
+            json_data.global_attrs.len(), json_data.per_variable_attrs.len());
+
+        // now that we have gotten our attributes all squared away, lets start
+        // looking at data. First things first, lets store those polyids
+        let polyid_var = find_variable(&weight_netcdf, &opts.polyid_var)?;
+        for polyid in read_polyids(&polyid_var)? {
+            json_data.add_polyid(polyid);
+        };
+
+        // next lets start processing those weights
+        let regridweights = find_variable(&weight_netcdf, &opts.weights_var)?;
+        let latvar = find_variable(&weight_netcdf, &opts.lat_var)?;
+        let lonvar = find_variable(&weight_netcdf, &opts.lon_var)?;
+        let lat_vals = read_as_f64(&latvar, ..)?.into_iter().map(|v| v as f32).collect::<Vec<f32>>();
+        let lon_vals = read_as_f64(&lonvar, ..)?.into_iter().map(|v| v as f32).collect::<Vec<f32>>();
+        let lat_len = find_dimension(&weight_netcdf, &opts.lat_dim)?.len() as u64;
+        let lon_len = find_dimension(&weight_netcdf, &opts.lon_dim)?.len() as u64;
+        grid_cells(lat_len, lon_len)?;
+        let lat_bounds = read_cell_bounds(&weight_netcdf, &latvar, lat_len)?;
+        let lon_bounds = read_cell_bounds(&weight_netcdf, &lonvar, lon_len)?;
+        let lon_first = weights_lon_first(&regridweights, &polyid_var, opts)?;
+        let fill = match opts.fill_value {
+            Some(fill) => Some(fill),
+            None => match regridweights.attribute_value("_FillValue") {
+                // read the attribute directly so a fill stored as some other
+                // numeric type still works
+                Some(value) => Some(fill_as_f32(&AttrVal::from(value?))?),
+                // without one, cells never written hold the library's default
+                // fill, or there's no fill at all and every value counts
+                None => default_fill(&regridweights)?,
+            },
+        };
+        // packed weights are unpacked as they're read. The stored weights
+        // are the unpacked ones, so the packing attributes don't carry over
+        let packing = packing(&regridweights)?;
+        if packing.is_some() {
+            if let Some(attrs) = json_data.per_variable_attrs.get_mut(&opts.weights_var) {
+                attrs.retain(|(key, _)| key != "scale_factor" && key != "add_offset");
+            }
+        }
+        log::debug!("scanning {} polyids on a {}x{} grid ({} layout, fill {:?}, packing {:?})", polyid_var.len(),
+            lat_len, lon_len, if lon_first { "lon-first" } else { "lat-first" }, fill, packing);
+        // within the tolerance of the fill, which the default of 0 makes an
+        // exact match
+        let is_fill = |value: f32| fill.is_some_and(|fill| (value - fill).abs() <= opts.fill_tolerance);
+        let mut polyid_gridpoints = PointArena::new();
+        // double-precision sources keep their weights as they are
+        let keep_f64 = regridweights.vartype().is_f64() && !opts.force_f32;
+        let mut f64_weights: Vec<Vec<f64>> = Vec::new();
+        // weights stored as doubles that don't survive the trip to f32
+        let mut lossy_weights = 0usize;
+        let mut zero_weights = 0u64;
+        let total_polyids = polyid_var.len();
+        let mut points = 0u64;
+        let (lat_len_us, lon_len_us) = (lat_len as usize, lon_len as usize);
+        let chunk_rows = opts.chunk_rows.max(1);
+
+        // for every polyid...
+        for polyid in 0..total_polyids {
+            // ... create a new entry into our lookup vector...
+            let mut curr_polyid = PolyidEntry::new();
+            let mut curr_f64 = Vec::new();
+            // ... read its weights a few latitude rows at a time, so only a
+            // chunk of the slab is ever in memory...
+            for rows_start in (0..lat_len_us).step_by(chunk_rows) {
+                let rows = rows_start..lat_len_us.min(rows_start.saturating_add(chunk_rows));
+                let n_rows = rows.len();
+                let chunk = if lon_first {
+                    read_as_f64(&regridweights, (polyid, .., rows.clone()))?
+                } else {
+                    read_as_f64(&regridweights, (polyid, rows.clone(), ..))?
+                };
+                // ... for every data value...
+                for lat_idx in rows {
+                    let row = lat_idx - rows_start;
+                    for lon_idx in 0..lon_len_us {
+                        let raw_value = if lon_first {
+                            chunk[lon_idx * n_rows + row]
+                        } else {
+                            chunk[row * lon_len_us + lon_idx]
+                        };
+                        // ...if it isnt a fill value (or NaN/inf, which a NaN
+                        // fill would otherwise let through), which like the
+                        // fill is still packed...
+                        let packed_value = raw_value as f32;
+                        if packed_value.is_finite() && !is_fill(packed_value) {
+                            let raw_value = match packing {
+                                Some((scale_factor, add_offset)) => raw_value * scale_factor + add_offset,
+                                None => raw_value,
+                            };
+                            // the NWT format stores f32
+                            let data_value = raw_value as f32;
+                            if opts.skip_zero_weights && data_value == 0.0 {
+                                zero_weights += 1;
+                                continue;
+                            }
+                            // ... then calculate the lat lon and save the weight
+                            if keep_f64 {
+                                curr_f64.push(raw_value);
+                            } else if loses_precision(raw_value) {
+                                lossy_weights += 1;
+                            }
+                            curr_polyid.add_point(lat_idx as u32, lon_idx as u32, lat_vals[lat_idx], lon_vals[lon_idx], data_value);
+                        }
+                    }
+                }
+            }
+
+            // now push the polyid entry to our lookup vector
+            log::trace!("polyid {} ({}) keeps {} cells", polyid, json_data.polyids[polyid], curr_polyid.data.len());
+            points += curr_polyid.data.len() as u64;
+            polyid_gridpoints.push(curr_polyid.data);
+            if keep_f64 {
+                f64_weights.push(curr_f64);
+            }
+            progress(Progress { polyid, total_polyids, points });
+        }
+
+        log::debug!("kept {} of {} cells, skipping {} zero weights", points, total_polyids as u64 * lat_len * lon_len,
+            zero_weights);
+        if lossy_weights > 0 {
+            log::warn!("{} weights in {} lost more than {} relative precision converting to f32",
+                lossy_weights, path.display(), MAX_RELATIVE_LOSS);
+        }
+
+        // and finally lets build our lookup table
+        let lookup_table = polyid_gridpoints.lookup_table();
+
+        if opts.provenance {
+            provenance::record(&mut json_data, path, opts)?;
+        }
+
+        // now we are done, so return ourselves
+        let file = Self {
+            json_data,
+            lat_len,
+            lon_len,
+            polyid_gridpoints,
+            lookup_table,
+            lat_axis: Some(lat_vals),
+            lon_axis: Some(lon_vals),
+            lat_bounds,
+            lon_bounds,
+            weight_set_name: opts.weight_set.clone(),
+            weight_sets: Vec::new(),
+            f64_weights: keep_f64.then_some(f64_weights),
+            polyid_lookup: OnceLock::new()
+        };
+        Ok(ConversionResult { file, warnings })
+    }
+
+    /// Same as `open`, with control over how a NetCDF file is converted and
+    /// where the result is cached
+    pub fn open_with(path: impl AsRef<Path>, opts: &OpenOptions) -> Result<Self, NwtError> {
+        if has_nwt_magic(path.as_ref())? {
+            return Self::from_nwt(path);
+        }
+        Self::convert_and_cache(path.as_ref(), opts, |_| {})
+    }
+
+    /// Same as `open_with`, calling `progress` after each polyid while
+    /// converting a NetCDF file, as `from_weight_file_with_progress` does. An
+    /// NWT file is read without calling it
+    pub fn open_with_progress(path: impl AsRef<Path>, opts: &OpenOptions, progress: impl FnMut(Progress)) -> Result<Self, NwtError> {
+        if has_nwt_magic(path.as_ref())? {
+            return Self::from_nwt(path);
+        }
+        Self::convert_and_cache(path.as_ref(), opts, progress)
+    }
+
+    /// converts a NetCDF file for `open`, caching the result as `opts` says.
+    /// The conversion is returned even if the cache can't be written
+    pub(crate) fn convert_and_cache(path: &Path, opts: &OpenOptions, progress: impl FnMut(Progress)) -> Result<Self, NwtError> {
+        let convert = match &opts.convert {
+            Some(convert) => convert.clone(),
+            None => ConvertOptions::default().with_aliases(&netcdf::open(path)?),
+        };
+        let a = Self::convert(path, &convert, progress)?.file;
+        if let Some(cache_path) = opts.cache.path_for(path) {
+            // a stale cache from an earlier conversion is replaced
+            match a.serialize_to_file_with(&cache_path, &WriteOptions { overwrite: true, ..Default::default() }) {
+                Ok(_) => log::info!("cached the converted weights in {}; open that next time to skip the conversion",
+                    cache_path.display()),
+                Err(e) => log::warn!("could not cache the converted weights in {}: {}", cache_path.display(), e),
+            }
+        }
+        Ok(a)
+    }
+
+    /// Returns a dummy weight file
+    pub fn dummy(input_file: impl AsRef<Path>) -> Result<Self, NwtError> {
+        let weight_netcdf = netcdf::open(input_file)?;
+        let (mut json_data, _) = read_netcdf_attrs(&weight_netcdf)?;
+
+        let polyid_var = find_variable(&weight_netcdf, "polyid")?;
+        for polyid in read_polyids(&polyid_var)? {
+            json_data.add_polyid(polyid);
+        };
+
+
+        Ok(Self { 
+            json_data, 
+            lat_len: 0, 
+            lon_len: 0, 
+            polyid_gridpoints: PointArena::new(), 
+            lookup_table: Vec::new(),
+            lat_axis: None,
+            lon_axis: None,
+            lat_bounds: None,
+            lon_bounds: None,
+            weight_set_name: DEFAULT_WEIGHT_SET.to_string(),
+            weight_sets: Vec::new(),
+            f64_weights: None,
+            polyid_lookup: OnceLock::new()
+        })
+    }
+}
+
+/// other names seen in the wild for each of the variables and dimensions,
+/// tried by `NextWeightFile::open` when the configured name is missing
+const POLYID_ALIASES: &[&str] = &["region_id", "region", "poly_id"];
+const WEIGHTS_ALIASES: &[&str] = &["weights", "weight"];
+const LAT_ALIASES: &[&str] = &["latitude"];
+const LON_ALIASES: &[&str] = &["longitude"];
+
+impl ConvertOptions {
+    /// swaps every name the file doesn't have for the first common alias it
+    /// does. Names that match nothing are left alone so the error reports
+    /// the configured one
+    pub(crate) fn with_aliases(mut self, file: &netcdf::File) -> Self {
+        let has_var = |name: &str| file.variable(name).is_some();
+        let has_dim = |name: &str| file.dimension(name).is_some();
+        resolve(&mut self.polyid_var, POLYID_ALIASES, has_var);
+        resolve(&mut self.weights_var, WEIGHTS_ALIASES, has_var);
+        resolve(&mut self.lat_var, LAT_ALIASES, has_var);
+        resolve(&mut self.lon_var, LON_ALIASES, has_var);
+        resolve(&mut self.lat_dim, LAT_ALIASES, has_dim);
+        resolve(&mut self.lon_dim, LON_ALIASES, has_dim);
+        self
+    }
+}
+
+/// CF `units` spellings identifying latitude and longitude coordinates
+const DEGREES_NORTH: &[&str] = &["degrees_north", "degree_north", "degrees_N", "degree_N", "degreesN", "degreeN"];
+const DEGREES_EAST: &[&str] = &["degrees_east", "degree_east", "degrees_E", "degree_E", "degreesE", "degreeE"];
+
+impl ConvertOptions {
+    /// when the configured latitude/longitude variables are missing, looks
+    /// for 1-D variables whose CF `standard_name` or `units` mark them as
+    /// coordinates, and takes the dimension from the variable if the
+    /// configured one is missing too
+    pub(crate) fn with_cf_coordinates(mut self, file: &netcdf::File) -> Self {
+        detect_coordinate(file, &mut self.lat_var, &mut self.lat_dim, "latitude", DEGREES_NORTH);
+        detect_coordinate(file, &mut self.lon_var, &mut self.lon_dim, "longitude", DEGREES_EAST);
+        self
+    }
+}
+
+fn detect_coordinate(file: &netcdf::File, var: &mut String, dim: &mut String, standard_name: &str, units: &[&str]) {
+    if file.variable(var).is_none() {
+        let attr_is = |v: &netcdf::Variable, key: &str, wanted: &[&str]| {
+            matches!(v.attribute_value(key), Some(Ok(netcdf::AttributeValue::Str(s))) if wanted.contains(&s.as_str()))
+        };
+        let found = file.variables().find(|v| {
+            v.dimensions().len() == 1
+                && (attr_is(v, "standard_name", &[standard_name]) || attr_is(v, "units", units))
+        });
+        if let Some(found) = found {
+            *var = found.name();
+        }
+    }
+    if file.dimension(dim).is_none() {
+        if let Some(coord) = file.variable(var).filter(|v| v.dimensions().len() == 1) {
+            *dim = coord.dimensions()[0].name();
+        }
+    }
+}
+
+fn resolve(name: &mut String, aliases: &[&str], present: impl Fn(&str) -> bool) {
+    if !present(name) {
+        if let Some(alias) = aliases.iter().find(|alias| present(alias)) {
+            *name = alias.to_string();
+        }
+    }
+}
+
+/// copies a NetCDF file's global and per-variable attributes. Attributes of
+/// user-defined types are skipped, and a warning naming each is logged and
+/// returned
+pub(crate) fn read_netcdf_attrs(file: &netcdf::File) -> Result<(JsonData, Vec<String>), NwtError> {
+    let mut json_data = JsonData::new();
+    let mut warnings = Vec::new();
+    for attr in file.attributes() {
+        // add it to our list of global attributes
+        if let Some(attr_value) = readable_attr(attr.value(), attr.name(), &mut warnings)? {
+            json_data.add_global_attr(attr.name(), attr_value);
+        }
+    }
+
+    // ... and add all of the variable attributes ...
+    for var in file.variables() {
+        let var_name = var.name();
+        json_data.add_variable(&var_name);
+        for attr in var.attributes() {
+            // typed values mean _FillValue can be kept as-is too
+            let name = format!("{}:{}", var_name, attr.name());
+            if let Some(attr_value) = readable_attr(attr.value(), &name, &mut warnings)? {
+                json_data.add_variable_attr(&var_name, attr.name(), attr_value);
+            }
+        }
+    }
+    Ok((json_data, warnings))
+}
+
+/// the value of the attribute `name`, or None, with a warning, if it's of a
+/// type the netcdf crate can't read
+fn readable_attr(
+    value: netcdf::Result<netcdf::AttributeValue>,
+    name: &str,
+    warnings: &mut Vec<String>,
+) -> Result<Option<AttrVal>, NwtError> {
+    match value {
+        Ok(value) => Ok(Some(AttrVal::from(value))),
+        Err(netcdf::Error::TypeUnknown(type_id)) => {
+            let warning = format!("skipped attribute {}, of unsupported type {}", name, type_id);
+            log::warn!("{}", warning);
+            warnings.push(warning);
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// looks up a variable, listing the ones the file does have if it's missing
+pub(crate) fn find_variable<'f>(file: &'f netcdf::File, name: &str) -> Result<netcdf::Variable<'f>, NwtError> {
+    file.variable(name).ok_or_else(|| NwtError::MissingVariable {
+        name: name.to_string(),
+        available: file.variables().map(|v| v.name()).collect(),
+    })
+}
+
+/// looks up a dimension, listing the ones the file does have if it's missing
+pub(crate) fn find_dimension<'f>(file: &'f netcdf::File, name: &str) -> Result<netcdf::Dimension<'f>, NwtError> {
+    file.dimension(name).ok_or_else(|| NwtError::MissingDimension {
+        name: name.to_string(),
+        available: file.dimensions().map(|d| d.name()).collect(),
+    })
+}
+
+/// reads the polyid names from a string variable, or from an integer one
+/// as their decimal codes, mapped to names through CF
+/// `flag_values`/`flag_meanings` when the variable has them
+fn read_polyids(var: &netcdf::Variable) -> Result<Vec<String>, NwtError> {
+    let vartype = var.vartype();
+    if vartype.is_string() {
+        return (0..var.len()).map(|idx| Ok(var.get_string(idx)?)).collect();
+    }
+    let codes: Vec<String> = match vartype.as_basic() {
+        Some(basic) if basic.is_u64() => var.get_values::<u64, _>(..)?.iter().map(u64::to_string).collect(),
+        Some(basic) if !(basic.is_char() || basic.is_f32() || basic.is_f64()) => {
+            var.get_values::<i64, _>(..)?.iter().map(i64::to_string).collect()
+        }
+        _ => return Err(NwtError::InvalidInput(format!(
+            "polyid variable {} must hold strings or integers, not {}", var.name(), vartype.name()))),
+    };
+
+    let flag_values = var.attribute_value("flag_values").transpose()?.map(AttrVal::from);
+    let flag_meanings = var.attribute_value("flag_meanings").transpose()?.map(AttrVal::from);
+    let names: HashMap<String, String> = match (flag_values, flag_meanings) {
+        (Some(values), Some(AttrVal::Str(meanings))) => {
+            let values = match values {
+                AttrVal::I64(v) => vec![v.to_string()],
+                AttrVal::I64s(v) => v.iter().map(i64::to_string).collect(),
+                AttrVal::U64(v) => vec![v.to_string()],
+                AttrVal::U64s(v) => v.iter().map(u64::to_string).collect(),
+                _ => Vec::new(),
+            };
+            values.into_iter().zip(meanings.split_whitespace().map(str::to_string)).collect()
+        }
+        _ => HashMap::new(),
+    };
+    Ok(codes.into_iter().map(|code| names.get(&code).cloned().unwrap_or(code)).collect())
+}
+
+/// reads the cell bounds of a coordinate variable from the variable its CF
+/// `bounds` attribute names, or else `<name>_bnds`. They may be stored as
+/// `(n, 2)` (lower, upper) pairs or as `n + 1` contiguous edges. `None` if
+/// there's no such variable
+fn read_cell_bounds(file: &netcdf::File, coord: &netcdf::Variable, len: u64) -> Result<Option<Vec<(f32, f32)>>, NwtError> {
+    let name = match coord.attribute_value("bounds").transpose()?.map(AttrVal::from) {
+        Some(AttrVal::Str(name)) => name,
+        _ => format!("{}_bnds", coord.name()),
+    };
+    let Some(var) = file.variable(&name) else {
+        return Ok(None);
+    };
+    let values = read_as_f64(&var, ..)?;
+    let shape: Vec<usize> = var.dimensions().iter().map(|d| d.len()).collect();
+    let len = len as usize;
+    let bounds = match shape[..] {
+        [n, 2] if n == len => values.chunks_exact(2).map(|b| (b[0] as f32, b[1] as f32)).collect(),
+        [n] if n == len + 1 => values.windows(2).map(|e| (e[0] as f32, e[1] as f32)).collect(),
+        _ => {
+            let shape: Vec<String> = shape.iter().map(usize::to_string).collect();
+            return Err(NwtError::InvalidInput(format!(
+                "{} is shaped ({}), expected ({}, 2) bounds or {} edges", name, shape.join(", "), len, len + 1)));
+        }
+    };
+    Ok(Some(bounds))
+}
+
+/// checks the weights are dimensioned (polyid, lat, lon) or
+/// (polyid, lon, lat), returning true for the latter
+fn weights_lon_first(weights: &netcdf::Variable, polyids: &netcdf::Variable, opts: &ConvertOptions) -> Result<bool, NwtError> {
+    let dims: Vec<String> = weights.dimensions().iter().map(|d| d.name()).collect();
+    let polyid_dim = polyids.dimensions().first().map(|d| d.name());
+    let names: Vec<&str> = dims.iter().map(String::as_str).collect();
+    if dims.len() == 3 && Some(&dims[0]) == polyid_dim.as_ref() {
+        if names[1..] == [opts.lat_dim.as_str(), &opts.lon_dim] {
+            return Ok(false);
+        }
+        if names[1..] == [opts.lon_dim.as_str(), &opts.lat_dim] {
+            return Ok(true);
+        }
+    }
+    Err(NwtError::InvalidInput(format!(
+        "{} is dimensioned ({}), expected ({}, {}, {}) in either lat/lon order",
+        weights.name(), names.join(", "), polyid_dim.unwrap_or_default(), opts.lat_dim, opts.lon_dim)))
+}
+
+/// reads part of a numeric variable at its stored precision, widening f32
+/// and packed integers so every kind of source is handled the same way
+pub(crate) fn read_as_f64<E>(var: &netcdf::Variable, extents: E) -> Result<Vec<f64>, NwtError>
+where
+    E: TryInto<netcdf::Extents>,
+    E::Error: Into<netcdf::Error>,
+{
+    if var.vartype().is_f32() {
+        Ok(var.get_values::<f32, _>(extents)?.into_iter().map(f64::from).collect())
+    } else {
+        Ok(var.get_values::<f64, _>(extents)?)
+    }
+}
+
+/// the fill of a variable without a `_FillValue` attribute: the library's
+/// default for its type, or none if it was created without fill
+fn default_fill(var: &netcdf::Variable) -> Result<Option<f32>, NwtError> {
+    Ok(match var.vartype().as_basic() {
+        Some(basic) if basic.is_f64() => var.fill_value::<f64>()?.map(|v| v as f32),
+        Some(basic) if basic.is_i8() => var.fill_value::<i8>()?.map(f32::from),
+        Some(basic) if basic.is_u8() => var.fill_value::<u8>()?.map(f32::from),
+        Some(basic) if basic.is_i16() => var.fill_value::<i16>()?.map(f32::from),
+        Some(basic) if basic.is_u16() => var.fill_value::<u16>()?.map(f32::from),
+        Some(basic) if basic.is_i32() => var.fill_value::<i32>()?.map(|v| v as f32),
+        _ => var.fill_value::<f32>()?,
+    })
+}
+
+/// the CF `scale_factor` and `add_offset` a variable's values are packed
+/// with, defaulting whichever is missing, or `None` if it has neither
+fn packing(var: &netcdf::Variable) -> Result<Option<(f64, f64)>, NwtError> {
+    let read = |name: &str| match var.attribute_value(name) {
+        Some(value) => single_number(name, &AttrVal::from(value?)).map(Some),
+        None => Ok(None),
+    };
+    Ok(match (read("scale_factor")?, read("add_offset")?) {
+        (None, None) => None,
+        (scale_factor, add_offset) => Some((scale_factor.unwrap_or(1.0), add_offset.unwrap_or(0.0))),
+    })
+}
+
+/// relative error above which narrowing a weight to f32 is worth a warning
+const MAX_RELATIVE_LOSS: f64 = 1e-6;
+
+/// true if `value` changes by more than `MAX_RELATIVE_LOSS` when stored as f32
+fn loses_precision(value: f64) -> bool {
+    let narrowed = value as f32 as f64;
+    value != 0.0 && ((narrowed - value) / value).abs() > MAX_RELATIVE_LOSS
+}
+
+/// converts a `_FillValue` attribute of any numeric type to `f32`
+pub(crate) fn fill_as_f32(value: &AttrVal) -> Result<f32, NwtError> {
+    single_number("_FillValue", value).map(|v| v as f32)
+}
+
+/// reads the attribute `name`, which should hold one number of any numeric
+/// type
+fn single_number(name: &str, value: &AttrVal) -> Result<f64, NwtError> {
+    match value {
+        AttrVal::F64s(v) if v.len() == 1 => Ok(v[0]),
+        AttrVal::I64s(v) if v.len() == 1 => Ok(v[0] as f64),
+        AttrVal::U64s(v) if v.len() == 1 => Ok(v[0] as f64),
+        other => other.as_f64().ok_or_else(|| NwtError::InvalidInput(format!(
+            "{} {} is not a single number", name, other))),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    use crate::tests::{sample_file, temp_path};
+    use crate::{CachePolicy, GridPoint, WeightDtype};
+
+    /// writes a NetCDF weight file holding the same weights as `sample_file`,
+    /// with `fill` as the declared fill value in every cell a polyid doesn't
+    /// use. Without a fill, those cells are written as dense zeros
+    pub(crate) fn write_sample_netcdf(path: &Path, fill: Option<f32>) -> netcdf::FileMut {
+        write_named_netcdf(path, fill, &ConvertOptions::default(), false)
+    }
+
+    /// `write_sample_netcdf` with the variable and dimension names in `names`,
+    /// optionally storing the weights as (polyid, lon, lat)
+    pub(crate) fn write_named_netcdf(path: &Path, fill: Option<f32>, names: &ConvertOptions, lon_first: bool) -> netcdf::FileMut {
+        let mut file = netcdf::create(path).unwrap();
+        file.add_dimension(&names.polyid_var, 3).unwrap();
+        file.add_dimension(&names.lat_dim, 2).unwrap();
+        file.add_dimension(&names.lon_dim, 3).unwrap();
+        file.add_attribute("title", "sample weights").unwrap();
+
+        let mut polyids = file.add_string_variable(&names.polyid_var, &[&names.polyid_var]).unwrap();
+        for (idx, name) in ["A", "B", "C"].iter().enumerate() {
+            polyids.put_string(name, idx).unwrap();
+        }
+        file.add_variable::<f32>(&names.lat_var, &[&names.lat_dim]).unwrap().put_values(&[-45.0f32, 45.0], ..).unwrap();
+        file.add_variable::<f32>(&names.lon_var, &[&names.lon_dim]).unwrap().put_values(&[0.0f32, 90.0, 180.0], ..).unwrap();
+
+        let dims = if lon_first {
+            [names.polyid_var.as_str(), &names.lon_dim, &names.lat_dim]
+        } else {
+            [names.polyid_var.as_str(), &names.lat_dim, &names.lon_dim]
+        };
+        let mut weights = file.add_variable::<f32>(&names.weights_var, &dims).unwrap();
+        if let Some(fill) = fill {
+            weights.set_fill_value(fill).unwrap();
+        }
+        weights.put_attribute("units", "1").unwrap();
+        let mut values = vec![fill.unwrap_or(0.0); 3 * 6];
+        for (idx, entry) in sample_file().get_gridpoints().iter().enumerate() {
+            for p in entry.data.iter() {
+                let cell = if lon_first { p.1 as usize * 2 + p.0 as usize } else { p.0 as usize * 3 + p.1 as usize };
+                values[idx * 6 + cell] = p.4;
+            }
+        }
+        weights.put_values(&values, ..).unwrap();
+        file
+    }
+
+    #[test]
+    fn string_list_attributes_survive_conversion() {
+        let path = temp_path("string_list.nc");
+        {
+            let mut file = write_sample_netcdf(&path, Some(-1.0));
+            file.add_attribute("source_files", vec!["a.nc", "b.nc", "c.nc"]).unwrap();
+        }
+        let converted = NextWeightFile::from_weight_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let expected = AttrVal::from(vec!["a.nc".to_string(), "b.nc".to_string(), "c.nc".to_string()]);
+        let attr = converted.json_data.get_global_attr_value("source_files");
+        assert_eq!(attr, Some(&expected));
+
+        let mut buff = Vec::new();
+        converted.serialize_to_writer(&mut buff).unwrap();
+        let reloaded = NextWeightFile::from_reader(&buff[..]).unwrap();
+        assert_eq!(reloaded.json_data.get_global_attr_value("source_files"), Some(&expected));
+        assert_eq!(reloaded.get_gridpoints(), sample_file().get_gridpoints());
+    }
+
+    #[test]
+    fn numeric_attributes_keep_their_types() {
+        let path = temp_path("numeric_attrs.nc");
+        {
+            let mut file = write_sample_netcdf(&path, Some(-1.0));
+            file.add_attribute("nco_openmp_thread_number", 1i32).unwrap();
+            file.add_attribute("tolerance", 1e-6f64).unwrap();
+            file.variable_mut("regridweights").unwrap().put_attribute("valid_range", vec![0i16, 1]).unwrap();
+        }
+        let result = NextWeightFile::from_weight_file_reporting(&path, &ConvertOptions::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(result.warnings.is_empty());
+        let json = &result.file.json_data;
+        assert_eq!(json.get_global_attr_value("nco_openmp_thread_number"), Some(&AttrVal::I64(1)));
+        assert_eq!(json.get_global_attr_value("tolerance"), Some(&AttrVal::F64(1e-6)));
+        let valid_range = json.per_variable_attrs["regridweights"].iter().find(|(key, _)| key == "valid_range");
+        assert_eq!(valid_range.map(|(_, value)| value), Some(&AttrVal::I64s(vec![0, 1])));
+    }
+
+    #[test]
+    fn unreadable_attribute_types_are_skipped() {
+        let mut warnings = Vec::new();
+        let value = readable_attr(Ok(netcdf::AttributeValue::Int(3)), "count", &mut warnings).unwrap();
+        assert_eq!(value, Some(AttrVal::I64(3)));
+        let value = readable_attr(Err(netcdf::Error::TypeUnknown(42)), "regridweights:flags", &mut warnings).unwrap();
+        assert_eq!(value, None);
+        assert_eq!(warnings, vec!["skipped attribute regridweights:flags, of unsupported type 42".to_string()]);
+        // anything else still fails the conversion
+        assert!(readable_attr(Err(netcdf::Error::Netcdf(-1)), "count", &mut warnings).is_err());
+    }
+
+    #[test]
+    fn non_weight_netcdf_is_an_error() {
+        // a perfectly valid NetCDF file that just isn't a weight file
+        let path = temp_path("not_weights.nc");
+        {
+            let mut file = netcdf::create(&path).unwrap();
+            file.add_dimension("time", 3).unwrap();
+            let mut var = file.add_variable::<f32>("temperature", &["time"]).unwrap();
+            var.put_values(&[1.0f32, 2.0, 3.0], ..).unwrap();
+        }
+        let err = NextWeightFile::from_weight_file(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(err, NwtError::MissingVariable { ref name, .. } if name == "polyid"));
+        // the message tells the user what they could have asked for instead
+        assert!(err.to_string().contains("temperature"));
+    }
+
+    #[test]
+    fn missing_regridweights_is_an_error() {
+        // has polyids, but nothing else a weight file needs
+        let path = temp_path("only_polyids.nc");
+        {
+            let mut file = netcdf::create(&path).unwrap();
+            file.add_dimension("polyid", 2).unwrap();
+            let mut var = file.add_string_variable("polyid", &["polyid"]).unwrap();
+            var.put_string("A", 0).unwrap();
+            var.put_string("B", 1).unwrap();
+        }
+        let err = NextWeightFile::from_weight_file(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(err, NwtError::MissingVariable { ref name, .. } if name == "regridweights"));
+    }
+
+    #[test]
+    fn cell_bounds_are_read_in_either_layout() {
+        let path = temp_path("cell_bounds.nc");
+        {
+            let mut file = write_sample_netcdf(&path, Some(-1.0));
+            file.add_dimension("nv", 2).unwrap();
+            file.add_dimension("lon_edges", 4).unwrap();
+            file.add_variable::<f64>("lat_bnds", &["lat", "nv"]).unwrap()
+                .put_values(&[-90.0f64, 0.0, 0.0, 90.0], ..).unwrap();
+            file.add_variable::<f32>("lon_edges", &["lon_edges"]).unwrap()
+                .put_values(&[-45.0f32, 45.0, 135.0, 225.0], ..).unwrap();
+            file.variable_mut("lon").unwrap().put_attribute("bounds", "lon_edges").unwrap();
+        }
+        let converted = NextWeightFile::from_weight_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(converted.lat_bounds(), Some(&[(-90.0, 0.0), (0.0, 90.0)][..]));
+        assert_eq!(converted.lon_bounds(), Some(&[(-45.0, 45.0), (45.0, 135.0), (135.0, 225.0)][..]));
+        let reread = NextWeightFile::from_bytes(&converted.to_bytes().unwrap()).unwrap();
+        assert_eq!(reread.lat_bounds(), converted.lat_bounds());
+        assert_eq!(reread.lon_bounds(), converted.lon_bounds());
+
+        let plain_path = temp_path("no_cell_bounds.nc");
+        write_sample_netcdf(&plain_path, Some(-1.0));
+        let plain = NextWeightFile::from_weight_file(&plain_path).unwrap();
+        std::fs::remove_file(&plain_path).unwrap();
+        assert_eq!((plain.lat_bounds(), plain.lon_bounds()), (None, None));
+    }
+
+    #[test]
+    fn repeated_conversions_are_byte_identical() {
+        let path = temp_path("stable.nc");
+        {
+            let mut file = write_sample_netcdf(&path, Some(-1.0));
+            for var in ["lat", "lon", "polyid"] {
+                file.variable_mut(var).unwrap().put_attribute("long_name", var).unwrap();
+            }
+        }
+        let opts = ConvertOptions { provenance: false, ..Default::default() };
+        let first = NextWeightFile::from_weight_file_with(&path, &opts).unwrap().to_bytes().unwrap();
+        let second = NextWeightFile::from_weight_file_with(&path, &opts).unwrap().to_bytes().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn conversion_records_provenance() {
+        let path = temp_path("provenance.nc");
+        {
+            let mut file = write_sample_netcdf(&path, Some(-1.0));
+            file.add_attribute("history", "created by hand").unwrap();
+        }
+        let converted = NextWeightFile::from_weight_file(&path).unwrap();
+        let opts = ConvertOptions { provenance: false, ..Default::default() };
+        let plain = NextWeightFile::from_weight_file_with(&path, &opts).unwrap();
+        let size = std::fs::metadata(&path).unwrap().len();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut buff = Vec::new();
+        converted.serialize_to_writer(&mut buff).unwrap();
+        let reloaded = NextWeightFile::from_reader(&buff[..]).unwrap();
+        let attrs = &reloaded.json_data;
+        assert_eq!(attrs.get_global_attr_value("nwt_source"), Some(&AttrVal::from(path.display().to_string())));
+        assert_eq!(attrs.get_global_attr_value("nwt_source_size"), Some(&AttrVal::U64(size)));
+        let version = attrs.get_global_attr("nwt_converter_version").unwrap();
+        assert!(version.ends_with(env!("CARGO_PKG_VERSION")));
+        let names = attrs.get_global_attr("nwt_source_names").unwrap();
+        assert_eq!(names, "polyid=polyid weights=regridweights lat=lat lon=lon lat_dim=lat lon_dim=lon");
+        let created = attrs.get_global_attr("nwt_created").unwrap();
+        let history = attrs.get_global_attr("history").unwrap();
+        assert_eq!(history, format!("created by hand\n{}: converted to NWT by {}", created, version));
+
+        assert_eq!(plain.json_data.get_global_attr_value("nwt_created"), None);
+        assert_eq!(plain.json_data.get_global_attr("history").unwrap(), "created by hand");
+    }
+
+    #[test]
+    fn nan_fill_cells_are_skipped() {
+        let path = temp_path("nan_fill.nc");
+        write_sample_netcdf(&path, Some(f32::NAN));
+        let converted = NextWeightFile::from_weight_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(converted.iter_points().all(|(_, p)| p.4.is_finite()));
+        assert_eq!(converted.get_gridpoints(), sample_file().get_gridpoints());
+    }
+
+    #[test]
+    fn packed_weights_match_their_unpacked_twin() {
+        let unpacked_path = temp_path("unpacked.nc");
+        write_sample_netcdf(&unpacked_path, Some(-1.0));
+        let path = temp_path("packed.nc");
+        {
+            let mut file = netcdf::create(&path).unwrap();
+            file.add_dimension("polyid", 3).unwrap();
+            file.add_dimension("lat", 2).unwrap();
+            file.add_dimension("lon", 3).unwrap();
+            file.add_attribute("title", "sample weights").unwrap();
+            let mut polyids = file.add_string_variable("polyid", &["polyid"]).unwrap();
+            for (idx, name) in ["A", "B", "C"].iter().enumerate() {
+                polyids.put_string(name, idx).unwrap();
+            }
+            file.add_variable::<f32>("lat", &["lat"]).unwrap().put_values(&[-45.0f32, 45.0], ..).unwrap();
+            file.add_variable::<f32>("lon", &["lon"]).unwrap().put_values(&[0.0f32, 90.0, 180.0], ..).unwrap();
+
+            // weight = packed / 16384 + 0.5, so the fill unpacks to about
+            // -1.5 and is only recognisable packed
+            let mut weights = file.add_variable::<i16>("regridweights", &["polyid", "lat", "lon"]).unwrap();
+            weights.set_fill_value(i16::MIN + 1).unwrap();
+            weights.put_attribute("units", "1").unwrap();
+            weights.put_attribute("scale_factor", 1.0f64 / 16384.0).unwrap();
+            weights.put_attribute("add_offset", 0.5f64).unwrap();
+            let mut values = vec![i16::MIN + 1; 3 * 6];
+            for (idx, entry) in sample_file().get_gridpoints().iter().enumerate() {
+                for p in entry.data.iter() {
+                    values[idx * 6 + p.0 as usize * 3 + p.1 as usize] = ((p.4 - 0.5) * 16384.0) as i16;
+                }
+            }
+            weights.put_values(&values, ..).unwrap();
+        }
+        let opts = ConvertOptions { provenance: false, ..Default::default() };
+        let packed = NextWeightFile::from_weight_file_with(&path, &opts).unwrap();
+        let unpacked = NextWeightFile::from_weight_file_with(&unpacked_path, &opts).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&unpacked_path).unwrap();
+
+        assert_eq!(packed.get_gridpoints(), unpacked.get_gridpoints());
+        assert_eq!(packed.weight_dtype(), WeightDtype::F32);
+        // the stored weights are unpacked, so nothing says to unpack them again
+        assert_eq!(packed.json_data.get_var_attr_value("regridweights", "scale_factor"), None);
+        assert_eq!(packed.json_data.get_var_attr_value("regridweights", "add_offset"), None);
+        assert_eq!(packed.json_data.get_var_attr("regridweights", "units"), Some("1"));
+    }
+
+    #[test]
+    fn fill_tolerance_and_zero_skipping() {
+        // NetCDF's default float fill, with a neighbour either side of it in
+        // two unused cells
+        let fill = 9.96921e36f32;
+        let path = temp_path("near_fill.nc");
+        {
+            let mut file = write_sample_netcdf(&path, Some(fill));
+            let mut weights = file.variable_mut("regridweights").unwrap();
+            weights.put_value(f32::from_bits(fill.to_bits() + 1), [1, 0, 0]).unwrap();
+            weights.put_value(f32::from_bits(fill.to_bits() - 1), [2, 0, 1]).unwrap();
+        }
+        let exact = NextWeightFile::from_weight_file(&path).unwrap();
+        let tolerant = ConvertOptions { fill_tolerance: 1e31, ..Default::default() };
+        let near = NextWeightFile::from_weight_file_with(&path, &tolerant).unwrap();
+        let no_zeros = ConvertOptions { skip_zero_weights: true, ..tolerant };
+        let sparse = NextWeightFile::from_weight_file_with(&path, &no_zeros).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // by default only the fill itself is skipped
+        assert_eq!(exact.get_entry("B").unwrap().data.len(), 2);
+        assert_eq!(exact.get_entry("C").unwrap().data.len(), 4);
+        assert_eq!(near.get_gridpoints()[..2], sample_file().get_gridpoints()[..2]);
+        assert_eq!(near.get_entry("C").unwrap().data.len(), 3);
+        // C's zero weight goes too
+        assert_eq!(sparse.get_gridpoints()[..2], sample_file().get_gridpoints()[..2]);
+        assert_eq!(sparse.get_entry("C").unwrap().data, vec![(1, 0, 45.0, 0.0, 0.5), (1, 2, 45.0, 180.0, 0.5)]);
+    }
+
+    #[test]
+    fn missing_fill_keeps_every_value() {
+        let path = temp_path("no_fill.nc");
+        write_sample_netcdf(&path, None);
+        let dense = NextWeightFile::from_weight_file(&path).unwrap();
+        // an explicit fill override treats the zeros as empty cells
+        let opts = ConvertOptions { fill_value: Some(0.0), ..Default::default() };
+        let sparse = NextWeightFile::from_weight_file_with(&path, &opts).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(dense.iter().all(|(_, points)| points.len() == 6));
+        assert_eq!(dense.apply(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap(), vec![1.75, 5.0, 5.0]);
+        assert_eq!(sparse.get_entry("C").unwrap().data.len(), 2);
+    }
+
+    #[test]
+    fn fill_values_of_other_types_are_converted() {
+        assert_eq!(fill_as_f32(&AttrVal::F64(-9999.0)).unwrap(), -9999.0);
+        assert_eq!(fill_as_f32(&AttrVal::F64s(vec![1e20])).unwrap(), 1e20);
+        assert_eq!(fill_as_f32(&AttrVal::I64(-1)).unwrap(), -1.0);
+        assert!(fill_as_f32(&AttrVal::F64(f64::NAN)).unwrap().is_nan());
+        assert!(matches!(fill_as_f32(&AttrVal::from("missing")), Err(NwtError::InvalidInput(_))));
+        assert!(matches!(single_number("scale_factor", &AttrVal::F64s(vec![1.0, 2.0])), Err(NwtError::InvalidInput(ref m))
+            if m == "scale_factor [1.0, 2.0] is not a single number"));
+    }
+
+    #[test]
+    fn renamed_variables_convert_with_options_or_aliases() {
+        let names = ConvertOptions {
+            provenance: false,
+            polyid_var: "region_id".to_string(),
+            weights_var: "weights".to_string(),
+            lat_var: "latitude".to_string(),
+            lon_var: "longitude".to_string(),
+            lat_dim: "latitude".to_string(),
+            lon_dim: "longitude".to_string(),
+            ..Default::default()
+        };
+        let path = temp_path("renamed.nc");
+        write_named_netcdf(&path, Some(-1.0), &names, false);
+
+        let err = NextWeightFile::from_weight_file(&path).unwrap_err();
+        assert!(matches!(err, NwtError::MissingVariable { ref name, ref available }
+            if name == "polyid" && available.contains(&"region_id".to_string())));
+
+        let converted = NextWeightFile::from_weight_file_with(&path, &names).unwrap();
+        assert_eq!(converted.get_gridpoints(), sample_file().get_gridpoints());
+
+        // open() finds the same names on its own
+        let opened = NextWeightFile::open(&path).unwrap();
+        let mut nwt_path = path.clone().into_os_string();
+        nwt_path.push(".nwt");
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(nwt_path).unwrap();
+        assert_eq!(opened.get_gridpoints(), sample_file().get_gridpoints());
+    }
+
+    #[test]
+    fn lon_first_weights_land_on_the_same_cells() {
+        let opts = ConvertOptions { provenance: false, ..Default::default() };
+        let path = temp_path("lon_first.nc");
+        write_named_netcdf(&path, Some(-1.0), &opts, true);
+        let converted = NextWeightFile::from_weight_file_with(&path, &opts).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // same (lat_idx, lon_idx, lat, lon, weight) points as the usual layout
+        assert_eq!(converted.get_gridpoints(), sample_file().get_gridpoints());
+    }
+
+    #[test]
+    fn chunked_reads_match_whole_slab() {
+        for lon_first in [false, true] {
+            let path = temp_path("chunked.nc");
+            write_named_netcdf(&path, Some(-1.0), &ConvertOptions::default(), lon_first);
+            let with_rows = |chunk_rows| {
+                let opts = ConvertOptions { provenance: false, chunk_rows, ..Default::default() };
+                NextWeightFile::from_weight_file_with(&path, &opts).unwrap()
+            };
+            // one row at a time vs. the whole 2-row slab in one read
+            let (chunked, whole) = (with_rows(1), with_rows(usize::MAX));
+            let zero = with_rows(0);
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(chunked, whole);
+            assert_eq!(zero, whole);
+            assert_eq!(whole.get_gridpoints(), sample_file().get_gridpoints());
+        }
+    }
+
+    #[test]
+    fn unexpected_weight_dimensions_are_an_error() {
+        let path = temp_path("bad_dims.nc");
+        {
+            let mut file = write_sample_netcdf(&path, Some(-1.0));
+            // a time dimension where lon should be
+            file.add_dimension("time", 1).unwrap();
+            let mut var = file.add_variable::<f32>("weights_t", &["polyid", "lat", "time"]).unwrap();
+            var.put_values(&[0.0f32; 6], ..).unwrap();
+        }
+        let opts = ConvertOptions { weights_var: "weights_t".to_string(), ..Default::default() };
+        let err = NextWeightFile::from_weight_file_with(&path, &opts).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(err, NwtError::InvalidInput(ref msg) if msg.contains("polyid, lat, time")));
+    }
+
+    #[test]
+    fn cf_units_identify_latitude_longitude() {
+        let names = ConvertOptions {
+            lat_var: "latitude".to_string(),
+            lon_var: "longitude".to_string(),
+            lat_dim: "latitude".to_string(),
+            lon_dim: "longitude".to_string(),
+            ..Default::default()
+        };
+        let path = temp_path("cf_units.nc");
+        {
+            let mut file = write_named_netcdf(&path, Some(-1.0), &names, false);
+            file.variable_mut("latitude").unwrap().put_attribute("units", "degrees_north").unwrap();
+            file.variable_mut("longitude").unwrap().put_attribute("units", "degrees_east").unwrap();
+        }
+        // default lat/lon names, found through their units instead
+        let converted = NextWeightFile::from_weight_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(converted.get_gridpoints(), sample_file().get_gridpoints());
+        let used = converted.json_data.get_global_attr("nwt_source_names").unwrap();
+        assert!(used.contains("lat=latitude lon=longitude lat_dim=latitude lon_dim=longitude"));
+    }
+
+    #[test]
+    fn cf_standard_name_identifies_y_x() {
+        let names = ConvertOptions {
+            lat_var: "y".to_string(),
+            lon_var: "x".to_string(),
+            lat_dim: "y".to_string(),
+            lon_dim: "x".to_string(),
+            ..Default::default()
+        };
+        let path = temp_path("cf_standard_name.nc");
+        {
+            let mut file = write_named_netcdf(&path, Some(-1.0), &names, false);
+            file.variable_mut("y").unwrap().put_attribute("standard_name", "latitude").unwrap();
+            file.variable_mut("x").unwrap().put_attribute("standard_name", "longitude").unwrap();
+        }
+        let converted = NextWeightFile::from_weight_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(converted.get_gridpoints(), sample_file().get_gridpoints());
+        let used = converted.json_data.get_global_attr("nwt_source_names").unwrap();
+        assert!(used.contains("lat=y lon=x"));
+    }
+
+    #[test]
+    fn integer_polyids_become_strings() {
+        // string polyids under another name, so integer codes can sit on the
+        // same dimension
+        let names = ConvertOptions { polyid_var: "names".to_string(), ..Default::default() };
+        let path = temp_path("int_polyids.nc");
+        {
+            let mut file = write_named_netcdf(&path, Some(-1.0), &names, false);
+            file.add_variable::<i32>("codes", &["names"]).unwrap().put_values(&[101, 102, 103], ..).unwrap();
+            let mut flagged = file.add_variable::<i32>("flagged", &["names"]).unwrap();
+            flagged.put_values(&[1, 2, 3], ..).unwrap();
+            flagged.put_attribute("flag_values", vec![1i32, 2]).unwrap();
+            flagged.put_attribute("flag_meanings", "north south").unwrap();
+        }
+        let opts = |var: &str| ConvertOptions { polyid_var: var.to_string(), ..Default::default() };
+        let codes = NextWeightFile::from_weight_file_with(&path, &opts("codes")).unwrap();
+        let flagged = NextWeightFile::from_weight_file_with(&path, &opts("flagged")).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(codes.get_polyids(), &vec!["101".to_string(), "102".to_string(), "103".to_string()]);
+        assert_eq!(codes.get_gridpoints(), sample_file().get_gridpoints());
+        // codes without a meaning keep their number
+        assert_eq!(flagged.get_polyids(), &vec!["north".to_string(), "south".to_string(), "3".to_string()]);
+    }
+
+    #[test]
+    fn narrowing_to_f32_flags_lost_precision() {
+        assert!(!loses_precision(0.0));
+        assert!(!loses_precision(0.5));
+        assert!(!loses_precision(1.0 / 3.0));
+        // beyond f32's range or below its precision
+        assert!(loses_precision(1e300));
+        assert!(loses_precision(1e-300));
+    }
+
+    #[test]
+    fn double_precision_sources_match_f32_reference() {
+        let path = temp_path("doubles.nc");
+        let weights = [1.0 / 3.0, 2.0 / 3.0, 0.1, 0.2, 0.7, 1.0];
+        {
+            let mut file = netcdf::create(&path).unwrap();
+            file.add_dimension("polyid", 1).unwrap();
+            file.add_dimension("lat", 2).unwrap();
+            file.add_dimension("lon", 3).unwrap();
+            file.add_string_variable("polyid", &["polyid"]).unwrap().put_string("A", 0).unwrap();
+            file.add_variable::<f64>("lat", &["lat"]).unwrap().put_values(&[-45.1, 45.1], ..).unwrap();
+            file.add_variable::<f64>("lon", &["lon"]).unwrap().put_values(&[0.1, 90.1, 180.1], ..).unwrap();
+            let mut var = file.add_variable::<f64>("regridweights", &["polyid", "lat", "lon"]).unwrap();
+            var.set_fill_value(-1.0f64).unwrap();
+            var.put_values(&weights, ..).unwrap();
+        }
+        let opts = ConvertOptions { provenance: false, ..Default::default() };
+        let converted = NextWeightFile::from_weight_file_with(&path, &opts).unwrap();
+        let forced = NextWeightFile::from_weight_file_with(&path, &ConvertOptions { force_f32: true, ..opts }).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // the doubles are kept as they are, unless asked not to
+        assert_eq!(converted.weight_dtype(), WeightDtype::F64);
+        let kept: Vec<f64> = converted.get_entry_f64("A").unwrap().iter().map(|p| p.4).collect();
+        assert_eq!(kept, weights);
+        assert_eq!(forced.weight_dtype(), WeightDtype::F32);
+        assert_eq!(forced.get_gridpoints(), converted.get_gridpoints());
+
+        let lats = [-45.1f64 as f32, 45.1f64 as f32];
+        let lons = [0.1f64 as f32, 90.1f64 as f32, 180.1f64 as f32];
+        let expected: Vec<GridPoint> = (0..6)
+            .map(|cell| (cell / 3, cell % 3, lats[cell as usize / 3], lons[cell as usize % 3], weights[cell as usize] as f32))
+            .collect();
+        assert_eq!(converted.get_entry("A").unwrap().data, expected);
+        assert_eq!(converted.get_latitudes(), Some(&lats[..]));
+    }
+
+    #[test]
+    fn progress_reports_every_polyid() {
+        let path = temp_path("progress.nc");
+        write_sample_netcdf(&path, Some(-1.0));
+        let opts = ConvertOptions { provenance: false, ..Default::default() };
+        let mut seen = Vec::new();
+        let converted = NextWeightFile::from_weight_file_with_progress(&path, &opts, |p| seen.push(p)).unwrap();
+        let plain = NextWeightFile::from_weight_file_with(&path, &opts).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(converted, plain);
+        let points: Vec<(usize, u64)> = seen.iter().map(|p| (p.polyid, p.points)).collect();
+        assert_eq!(points, vec![(0, 2), (1, 3), (2, 6)]);
+        assert!(seen.iter().all(|p| p.total_polyids == 3));
+    }
+
+    #[test]
+    fn open_caches_as_configured() {
+        let src = temp_path("cache_src.nc");
+        write_sample_netcdf(&src, Some(-1.0));
+        let alongside = PathBuf::from(format!("{}.nwt", src.display()));
+        let converted = NextWeightFile::open(&src).unwrap();
+        assert_eq!(&NextWeightFile::from_nwt(&alongside).unwrap(), &converted);
+        std::fs::remove_file(&alongside).unwrap();
+
+        let never = OpenOptions { cache: CachePolicy::Never, ..Default::default() };
+        NextWeightFile::open_with(&src, &never).unwrap();
+        assert!(!alongside.exists());
+
+        // an unwritable cache still hands back the conversion
+        let missing_dir = temp_path("no_such_cache_dir");
+        let broken = OpenOptions { cache: CachePolicy::Directory(missing_dir), ..Default::default() };
+        assert_eq!(NextWeightFile::open_with(&src, &broken).unwrap().get_gridpoints(), converted.get_gridpoints());
+        std::fs::remove_file(&src).unwrap();
+    }
+}
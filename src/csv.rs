@@ -0,0 +1,181 @@
+//! CSV export and import of the weights, one row per gridpoint.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use crate::{GridPoint, NextWeightFile, NextWeightFileBuilder, NwtError};
+
+const HEADER: [&str; 6] = ["polyid", "lat_idx", "lon_idx", "lat", "lon", "weight"];
+
+impl NextWeightFile {
+    /// Writes every gridpoint as a `polyid,lat_idx,lon_idx,lat,lon,weight`
+    /// row, after a header row. Polyids are quoted when they need to be
+    pub fn to_csv<W: Write>(&self, w: W) -> Result<(), NwtError> {
+        self.write_csv(w, |_| true)
+    }
+
+    /// Same as `to_csv`, but only writes the rows of the given polyids
+    pub fn to_csv_filtered<W: Write>(&self, w: W, polyids: &[&str]) -> Result<(), NwtError> {
+        self.write_csv(w, |name| polyids.contains(&name))
+    }
+
+    fn write_csv<W: Write>(&self, mut w: W, keep: impl Fn(&str) -> bool) -> Result<(), NwtError> {
+        writeln!(w, "{}", HEADER.join(","))?;
+        for (name, points) in self.iter() {
+            if !keep(name) {
+                continue;
+            }
+            let name = quote(name);
+            for p in points {
+                writeln!(w, "{},{},{},{},{},{}", name, p.0, p.1, p.2, p.3, p.4)?;
+            }
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Reads weights written by `to_csv` (or edited by hand) for a
+    /// `lat_len x lon_len` grid. A polyid's rows don't have to be next to
+    /// each other; polyids are kept in the order they first appear
+    pub fn from_csv<R: Read>(mut r: R, lat_len: u64, lon_len: u64) -> Result<Self, NwtError> {
+        let mut text = String::new();
+        r.read_to_string(&mut text)?;
+        let mut records = parse_records(&text)?.into_iter();
+
+        match records.next() {
+            Some((_, header)) if header == HEADER => {}
+            _ => return Err(NwtError::InvalidInput(format!("CSV must start with the header {}", HEADER.join(",")))),
+        }
+
+        let mut order: Vec<String> = Vec::new();
+        let mut points: HashMap<String, Vec<GridPoint>> = HashMap::new();
+        for (line, fields) in records {
+            let [name, lat_idx, lon_idx, lat, lon, weight]: [String; 6] = fields.try_into().map_err(|fields: Vec<String>|
+                NwtError::InvalidInput(format!("line {}: expected 6 fields, found {}", line, fields.len())))?;
+            let point = (
+                parse_field(&lat_idx, line, "lat_idx")?,
+                parse_field(&lon_idx, line, "lon_idx")?,
+                parse_field(&lat, line, "lat")?,
+                parse_field(&lon, line, "lon")?,
+                parse_field(&weight, line, "weight")?,
+            );
+            if point.0 as u64 >= lat_len || point.1 as u64 >= lon_len {
+                return Err(NwtError::InvalidInput(format!(
+                    "line {}: cell ({}, {}) is outside the {}x{} grid", line, point.0, point.1, lat_len, lon_len)));
+            }
+            if !points.contains_key(&name) {
+                order.push(name.clone());
+            }
+            points.entry(name).or_default().push(point);
+        }
+
+        let mut builder = NextWeightFileBuilder::new().dimensions(lat_len, lon_len);
+        for name in order {
+            let data = points.remove(&name).unwrap_or_default();
+            builder = builder.add_polyid(name, data);
+        }
+        Ok(builder.build())
+    }
+}
+
+/// quotes a field if it contains a comma, quote or line break
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn parse_field<T: std::str::FromStr>(field: &str, line: usize, column: &str) -> Result<T, NwtError> {
+    field.trim().parse().map_err(|_| NwtError::InvalidInput(format!("line {}: invalid {} {:?}", line, column, field)))
+}
+
+/// splits CSV text into records of unquoted fields, each with the line it
+/// starts on. Blank lines are skipped
+fn parse_records(text: &str) -> Result<Vec<(usize, Vec<String>)>, NwtError> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let (mut line, mut start_line) = (1, 1);
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if in_quotes => in_quotes = false,
+            '"' if field.is_empty() => in_quotes = true,
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+            '\r' if !in_quotes && chars.peek() == Some(&'\n') => {}
+            '\n' if !in_quotes => {
+                fields.push(std::mem::take(&mut field));
+                let record = std::mem::take(&mut fields);
+                if record.len() > 1 || !record[0].is_empty() {
+                    records.push((start_line, record));
+                }
+                line += 1;
+                start_line = line;
+            }
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+    if in_quotes {
+        return Err(NwtError::InvalidInput(format!("line {}: unterminated quoted field", start_line)));
+    }
+    if !fields.is_empty() || !field.is_empty() {
+        fields.push(field);
+        records.push((start_line, fields));
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::tests::sample_file;
+
+    #[test]
+    fn csv_round_trip() {
+        let mut nwt = sample_file();
+        nwt.add_polyid_entry("Smith, \"J\"".to_string(), crate::PolyidEntry { data: vec![(1, 2, 45.0, 180.0, 0.125)] });
+
+        let mut out = Cursor::new(Vec::new());
+        nwt.to_csv(&mut out).unwrap();
+        let text = String::from_utf8(out.get_ref().clone()).unwrap();
+        assert!(text.starts_with("polyid,lat_idx,lon_idx,lat,lon,weight\nA,0,0,-45,0,0.25\n"));
+        assert!(text.ends_with("\"Smith, \"\"J\"\"\",1,2,45,180,0.125\n"));
+
+        out.set_position(0);
+        let back = NextWeightFile::from_csv(out, 2, 3).unwrap();
+        assert_eq!(back.get_polyids(), nwt.get_polyids());
+        assert_eq!(back.get_gridpoints(), nwt.get_gridpoints());
+    }
+
+    #[test]
+    fn csv_filter_and_interleaved_rows() {
+        let mut out = Vec::new();
+        sample_file().to_csv_filtered(&mut out, &["B"]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "polyid,lat_idx,lon_idx,lat,lon,weight\nB,1,1,45,90,1\n");
+
+        let edited = "polyid,lat_idx,lon_idx,lat,lon,weight\r\nX,0,0,-45,0,0.5\r\nY,1,1,45,90,1\r\n\r\nX,0,1,-45,90,0.5\r\n";
+        let nwt = NextWeightFile::from_csv(edited.as_bytes(), 2, 3).unwrap();
+        assert_eq!(nwt.get_polyids(), &vec!["X".to_string(), "Y".to_string()]);
+        assert_eq!(nwt.get_entry("X").unwrap().data, vec![(0, 0, -45.0, 0.0, 0.5), (0, 1, -45.0, 90.0, 0.5)]);
+
+        let outside = "polyid,lat_idx,lon_idx,lat,lon,weight\nX,2,0,0,0,1\n";
+        assert!(matches!(NextWeightFile::from_csv(outside.as_bytes(), 2, 3), Err(NwtError::InvalidInput(_))));
+        let short = "polyid,lat_idx,lon_idx,lat,lon,weight\nX,0,0\n";
+        assert!(matches!(NextWeightFile::from_csv(short.as_bytes(), 2, 3), Err(NwtError::InvalidInput(ref m)) if m.starts_with("line 2")));
+    }
+}
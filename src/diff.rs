@@ -0,0 +1,240 @@
+//! Structured comparison of two weight files, matched by polyid name.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{AttrVal, GridPoint, NextWeightFile};
+
+/// A polyid that covers different cells in the two files
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PointsChange {
+    pub polyid: String,
+    /// cells only the other file's entry covers
+    pub added: usize,
+    /// cells only this file's entry covers
+    pub removed: usize,
+}
+
+/// A polyid covering the same cells with weights that moved by more than
+/// the tolerance
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WeightChange {
+    pub polyid: String,
+    /// largest absolute weight difference over the cells
+    pub max_delta: f32,
+}
+
+/// An attribute that was added, removed or given a new value. `old` is
+/// `None` for an addition and `new` for a removal
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttrChange {
+    /// the variable the attribute belongs to, or `None` for a global one
+    pub variable: Option<String>,
+    pub key: String,
+    pub old: Option<AttrVal>,
+    pub new: Option<AttrVal>,
+}
+
+/// Everything `NextWeightFile::diff` found different, going from `self` to
+/// `other`. Polyids are listed in the order their file has them
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NwtDiff {
+    /// `(lat_len, lon_len)` of both files, if they differ
+    pub dimensions: Option<((u64, u64), (u64, u64))>,
+    pub only_in_self: Vec<String>,
+    pub only_in_other: Vec<String>,
+    pub changed_points: Vec<PointsChange>,
+    pub changed_weights: Vec<WeightChange>,
+    pub changed_attrs: Vec<AttrChange>,
+}
+
+impl NwtDiff {
+    /// true if the files matched within the tolerance
+    pub fn is_empty(&self) -> bool {
+        self.dimensions.is_none()
+            && self.only_in_self.is_empty()
+            && self.only_in_other.is_empty()
+            && self.changed_points.is_empty()
+            && self.changed_weights.is_empty()
+            && self.changed_attrs.is_empty()
+    }
+}
+
+impl fmt::Display for NwtDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no differences");
+        }
+        let mut lines = Vec::new();
+        if let Some(((lat_a, lon_a), (lat_b, lon_b))) = self.dimensions {
+            lines.push(format!("dimensions: {}x{} -> {}x{}", lat_a, lon_a, lat_b, lon_b));
+        }
+        for polyid in self.only_in_self.iter() {
+            lines.push(format!("polyid {} removed", polyid));
+        }
+        for polyid in self.only_in_other.iter() {
+            lines.push(format!("polyid {} added", polyid));
+        }
+        for change in self.changed_points.iter() {
+            lines.push(format!("polyid {}: {} cells added, {} removed", change.polyid, change.added, change.removed));
+        }
+        for change in self.changed_weights.iter() {
+            lines.push(format!("polyid {}: weights changed by up to {}", change.polyid, change.max_delta));
+        }
+        for change in self.changed_attrs.iter() {
+            let name = match &change.variable {
+                Some(var) => format!("attribute {}:{}", var, change.key),
+                None => format!("global attribute {}", change.key),
+            };
+            lines.push(match (&change.old, &change.new) {
+                (None, Some(new)) => format!("{} added: {}", name, new),
+                (Some(old), None) => format!("{} removed (was {})", name, old),
+                (Some(old), Some(new)) => format!("{} changed: {} -> {}", name, old, new),
+                (None, None) => format!("{} unchanged", name),
+            });
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl NextWeightFile {
+    /// Compares this file with `other`, matching polyids by name so a
+    /// reordered file isn't reported as changed. A polyid's points are
+    /// matched by `(lat_idx, lon_idx)` cell, a cell listed twice counting
+    /// with its weights summed. When both entries cover the same cells, a
+    /// weight change is reported if any cell's weight moved by more than
+    /// `weight_tol`
+    pub fn diff(&self, other: &NextWeightFile, weight_tol: f32) -> NwtDiff {
+        let mut diff = NwtDiff::default();
+        if self.get_dimensions() != other.get_dimensions() {
+            diff.dimensions = Some((self.get_dimensions(), other.get_dimensions()));
+        }
+
+        for (name, entry) in self.iter() {
+            let Some(other_entry) = other.polyid_index(name).map(|idx| other.entry_points(idx)) else {
+                diff.only_in_self.push(name.to_string());
+                continue;
+            };
+            let (cells, other_cells) = (cell_weights(entry), cell_weights(other_entry));
+            let added = other_cells.keys().filter(|cell| !cells.contains_key(cell)).count();
+            let removed = cells.keys().filter(|cell| !other_cells.contains_key(cell)).count();
+            if added > 0 || removed > 0 {
+                diff.changed_points.push(PointsChange { polyid: name.to_string(), added, removed });
+                continue;
+            }
+            let max_delta = cells.iter()
+                .map(|(cell, w)| (w - other_cells[cell]).abs() as f32)
+                .fold(0.0, f32::max);
+            if max_delta > weight_tol {
+                diff.changed_weights.push(WeightChange { polyid: name.to_string(), max_delta });
+            }
+        }
+        diff.only_in_other = other.get_polyids().iter()
+            .filter(|name| self.polyid_index(name).is_none())
+            .cloned()
+            .collect();
+
+        diff_attrs(&mut diff.changed_attrs, None, &self.json_data.global_attrs, &other.json_data.global_attrs);
+        let mut variables: Vec<&String> = self.json_data.per_variable_attrs.keys()
+            .chain(other.json_data.per_variable_attrs.keys())
+            .collect();
+        variables.sort();
+        variables.dedup();
+        for var in variables {
+            let attrs = self.json_data.per_variable_attrs.get(var).map(Vec::as_slice).unwrap_or_default();
+            let other_attrs = other.json_data.per_variable_attrs.get(var).map(Vec::as_slice).unwrap_or_default();
+            diff_attrs(&mut diff.changed_attrs, Some(var), attrs, other_attrs);
+        }
+        diff
+    }
+}
+
+/// a polyid's weight per cell, repeated cells summed
+fn cell_weights(entry: &[GridPoint]) -> BTreeMap<(u32, u32), f64> {
+    let mut cells = BTreeMap::new();
+    for p in entry {
+        *cells.entry((p.0, p.1)).or_insert(0.0) += p.4 as f64;
+    }
+    cells
+}
+
+/// records the attributes `other` adds, removes or changes from `attrs`
+fn diff_attrs(changes: &mut Vec<AttrChange>, variable: Option<&String>, attrs: &[(String, AttrVal)], other: &[(String, AttrVal)]) {
+    let find = |attrs: &[(String, AttrVal)], key: &str| attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone());
+    for (key, value) in attrs.iter() {
+        let new = find(other, key);
+        if new.as_ref() != Some(value) {
+            changes.push(AttrChange { variable: variable.cloned(), key: key.clone(), old: Some(value.clone()), new });
+        }
+    }
+    for (key, value) in other.iter() {
+        if find(attrs, key).is_none() {
+            changes.push(AttrChange { variable: variable.cloned(), key: key.clone(), old: None, new: Some(value.clone()) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::sample_file;
+    use crate::NextWeightFileBuilder;
+
+    #[test]
+    fn reordered_file_has_no_differences() {
+        let nwt = sample_file();
+        let mut builder = NextWeightFileBuilder::new()
+            .dimensions(2, 3)
+            .global_attr("title", "sample weights")
+            .variable_attr("regridweights", "units", "1");
+        for name in ["C", "A", "B"] {
+            builder = builder.add_polyid(name, nwt.get_entry(name).unwrap().data.clone());
+        }
+        let diff = nwt.diff(&builder.build(), 0.0);
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "no differences");
+    }
+
+    #[test]
+    fn diff_reports_every_kind_of_change() {
+        let nwt = sample_file();
+        let mut c = nwt.get_entry("C").unwrap().data.clone();
+        c[2] = (1, 1, 45.0, 90.0, 0.0);
+        let other = NextWeightFileBuilder::new()
+            .dimensions(2, 3)
+            .global_attr("title", "regenerated weights")
+            .global_attr("history", "rebuilt")
+            .add_polyid("D", vec![(0, 0, -45.0, 0.0, 1.0)])
+            .add_polyid("C", c)
+            .add_polyid("B", vec![(1, 1, 45.0, 90.0, 0.9)])
+            .build();
+
+        let diff = nwt.diff(&other, 0.05);
+        assert_eq!(diff.dimensions, None);
+        assert_eq!(diff.only_in_self, vec!["A"]);
+        assert_eq!(diff.only_in_other, vec!["D"]);
+        assert_eq!(diff.changed_points, vec![PointsChange { polyid: "C".to_string(), added: 1, removed: 1 }]);
+        assert_eq!(diff.changed_weights.len(), 1);
+        assert_eq!(diff.changed_weights[0].polyid, "B");
+        assert!((diff.changed_weights[0].max_delta - 0.1).abs() < 1e-6);
+        assert!(nwt.diff(&other, 0.2).changed_weights.is_empty());
+        assert_eq!(diff.changed_attrs.len(), 3);
+        assert_eq!(diff.changed_attrs[2], AttrChange {
+            variable: Some("regridweights".to_string()),
+            key: "units".to_string(),
+            old: Some(AttrVal::from("1")),
+            new: None,
+        });
+
+        let text = diff.to_string();
+        assert!(text.starts_with("polyid A removed\npolyid D added\npolyid C: 1 cells added, 1 removed\n"));
+        assert!(text.contains("global attribute title changed: sample weights -> regenerated weights\n"));
+        assert!(text.contains("global attribute history added: rebuilt\n"));
+        assert!(text.ends_with("attribute regridweights:units removed (was 1)"));
+
+        let json = serde_json::to_string(&diff).unwrap();
+        assert_eq!(serde_json::from_str::<NwtDiff>(&json).unwrap(), diff);
+    }
+}
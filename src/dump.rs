@@ -0,0 +1,232 @@
+//! A human-readable JSON dump of a whole weight file, for debugging, which
+//! doubles as `NextWeightFile`'s serde representation.
+
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::arena::PointArena;
+use crate::weight_sets::WeightSet;
+use crate::{build_lookup_table, GridPoint, JsonData, LookupEntry, NextWeightFile, NwtError, PolyidEntry, DEFAULT_WEIGHT_SET,
+    FORMAT_VERSION};
+
+/// Everything in a weight file, laid out for reading rather than parsing.
+/// Borrows from the file when serializing
+#[derive(Serialize, Deserialize)]
+struct Dump<'a> {
+    format_version: u16,
+    lat_len: u64,
+    lon_len: u64,
+    num_polyids: usize,
+    metadata: Cow<'a, JsonData>,
+    lookup_table: Cow<'a, [LookupEntry]>,
+    lat_axis: Option<Cow<'a, [f32]>>,
+    lon_axis: Option<Cow<'a, [f32]>>,
+    /// (lower, upper) cell bounds along each axis, left out when unknown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lat_bounds: Option<Cow<'a, [(f32, f32)]>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lon_bounds: Option<Cow<'a, [(f32, f32)]>>,
+    /// one entry per polyid, in `metadata.polyids` order
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gridpoints: Option<Gridpoints<'a>>,
+    /// the full-precision weights of a double-precision file, parallel to
+    /// `gridpoints`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    weights_f64: Option<Cow<'a, [Vec<f64>]>>,
+    /// name of the weight set `gridpoints` holds
+    #[serde(default = "default_weight_set", skip_serializing_if = "is_default_weight_set")]
+    weight_set: Cow<'a, str>,
+    /// any other weight sets, along with `gridpoints`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    other_weight_sets: Option<Cow<'a, [WeightSet]>>,
+}
+
+/// `gridpoints`: the file's arena when serializing, so no `PolyidEntry`
+/// copies are built, and owned entries when deserializing
+enum Gridpoints<'a> {
+    Arena(&'a PointArena),
+    Owned(Vec<PolyidEntry>),
+}
+
+impl Gridpoints<'_> {
+    fn into_owned(self) -> Vec<PolyidEntry> {
+        match self {
+            Self::Arena(arena) => arena.iter().map(|points| PolyidEntry { data: points.to_vec() }).collect(),
+            Self::Owned(entries) => entries,
+        }
+    }
+}
+
+/// a `PolyidEntry` serialized straight from the arena's slice
+#[derive(Serialize)]
+struct EntryRef<'a> {
+    data: &'a [GridPoint],
+}
+
+impl Serialize for Gridpoints<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Arena(arena) => serializer.collect_seq(arena.iter().map(|data| EntryRef { data })),
+            Self::Owned(entries) => entries.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Gridpoints<'_> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(Self::Owned)
+    }
+}
+
+fn default_weight_set() -> Cow<'static, str> {
+    Cow::Borrowed(DEFAULT_WEIGHT_SET)
+}
+
+fn is_default_weight_set(name: &str) -> bool {
+    name == DEFAULT_WEIGHT_SET
+}
+
+/// Serializes as the `to_json(true)` dump, so through any serde format.
+/// The binary NWT format remains the way to store weight files on disk
+impl Serialize for NextWeightFile {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.dump(true).serialize(serializer)
+    }
+}
+
+/// Checks the lookup table against the entries as `from_json` does. Formats
+/// like JSON that can't hold NaN won't read back NaN coordinates
+impl<'de> Deserialize<'de> for NextWeightFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Self::from_dump(Dump::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
+
+impl NextWeightFile {
+    /// Dumps the header fields, attributes, polyids, lookup table, axes and
+    /// cell bounds as JSON, along with every gridpoint if `include_points` is
+    /// set. NaN coordinates come out as `null`
+    pub fn to_json(&self, include_points: bool) -> serde_json::Value {
+        let dump = self.dump(include_points);
+        serde_json::to_value(&dump).expect("weight file dumps are always valid JSON")
+    }
+
+    /// `to_json`, pretty-printed
+    pub fn to_json_string_pretty(&self, include_points: bool) -> Result<String, NwtError> {
+        Ok(serde_json::to_string_pretty(&self.dump(include_points))?)
+    }
+
+    /// Rebuilds a weight file from a `to_json` dump that includes its
+    /// gridpoints, e.g. a hand-written test fixture. The lookup table is
+    /// recomputed, and must match the dump's if it has one
+    pub fn from_json(value: serde_json::Value) -> Result<Self, NwtError> {
+        Self::from_dump(serde_json::from_value(value)?)
+    }
+
+    fn from_dump(dump: Dump) -> Result<Self, NwtError> {
+        let gridpoints = dump.gridpoints
+            .ok_or_else(|| NwtError::InvalidInput("the dump has no gridpoints".to_string()))?
+            .into_owned();
+        if gridpoints.len() != dump.metadata.polyids.len() {
+            return Err(NwtError::InvalidInput(format!(
+                "{} gridpoint entries for {} polyids", gridpoints.len(), dump.metadata.polyids.len())));
+        }
+        let f64_weights = dump.weights_f64.map(Cow::into_owned);
+        if let Some(weights) = &f64_weights {
+            let parallel = weights.len() == gridpoints.len()
+                && weights.iter().zip(gridpoints.iter()).all(|(w, entry)| w.len() == entry.data.len());
+            if !parallel {
+                return Err(NwtError::InvalidInput("weights_f64 doesn't match the gridpoints".to_string()));
+            }
+        }
+        let weight_sets = dump.other_weight_sets.map(Cow::into_owned).unwrap_or_default();
+        if let Some(set) = weight_sets.iter().find(|set| set.entries.len() != gridpoints.len()) {
+            return Err(NwtError::InvalidInput(format!(
+                "weight set {} has {} entries for {} polyids", set.name, set.entries.len(), gridpoints.len())));
+        }
+        let lookup_table = build_lookup_table(&gridpoints);
+        if !dump.lookup_table.is_empty() && *dump.lookup_table != lookup_table[..] {
+            return Err(NwtError::InvalidInput("the lookup table doesn't match the gridpoints".to_string()));
+        }
+        Ok(Self {
+            json_data: dump.metadata.into_owned(),
+            lat_len: dump.lat_len,
+            lon_len: dump.lon_len,
+            polyid_gridpoints: gridpoints.into_iter().collect(),
+            lookup_table,
+            lat_axis: dump.lat_axis.map(Cow::into_owned),
+            lon_axis: dump.lon_axis.map(Cow::into_owned),
+            lat_bounds: dump.lat_bounds.map(Cow::into_owned),
+            lon_bounds: dump.lon_bounds.map(Cow::into_owned),
+            weight_set_name: dump.weight_set.into_owned(),
+            weight_sets,
+            f64_weights,
+            polyid_lookup: OnceLock::new(),
+        })
+    }
+
+    fn dump(&self, include_points: bool) -> Dump<'_> {
+        Dump {
+            format_version: FORMAT_VERSION,
+            lat_len: self.lat_len,
+            lon_len: self.lon_len,
+            num_polyids: self.len(),
+            metadata: Cow::Borrowed(&self.json_data),
+            lookup_table: Cow::Borrowed(&self.lookup_table),
+            lat_axis: self.lat_axis.as_deref().map(Cow::Borrowed),
+            lon_axis: self.lon_axis.as_deref().map(Cow::Borrowed),
+            lat_bounds: self.lat_bounds.as_deref().map(Cow::Borrowed),
+            lon_bounds: self.lon_bounds.as_deref().map(Cow::Borrowed),
+            gridpoints: include_points.then_some(Gridpoints::Arena(&self.polyid_gridpoints)),
+            weights_f64: self.f64_weights.as_deref().filter(|_| include_points).map(Cow::Borrowed),
+            weight_set: Cow::Borrowed(&self.weight_set_name),
+            other_weight_sets: Some(&self.weight_sets[..]).filter(|sets| include_points && !sets.is_empty()).map(Cow::Borrowed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::sample_file;
+
+    #[test]
+    fn json_dump_round_trip() {
+        let nwt = sample_file();
+        let summary = nwt.to_json(false);
+        assert_eq!(summary["num_polyids"], 3);
+        assert_eq!(summary["lookup_table"], serde_json::json!([[0, 2], [2, 1], [3, 3]]));
+        assert_eq!(summary["metadata"]["polyids"], serde_json::json!(["A", "B", "C"]));
+        assert!(summary.get("gridpoints").is_none());
+        assert!(matches!(NextWeightFile::from_json(summary), Err(NwtError::InvalidInput(_))));
+
+        // the points are serialized from where they're stored, not copied out
+        let before = nwt.memory_footprint();
+        let full = nwt.to_json(true);
+        assert_eq!(nwt.memory_footprint(), before);
+        assert_eq!(full["gridpoints"][1]["data"], serde_json::json!([[1, 1, 45.0, 90.0, 1.0]]));
+        assert_eq!(NextWeightFile::from_json(full).unwrap(), nwt);
+
+        let text = nwt.to_json_string_pretty(true).unwrap();
+        assert!(text.contains("\n  \"lat_len\": 2,"));
+    }
+
+    #[test]
+    fn serde_round_trip_checks_lookup_table() {
+        let nwt = crate::NextWeightFileBuilder::new()
+            .dimensions(2, 3)
+            .axes(vec![-45.0, 45.0], vec![0.0, 90.0, 180.0])
+            .add_polyid("A", vec![(0, 1, -45.0, 90.0, 1.0)])
+            .build();
+        let text = serde_json::to_string(&nwt).unwrap();
+        let back: NextWeightFile = serde_json::from_str(&text).unwrap();
+        assert_eq!(back, nwt);
+
+        let tampered = text.replace("\"lookup_table\":[[0,1]]", "\"lookup_table\":[[0,2]]");
+        assert_ne!(tampered, text);
+        let err = serde_json::from_str::<NextWeightFile>(&tampered).unwrap_err();
+        assert!(err.to_string().contains("lookup table"));
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+
+/// Errors that can occur while reading, converting, or writing weight files
+#[derive(Debug)]
+pub enum NwtError {
+    /// An underlying I/O operation failed
+    Io(std::io::Error),
+    /// The NetCDF library reported an error
+    #[cfg(feature = "netcdf")]
+    NetCdf(netcdf::Error),
+    /// An object store request failed
+    #[cfg(feature = "object_store")]
+    ObjectStore(object_store::Error),
+    /// The file does not start with the NWT magic bytes
+    InvalidMagic,
+    /// A required variable is not present in the source file. `available`
+    /// lists the variables it does have
+    MissingVariable { name: String, available: Vec<String> },
+    /// A required dimension is not present in the source file. `available`
+    /// lists the dimensions it does have
+    MissingDimension { name: String, available: Vec<String> },
+    /// The file to be written already exists and overwriting wasn't asked for
+    AlreadyExists(std::path::PathBuf),
+    /// No polyid with the given name exists in the file
+    PolyidNotFound(String),
+    /// A data field passed to `apply` does not match the grid size
+    FieldSizeMismatch { expected: usize, got: usize },
+    /// An array passed to `apply_array` does not have the grid's (lat, lon) shape
+    ShapeMismatch { expected: (usize, usize), got: (usize, usize) },
+    /// Data handed to a constructor or conversion is inconsistent
+    InvalidInput(String),
+    /// The JSON metadata block could not be (de)serialized
+    JsonParse(serde_json::Error),
+    /// The file ended before all expected bytes could be read
+    Truncated { section: &'static str, expected: usize, got: usize },
+    /// The file's header or lookup table is internally inconsistent
+    Corrupt(String),
+    /// The file was written in a format version this library can't read
+    UnsupportedVersion(u16),
+    /// The file's data is compressed with a codec this build can't decode
+    UnsupportedCodec(u16),
+    /// The data doesn't match the checksum stored in the file
+    ChecksumMismatch { expected: u32, got: u32 },
+    /// The grid's indices don't fit in the u32 the format stores them as,
+    /// or its cells can't be addressed on this platform
+    GridTooLarge { lat_len: u64, lon_len: u64 },
+    /// The file declares more of something than `ParseOptions` allows
+    LimitExceeded { what: &'static str, declared: u64, limit: u64 },
+}
+
+impl fmt::Display for NwtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NwtError::Io(e) => write!(f, "I/O error: {}", e),
+            #[cfg(feature = "netcdf")]
+            NwtError::NetCdf(e) => write!(f, "NetCDF error: {}", e),
+            #[cfg(feature = "object_store")]
+            NwtError::ObjectStore(e) => write!(f, "Object store error: {}", e),
+            NwtError::InvalidMagic => write!(f, "Invalid file format"),
+            NwtError::MissingVariable { name, available } => {
+                write!(f, "Variable {} not found in the weight file (it has: {})", name, available.join(", "))
+            }
+            NwtError::MissingDimension { name, available } => {
+                write!(f, "Dimension {} not found in the weight file (it has: {})", name, available.join(", "))
+            }
+            NwtError::AlreadyExists(path) => {
+                write!(f, "{} already exists (set WriteOptions::overwrite to replace it)", path.display())
+            }
+            NwtError::PolyidNotFound(name) => write!(f, "Polyid {} not found in the weight file", name),
+            NwtError::FieldSizeMismatch { expected, got } => {
+                write!(f, "Field has {} values but the weight grid has {}", got, expected)
+            }
+            NwtError::ShapeMismatch { expected, got } => {
+                write!(f, "Array has (lat, lon) shape {:?} but the weight grid is {:?}", got, expected)
+            }
+            NwtError::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
+            NwtError::JsonParse(e) => write!(f, "Failed to parse JSON metadata: {}", e),
+            NwtError::Truncated { section, expected, got } => {
+                write!(f, "file truncated while reading {} (needed {} bytes, {} remain)", section, expected, got)
+            }
+            NwtError::Corrupt(msg) => write!(f, "Corrupt NWT file: {}", msg),
+            NwtError::UnsupportedVersion(v) => {
+                write!(f, "NWT format version {} is not supported (newest supported is {})", v, crate::FORMAT_VERSION)
+            }
+            NwtError::UnsupportedCodec(c) => {
+                write!(f, "Compression codec {} is not supported (is the `compression` feature enabled?)", c)
+            }
+            NwtError::ChecksumMismatch { expected, got } => {
+                write!(f, "Checksum mismatch: file says {:08x} but the data hashes to {:08x}", expected, got)
+            }
+            NwtError::GridTooLarge { lat_len, lon_len } => {
+                write!(f, "A {}x{} grid is too large to index on this platform", lat_len, lon_len)
+            }
+            NwtError::LimitExceeded { what, declared, limit } => {
+                write!(f, "File declares {} {}, more than the limit of {}", declared, what, limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for NwtError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NwtError::Io(e) => Some(e),
+            #[cfg(feature = "netcdf")]
+            NwtError::NetCdf(e) => Some(e),
+            #[cfg(feature = "object_store")]
+            NwtError::ObjectStore(e) => Some(e),
+            NwtError::JsonParse(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for NwtError {
+    fn from(e: std::io::Error) -> Self {
+        NwtError::Io(e)
+    }
+}
+
+#[cfg(feature = "netcdf")]
+impl From<netcdf::Error> for NwtError {
+    fn from(e: netcdf::Error) -> Self {
+        NwtError::NetCdf(e)
+    }
+}
+
+#[cfg(feature = "object_store")]
+impl From<object_store::Error> for NwtError {
+    fn from(e: object_store::Error) -> Self {
+        NwtError::ObjectStore(e)
+    }
+}
+
+impl From<serde_json::Error> for NwtError {
+    fn from(e: serde_json::Error) -> Self {
+        NwtError::JsonParse(e)
+    }
+}
@@ -0,0 +1,173 @@
+//! Exporting weight files back to other formats.
+
+use std::path::Path;
+
+use netcdf::AttributeValue;
+
+use crate::convert::fill_as_f32;
+use crate::{NextWeightFile, NwtError, WeightDtype};
+
+/// `_FillValue` used when the source didn't record one: NetCDF's default
+/// fill for floats
+const DEFAULT_FILL: f32 = 9.969_21e36;
+
+impl NextWeightFile {
+    /// Writes the weights back out in the original NetCDF layout: `polyid`,
+    /// `lat` and `lon` variables and a dense `regridweights(polyid, lat, lon)`
+    /// holding the `_FillValue` everywhere but the stored points, as doubles
+    /// for a double-precision file and floats otherwise. Global
+    /// attributes and those of these four variables and any cell bounds are
+    /// restored; attributes of any other source variables are dropped, as
+    /// there is no data to attach them to.
+    ///
+    /// The `lat`/`lon` values come from the stored coordinate axes. Files
+    /// without them (older formats, or built without `axes`) have the axes
+    /// rebuilt from their points, and indices no point uses are written as
+    /// NaN. Cell bounds, if known, go in an `(axis, nv)` variable named by
+    /// the axis's `bounds` attribute, or `lat_bnds`/`lon_bnds` along with
+    /// the attribute
+    pub fn to_netcdf(&self, path: impl AsRef<Path>) -> Result<(), NwtError> {
+        let (lat_len, lon_len) = (self.lat_len as usize, self.lon_len as usize);
+        let (lats, lons) = self.axis_values();
+        let lats: Vec<f32> = lats.into_iter().map(|v| v.unwrap_or(f32::NAN)).collect();
+        let lons: Vec<f32> = lons.into_iter().map(|v| v.unwrap_or(f32::NAN)).collect();
+        let fill = match self.json_data.get_var_attr_value("regridweights", "_FillValue") {
+            Some(value) => fill_as_f32(value)?,
+            None => DEFAULT_FILL,
+        };
+
+        let mut file = netcdf::create(path.as_ref())?;
+        file.add_dimension("polyid", self.len())?;
+        file.add_dimension("lat", lat_len)?;
+        file.add_dimension("lon", lon_len)?;
+        for (key, value) in self.json_data.global_attrs.iter() {
+            file.add_attribute(key, AttributeValue::from(value))?;
+        }
+
+        let mut polyid_var = file.add_string_variable("polyid", &["polyid"])?;
+        self.put_var_attrs(&mut polyid_var, "polyid")?;
+        for (idx, name) in self.get_polyids().iter().enumerate() {
+            polyid_var.put_string(name, idx)?;
+        }
+        let mut lat_var = file.add_variable::<f32>("lat", &["lat"])?;
+        self.put_var_attrs(&mut lat_var, "lat")?;
+        lat_var.put_values(&lats, ..)?;
+        let mut lon_var = file.add_variable::<f32>("lon", &["lon"])?;
+        self.put_var_attrs(&mut lon_var, "lon")?;
+        lon_var.put_values(&lons, ..)?;
+        for (axis, bounds) in [("lat", &self.lat_bounds), ("lon", &self.lon_bounds)] {
+            let Some(bounds) = bounds else { continue };
+            let name = match self.json_data.get_var_attr(axis, "bounds") {
+                Some(name) => name.to_string(),
+                None => {
+                    let name = format!("{}_bnds", axis);
+                    file.variable_mut(axis).expect("axis variables were just added").put_attribute("bounds", name.as_str())?;
+                    name
+                }
+            };
+            if file.dimension("nv").is_none() {
+                file.add_dimension("nv", 2)?;
+            }
+            let mut bounds_var = file.add_variable::<f32>(&name, &[axis, "nv"])?;
+            self.put_var_attrs(&mut bounds_var, &name)?;
+            let values: Vec<f32> = bounds.iter().flat_map(|&(lower, upper)| [lower, upper]).collect();
+            bounds_var.put_values(&values, ..)?;
+        }
+
+        let dims = ["polyid", "lat", "lon"];
+        let no_fill_attr = self.json_data.get_var_attr_value("regridweights", "_FillValue").is_none();
+        match self.weight_dtype() {
+            WeightDtype::F32 => {
+                let mut weights = file.add_variable::<f32>("regridweights", &dims)?;
+                if no_fill_attr {
+                    weights.set_fill_value(fill)?;
+                }
+                self.put_var_attrs(&mut weights, "regridweights")?;
+                self.put_weights(&mut weights, fill, |idx| self.polyid_gridpoints[idx].iter().map(|p| p.4).collect())?;
+            }
+            WeightDtype::F64 => {
+                let mut weights = file.add_variable::<f64>("regridweights", &dims)?;
+                if no_fill_attr {
+                    weights.set_fill_value(fill as f64)?;
+                }
+                self.put_var_attrs(&mut weights, "regridweights")?;
+                let f64_weights = self.f64_weights.as_ref().expect("F64 files have double-precision weights");
+                self.put_weights(&mut weights, fill as f64, |idx| f64_weights[idx].clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// writes every polyid's weights, as given by `weights_of`, into its
+    /// slab of the weights variable. One polyid at a time, so only a single
+    /// lat x lon slab is ever dense
+    fn put_weights<T: netcdf::NcPutGet + Copy>(
+        &self,
+        var: &mut netcdf::VariableMut,
+        fill: T,
+        weights_of: impl Fn(usize) -> Vec<T>,
+    ) -> Result<(), NwtError> {
+        let lon_len = self.lon_len as usize;
+        let mut slab = vec![fill; self.lat_len as usize * lon_len];
+        for (idx, entry) in self.polyid_gridpoints.iter().enumerate() {
+            slab.fill(fill);
+            for (p, w) in entry.iter().zip(weights_of(idx)) {
+                slab[p.0 as usize * lon_len + p.1 as usize] = w;
+            }
+            var.put_values(&slab, (idx, .., ..))?;
+        }
+        Ok(())
+    }
+
+    /// writes a variable's stored attributes in their original order. A
+    /// floating-point variable's `_FillValue` is set through the variable so
+    /// it gets the variable's type; the others don't have one
+    fn put_var_attrs(&self, var: &mut netcdf::VariableMut, name: &str) -> Result<(), NwtError> {
+        let attrs = self.json_data.per_variable_attrs.get(name).map(Vec::as_slice).unwrap_or_default();
+        for (key, value) in attrs.iter() {
+            if key == "_FillValue" {
+                if var.vartype().is_f32() {
+                    var.set_fill_value(fill_as_f32(value)?)?;
+                } else if var.vartype().is_f64() {
+                    var.set_fill_value(fill_as_f32(value)? as f64)?;
+                }
+            } else {
+                var.put_attribute(key, AttributeValue::from(value))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::convert::tests::write_sample_netcdf;
+    use crate::tests::temp_path;
+    use crate::{ConvertOptions, NextWeightFile};
+
+    #[test]
+    fn netcdf_round_trip() {
+        let src = temp_path("export_src.nc");
+        let exported = temp_path("export_out.nc");
+        {
+            let mut file = write_sample_netcdf(&src, Some(-1.0));
+            file.add_attribute("source_files", vec!["a.nc", "b.nc"]).unwrap();
+            file.add_dimension("nv", 2).unwrap();
+            file.add_variable::<f32>("lat_bnds", &["lat", "nv"]).unwrap()
+                .put_values(&[-90.0f32, 0.0, 0.0, 90.0], ..).unwrap();
+            file.variable_mut("lat").unwrap().put_attribute("bounds", "lat_bnds").unwrap();
+        }
+        let opts = ConvertOptions { provenance: false, ..Default::default() };
+        let first = NextWeightFile::from_weight_file_with(&src, &opts).unwrap();
+        let mut buff = Vec::new();
+        first.serialize_to_writer(&mut buff).unwrap();
+        NextWeightFile::from_reader(&buff[..]).unwrap().to_netcdf(&exported).unwrap();
+        let second = NextWeightFile::from_weight_file_with(&exported, &opts).unwrap();
+        std::fs::remove_file(&src).unwrap();
+        std::fs::remove_file(&exported).unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(second.lat_bounds(), Some(&[(-90.0, 0.0), (0.0, 90.0)][..]));
+        assert_eq!(second.lon_bounds(), None);
+    }
+}
@@ -0,0 +1,227 @@
+//! C ABI for reading NWT files from C and Fortran. See `include/nwt.h`.
+//!
+//! Every function returns an error code (or a null/negative value where
+//! noted) instead of unwinding, and records a message for
+//! `nwt_last_error_message`. Panics are caught at the boundary.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::OnceLock;
+
+use crate::{NextWeightFile, NwtError};
+
+/// success
+pub const NWT_OK: i32 = 0;
+/// a null pointer or out-of-range index was passed in
+pub const NWT_ERR_INVALID_ARGUMENT: i32 = -1;
+/// the library returned an error; see `nwt_last_error_message`
+pub const NWT_ERR_LIBRARY: i32 = -2;
+/// the output buffer is too small; the message says how big it must be
+pub const NWT_ERR_BUFFER_TOO_SMALL: i32 = -3;
+/// the library panicked
+pub const NWT_ERR_PANIC: i32 = -4;
+
+/// One gridpoint, packed into 20 bytes like the records of NWT format 6
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(C)]
+pub struct NwtPoint {
+    pub lat_idx: u32,
+    pub lon_idx: u32,
+    pub lat: f32,
+    pub lon: f32,
+    pub weight: f32,
+}
+
+/// An open weight file. Entries are laid out as `NwtPoint` arrays the first
+/// time they're asked for, and stay valid until `nwt_close`
+pub struct NwtHandle {
+    nwt: NextWeightFile,
+    entries: Vec<OnceLock<Vec<NwtPoint>>>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(msg: impl ToString) {
+    // interior NULs would truncate the message anyway
+    let msg = msg.to_string().replace('\0', " ");
+    LAST_ERROR.with(|e| *e.borrow_mut() = CString::new(msg).ok());
+}
+
+/// runs `f`, turning errors and panics into codes
+fn guard(f: impl FnOnce() -> Result<i32, (i32, String)>) -> i32 {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(code)) => code,
+        Ok(Err((code, msg))) => {
+            set_error(msg);
+            code
+        }
+        Err(_) => {
+            set_error("panic inside the NWT library");
+            NWT_ERR_PANIC
+        }
+    }
+}
+
+fn invalid(msg: &str) -> (i32, String) {
+    (NWT_ERR_INVALID_ARGUMENT, msg.to_string())
+}
+
+fn library(e: NwtError) -> (i32, String) {
+    (NWT_ERR_LIBRARY, e.to_string())
+}
+
+/// Opens an NWT file (or converts a NetCDF one, as `NextWeightFile::open`
+/// does). Returns null on failure
+///
+/// # Safety
+/// `path` must be null or a NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn nwt_open(path: *const c_char) -> *mut NwtHandle {
+    let mut handle = std::ptr::null_mut();
+    guard(|| {
+        if path.is_null() {
+            return Err(invalid("path is null"));
+        }
+        let path = CStr::from_ptr(path).to_str().map_err(|_| invalid("path is not UTF-8"))?;
+        let nwt = NextWeightFile::open(path).map_err(library)?;
+        let entries = (0..nwt.len()).map(|_| OnceLock::new()).collect();
+        handle = Box::into_raw(Box::new(NwtHandle { nwt, entries }));
+        Ok(NWT_OK)
+    });
+    handle
+}
+
+/// Returns the number of polyids, or a negative error code
+///
+/// # Safety
+/// `handle` must be null or a live handle from `nwt_open`
+#[no_mangle]
+pub unsafe extern "C" fn nwt_num_polyids(handle: *const NwtHandle) -> i64 {
+    let mut count = 0;
+    let code = guard(|| {
+        let handle = handle.as_ref().ok_or_else(|| invalid("handle is null"))?;
+        count = handle.nwt.len() as i64;
+        Ok(NWT_OK)
+    });
+    if code == NWT_OK { count } else { code.into() }
+}
+
+/// Copies the name of polyid `idx` into `buf` as a NUL-terminated string
+///
+/// # Safety
+/// `handle` must be null or a live handle from `nwt_open`, and `buf` null or
+/// valid for `len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn nwt_get_polyid_name(handle: *const NwtHandle, idx: usize, buf: *mut c_char, len: usize) -> i32 {
+    guard(|| {
+        let handle = handle.as_ref().ok_or_else(|| invalid("handle is null"))?;
+        if buf.is_null() {
+            return Err(invalid("buf is null"));
+        }
+        let name = handle.nwt.get_polyids().get(idx)
+            .ok_or_else(|| invalid(&format!("polyid index {} is out of range", idx)))?;
+        if name.len() >= len {
+            return Err((NWT_ERR_BUFFER_TOO_SMALL, format!("polyid name needs a {} byte buffer", name.len() + 1)));
+        }
+        std::ptr::copy_nonoverlapping(name.as_ptr(), buf.cast::<u8>(), name.len());
+        *buf.add(name.len()) = 0;
+        Ok(NWT_OK)
+    })
+}
+
+/// Points `*out_ptr` at polyid `idx`'s `*out_len` gridpoints. The array
+/// belongs to the handle and stays valid until `nwt_close`
+///
+/// # Safety
+/// `handle` must be null or a live handle from `nwt_open`, and `out_ptr`
+/// and `out_len` null or valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn nwt_get_entry(handle: *const NwtHandle, idx: usize, out_ptr: *mut *const NwtPoint, out_len: *mut usize) -> i32 {
+    guard(|| {
+        let handle = handle.as_ref().ok_or_else(|| invalid("handle is null"))?;
+        if out_ptr.is_null() || out_len.is_null() {
+            return Err(invalid("output pointer is null"));
+        }
+        if idx >= handle.nwt.len() {
+            return Err(invalid(&format!("polyid index {} is out of range", idx)));
+        }
+        let points = handle.entries[idx].get_or_init(|| {
+            handle.nwt.entry_points(idx).iter()
+                .map(|p| NwtPoint { lat_idx: p.0, lon_idx: p.1, lat: p.2, lon: p.3, weight: p.4 })
+                .collect()
+        });
+        *out_ptr = points.as_ptr();
+        *out_len = points.len();
+        Ok(NWT_OK)
+    })
+}
+
+/// Frees a handle and every entry array it handed out
+///
+/// # Safety
+/// `handle` must be null or a live handle from `nwt_open`, and is invalid
+/// afterwards
+#[no_mangle]
+pub unsafe extern "C" fn nwt_close(handle: *mut NwtHandle) {
+    guard(|| {
+        if !handle.is_null() {
+            drop(Box::from_raw(handle));
+        }
+        Ok(NWT_OK)
+    });
+}
+
+/// Returns the message of this thread's most recent error, or null if there
+/// hasn't been one. The string is valid until the next failing call
+#[no_mangle]
+pub extern "C" fn nwt_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(std::ptr::null(), |msg| msg.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{sample_file, temp_path};
+
+    #[test]
+    fn points_are_packed_records() {
+        assert_eq!(std::mem::size_of::<NwtPoint>(), 20);
+    }
+
+    #[test]
+    fn reads_entries_and_reports_errors() {
+        let path = temp_path("ffi.nwt");
+        sample_file().serialize_to_file(&path).unwrap();
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        unsafe {
+            let handle = nwt_open(c_path.as_ptr());
+            std::fs::remove_file(&path).unwrap();
+            assert!(!handle.is_null());
+            assert_eq!(nwt_num_polyids(handle), 3);
+
+            let mut buf = [0 as c_char; 8];
+            assert_eq!(nwt_get_polyid_name(handle, 2, buf.as_mut_ptr(), buf.len()), NWT_OK);
+            assert_eq!(CStr::from_ptr(buf.as_ptr()).to_str().unwrap(), "C");
+            assert_eq!(nwt_get_polyid_name(handle, 2, buf.as_mut_ptr(), 1), NWT_ERR_BUFFER_TOO_SMALL);
+
+            let (mut ptr, mut len) = (std::ptr::null(), 0);
+            let footprint = (*handle).nwt.memory_footprint();
+            assert_eq!(nwt_get_entry(handle, 0, &mut ptr, &mut len), NWT_OK);
+            let points = std::slice::from_raw_parts(ptr, len);
+            assert_eq!(points[1], NwtPoint { lat_idx: 0, lon_idx: 1, lat: -45.0, lon: 90.0, weight: 0.75 });
+            // read straight from the shared buffer, not a copy of every entry
+            assert_eq!((*handle).nwt.memory_footprint(), footprint);
+
+            assert_eq!(nwt_get_entry(handle, 3, &mut ptr, &mut len), NWT_ERR_INVALID_ARGUMENT);
+            let msg = CStr::from_ptr(nwt_last_error_message()).to_str().unwrap();
+            assert!(msg.contains("out of range"));
+            nwt_close(handle);
+
+            assert!(nwt_open(CString::new("/nonexistent.nwt").unwrap().as_ptr()).is_null());
+            assert_eq!(nwt_num_polyids(std::ptr::null()), i64::from(NWT_ERR_INVALID_ARGUMENT));
+        }
+    }
+}
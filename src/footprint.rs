@@ -0,0 +1,219 @@
+//! Estimates of what a weight file costs on disk and in memory.
+
+use std::collections::{BTreeMap, HashMap};
+use std::mem::size_of;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::{read_header, AttrVal, GridPoint, JsonData, LookupEntry, NextWeightFile, NwtError, NwtHeader, PolyidEntry, WeightDtype};
+use crate::weight_sets::{weight_sets_size, WeightSet};
+use crate::{AXES_VERSION, DEFAULT_WEIGHT_SET, FORMAT_VERSION};
+
+/// What `nwt_file_stats` learns about a file from its header alone
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NwtFileStats {
+    /// format version the file was written with
+    pub version: u16,
+    /// how the weights are stored
+    pub weight_dtype: WeightDtype,
+    pub num_polyids: u64,
+    pub lat_len: u64,
+    pub lon_len: u64,
+    /// gridpoints across all polyids
+    pub total_points: u64,
+    /// size of the file on disk
+    pub file_size: u64,
+    /// heap bytes `NextWeightFile::from_nwt` will need to hold the file,
+    /// assuming every Vec is allocated exactly to size. Files older than
+    /// format version 7 are assumed to have coordinate axes, and every file
+    /// a single weight set named `default`, as any others follow the
+    /// gridpoint data
+    pub projected_memory: u64,
+}
+
+/// Reads just the header, metadata and lookup table of an NWT file and
+/// reports its size and what loading it would cost, without reading any
+/// gridpoint data
+pub fn nwt_file_stats(path: impl AsRef<Path>) -> Result<NwtFileStats, NwtError> {
+    let path = path.as_ref();
+    let file_size = std::fs::metadata(path)?.len();
+    let header = read_header(path)?;
+
+    let total_points: u64 = header.lookup_table.iter().map(|e| e.count).sum();
+    let num_polyids = header.num_polyids;
+    let has_axes = match &header.coordinates {
+        Some(coords) => coords.are_axes,
+        None => header.version >= AXES_VERSION,
+    };
+    // measured on a clone, which sizes everything exactly like the rest of
+    // the projection rather than as the JSON parser grew it
+    let mut projected = json_heap_size(&header.json_data.clone()) as u64
+        + DEFAULT_WEIGHT_SET.len() as u64
+        + num_polyids * (size_of::<Range<usize>>() + size_of::<LookupEntry>()) as u64
+        + total_points * size_of::<GridPoint>() as u64;
+    if has_axes {
+        projected += (header.lat_len + header.lon_len) * size_of::<f32>() as u64;
+    }
+    for bounds in [&header.lat_bounds, &header.lon_bounds] {
+        projected += bounds.as_ref().map_or(0, |b| b.len() * size_of::<(f32, f32)>()) as u64;
+    }
+    if header.weight_dtype == WeightDtype::F64 {
+        projected += num_polyids * size_of::<Vec<f64>>() as u64 + total_points * size_of::<f64>() as u64;
+    }
+
+    Ok(NwtFileStats {
+        version: header.version,
+        weight_dtype: header.weight_dtype,
+        num_polyids,
+        lat_len: header.lat_len,
+        lon_len: header.lon_len,
+        total_points,
+        file_size,
+        projected_memory: projected,
+    })
+}
+
+impl NextWeightFile {
+    /// The exact number of bytes `serialize_to_writer` would write for the
+    /// file as it is now (with default `WriteOptions`): the header, JSON
+    /// metadata, lookup table, shared coordinates, cell bounds, gridpoint
+    /// records, weight sets and checksum
+    pub fn estimated_file_size(&self) -> u64 {
+        // magic, version, codec and dtype, then six u64 fields
+        let header = 4 + 3 * size_of::<u16>() as u64 + 6 * size_of::<u64>() as u64;
+        // the metadata always serializes; NaN attributes are written as strings
+        let json = serde_json::to_vec(&self.json_data).map_or(0, |json| json.len()) as u64;
+        let lookup = (self.lookup_table.len() * LookupEntry::SERIALIZED_SIZE) as u64;
+        let coordinates = 1 + (self.lat_len + self.lon_len) * size_of::<f32>() as u64;
+        let bounds = 1 + [&self.lat_bounds, &self.lon_bounds].iter()
+            .map(|bounds| bounds.as_ref().map_or(0, |b| b.len() as u64 * 2 * size_of::<f32>() as u64))
+            .sum::<u64>();
+        let points = self.polyid_gridpoints.points().len() as u64;
+        let point_size = NwtHeader::point_size_of(FORMAT_VERSION, self.weight_dtype(), false) as u64;
+        let weight_sets = weight_sets_size(&self.weight_set_name, &self.weight_sets, self.len());
+        header + json + lookup + coordinates + bounds + points * point_size + weight_sets + size_of::<u32>() as u64
+    }
+
+    /// Heap bytes held by the file's in-memory structures, counting the
+    /// full capacity of every Vec, String and map, including the polyid
+    /// index once it has been built
+    pub fn memory_footprint(&self) -> usize {
+        let entries = self.polyid_gridpoints.heap_size();
+        let axes = [&self.lat_axis, &self.lon_axis].iter()
+            .map(|axis| axis.as_ref().map_or(0, |v| v.capacity() * size_of::<f32>()))
+            .sum::<usize>();
+        let bounds = [&self.lat_bounds, &self.lon_bounds].iter()
+            .map(|bounds| bounds.as_ref().map_or(0, |b| b.capacity() * size_of::<(f32, f32)>()))
+            .sum::<usize>();
+        let f64_weights = self.f64_weights.as_ref().map_or(0, |weights| {
+            weights.capacity() * size_of::<Vec<f64>>()
+                + weights.iter().map(|w| w.capacity() * size_of::<f64>()).sum::<usize>()
+        });
+        let weight_sets = self.weight_set_name.capacity()
+            + self.weight_sets.capacity() * size_of::<WeightSet>()
+            + self.weight_sets.iter().map(|set| {
+                set.name.capacity()
+                    + set.entries.capacity() * size_of::<PolyidEntry>()
+                    + set.entries.iter().map(|e| e.data.capacity() * size_of::<GridPoint>()).sum::<usize>()
+            }).sum::<usize>();
+        let lookup = self.polyid_lookup.get().map_or(0, |index| {
+            map_size(index) + index.keys().map(String::capacity).sum::<usize>()
+        });
+        json_heap_size(&self.json_data)
+            + entries
+            + self.lookup_table.capacity() * size_of::<LookupEntry>()
+            + axes
+            + bounds
+            + f64_weights
+            + weight_sets
+            + lookup
+    }
+}
+
+/// heap bytes of the metadata: attribute names and values, polyid names and
+/// per-polyid metadata
+fn json_heap_size(json: &JsonData) -> usize {
+    let attrs_size = |attrs: &Vec<(String, AttrVal)>| {
+        attrs.capacity() * size_of::<(String, AttrVal)>()
+            + attrs.iter().map(|(key, value)| key.capacity() + attr_heap_size(value)).sum::<usize>()
+    };
+    attrs_size(&json.global_attrs)
+        + btree_size(&json.per_variable_attrs)
+        + json.per_variable_attrs.iter().map(|(name, attrs)| name.capacity() + attrs_size(attrs)).sum::<usize>()
+        + json.polyids.capacity() * size_of::<String>()
+        + json.polyids.iter().map(String::capacity).sum::<usize>()
+        + btree_size(&json.polyid_meta)
+        + json.polyid_meta.iter().map(|(name, values)| {
+            name.capacity()
+                + btree_size(values)
+                + values.iter().map(|(key, value)| key.capacity() + value.capacity()).sum::<usize>()
+        }).sum::<usize>()
+}
+
+fn attr_heap_size(value: &AttrVal) -> usize {
+    match value {
+        AttrVal::Str(s) => s.capacity(),
+        AttrVal::Strs(v) => v.capacity() * size_of::<String>() + v.iter().map(String::capacity).sum::<usize>(),
+        AttrVal::F64(_) | AttrVal::I64(_) | AttrVal::U64(_) => 0,
+        AttrVal::F64s(v) => v.capacity() * size_of::<f64>(),
+        AttrVal::I64s(v) => v.capacity() * size_of::<i64>(),
+        AttrVal::U64s(v) => v.capacity() * size_of::<u64>(),
+    }
+}
+
+/// the table of a hash map: a slot and a control byte per bucket, taking
+/// the bucket count as the next power of two above capacity
+fn map_size<K, V>(map: &HashMap<K, V>) -> usize {
+    if map.capacity() == 0 {
+        return 0;
+    }
+    let buckets = (map.capacity() * 8 / 7).next_power_of_two();
+    buckets * (size_of::<(K, V)>() + 1)
+}
+
+/// the nodes of a B-tree map, counted as one (key, value) slot per entry
+/// since their fill varies
+fn btree_size<K, V>(map: &BTreeMap<K, V>) -> usize {
+    map.len() * size_of::<(K, V)>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{sample_file, temp_path};
+    use crate::NextWeightFileBuilder;
+
+    #[test]
+    fn estimated_size_matches_what_is_written() {
+        let with_axes = NextWeightFileBuilder::new()
+            .dimensions(2, 3)
+            .axes(vec![-45.0, 45.0], vec![0.0, 90.0, 180.0])
+            .bounds(vec![(-90.0, 0.0), (0.0, 90.0)], vec![(-45.0, 45.0), (45.0, 135.0), (135.0, 225.0)])
+            .add_polyid_f64("A", vec![(0, 0, -45.0, 0.0, 1.0 / 3.0)])
+            .build();
+        for nwt in [sample_file(), with_axes] {
+            assert_eq!(nwt.estimated_file_size(), nwt.to_bytes().unwrap().len() as u64);
+        }
+    }
+
+    #[test]
+    fn header_stats_project_the_loaded_footprint() {
+        let path = temp_path("footprint.nwt");
+        let nwt = sample_file();
+        nwt.serialize_to_file(&path).unwrap();
+        let stats = nwt_file_stats(&path).unwrap();
+        let loaded = NextWeightFile::from_nwt(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((stats.num_polyids, stats.lat_len, stats.lon_len, stats.total_points), (3, 2, 3, 6));
+        assert_eq!(stats.file_size, nwt.estimated_file_size());
+        // a clone allocates everything exactly to size, as the projection assumes
+        assert_eq!(stats.projected_memory, loaded.clone().memory_footprint() as u64);
+        assert!(loaded.memory_footprint() >= stats.projected_memory as usize);
+
+        // looking a polyid up builds the index, which counts too
+        let before = loaded.memory_footprint();
+        loaded.polyid_index("B").unwrap();
+        assert!(loaded.memory_footprint() > before);
+    }
+}
@@ -0,0 +1,279 @@
+//! Importers for the sparse weight files written by other regridding tools
+//! (ESMF/SCRIP, xESMF).
+//!
+//! These store one `(row, col, S)` triplet per weight, where the 1-based
+//! `row` is the destination cell (our polyid) and `col` the source gridcell,
+//! flattened row-major over (lat, lon) as in `to_csr`.
+
+use std::path::Path;
+
+use crate::convert::{find_dimension, find_variable, read_as_f64, read_netcdf_attrs};
+use crate::{grid_cells, Axes, GridPoint, NextWeightFile, NextWeightFileBuilder, NwtError, ScripOptions};
+
+impl NextWeightFile {
+    /// Imports an ESMF/SCRIP sparse weight file, as written by
+    /// `ESMF_RegridWeightGen` or CDO, without densifying it first. The source
+    /// grid's shape comes from `src_grid_dims` and point coordinates from its
+    /// `yc_a`/`xc_a` cell centers. Each destination cell becomes a polyid
+    pub fn from_scrip(path: impl AsRef<Path>, opts: &ScripOptions) -> Result<Self, NwtError> {
+        let file = netcdf::open(path.as_ref())?;
+        let dims = find_variable(&file, "src_grid_dims")?.get_values::<i64, _>(..)?;
+        // SCRIP lists dimensions fastest-varying first, so (lon, lat)
+        let (lat_len, lon_len) = match dims[..] {
+            [n] => (1, n),
+            [nlon, nlat] => (nlat, nlon),
+            _ => return Err(NwtError::InvalidInput(format!(
+                "src_grid_dims {:?} does not describe a 1-D or 2-D grid", dims))),
+        };
+        let (lat_len, lon_len) = match (usize::try_from(lat_len), usize::try_from(lon_len)) {
+            (Ok(lat_len), Ok(lon_len)) => (lat_len, lon_len),
+            _ => return Err(NwtError::InvalidInput(format!("src_grid_dims {:?} has a negative size", dims))),
+        };
+
+        let n_b = find_dimension(&file, "n_b")?.len();
+        let polyids = match &opts.polyids {
+            Some(ids) if ids.len() != n_b => return Err(NwtError::InvalidInput(format!(
+                "{} polyids were given but the file has {} destination cells", ids.len(), n_b))),
+            Some(ids) => ids.clone(),
+            None => (0..n_b).map(|idx| idx.to_string()).collect(),
+        };
+
+        let lats = read_degrees(&file, "yc_a")?;
+        let lons = read_degrees(&file, "xc_a")?;
+        if lats.len() != lat_len * lon_len || lons.len() != lat_len * lon_len {
+            return Err(NwtError::InvalidInput(format!(
+                "yc_a/xc_a have {}/{} values but src_grid_dims describe {} cells", lats.len(), lons.len(), lat_len * lon_len)));
+        }
+
+        let rows = find_variable(&file, "row")?.get_values::<i64, _>(..)?;
+        let cols = find_variable(&file, "col")?.get_values::<i64, _>(..)?;
+        let weights = read_as_f64(&find_variable(&file, "S")?, ..)?;
+
+        let mut builder = assemble(lat_len, lon_len, polyids, &rows, &cols, &weights, |cell| (lats[cell], lons[cell]))?;
+        if let Some((lat_axis, lon_axis)) = rectilinear_axes(&lats, &lons, lon_len) {
+            builder = builder.axes(lat_axis, lon_axis);
+        }
+        with_attrs(builder.build(), &file)
+    }
+
+    /// Imports a weight file written by xESMF's `Regridder.to_netcdf`, where
+    /// `row` indexes `polyids` and `col` the source grid of shape
+    /// `src_shape = (lat_len, lon_len)`. Point coordinates come from `lat`
+    /// and `lon` variables if the file has them, either as 1-D axes or as
+    /// per-cell centers, and are NaN otherwise
+    pub fn from_xesmf(path: impl AsRef<Path>, src_shape: (usize, usize), polyids: Vec<String>) -> Result<Self, NwtError> {
+        let file = netcdf::open(path.as_ref())?;
+        let (lat_len, lon_len) = src_shape;
+        let ncells = grid_cells(lat_len as u64, lon_len as u64)?;
+        let centers = match (file.variable("lat"), file.variable("lon")) {
+            (Some(_), Some(_)) => {
+                let (lats, lons) = (read_degrees(&file, "lat")?, read_degrees(&file, "lon")?);
+                if lats.len() == lat_len && lons.len() == lon_len {
+                    let lats = (0..ncells).map(|cell| lats[cell / lon_len]).collect();
+                    let lons = (0..ncells).map(|cell| lons[cell % lon_len]).collect();
+                    Some((lats, lons))
+                } else if lats.len() == ncells && lons.len() == ncells {
+                    Some((lats, lons))
+                } else {
+                    return Err(NwtError::InvalidInput(format!(
+                        "lat/lon have {}/{} values, which fit neither the {}x{} source grid's axes nor its cells",
+                        lats.len(), lons.len(), lat_len, lon_len)));
+                }
+            }
+            _ => None,
+        };
+
+        let rows = find_variable(&file, "row")?.get_values::<i64, _>(..)?;
+        let cols = find_variable(&file, "col")?.get_values::<i64, _>(..)?;
+        let weights = read_as_f64(&find_variable(&file, "S")?, ..)?;
+
+        let coords = |cell: usize| match &centers {
+            Some((lats, lons)) => (lats[cell], lons[cell]),
+            None => (f32::NAN, f32::NAN),
+        };
+        let mut builder = assemble(lat_len, lon_len, polyids, &rows, &cols, &weights, coords)?;
+        if let Some((lat_axis, lon_axis)) = centers.and_then(|(lats, lons)| rectilinear_axes(&lats, &lons, lon_len)) {
+            builder = builder.axes(lat_axis, lon_axis);
+        }
+        with_attrs(builder.build(), &file)
+    }
+}
+
+/// gives an imported file the source's global and variable attributes
+fn with_attrs(mut nwt: NextWeightFile, file: &netcdf::File) -> Result<NextWeightFile, NwtError> {
+    let (attrs, _) = read_netcdf_attrs(file)?;
+    nwt.json_data.global_attrs = attrs.global_attrs;
+    nwt.json_data.per_variable_attrs = attrs.per_variable_attrs;
+    Ok(nwt)
+}
+
+/// reads a coordinate variable in degrees, converting from radians if its
+/// `units` say so
+fn read_degrees(file: &netcdf::File, name: &str) -> Result<Vec<f32>, NwtError> {
+    let var = find_variable(file, name)?;
+    let radians = matches!(var.attribute_value("units"), Some(Ok(netcdf::AttributeValue::Str(u))) if u == "radians");
+    Ok(read_as_f64(&var, ..)?
+        .into_iter()
+        .map(|v| if radians { v.to_degrees() } else { v } as f32)
+        .collect())
+}
+
+/// the (lat, lon) axes of a grid whose cell centers form one, or None for a
+/// curvilinear grid
+fn rectilinear_axes(lats: &[f32], lons: &[f32], lon_len: usize) -> Option<Axes> {
+    if lon_len == 0 {
+        return None;
+    }
+    let lat_axis: Vec<f32> = lats.iter().step_by(lon_len).copied().collect();
+    let lon_axis: Vec<f32> = lons.iter().take(lon_len).copied().collect();
+    let regular = (0..lats.len()).all(|cell| {
+        lats[cell] == lat_axis[cell / lon_len] && lons[cell] == lon_axis[cell % lon_len]
+    });
+    regular.then_some((lat_axis, lon_axis))
+}
+
+/// groups 1-based `(row, col, weight)` triplets into one entry per polyid,
+/// in the order they appear. Non-finite weights are skipped, as in
+/// `from_weight_file`
+pub(crate) fn assemble(
+    lat_len: usize,
+    lon_len: usize,
+    polyids: Vec<String>,
+    rows: &[i64],
+    cols: &[i64],
+    weights: &[f64],
+    coords: impl Fn(usize) -> (f32, f32),
+) -> Result<NextWeightFileBuilder, NwtError> {
+    if rows.len() != cols.len() || rows.len() != weights.len() {
+        return Err(NwtError::InvalidInput(format!(
+            "row, col and S have different lengths ({}, {}, {})", rows.len(), cols.len(), weights.len())));
+    }
+    let ncells = grid_cells(lat_len as u64, lon_len as u64)?;
+    let index = |value: i64, len: usize, what: &str| {
+        usize::try_from(value - 1).ok().filter(|&idx| idx < len).ok_or_else(|| NwtError::InvalidInput(format!(
+            "{} index {} is outside the {} cells it refers to", what, value, len)))
+    };
+
+    let mut points: Vec<Vec<GridPoint>> = vec![Vec::new(); polyids.len()];
+    for ((&row, &col), &weight) in rows.iter().zip(cols).zip(weights) {
+        let dst = index(row, polyids.len(), "row")?;
+        let cell = index(col, ncells, "col")?;
+        if weight.is_finite() {
+            let (lat, lon) = coords(cell);
+            points[dst].push(((cell / lon_len) as u32, (cell % lon_len) as u32, lat, lon, weight as f32));
+        }
+    }
+
+    let mut builder = NextWeightFileBuilder::new().dimensions(lat_len as u64, lon_len as u64);
+    for (name, data) in polyids.into_iter().zip(points) {
+        builder = builder.add_polyid(name, data);
+    }
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{sample_file, temp_path};
+
+    /// the sample file's weights as 1-based (row, col, S) triplets
+    fn sample_triplets() -> (Vec<i64>, Vec<i64>, Vec<f64>) {
+        let (mut rows, mut cols, mut weights) = (Vec::new(), Vec::new(), Vec::new());
+        for (row, entry) in sample_file().get_gridpoints().iter().enumerate() {
+            for p in entry.data.iter() {
+                rows.push(row as i64 + 1);
+                cols.push((p.0 * 3 + p.1) as i64 + 1);
+                weights.push(p.4 as f64);
+            }
+        }
+        (rows, cols, weights)
+    }
+
+    #[test]
+    fn triplets_assemble_into_entries() {
+        let (rows, cols, weights) = sample_triplets();
+        let polyids = sample_file().get_polyids().clone();
+        let lats = [-45.0, 45.0];
+        let lons = [0.0, 90.0, 180.0];
+        let coords = |cell: usize| (lats[cell / 3], lons[cell % 3]);
+        let built = assemble(2, 3, polyids.clone(), &rows, &cols, &weights, coords).unwrap().build();
+        assert_eq!(built.get_gridpoints(), sample_file().get_gridpoints());
+
+        // 0 and 7 are outside the 1-based 2x3 grid
+        for bad_col in [0, 7] {
+            let err = assemble(2, 3, polyids.clone(), &[1], &[bad_col], &[1.0], coords).err().unwrap();
+            assert!(matches!(err, NwtError::InvalidInput(_)));
+        }
+        assert!(assemble(2, 3, polyids, &[1, 2], &[1], &[1.0], coords).is_err());
+    }
+
+    #[test]
+    fn curvilinear_grids_have_no_axes() {
+        let lats = [-45.0, -45.0, 45.0, 45.0];
+        let lons = [0.0, 90.0, 0.0, 90.0];
+        assert_eq!(rectilinear_axes(&lats, &lons, 2), Some((vec![-45.0, 45.0], vec![0.0, 90.0])));
+        let skewed = [0.0, 90.0, 10.0, 100.0];
+        assert_eq!(rectilinear_axes(&lats, &skewed, 2), None);
+    }
+
+    #[test]
+    fn scrip_file_imports() {
+        let path = temp_path("scrip.nc");
+        let (rows, cols, weights) = sample_triplets();
+        {
+            let mut file = netcdf::create(&path).unwrap();
+            file.add_dimension("n_s", rows.len()).unwrap();
+            file.add_dimension("n_a", 6).unwrap();
+            file.add_dimension("n_b", 3).unwrap();
+            file.add_dimension("src_grid_rank", 2).unwrap();
+            file.add_variable::<i32>("src_grid_dims", &["src_grid_rank"]).unwrap().put_values(&[3, 2], ..).unwrap();
+            let yc = [-45.0f64, -45.0, -45.0, 45.0, 45.0, 45.0];
+            let xc = [0.0f64, 90.0, 180.0, 0.0, 90.0, 180.0].map(f64::to_radians);
+            file.add_variable::<f64>("yc_a", &["n_a"]).unwrap().put_values(&yc, ..).unwrap();
+            let mut xc_a = file.add_variable::<f64>("xc_a", &["n_a"]).unwrap();
+            xc_a.put_values(&xc, ..).unwrap();
+            xc_a.put_attribute("units", "radians").unwrap();
+            file.add_variable::<i32>("row", &["n_s"]).unwrap()
+                .put_values(&rows.iter().map(|&r| r as i32).collect::<Vec<_>>(), ..).unwrap();
+            file.add_variable::<i32>("col", &["n_s"]).unwrap()
+                .put_values(&cols.iter().map(|&c| c as i32).collect::<Vec<_>>(), ..).unwrap();
+            file.add_variable::<f64>("S", &["n_s"]).unwrap().put_values(&weights, ..).unwrap();
+        }
+        let opts = ScripOptions { polyids: Some(vec!["A".into(), "B".into(), "C".into()]) };
+        let named = NextWeightFile::from_scrip(&path, &opts).unwrap();
+        let numbered = NextWeightFile::from_scrip(&path, &ScripOptions::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(named.get_gridpoints(), sample_file().get_gridpoints());
+        assert_eq!(named.get_polyids(), sample_file().get_polyids());
+        assert_eq!(named.get_latitudes(), Some(&[-45.0, 45.0][..]));
+        assert_eq!(numbered.get_polyids(), &vec!["0".to_string(), "1".to_string(), "2".to_string()]);
+    }
+
+    #[test]
+    fn xesmf_fixture_imports() {
+        // written the way xESMF's Regridder.to_netcdf lays out the sample
+        // weights: just n_s and the col/row/S triplets
+        let path = temp_path("xesmf.nc");
+        std::fs::write(&path, include_bytes!("../tests/fixtures/xesmf_weights.nc")).unwrap();
+        let polyids = sample_file().get_polyids().clone();
+        let imported = NextWeightFile::from_xesmf(&path, (2, 3), polyids.clone()).unwrap();
+        let too_few = NextWeightFile::from_xesmf(&path, (2, 3), vec!["A".to_string()]);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(imported.get_polyids(), &polyids);
+        assert_eq!(imported.json_data.get_global_attr("regrid_method"), Some("conservative"));
+        assert_eq!(imported.get_latitudes(), None);
+        for (got, want) in imported.get_gridpoints().iter().zip(sample_file().get_gridpoints()) {
+            assert_eq!(got.data.len(), want.data.len());
+            for (g, w) in got.data.iter().zip(want.data.iter()) {
+                assert_eq!((g.0, g.1, g.4), (w.0, w.1, w.4));
+                assert!(g.2.is_nan() && g.3.is_nan());
+            }
+        }
+        assert!(matches!(too_few, Err(NwtError::InvalidInput(_))));
+
+        // and back out through the sparse export it came from
+        assert_eq!(imported.to_csr().unwrap(), sample_file().to_csr().unwrap());
+    }
+}
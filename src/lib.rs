@@ -1,451 +1,1102 @@
-use std::path::Path;
-use std::str::FromStr;
-use std::{path::PathBuf, io::Write, mem::size_of, io::Read};
-
+//! Reading and writing NextGen weight files (NWT), a compact binary form
+//! of NetCDF regridding weights.
+//!
+//! # File layout
+//!
+//! NWT is a little-endian format: every integer and float is stored least
+//! significant byte first on any host, and every size and offset is a u64
+//! whatever the platform. A current file starts with a 58 byte header:
+//!
+//! | bytes  | field |
+//! |--------|-------|
+//! | 0..4   | magic, `NEWV` |
+//! | 4..6   | u16 format version |
+//! | 6..8   | u16 compression codec of the lookup table onward (version 4 on) |
+//! | 8..10  | u16 weight dtype: 0 for f32, 1 for f64, 2 for quantized u16 (version 8 on) |
+//! | 10..18 | u64 length of the JSON metadata |
+//! | 18..26 | u64 number of polyids |
+//! | 26..34 | u64 latitude length |
+//! | 34..42 | u64 longitude length |
+//! | 42..50 | u64 offset of the JSON metadata, the end of the header |
+//! | 50..58 | u64 offset of the lookup table, the end of the JSON metadata |
+//!
+//! Older versions leave out the u16 fields they predate, and version 1
+//! files start with `NEWT` and no version at all, so the u64 fields move up
+//! accordingly. After the header come, in order:
+//!
+//! - the JSON metadata: attributes, polyid names and per-polyid metadata
+//! - the lookup table, a u64 offset and u64 count of points per polyid
+//! - the lat/lon of every index (version 7 on) and the cell bounds
+//!   (version 9 on)
+//! - each polyid's (min, max) weight, for quantized files
+//! - the gridpoint records of every polyid, in lookup table order
+//! - the coordinate axes (versions 5 and 6)
+//! - the weight sets (version 10 on)
+//! - a u32 CRC32 of everything after the header (version 3 on)
+//!
+//! Compressed files store everything from the lookup table to the weight
+//! sets as a u64 length followed by the compressed bytes.
 
+use std::path::Path;
+use std::{path::PathBuf, io::Write, io::BufReader, mem::size_of, io::Read};
+
+
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+
+
+// the NetCDF C library doesn't build for wasm; the NWT format code does
+#[cfg(all(target_arch = "wasm32", feature = "netcdf"))]
+compile_error!("the `netcdf` feature is not available on wasm32, build with `default-features = false`");
+
+mod error;
+pub use error::NwtError;
+mod attrs;
+pub use attrs::AttrVal;
+
+mod apply;
+pub use apply::PolyidFieldStats;
+mod applicator;
+pub use applicator::WeightApplicator;
+mod append;
+pub use append::NwtAppender;
+mod arena;
+use arena::PointArena;
+mod atomic;
+mod options;
+pub use options::{Compression, ParseMode, ParseOptions, ParseWarning, WriteOptions};
+pub use options::{DEFAULT_MAX_POLYIDS, DEFAULT_MAX_TOTAL_POINTS};
+#[cfg(feature = "netcdf")]
+pub use options::{CachePolicy, ConvertOptions, OpenOptions, ScripOptions, DEFAULT_CHUNK_ROWS};
+#[cfg(feature = "netcdf")]
+mod provenance;
+mod codec;
+use codec::{read_f32_le, read_f64_le, read_u16_le, read_u32_le, read_u64_le};
+use codec::{write_f32_le, write_f64_le, write_u16_le, write_u32_le, write_u64_le};
+mod record;
+use record::{records, CompactPoint, OnDiskPoint, Record};
+mod recover;
+pub use recover::{LostPolyid, RecoveryReport};
+mod builder;
+#[cfg(feature = "netcdf")]
+mod convert;
+#[cfg(feature = "netcdf")]
+pub use convert::{ConversionResult, Progress};
+mod csv;
+mod diff;
+pub use diff::{AttrChange, NwtDiff, PointsChange, WeightChange};
+mod dump;
+#[cfg(feature = "netcdf")]
+mod export;
+#[cfg(feature = "netcdf")]
+mod import;
+mod rename;
+pub use rename::RemapReport;
+mod sparse;
+mod spatial;
+pub use spatial::{LonConvention, ReverseIndex};
+mod stats;
+pub use stats::{FileSummary, PolyidStats};
+mod footprint;
+pub use footprint::{nwt_file_stats, NwtFileStats};
+mod validate;
+pub use validate::{LookupError, ValidationIssue, ValidationReport, DEFAULT_SUM_TOLERANCE};
+mod weights;
+mod weight_sets;
+pub use weight_sets::DEFAULT_WEIGHT_SET;
+pub use builder::NextWeightFileBuilder;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "tokio")]
+mod async_io;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "mmap")]
+pub use mmap::NextWeightFileMmap;
+#[cfg(feature = "object_store")]
+mod remote;
+#[cfg(feature = "object_store")]
+pub use remote::NextWeightFileRemote;
+
+/// magic of legacy files, which implicitly use format version 1 and have no
+/// version field
+const LEGACY_MAGIC: &[u8; 4] = b"NEWT";
+/// magic of versioned files, followed by a u16 format version
+const MAGIC: &[u8; 4] = b"NEWV";
+/// The NWT format version written by this library. Versions 1 through this
+/// one can all be read
+pub const FORMAT_VERSION: u16 = 10;
+/// oldest format version `WriteOptions::version` can ask for
+const OLDEST_WRITABLE_VERSION: u16 = 6;
+/// first format version that ends with a CRC32 of everything after the header
+const CHECKSUM_VERSION: u16 = 3;
+/// first format version with a compression codec in the header
+const CODEC_VERSION: u16 = 4;
+/// first format version with the lat/lon axes section after the gridpoint data
+const AXES_VERSION: u16 = 5;
+/// first format version whose JSON block stores typed attribute values
+const TYPED_ATTRS_VERSION: u16 = 6;
+/// first format version that stores the lat/lon of every index once, after
+/// the lookup table, and only (lat_idx, lon_idx, weight) per point
+const COMPACT_POINTS_VERSION: u16 = 7;
+/// first format version with a weight dtype in the header
+const WEIGHT_DTYPE_VERSION: u16 = 8;
+/// first format version with the optional lat/lon cell bounds section after
+/// the coordinates
+const BOUNDS_VERSION: u16 = 9;
+/// first format version that names its weight set and can hold more after
+/// the gridpoint data
+const WEIGHT_SETS_VERSION: u16 = 10;
+
+/// the fields of the JSON metadata, the first three of which are all that
+/// files from before typed attributes have
+const JSON_FIELDS: &[&str] = &["global_attrs", "per_variable_attrs", "polyids", "polyid_meta"];
+
+/// weight dtype code in the header of quantized files
+const QUANTIZED_DTYPE: u16 = 2;
+/// the largest quantized weight, which stands for its polyid's maximum
+const QUANTIZED_MAX: f64 = u16::MAX as f64;
+
+/// quantizes a weight against its polyid's (min, max)
+fn quantize(weight: f64, (min, max): (f32, f32)) -> u16 {
+    let span = max as f64 - min as f64;
+    if span <= 0.0 {
+        return 0;
+    }
+    ((weight - min as f64) / span * QUANTIZED_MAX).round().clamp(0.0, QUANTIZED_MAX) as u16
+}
 
-use std::collections::HashMap;
+/// the (min, max) one polyid's finite weights are quantized against, and
+/// the largest error that leaves any of them with relative to the largest
+/// weight magnitude
+fn quantization_range(weights: &[f64]) -> ((f32, f32), f64) {
+    let min = weights.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = weights.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let range = if weights.is_empty() { (0.0, 0.0) } else { (min as f32, max as f32) };
+    let scale = (range.0 as f64).abs().max((range.1 as f64).abs());
+    let mut max_error: f64 = 0.0;
+    for &w in weights.iter() {
+        let error = (dequantize(quantize(w, range), range) as f64 - w).abs();
+        if scale > 0.0 {
+            max_error = max_error.max(error / scale);
+        }
+    }
+    (range, max_error)
+}
 
-use netcdf::AttributeValue;
+/// undoes `quantize`, to within half a step of the polyid's weight range
+pub(crate) fn dequantize(q: u16, (min, max): (f32, f32)) -> f32 {
+    (min as f64 + q as f64 / QUANTIZED_MAX * (max as f64 - min as f64)) as f32
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct NextWeightFile {
     json_data: JsonData,
     lat_len: u64,
     lon_len: u64,
-    polyid_gridpoints: Vec<PolyidEntry>,
-    lookup_table: Vec<(u64, u64)>,
+    /// every polyid's points, in one buffer
+    polyid_gridpoints: PointArena,
+    lookup_table: Vec<LookupEntry>,
+    /// the source file's 1D coordinate axes, if known
+    lat_axis: Option<Vec<f32>>,
+    lon_axis: Option<Vec<f32>>,
+    /// the source grid's (lower, upper) cell bounds along each axis, if known
+    lat_bounds: Option<Vec<(f32, f32)>>,
+    lon_bounds: Option<Vec<(f32, f32)>>,
+    /// name of the weight set the entries below hold
+    weight_set_name: String,
+    /// any other sets of weights for the same polyids
+    weight_sets: Vec<weight_sets::WeightSet>,
+    /// full-precision weights of a double-precision file, one Vec per entry
+    /// and parallel to its points, whose weights are these rounded to f32
+    f64_weights: Option<Vec<Vec<f64>>>,
+    /// polyid name -> index, built on first lookup
+    polyid_lookup: OnceLock<HashMap<String, usize>>,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone, PartialEq)]
 pub struct JsonData {
+    global_attrs: Vec<(String, AttrVal)>,
+    /// sorted by variable name, so the same attributes always serialize to
+    /// the same bytes. Files written while this was a hash map list the
+    /// variables in arbitrary order, which readers never depended on
+    per_variable_attrs: BTreeMap<String, Vec<(String, AttrVal)>>,
+    polyids: Vec<String>,
+    /// free-form key/value pairs per polyid name, such as a display `name`
+    /// or an `area_km2`. Left out of the JSON when empty, and absent from
+    /// files written before it existed
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    polyid_meta: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// The JSON block of files from before attributes were typed, when every
+/// value was a string
+#[derive(serde::Deserialize)]
+struct LegacyJsonData {
     global_attrs: Vec<(String, String)>,
-    per_variable_attrs: HashMap<String, Vec<(String, String)>>,
+    per_variable_attrs: BTreeMap<String, Vec<(String, String)>>,
     polyids: Vec<String>
 }
 
-#[derive(Debug)]
+impl From<LegacyJsonData> for JsonData {
+    fn from(legacy: LegacyJsonData) -> Self {
+        let typed = |attrs: Vec<(String, String)>| attrs.into_iter().map(|(k, v)| (k, AttrVal::Str(v))).collect();
+        JsonData {
+            global_attrs: typed(legacy.global_attrs),
+            per_variable_attrs: legacy.per_variable_attrs.into_iter().map(|(var, attrs)| (var, typed(attrs))).collect(),
+            polyids: legacy.polyids,
+            polyid_meta: BTreeMap::new(),
+        }
+    }
+}
+
+/// a grid's (latitude, longitude) coordinate axes
+type Axes = (Vec<f32>, Vec<f32>);
+/// the (lower, upper) bounds of every cell along one axis
+type Bounds = Vec<(f32, f32)>;
+
+/// A single weighted grid cell: lat_idx, lon_idx, lat_val, lon_val, data_value
+pub type GridPoint = (u32, u32, f32, f32, f32);
+
+/// A `GridPoint` with its weight in double precision
+pub type GridPointF64 = (u32, u32, f32, f32, f64);
+
+/// How a file's weights are stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WeightDtype {
+    #[default]
+    F32,
+    F64,
+}
+
+impl WeightDtype {
+    /// bytes per stored weight
+    fn size(self) -> usize {
+        match self {
+            WeightDtype::F32 => size_of::<f32>(),
+            WeightDtype::F64 => size_of::<f64>(),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Default, Clone, PartialEq)]
 #[repr(C)]
 pub struct PolyidEntry {
     // lat_idx, lon_idx, lat_val, lon_val, data_value
-    pub data: Vec<(u32, u32, f32, f32, f32)>
+    pub data: Vec<GridPoint>
 }
 
-impl NextWeightFile {
-    /// opens a NetCDF weight file and converts it to
-    pub fn from_weight_file(path: impl AsRef<Path> + Clone) -> Result<Self, String> {
-        // open the weight file
-        let weight_netcdf = netcdf::open(path).unwrap();
-        let mut json_data = JsonData::new();
+/// A row of the lookup table: where a polyid's gridpoints start and how
+/// many there are. Both count points, not bytes; the first point of an
+/// entry is `offset` points into the gridpoint data, whatever size the
+/// file's points are stored at
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(from = "(u64, u64)", into = "(u64, u64)")]
+#[repr(C)]
+pub struct LookupEntry {
+    /// number of points stored before this polyid's
+    pub offset: u64,
+    /// number of points this polyid has
+    pub count: u64,
+}
 
-        // now we get all of the attributes...
-        for attr in weight_netcdf.attributes() {
-            let attr_value = match attr.value().unwrap() {
-                AttributeValue::Str(a) => a,
-                AttributeValue::Strs(a) => a[0].clone(),
-                AttributeValue::Double(a) => format!("{}", a),
-                AttributeValue::Doubles(a) => format!("{:?}", a),
-                AttributeValue::Float(a) => format!("{}", a),
-                AttributeValue::Floats(a) => format!("{:?}", a),
-                AttributeValue::Int(a) => format!("{}", a),
-                AttributeValue::Ints(a) => format!("{:?}", a),
-                AttributeValue::Longlong(a) => format!("{}", a),
-                AttributeValue::Longlongs(a) => format!("{:?}", a),
-                AttributeValue::Schar(a) => format!("{}", a),
-                AttributeValue::Schars(a) => format!("{:?}", a),
-                AttributeValue::Short(a) => format!("{}", a),
-                AttributeValue::Shorts(a) => format!("{:?}", a),
-                AttributeValue::Uchar(a) => format!("{}", a),
-                AttributeValue::Uchars(a) => format!("{:?}",a),
-                AttributeValue::Uint(a) => format!("{}", a),
-                AttributeValue::Uints(a) => format!("{:?}", a),
-                AttributeValue::Ulonglong(a) => format!("{}",a),
-                AttributeValue::Ulonglongs(a) => format!("{:?}", a),
-                AttributeValue::Ushort(a) => format!("{}",a),
-                AttributeValue::Ushorts(a) => format!("{:?}", a)
-            };
-            // add it to our list of global attributes
-            json_data.add_global_attr(attr.name().to_string(), attr_value);
-        }
-
-        // ... and add all of the variable attributes ...
-        for var in weight_netcdf.variables() {
-            let var_name = var.name();
-            json_data.add_variable(&var_name);
-            for attr in var.attributes() {
-                if attr.name() != "_FillValue" {
-                    let attr_value = match attr.value().unwrap() {
-                        AttributeValue::Str(a) => a,
-                        AttributeValue::Strs(a) => a[0].clone(),
-                        AttributeValue::Double(a) => format!("{}", a),
-                        AttributeValue::Doubles(a) => format!("{:?}", a),
-                        AttributeValue::Float(a) => format!("{}", a),
-                        AttributeValue::Floats(a) => format!("{:?}", a),
-                        AttributeValue::Int(a) => format!("{}", a),
-                        AttributeValue::Ints(a) => format!("{:?}", a),
-                        AttributeValue::Longlong(a) => format!("{}", a),
-                        AttributeValue::Longlongs(a) => format!("{:?}", a),
-                        AttributeValue::Schar(a) => format!("{}", a),
-                        AttributeValue::Schars(a) => format!("{:?}", a),
-                        AttributeValue::Short(a) => format!("{}", a),
-                        AttributeValue::Shorts(a) => format!("{:?}", a),
-                        AttributeValue::Uchar(a) => format!("{}", a),
-                        AttributeValue::Uchars(a) => format!("{:?}",a),
-                        AttributeValue::Uint(a) => format!("{}", a),
-                        AttributeValue::Uints(a) => format!("{:?}", a),
-                        AttributeValue::Ulonglong(a) => format!("{}",a),
-                        AttributeValue::Ulonglongs(a) => format!("{:?}", a),
-                        AttributeValue::Ushort(a) => format!("{}",a),
-                        AttributeValue::Ushorts(a) => format!("{:?}", a)
-                    };
-                    // add it to our list of global attributes
-                    json_data.add_variable_attr(&var_name, attr.name().to_string(), attr_value);
+impl LookupEntry {
+    /// Bytes a row takes in a file: its offset, then its count, each a
+    /// little-endian u64
+    pub const SERIALIZED_SIZE: usize = 2 * size_of::<u64>();
+
+    pub fn new(offset: u64, count: u64) -> Self {
+        Self { offset, count }
+    }
+
+    /// writes the row as it's stored in a file
+    fn write<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        write_u64_le(w, self.offset)?;
+        write_u64_le(w, self.count)
+    }
+
+    /// reads a row written by `write`
+    fn read<R: Read>(cursor: &mut NwtReader<R>, section: &'static str) -> Result<Self, NwtError> {
+        let offset = cursor.read_u64(section)?;
+        let count = cursor.read_u64(section)?;
+        Ok(Self { offset, count })
+    }
+}
+
+// the format's stride, which the struct matches so a table could be viewed
+// in place
+const _: () = assert!(LookupEntry::SERIALIZED_SIZE == 16 && size_of::<LookupEntry>() == 16);
+
+/// Shown as the `(offset, count)` pair it's stored as
+impl std::fmt::Display for LookupEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({}, {})", self.offset, self.count)
+    }
+}
+
+impl From<(u64, u64)> for LookupEntry {
+    fn from((offset, count): (u64, u64)) -> Self {
+        Self { offset, count }
+    }
+}
+
+impl From<LookupEntry> for (u64, u64) {
+    fn from(entry: LookupEntry) -> Self {
+        (entry.offset, entry.count)
+    }
+}
+
+/// Lets a table be compared against `(offset, count)` pairs
+impl PartialEq<(u64, u64)> for LookupEntry {
+    fn eq(&self, other: &(u64, u64)) -> bool {
+        (self.offset, self.count) == *other
+    }
+}
+
+/// number of points `PolyidEntry`'s `Debug` shows before eliding the rest
+const DEBUG_POINTS: usize = 3;
+
+/// Prints the point count and only the first few points, so debug output
+/// of a big file stays readable
+impl std::fmt::Debug for PolyidEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        struct Preview<'a>(&'a [GridPoint]);
+        impl std::fmt::Debug for Preview<'_> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                let mut list = f.debug_list();
+                list.entries(self.0.iter().take(DEBUG_POINTS));
+                if self.0.len() > DEBUG_POINTS {
+                    list.entry(&format_args!(".."));
                 }
+                list.finish()
             }
         }
+        f.debug_struct("PolyidEntry").field("len", &self.data.len()).field("data", &Preview(&self.data)).finish()
+    }
+}
 
-        // now that we have gotten our attributes all squared away, lets start
-        // looking at data. First things first, lets store those polyids
-        let polyid_var = weight_netcdf.variable("polyid").unwrap();
-        for polyid in 0..polyid_var.len() {
-            json_data.add_polyid(polyid_var.get_string(polyid).unwrap());
-        };
+impl NextWeightFile {
 
-        // next lets start processing those weights
-        let regridweights = weight_netcdf.variable("regridweights").unwrap();
-        let latvar = weight_netcdf.variable("lat").unwrap();
-        let lonvar = weight_netcdf.variable("lon").unwrap();
-        let lat_vals = latvar.get_values::<f32,_>(..).unwrap();
-        let lon_vals = lonvar.get_values::<f32,_>(..).unwrap();
-        let lat_len = weight_netcdf.dimension("lat").unwrap().len() as u64;
-        let lon_len = weight_netcdf.dimension("lon").unwrap().len() as u64;
-        let fill = regridweights.fill_value::<f32>().unwrap().unwrap();
-        let mut polyid_gridpoints: Vec<PolyidEntry> = Vec::new();
-
-        // for every polyid...
-        for polyid in 0..polyid_var.len() {
-            // ... create a new entry into our lookup vector...
-            let mut curr_polyid = PolyidEntry::new();
-            let data = regridweights.get::<f32,_>((polyid,..,..)).unwrap();
-            let dat_slice = data.as_slice().unwrap();
-            // ... for every data value...
-            for lat_idx in 0..lat_len as usize {
-                for lon_idx in 0..lon_len as usize {
-                    let data_value = dat_slice[lat_idx * lon_len as usize+  lon_idx];
-                    // ...if it isnt a fill value...
-                    if data_value != fill {
-                        // ... then calculate the lat lon and save the weight
-                        curr_polyid.add_point(lat_idx as u32, lon_idx as u32, lat_vals[lat_idx], lon_vals[lon_idx], data_value);
-                    }
+    /// create new structure from .NWT file, verifying its checksum if it has one.
+    /// Any anomaly is an error; see `from_nwt_with` to salvage what can be
+    /// read from a damaged file
+    pub fn from_nwt(path: impl AsRef<Path>) -> Result<Self, NwtError> {
+        // an uncompressed file is streamed through a buffer, so its raw bytes
+        // are never held alongside the parsed structures; a compressed one's
+        // section is read and inflated whole before parsing
+        Self::from_nwt_with(path, &ParseOptions::default()).map(|(nwt, _)| nwt)
+    }
+
+    /// Same as `from_nwt`, failing on the first anomaly or reading past what
+    /// it can, as `opts.mode` says. Also returns the anomalies a lenient
+    /// parse read past, of which a strict one has none
+    pub fn from_nwt_with(path: impl AsRef<Path>, opts: &ParseOptions) -> Result<(Self, Vec<ParseWarning>), NwtError> {
+        Self::parse(NwtReader::open(path.as_ref(), true)?, opts)
+    }
+
+    /// Same as `from_nwt`, but skips checksum verification for speed
+    pub fn open_unchecked(path: impl AsRef<Path>) -> Result<Self, NwtError> {
+        Self::parse(NwtReader::open(path.as_ref(), false)?, &ParseOptions::default()).map(|(nwt, _)| nwt)
+    }
+
+    /// parses NWT data from any reader, verifying its checksum if it has one.
+    /// The stream is consumed front-to-back, so it does not need to support
+    /// seeking, and must end where the NWT data does
+    pub fn from_reader<R: Read>(r: R) -> Result<Self, NwtError> {
+        Self::parse(NwtReader::verifying(r), &ParseOptions::default()).map(|(nwt, _)| nwt)
+    }
+
+    /// parses NWT data held in memory, verifying its checksum if it has one
+    pub fn from_bytes(data: &[u8]) -> Result<Self, NwtError> {
+        let cursor = NwtReader::verifying(data).sized(data.len() as u64);
+        Self::parse(cursor, &ParseOptions::default()).map(|(nwt, _)| nwt)
+    }
+
+    fn parse<R: Read>(mut cursor: NwtReader<R>, opts: &ParseOptions) -> Result<(Self, Vec<ParseWarning>), NwtError> {
+        let lenient = opts.mode == ParseMode::Lenient;
+        let mut warnings = Vec::new();
+        let NwtHeader {
+            version, codec, weight_dtype, json_data, lat_len, lon_len, lookup_table, coordinates, lat_bounds, lon_bounds,
+            quantization, ..
+        } = Self::read_preamble_with(&mut cursor, opts, &mut warnings)?;
+        log::debug!("reading NWT v{} (codec {}, {:?} weights{}): {} polyids on a {}x{} grid",
+            version, codec, weight_dtype, if quantization.is_some() { ", quantized" } else { "" },
+            lookup_table.len(), lat_len, lon_len);
+
+        // and finally now that we have that, we pull all of our weight values
+        let total_points = lookup_table.iter().map(|e| e.count).sum::<u64>();
+        // reserved up front only once the points are known to be there,
+        // which a lenient parse or a stream of unknown length doesn't check
+        let capacity = if cursor.is_sized() && !lenient { to_usize(total_points)? } else { 0 };
+        let mut polyid_gridpoints = PointArena::with_capacity(lookup_table.len(), capacity);
+        let mut f64_weights = (weight_dtype == WeightDtype::F64).then(Vec::new);
+        // polyids a lenient parse couldn't read, and whether it had to stop
+        // short, leaving the position of anything further unknown
+        let mut dropped = vec![false; lookup_table.len()];
+        // including any rows beyond the names a lenient parse found, whose
+        // points are still read to reach the ones after them
+        dropped.iter_mut().skip(json_data.polyids.len()).for_each(|gone| *gone = true);
+        let mut truncated = false;
+        for (idx, &LookupEntry { count: num_coords, .. }) in lookup_table.iter().enumerate() {
+            let range = quantization.as_ref().map(|ranges| ranges[idx]);
+            let read = polyid_gridpoints.push_with(|points| {
+                read_entry(&mut cursor, points, num_coords, coordinates.as_ref(), weight_dtype, range, lenient)
+            });
+            let weights = match read {
+                Ok((weights, None)) => weights,
+                Ok((_, Some(reason))) => {
+                    let polyid = json_data.polyids.get(idx).cloned();
+                    warnings.push(ParseWarning::PolyidDropped { index: idx, polyid, reason });
+                    dropped[idx] = true;
+                    Vec::new()
                 }
-            }
+                Err(e) if lenient => {
+                    let dropped_count = lookup_table.len() - idx;
+                    warnings.push(ParseWarning::Truncated { index: idx, dropped: dropped_count, reason: e.to_string() });
+                    dropped[idx..].fill(true);
+                    truncated = true;
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
 
-            // now push the polyid entry to our lookup vector
-            polyid_gridpoints.push(curr_polyid);
+            if let Some(f64_weights) = f64_weights.as_mut() {
+                f64_weights.push(weights);
+            }
+        }
+        // anything a truncated file didn't get to is empty, and dropped
+        polyid_gridpoints.pad_to(lookup_table.len());
+        if let Some(f64_weights) = f64_weights.as_mut() {
+            f64_weights.resize_with(lookup_table.len(), Vec::new);
         }
 
-        // and finally lets build our lookup table
-        let mut lookup_table: Vec<(u64, u64)> = Vec::new();
-        let mut running_total: u64 = 0;
-        for entry in polyid_gridpoints.iter() {
-            let entry_size = entry.data.len()as u64;
-            lookup_table.push((running_total, entry.data.len() as u64));
-            running_total += entry_size;
+        // a lenient parse leaves out any section after the gridpoint data it
+        // can't read, and everything after it
+        let mut skip_section = |section: &'static str, e: NwtError, truncated: &mut bool| {
+            if !lenient {
+                return Err(e);
+            }
+            warnings.push(ParseWarning::SectionSkipped { section, reason: e.to_string() });
+            *truncated = true;
+            Ok(())
+        };
+        let mut weight_set_name = DEFAULT_WEIGHT_SET.to_string();
+        let mut weight_sets = Vec::new();
+        if let (Some(coords), true, false) = (&coordinates, version >= WEIGHT_SETS_VERSION, truncated) {
+            match weight_sets::read_weight_sets(&mut cursor, lookup_table.len() as u64, coords) {
+                Ok((name, sets)) => (weight_set_name, weight_sets) = (name, sets),
+                Err(e) => skip_section("weight sets", e, &mut truncated)?,
+            }
+        }
+        let (lat_axis, lon_axis) = match coordinates {
+            Some(coords) if coords.are_axes => (Some(coords.lats), Some(coords.lons)),
+            Some(_) => (None, None),
+            None if version >= AXES_VERSION && !truncated => match read_axes(&mut cursor, lat_len, lon_len) {
+                Ok(axes) => axes.unzip(),
+                Err(e) => {
+                    skip_section("coordinate axes", e, &mut truncated)?;
+                    (None, None)
+                }
+            },
+            None => (None, None),
+        };
+        if version >= CHECKSUM_VERSION && !truncated {
+            match cursor.finish_checksum() {
+                Ok(()) => {}
+                Err(NwtError::ChecksumMismatch { expected, got }) if lenient => {
+                    warnings.push(ParseWarning::ChecksumMismatch { expected, got });
+                }
+                Err(e) => skip_section("checksum", e, &mut truncated)?,
+            }
+        }
+        if !truncated {
+            let trailing = cursor.remaining()?;
+            if trailing != 0 && !lenient {
+                return Err(NwtError::Corrupt(format!("{} unexpected bytes after the end of the file", trailing)));
+            }
+            if trailing != 0 {
+                warnings.push(ParseWarning::TrailingBytes(trailing));
+            }
         }
 
-        // now we are done, so return ourselves
-        Ok(Self {
+        // now that we have everything, lets return stuff
+        let mut nwt = Self {
             json_data,
             lat_len,
             lon_len,
             polyid_gridpoints,
-            lookup_table
-        })
+            lookup_table,
+            lat_axis,
+            lon_axis,
+            lat_bounds,
+            lon_bounds,
+            weight_set_name,
+            weight_sets,
+            f64_weights,
+            polyid_lookup: OnceLock::new(),
+        };
+        if dropped.contains(&true) {
+            nwt.drop_unreadable(&dropped);
+        }
+        Ok((nwt, warnings))
+    }
+
+    /// removes the polyids a lenient parse flagged in `dropped`, along with
+    /// their entries in every weight set and any metadata no polyid of the
+    /// same name still needs
+    fn drop_unreadable(&mut self, dropped: &[bool]) {
+        let removed: Vec<String> = self.json_data.polyids.iter().zip(dropped)
+            .filter(|(_, &gone)| gone)
+            .map(|(name, _)| name.clone())
+            .collect();
+        // the JSON may list more polyids than the lookup table
+        let mut flags = dropped.to_vec();
+        flags.resize(self.json_data.polyids.len(), false);
+        self.json_data.polyids = rename::without(std::mem::take(&mut self.json_data.polyids), &flags);
+        for name in removed {
+            if !self.json_data.polyids.contains(&name) {
+                self.json_data.polyid_meta.remove(&name);
+            }
+        }
+        self.polyid_gridpoints = self.polyid_gridpoints.without(dropped);
+        if let Some(weights) = self.f64_weights.take() {
+            self.f64_weights = Some(rename::without(weights, dropped));
+        }
+        for set in self.weight_sets.iter_mut() {
+            set.entries = rename::without(std::mem::take(&mut set.entries), dropped);
+        }
+        self.entries_changed();
+    }
+
+    /// reads everything up to the start of the gridpoint data: the header,
+    /// the json metadata, and the lookup table
+    fn read_preamble<R: Read>(cursor: &mut NwtReader<R>) -> Result<NwtHeader, NwtError> {
+        Self::read_preamble_with(cursor, &ParseOptions::default(), &mut Vec::new())
     }
 
-    /// create new structure from .NWT file
-    pub fn from_nwt(path: impl AsRef<Path> + Clone) -> Result<Self, String> {
-        // open the file
-        let mut input_file = std::fs::File::open(path).unwrap();
-        let mut data: Vec<u8> = Vec::new();
-        input_file.read_to_end(&mut data).unwrap();
+    /// `read_preamble`, recording what a lenient parse reads past in
+    /// `warnings`
+    fn read_preamble_with<R: Read>(
+        cursor: &mut NwtReader<R>,
+        opts: &ParseOptions,
+        warnings: &mut Vec<ParseWarning>,
+    ) -> Result<NwtHeader, NwtError> {
+        let lenient = opts.mode == ParseMode::Lenient;
+        let fixed = Self::read_fixed_header(cursor)?;
+        let FixedHeader { version, codec, weight_dtype, quantized, num_polyids, lat_len, lon_len, .. } = fixed;
+        let mut json_data = Self::read_json_metadata(cursor, &fixed, lenient, warnings)?;
+        // names are matched to lookup table rows by position, so a count
+        // that disagrees would pair every name after a gap with the wrong
+        // points. A lenient parse keeps the names it has rows for, and
+        // `parse` drops the rows without names
+        let named = json_data.polyids.len();
+        if named as u64 != num_polyids && !lenient {
+            return Err(NwtError::Corrupt(format!(
+                "header declares {} polyids but the JSON metadata names {}", num_polyids, named)));
+        }
+        if named as u64 != num_polyids {
+            warnings.push(ParseWarning::PolyidCountMismatch { header: num_polyids, json: named });
+            if named as u64 > num_polyids {
+                for name in json_data.polyids.split_off(num_polyids as usize) {
+                    if !json_data.polyids.contains(&name) {
+                        json_data.polyid_meta.remove(&name);
+                    }
+                }
+            }
+        }
+        // now we get the lookup table information, checking the caps before
+        // anything compressed is inflated
+        if num_polyids > opts.max_polyids {
+            return Err(NwtError::LimitExceeded { what: "polyids", declared: num_polyids, limit: opts.max_polyids });
+        }
+        Self::read_compressed(cursor, &fixed, opts)?;
+        cursor.ensure_available(num_polyids.saturating_mul(LookupEntry::SERIALIZED_SIZE as u64), "lookup table")?;
+        let mut lookup_table: Vec<LookupEntry> = Vec::new();
+        let mut running_total: u64 = 0;
+        for _ in 0..num_polyids {
+            let LookupEntry { offset, count } = LookupEntry::read(cursor, "lookup table")?;
+            // offsets are a running sum of the preceding counts, which a
+            // lenient parse can go by instead
+            if offset != running_total && lenient {
+                warnings.push(ParseWarning::LookupOffset { index: lookup_table.len(), expected: running_total, got: offset });
+            } else if offset != running_total {
+                let error = LookupError::OffsetMismatch { index: lookup_table.len(), expected: running_total, got: offset };
+                return Err(NwtError::Corrupt(error.to_string()));
+            }
+            lookup_table.push(LookupEntry::new(running_total, count));
+            running_total = running_total.checked_add(count)
+                .ok_or_else(|| NwtError::Corrupt("lookup table point counts overflow".to_string()))?;
+        }
+        // every point must be addressable too
+        to_usize(running_total)?;
+        if running_total > opts.max_total_points {
+            return Err(NwtError::LimitExceeded { what: "gridpoints", declared: running_total, limit: opts.max_total_points });
+        }
+
+        let GridSections { coordinates, lat_bounds, lon_bounds, quantization } = Self::read_grid(cursor, &fixed)?;
+        // a lenient parse reads what there is of the points instead
+        if !lenient {
+            let point_size = NwtHeader::point_size_of(version, weight_dtype, quantized) as u64;
+            cursor.ensure_available(running_total.saturating_mul(point_size), "gridpoint data")?;
+        }
+
+        Ok(NwtHeader {
+            version, codec, weight_dtype, num_polyids, lat_len, lon_len, json_data, lookup_table, coordinates,
+            lat_bounds, lon_bounds, quantization,
+        })
+    }
 
-        let mut co: usize = 0;
-        // first check for magic
-        if &data[co..co+4] != b"NEWT" {
-            return Err("Invalid file format".to_string());
+    /// reads the fixed-size header, checking that its offsets line up
+    fn read_fixed_header<R: Read>(cursor: &mut NwtReader<R>) -> Result<FixedHeader, NwtError> {
+        // first check for magic, which tells us whether a version follows
+        let magic = cursor.read_bytes(4, "magic")?;
+        let version = if magic == LEGACY_MAGIC {
+            1
+        } else if magic == MAGIC {
+            cursor.read_u16("header")?
+        } else {
+            return Err(NwtError::InvalidMagic);
+        };
+        if version < 2 && magic == MAGIC || version > FORMAT_VERSION {
+            return Err(NwtError::UnsupportedVersion(version));
+        }
+        let codec = if version >= CODEC_VERSION { cursor.read_u16("header")? } else { options::CODEC_NONE };
+        if !options::codec_supported(codec) {
+            return Err(NwtError::UnsupportedCodec(codec));
         }
-        co += 4;
+        let (weight_dtype, quantized) = if version >= WEIGHT_DTYPE_VERSION {
+            match cursor.read_u16("header")? {
+                0 => (WeightDtype::F32, false),
+                1 => (WeightDtype::F64, false),
+                QUANTIZED_DTYPE => (WeightDtype::F32, true),
+                dtype => return Err(NwtError::Corrupt(format!("unknown weight dtype {}", dtype))),
+            }
+        } else {
+            (WeightDtype::F32, false)
+        };
 
         // now we read all the crap we need
-        let mut u64_buff = [0u8; size_of::<u64>()];
-        // json_strlen
-        u64_buff.copy_from_slice(&data[co..co+8]);
-        let json_len = u64::from_le_bytes(u64_buff);
-        co += 8;
-        // number of polyids
-        u64_buff.copy_from_slice(&data[co..co+8]);
-        let num_polyids = u64::from_le_bytes(u64_buff);
-        co += 8;
-        // latitude length
-        u64_buff.copy_from_slice(&data[co..co+8]);
-        let lat_len = u64::from_le_bytes(u64_buff);
-        co += 8;
-        // longitude length
-        u64_buff.copy_from_slice(&data[co..co+8]);
-        let lon_len = u64::from_le_bytes(u64_buff);
-        co += 8;
-        // json string offset
-        u64_buff.copy_from_slice(&data[co..co+8]);
-        let json_offset = u64::from_le_bytes(u64_buff);
-        co += 8;
-        // lookup offset
-        u64_buff.copy_from_slice(&data[co..co+8]);
-        let lookup_offset = u64::from_le_bytes(u64_buff);
-        
-        // json data
-        let mut string_buffer: Vec<u8> = vec![0u8;json_len as usize];
-        string_buffer.copy_from_slice(&data[(json_offset as usize )..(json_offset+json_len) as usize]);
-        let json_dat_str = String::from_utf8(string_buffer).unwrap();
-        let json_data = serde_json::from_str(&json_dat_str).unwrap();
-        
-        // now we get the lookup table information
-        let mut lookup_table_bytes: Vec<u8> = vec![0u8;num_polyids as usize * size_of::<(u64,u64)>()]; //Vec::with_capacity(num_polyids as usize * size_of::<u64>());
-        lookup_table_bytes.copy_from_slice(
-            &data[
-                (lookup_offset as usize) ..
-                (lookup_offset as usize + num_polyids as usize *size_of::<(u64,u64)>())
-                ]);
-
-        let mut lookup_table: Vec<(u64,u64)> = Vec::new();
-        for i in (0..lookup_table_bytes.len()).step_by(16) {
-            u64_buff.copy_from_slice(&lookup_table_bytes[i..i+8]);
-            let tmp_u64 = u64::from_le_bytes(u64_buff);
-            u64_buff.copy_from_slice(&lookup_table_bytes[i+8..i+16]);
-            let count_u64 = u64::from_le_bytes(u64_buff);
-            lookup_table.push((tmp_u64, count_u64));
-        }
-        
-        // and finally now that we have that, we pull all of our weight values
-        let mut polyid_gridpoints: Vec<PolyidEntry> = Vec::new();
-        co = lookup_offset as usize + num_polyids as usize * size_of::<(u64,u64)>();
-        for i in 0..num_polyids {
-            // read in the number of grid coordinates we are to expect
-            let num_coords = lookup_table[i as usize].1;
-            let mut buff_32 = [0u8; size_of::<f32>()];
-            let mut curr_polyid = PolyidEntry::new();
-            for _ in 0..num_coords {
-                // read lat index
-                buff_32.copy_from_slice(&data[co..co+4]);
-                co += 4;
-                let lat_idx = u32::from_le_bytes(buff_32);
-                // read lon index
-                buff_32.copy_from_slice(&data[co..co+4]);
-                co += 4;
-                let lon_idx = u32::from_le_bytes(buff_32);
-                // read actual latitude
-                buff_32.copy_from_slice(&data[co..co+4]);
-                co += 4;
-                let lat = f32::from_le_bytes(buff_32);
-                // read actual longitude
-                buff_32.copy_from_slice(&data[co..co+4]);
-                co += 4;
-                let lon = f32::from_le_bytes(buff_32);
-                // read weight
-                buff_32.copy_from_slice(&data[co..co+4]);
-                co += 4;
-                let weight = f32::from_le_bytes(buff_32);
-
-                // and add it to our list
-                curr_polyid.add_point(lat_idx, lon_idx, lat, lon, weight);
-            }
-
-            // add the polyid to our polyid gridpoitns
-            polyid_gridpoints.push(curr_polyid);
+        let json_len = cursor.read_u64("header")?;
+        let num_polyids = cursor.read_u64("header")?;
+        let lat_len = cursor.read_u64("header")?;
+        let lon_len = cursor.read_u64("header")?;
+        let json_offset = cursor.read_u64("header")?;
+        let lookup_offset = cursor.read_u64("header")?;
+        grid_cells(lat_len, lon_len)?;
+        to_usize(json_offset)?;
+        to_usize(lookup_offset)?;
+
+        // before trusting any of the offsets, make sure they actually line up
+        // with each other: header, then json, then lookup table
+        let header_end = cursor.position();
+        if json_offset < header_end {
+            return Err(NwtError::Corrupt(format!(
+                "json offset {} points inside the header ({} bytes)", json_offset, header_end)));
         }
+        if json_offset.checked_add(json_len) != Some(lookup_offset) {
+            return Err(NwtError::Corrupt(format!(
+                "lookup offset {} does not follow json block ({} + {})", lookup_offset, json_offset, json_len)));
+        }
+        Ok(FixedHeader {
+            version, codec, weight_dtype, quantized, json_len, num_polyids, lat_len, lon_len, json_offset, lookup_offset,
+        })
+    }
 
+    /// reads the JSON metadata that follows the fixed header, recording the
+    /// fields a lenient parse ignores in `warnings`
+    fn read_json_metadata<R: Read>(
+        cursor: &mut NwtReader<R>,
+        fixed: &FixedHeader,
+        lenient: bool,
+        warnings: &mut Vec<ParseWarning>,
+    ) -> Result<JsonData, NwtError> {
+        let &FixedHeader { version, json_len, json_offset, .. } = fixed;
+        // json data. Everything from here on is covered by the checksum
+        cursor.skip_to(json_offset, "json metadata")?;
+        if version >= CHECKSUM_VERSION {
+            cursor.start_checksum();
+        }
+        // sizes are checked against what's left of the file before anything
+        // is allocated for them
+        cursor.ensure_available(json_len, "json metadata")?;
+        let json_bytes = cursor.read_bytes(to_usize(json_len)?, "json metadata")?;
+        let (json_data, known_fields) = if version >= TYPED_ATTRS_VERSION {
+            (serde_json::from_slice(&json_bytes)?, JSON_FIELDS)
+        } else {
+            (serde_json::from_slice::<LegacyJsonData>(&json_bytes)?.into(), &JSON_FIELDS[..3])
+        };
+        let fields = serde_json::from_slice::<BTreeMap<String, serde::de::IgnoredAny>>(&json_bytes)?;
+        for field in fields.into_keys().filter(|field| !known_fields.contains(&field.as_str())) {
+            if !lenient {
+                return Err(NwtError::Corrupt(format!("unknown JSON field {}", field)));
+            }
+            warnings.push(ParseWarning::UnknownJsonField(field));
+        }
+        Ok(json_data)
+    }
 
-        // now that we have everything, lets return stuff
+    /// A compressed lookup table and gridpoint section is stored as its
+    /// length followed by the compressed bytes. Decodes it up front, for
+    /// the rest to be read from there, refusing to inflate it past what the
+    /// header and `opts` allow
+    fn read_compressed<R: Read>(cursor: &mut NwtReader<R>, fixed: &FixedHeader, opts: &ParseOptions) -> Result<(), NwtError> {
+        if fixed.codec != options::CODEC_NONE {
+            let compressed_len = cursor.read_u64("compressed data")?;
+            let compressed = cursor.read_bytes(to_usize(compressed_len)?, "compressed data")?;
+            let limit = Self::max_payload_len(fixed, opts);
+            cursor.set_payload(options::decompress(fixed.codec, &compressed, limit)?, fixed.lookup_offset);
+        }
+        Ok(())
+    }
 
-        Ok(Self { json_data, lat_len, lon_len, polyid_gridpoints, lookup_table })
+    /// the most a compressed section can hold: the lookup table, the grid
+    /// sections and axes the header's dimensions size, and at most
+    /// `max_total_points` points' worth of gridpoint data and weight sets
+    fn max_payload_len(fixed: &FixedHeader, opts: &ParseOptions) -> u64 {
+        let &FixedHeader { version, weight_dtype, quantized, num_polyids, lat_len, lon_len, .. } = fixed;
+        let axes = lat_len.saturating_add(lon_len).saturating_mul(size_of::<f32>() as u64);
+        let point_size = NwtHeader::point_size_of(version, weight_dtype, quantized) as u64;
+        let sections = [
+            num_polyids.saturating_mul(LookupEntry::SERIALIZED_SIZE as u64),
+            // the coordinates, or the axes after the points before them
+            1 + axes,
+            if version >= BOUNDS_VERSION { 1 + axes.saturating_mul(2) } else { 0 },
+            if quantized { num_polyids.saturating_mul(2 * size_of::<f32>() as u64) } else { 0 },
+            opts.max_total_points.saturating_mul(point_size),
+        ];
+        sections.into_iter().fold(0, u64::saturating_add)
+    }
 
+    /// reads the sections between the lookup table and the gridpoint data:
+    /// the coordinates, the cell bounds and the quantization ranges, as far
+    /// as the file's version has them
+    fn read_grid<R: Read>(cursor: &mut NwtReader<R>, fixed: &FixedHeader) -> Result<GridSections, NwtError> {
+        let &FixedHeader { version, quantized, num_polyids, lat_len, lon_len, .. } = fixed;
+        // from version 7, the lat/lon of every index, which the points only
+        // refer to
+        let coordinates = if version >= COMPACT_POINTS_VERSION {
+            let are_axes = match cursor.read_bytes(1, "coordinates")?[0] {
+                0 => false,
+                1 => true,
+                flag => return Err(NwtError::Corrupt(format!("invalid coordinates flag {}", flag))),
+            };
+            let lats = read_f32s(cursor, lat_len, "coordinates")?;
+            let lons = read_f32s(cursor, lon_len, "coordinates")?;
+            Some(Coordinates { lats, lons, are_axes })
+        } else {
+            None
+        };
+        // from version 9, the cell bounds along either axis, if known
+        let (lat_bounds, lon_bounds) = if version >= BOUNDS_VERSION {
+            read_bounds(cursor, lat_len, lon_len)?
+        } else {
+            (None, None)
+        };
 
+        // and for quantized files, the (min, max) of every polyid's weights
+        let quantization = if quantized {
+            let ranges = (0..num_polyids)
+                .map(|_| Ok((cursor.read_f32("quantization ranges")?, cursor.read_f32("quantization ranges")?)))
+                .collect::<Result<Vec<_>, NwtError>>()?;
+            Some(ranges)
+        } else {
+            None
+        };
+        Ok(GridSections { coordinates, lat_bounds, lon_bounds, quantization })
     }
 
     /// Generically opens a weight file. If it is a NetCDF file, it is converted 
     /// to the NWT format. Otherwise it is opened as standard
-    pub fn open(path: impl AsRef<Path> + Clone) -> Result<Self, String> {
-        let mut data = [0u8; 4];
-        // scope brackets here to make sure `input_file` is closed before opening
-        {
-            let mut input_file = std::fs::File::open(&path).unwrap();
-            input_file.read(&mut data).unwrap();
+    ///
+    /// NetCDF variables and dimensions missing under their usual names are
+    /// looked up under a few common aliases (`region_id`, `weights`,
+    /// `latitude`, `longitude`, ...). The converted file is cached next to
+    /// the source as `<path>.nwt`, if it can be written. Use `open_with` to
+    /// name the variables exactly or cache somewhere else.
+    /// Without the `netcdf` feature, anything but an NWT file is an
+    /// `InvalidMagic` error
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, NwtError> {
+        if has_nwt_magic(path.as_ref())? {
+            return Self::from_nwt(path);
         }
+        #[cfg(feature = "netcdf")]
+        { Self::convert_and_cache(path.as_ref(), &OpenOptions::default(), |_| {}) }
+        #[cfg(not(feature = "netcdf"))]
+        { Err(NwtError::InvalidMagic) }
+    }
 
-        // first check for magic
-        if &data[0..4] == b"NEWT" {
-            Self::from_nwt(path)
-        } else {
-            let t_path = path.clone();
-            let name = t_path.as_ref().to_str().unwrap();
-            let a = Self::from_weight_file(path)?;
-            let new_path = PathBuf::from_str(&format!("{}.nwt", name)[..]).unwrap();
-            println!("[libNextWeightFile] Serializing new weight file to {}. Use this next time to avoid precomputation step", new_path.display());
-            a.serialize_to_file(Some(new_path.to_str().unwrap().to_string()))?;
-            Ok(a)
-        }
+    /// serializes the new weight file to disk, returning the path written.
+    /// An existing file is an `AlreadyExists` error; see
+    /// `serialize_to_file_with` to replace it. The file is written under a
+    /// temporary name and renamed into place once complete, so an
+    /// interrupted write never leaves a partial file behind
+    pub fn serialize_to_file(&self, path: impl AsRef<Path>) -> Result<PathBuf, NwtError> {
+        self.serialize_to_file_with(path, &WriteOptions::default())
     }
 
-    /// Returns a dummy weight file
-    pub fn dummy(input_file: impl AsRef<Path> + Clone) -> Result<Self, String> {
-        let weight_netcdf = netcdf::open(input_file).unwrap();
-        let mut json_data = JsonData::new();
+    /// serializes the weight file to disk with non-default options, e.g.
+    /// compression or overwriting
+    pub fn serialize_to_file_with(&self, path: impl AsRef<Path>, opts: &WriteOptions) -> Result<PathBuf, NwtError> {
+        let path = path.as_ref();
+        // write it through a temporary file. Buffered, so the many small
+        // header writes don't each turn into a syscall
+        atomic::write_atomically(path, opts.overwrite, |output_file| {
+            self.serialize_to_writer_with(output_file, opts)?;
+            Ok(())
+        })?;
+        Ok(path.to_path_buf())
+    }
 
+    /// serializes the weight file in NWT format to any writer, returning the
+    /// number of bytes written
+    pub fn serialize_to_writer<W: Write>(&self, w: &mut W) -> Result<u64, NwtError> {
+        self.serialize_to_writer_with(w, &WriteOptions::default())
+    }
 
-        for attr in weight_netcdf.attributes() {
-            let attr_value = match attr.value().unwrap() {
-                AttributeValue::Str(a) => a,
-                AttributeValue::Strs(a) => a[0].clone(),
-                AttributeValue::Double(a) => format!("{}", a),
-                AttributeValue::Doubles(a) => format!("{:?}", a),
-                AttributeValue::Float(a) => format!("{}", a),
-                AttributeValue::Floats(a) => format!("{:?}", a),
-                AttributeValue::Int(a) => format!("{}", a),
-                AttributeValue::Ints(a) => format!("{:?}", a),
-                AttributeValue::Longlong(a) => format!("{}", a),
-                AttributeValue::Longlongs(a) => format!("{:?}", a),
-                AttributeValue::Schar(a) => format!("{}", a),
-                AttributeValue::Schars(a) => format!("{:?}", a),
-                AttributeValue::Short(a) => format!("{}", a),
-                AttributeValue::Shorts(a) => format!("{:?}", a),
-                AttributeValue::Uchar(a) => format!("{}", a),
-                AttributeValue::Uchars(a) => format!("{:?}",a),
-                AttributeValue::Uint(a) => format!("{}", a),
-                AttributeValue::Uints(a) => format!("{:?}", a),
-                AttributeValue::Ulonglong(a) => format!("{}",a),
-                AttributeValue::Ulonglongs(a) => format!("{:?}", a),
-                AttributeValue::Ushort(a) => format!("{}",a),
-                AttributeValue::Ushorts(a) => format!("{:?}", a)
-            };
-            // add it to our list of global attributes
-            json_data.add_global_attr(attr.name().to_string(), attr_value);
-        }
-
-        // ... and add all of the variable attributes ...
-        for var in weight_netcdf.variables() {
-            let var_name = var.name();
-            json_data.add_variable(&var_name);
-            for attr in var.attributes() {
-                if attr.name() != "_FillValue" {
-                    let attr_value = match attr.value().unwrap() {
-                        AttributeValue::Str(a) => a,
-                        AttributeValue::Strs(a) => a[0].clone(),
-                        AttributeValue::Double(a) => format!("{}", a),
-                        AttributeValue::Doubles(a) => format!("{:?}", a),
-                        AttributeValue::Float(a) => format!("{}", a),
-                        AttributeValue::Floats(a) => format!("{:?}", a),
-                        AttributeValue::Int(a) => format!("{}", a),
-                        AttributeValue::Ints(a) => format!("{:?}", a),
-                        AttributeValue::Longlong(a) => format!("{}", a),
-                        AttributeValue::Longlongs(a) => format!("{:?}", a),
-                        AttributeValue::Schar(a) => format!("{}", a),
-                        AttributeValue::Schars(a) => format!("{:?}", a),
-                        AttributeValue::Short(a) => format!("{}", a),
-                        AttributeValue::Shorts(a) => format!("{:?}", a),
-                        AttributeValue::Uchar(a) => format!("{}", a),
-                        AttributeValue::Uchars(a) => format!("{:?}",a),
-                        AttributeValue::Uint(a) => format!("{}", a),
-                        AttributeValue::Uints(a) => format!("{:?}", a),
-                        AttributeValue::Ulonglong(a) => format!("{}",a),
-                        AttributeValue::Ulonglongs(a) => format!("{:?}", a),
-                        AttributeValue::Ushort(a) => format!("{}",a),
-                        AttributeValue::Ushorts(a) => format!("{:?}", a)
-                    };
-                    // add it to our list of global attributes
-                    json_data.add_variable_attr(&var_name, attr.name().to_string(), attr_value);
+    /// returns the weight file in NWT format, exactly as `serialize_to_file`
+    /// would write it. The whole file is held in memory, so prefer
+    /// `serialize_to_writer` to stream large files. Fails only if the stored
+    /// axes don't match the grid dimensions
+    pub fn to_bytes(&self) -> Result<Vec<u8>, NwtError> {
+        let mut buff = Vec::new();
+        self.serialize_to_writer(&mut buff)?;
+        Ok(buff)
+    }
+
+    /// serializes the weight file in NWT format to any writer with non-default
+    /// options, returning the number of bytes written. A compressed file is
+    /// assembled in memory before it is compressed
+    pub fn serialize_to_writer_with<W: Write>(&self, w: &mut W, opts: &WriteOptions) -> Result<u64, NwtError> {
+        if !(OLDEST_WRITABLE_VERSION..=FORMAT_VERSION).contains(&opts.version) {
+            return Err(NwtError::InvalidInput(format!(
+                "can't write NWT format version {}, only {} through {}", opts.version, OLDEST_WRITABLE_VERSION, FORMAT_VERSION)));
+        }
+        if let (Some(lats), Some(lons)) = (&self.lat_axis, &self.lon_axis) {
+            if lats.len() as u64 != self.lat_len || lons.len() as u64 != self.lon_len {
+                return Err(NwtError::InvalidInput(format!(
+                    "coordinate axes have lengths {}x{} but the grid is {}x{}", lats.len(), lons.len(), self.lat_len, self.lon_len)));
+            }
+        }
+        for (name, bounds, len) in [("latitude", &self.lat_bounds, self.lat_len), ("longitude", &self.lon_bounds, self.lon_len)] {
+            if let Some(bounds) = bounds {
+                if bounds.len() as u64 != len {
+                    return Err(NwtError::InvalidInput(format!(
+                        "{} bounds have length {} but the grid has {}", name, bounds.len(), len)));
+                }
+                if opts.version < BOUNDS_VERSION {
+                    return Err(NwtError::InvalidInput(format!("cell bounds need format version {} or later", BOUNDS_VERSION)));
                 }
             }
         }
-
-        let polyid_var = weight_netcdf.variable("polyid").unwrap();
-        for polyid in 0..polyid_var.len() {
-            json_data.add_polyid(polyid_var.get_string(polyid).unwrap());
+        if !self.weight_sets.is_empty() && opts.version < WEIGHT_SETS_VERSION {
+            return Err(NwtError::InvalidInput(format!(
+                "more than one weight set needs format version {} or later", WEIGHT_SETS_VERSION)));
+        }
+        if self.f64_weights.is_some() && opts.version < WEIGHT_DTYPE_VERSION {
+            return Err(NwtError::InvalidInput(format!(
+                "double-precision weights need format version {} or later; call set_weight_dtype(WeightDtype::F32) to round them",
+                WEIGHT_DTYPE_VERSION)));
+        }
+        if opts.quantize && opts.version < WEIGHT_DTYPE_VERSION {
+            return Err(NwtError::InvalidInput(format!(
+                "quantized weights need format version {} or later", WEIGHT_DTYPE_VERSION)));
+        }
+        // worked out before anything is written, as the points may not fit them
+        let coordinates = if opts.version >= COMPACT_POINTS_VERSION { Some(self.coordinates()?) } else { None };
+        let quantization = if opts.quantize { Some(self.quantization()?) } else { None };
+
+        log::debug!("serializing {} polyids and {} points as NWT v{} ({:?}{})",
+            self.len(), self.lookup_table.iter().map(|e| e.count).sum::<u64>(), opts.version, opts.compression,
+            if opts.quantize { ", quantized" } else { "" });
+        // first we write some of the important things we need in the header.
+        // A quantized file records how, and how far off its weights may be
+        let serialized_dat = match &quantization {
+            Some((_, max_error)) => {
+                let mut json_data = self.json_data.clone();
+                json_data.set_global_attr("nwt_quantization", "u16 per-polyid min/max");
+                json_data.set_global_attr("nwt_quantization_max_error", *max_error);
+                serde_json::to_string(&json_data)?
+            }
+            None => serde_json::to_string(&self.json_data)?,
         };
+        let dtype = if opts.quantize { QUANTIZED_DTYPE } else { self.weight_dtype() as u16 };
+        let json_offset = write_header(w, opts.version, opts.compression.codec(), dtype, &serialized_dat,
+            self.json_data.polyids.len() as u64, self.lat_len, self.lon_len)?;
+        // everything after the header goes through the checksum
+        let mut out = ChecksumWriter::new(w);
 
+        // the actual json data
+        out.write_all(serialized_dat.as_bytes())?;
 
-        Ok(Self { 
-            json_data, 
-            lat_len: 0, 
-            lon_len: 0, 
-            polyid_gridpoints: Vec::new(), 
-            lookup_table: Vec::new() 
-        })
-    }
+        // then the lookup table and points, either directly or compressed
+        let ranges = quantization.as_ref().map(|(ranges, _)| &ranges[..]);
+        if opts.compression == Compression::None {
+            self.write_body(&mut out, opts.version, coordinates.as_ref(), ranges)?;
+        } else {
+            let mut body = Vec::new();
+            self.write_body(&mut body, opts.version, coordinates.as_ref(), ranges)?;
+            let compressed = options::compress(opts.compression, body)?;
+            write_u64_le(&mut out, compressed.len() as u64)?;
+            out.write_all(&compressed)?;
+        }
 
-    /// serializes the new weight file to disk
-    pub fn serialize_to_file(&self, filename: Option<String>) -> Result<(), String> {
-        // first determine our filename. Default is "test.nwt"
-        let fname = match filename {
-            Some(a) => a,
-            None => "test.nwt".to_string()
-        };
-        // then lets create/open our file
-        let mut output_file = match std::fs::File::create(fname) {
-            Ok(a) => a,
-            Err(e) => return Err(format!("Failed to open file for serialization: {}",e))
-        };
+        // and the checksum trailer
+        let (body_len, checksum) = out.finish();
+        write_u32_le(w, checksum)?;
+        log::debug!("wrote {} bytes, checksum {:08x}", json_offset + body_len + 4, checksum);
 
-        // first we write some of the important things we need in the header
-        let serialized_dat = serde_json::to_string(&self.json_data).unwrap();
-        // magic bytes
-        output_file.write(b"NEWT").unwrap();
-        // u64: length of json string
-        output_file.write(&(serialized_dat.len() as u64).to_le_bytes()).unwrap();
-        // u64: number of polyids
-        output_file.write(&(self.json_data.polyids.len() as u64).to_le_bytes()).unwrap();
-        // u64: latitude length
-        output_file.write(&self.lat_len.to_le_bytes()).unwrap();
-        // u64: longitude length
-        output_file.write(&self.lon_len.to_le_bytes()).unwrap();
-        // beginning of json attributes string
-        let json_offset = size_of::<u64>() * 6 + 4;
-        output_file.write(&json_offset.to_le_bytes()).unwrap();
-        // beginning of lookup vector
-        let lookup_offset = json_offset + serialized_dat.len();
-        output_file.write(&lookup_offset.to_le_bytes()).unwrap();
-        // the actual json data
-        write!(output_file, "{}", serialized_dat).unwrap();
+        Ok(json_offset + body_len + 4)
+    }
 
+    /// writes the lookup table and gridpoint data. With `coordinates` (format
+    /// 7 on), those are written once and the points only refer to them. With
+    /// `quantization`, each weight is a u16 scaled against its polyid's range
+    fn write_body<W: Write>(
+        &self,
+        w: &mut W,
+        version: u16,
+        coordinates: Option<&Coordinates>,
+        quantization: Option<&[(f32, f32)]>,
+    ) -> std::io::Result<()> {
         // next we build our lookup table
-        for v in self.lookup_table.iter() {
-            output_file.write(&v.0.to_le_bytes()).unwrap();
-            output_file.write(&v.1.to_le_bytes()).unwrap();
+        for entry in self.lookup_table.iter() {
+            entry.write(w)?;
+        }
+
+        if let Some(coords) = coordinates {
+            w.write_all(&[coords.are_axes as u8])?;
+            for &v in coords.lats.iter().chain(coords.lons.iter()) {
+                write_f32_le(w, v)?;
+            }
+        }
+        if version >= BOUNDS_VERSION {
+            write_bounds(w, self.lat_bounds.as_deref(), self.lon_bounds.as_deref())?;
+        }
+        if let Some(ranges) = quantization {
+            for &(min, max) in ranges.iter() {
+                write_f32_le(w, min)?;
+                write_f32_le(w, max)?;
+            }
         }
 
-        // and finally we can now serialize all data
-        for d in self.polyid_gridpoints.iter() {
+        // and finally we can now serialize all data. Each entry is packed into
+        // a reusable buffer first so it goes out in a single write
+        let mut entry_buff: Vec<u8> = Vec::new();
+        for (idx, d) in self.polyid_gridpoints.iter().enumerate() {
+            entry_buff.clear();
+            let f64_weights = self.f64_weights.as_ref().map(|weights| &weights[idx]);
+            let range = quantization.map(|ranges| ranges[idx]);
             // and then the values
-            for v in d.data.iter() {
-                output_file.write(&v.0.to_le_bytes()).unwrap();
-                output_file.write(&v.1.to_le_bytes()).unwrap();
-                output_file.write(&v.2.to_le_bytes()).unwrap();
-                output_file.write(&v.3.to_le_bytes()).unwrap();
-                output_file.write(&v.4.to_le_bytes()).unwrap();
+            for (point, v) in d.iter().enumerate() {
+                let weight = f64_weights.map_or(v.4 as f64, |weights| weights[point]);
+                encode_point(&mut entry_buff, v, weight, coordinates.is_some(), self.weight_dtype(), range)?;
             }
+            w.write_all(&entry_buff)?;
+        }
+        if version >= WEIGHT_SETS_VERSION {
+            weight_sets::write_weight_sets(w, &self.weight_set_name, &self.weight_sets)?;
         }
 
-        Ok(())
+        // the coordinates already said all there is to say about the axes
+        if coordinates.is_some() {
+            return Ok(());
+        }
+        write_axes(w, self.lat_axis.as_deref().zip(self.lon_axis.as_deref()))
+    }
+
+    /// the lat/lon of every index as a format 7 file stores them: the
+    /// coordinate axes if there are any, else rebuilt from the points (NaN
+    /// where there's no point). Fails if a point's lat/lon differ from them,
+    /// since only its indices are written
+    fn coordinates(&self) -> Result<Coordinates, NwtError> {
+        let (lats, lons) = self.axis_values();
+        let coords = Coordinates {
+            lats: lats.into_iter().map(|v| v.unwrap_or(f32::NAN)).collect(),
+            lons: lons.into_iter().map(|v| v.unwrap_or(f32::NAN)).collect(),
+            are_axes: self.lat_axis.is_some() && self.lon_axis.is_some(),
+        };
+        for (name, points) in self.iter() {
+            for p in points {
+                match coords.get(p.0, p.1) {
+                    Some((lat, lon)) if lat.to_bits() == p.2.to_bits() && lon.to_bits() == p.3.to_bits() => {}
+                    _ => return Err(NwtError::InvalidInput(format!(
+                        "polyid {} has a point at ({}, {}) whose lat/lon don't match the grid's; write format version {} to keep them",
+                        name, p.0, p.1, OLDEST_WRITABLE_VERSION))),
+                }
+            }
+        }
+        for set in self.weight_sets.iter() {
+            for (name, entry) in self.json_data.polyids.iter().zip(set.entries.iter()) {
+                let matches = |p: &&GridPoint| matches!(coords.get(p.0, p.1),
+                    Some((lat, lon)) if lat.to_bits() == p.2.to_bits() && lon.to_bits() == p.3.to_bits());
+                if let Some(p) = entry.data.iter().find(|p| !matches(p)) {
+                    return Err(NwtError::InvalidInput(format!(
+                        "weight set {} has a point of polyid {} at ({}, {}) whose lat/lon don't match the grid's",
+                        set.name, name, p.0, p.1)));
+                }
+            }
+        }
+        Ok(coords)
+    }
 
+    /// the (min, max) each polyid's weights are quantized against, and the
+    /// largest error that leaves any weight with, relative to the largest
+    /// weight magnitude of its polyid
+    fn quantization(&self) -> Result<(Vec<(f32, f32)>, f64), NwtError> {
+        let mut ranges = Vec::with_capacity(self.len());
+        let mut max_error: f64 = 0.0;
+        for (idx, name) in self.json_data.polyids.iter().enumerate() {
+            let weights: Vec<f64> = self.entry_f64(idx).into_iter().map(|p| p.4).collect();
+            if let Some(bad) = weights.iter().find(|w| !w.is_finite()) {
+                return Err(NwtError::InvalidInput(format!("polyid {} has weight {}, which can't be quantized", name, bad)));
+            }
+            let (range, error) = quantization_range(&weights);
+            max_error = max_error.max(error);
+            ranges.push(range);
+        }
+        Ok((ranges, max_error))
     }
 
     /// Returns all global attributes in the file
-    pub fn get_global_attrs(&self) -> &Vec<(String, String)> {
+    pub fn get_global_attrs(&self) -> &Vec<(String, AttrVal)> {
         &self.json_data.global_attrs
     }
 
+    /// Returns the file's attributes and polyid names
+    pub fn metadata(&self) -> &JsonData {
+        &self.json_data
+    }
+
+    /// Gives access to the attributes for editing, which serializing
+    /// afterwards writes out. The polyids can't be changed through it, as
+    /// the file keeps them in step with their entries: add them through
+    /// `add_polyid_entry`
+    pub fn metadata_mut(&mut self) -> &mut JsonData {
+        &mut self.json_data
+    }
+
+    /// Sets a metadata value of a polyid, replacing any under the same key.
+    /// Fails if `polyid` isn't in the file
+    pub fn set_polyid_meta(&mut self, polyid: &str, key: impl Into<String>, value: impl Into<String>) -> Result<(), NwtError> {
+        if self.polyid_index(polyid).is_none() {
+            return Err(NwtError::PolyidNotFound(polyid.to_string()));
+        }
+        self.json_data.polyid_meta.entry(polyid.to_string()).or_default().insert(key.into(), value.into());
+        Ok(())
+    }
+
+    /// Retrieves a metadata value of a polyid
+    pub fn get_polyid_meta(&self, polyid: &str, key: &str) -> Option<&str> {
+        self.json_data.polyid_meta.get(polyid)?.get(key).map(String::as_str)
+    }
+
     /// Returns all attributes associated with a given variable
-    pub fn get_var_attrs(&self, var: String) -> Option<&Vec<(String,String)>> {
-        self.json_data.per_variable_attrs.get(&var)
+    pub fn get_var_attrs(&self, var: &str) -> Option<&Vec<(String, AttrVal)>> {
+        self.json_data.per_variable_attrs.get(var)
     }
 
     /// Returns a list of polyids 
@@ -453,189 +1104,2040 @@ impl NextWeightFile {
         &self.json_data.polyids
     }
 
-    /// Returns a reference to all grid points in the weight file
+    /// Returns a reference to all grid points in the weight file. The weights
+    /// of a double-precision file are rounded to f32; see
+    /// `get_gridpoints_f64` for the full values. The points are stored in
+    /// one buffer, which the first call copies out into `PolyidEntry`s;
+    /// prefer `entry_points` or `iter_points`, which don't
     pub fn get_gridpoints(&self) -> &Vec<PolyidEntry> {
-        &self.polyid_gridpoints
+        self.polyid_gridpoints.entries()
     }
 
-    /// Returns a reference to the data lookup table
-    pub fn get_lookup_table(&self) -> &Vec<(u64,u64)> {
-        &self.lookup_table
+    /// Returns the points of the polyid at `idx` in `get_polyids()` order,
+    /// without copying them. Panics if `idx` is out of range
+    pub fn entry_points(&self, idx: usize) -> &[GridPoint] {
+        &self.polyid_gridpoints[idx]
     }
 
-    /// Returns the dimensions of the weight file
-    pub fn get_dimensions(&self) -> (u64, u64) {
-        (self.lat_len, self.lon_len)
+    /// How the weights are stored: `F64` for files converted from
+    /// double-precision sources (or built with f64 weights)
+    pub fn weight_dtype(&self) -> WeightDtype {
+        if self.f64_weights.is_some() { WeightDtype::F64 } else { WeightDtype::F32 }
+    }
+
+    /// Returns every polyid's points with double-precision weights, in
+    /// `get_polyids()` order. The weights of an f32 file are widened
+    pub fn get_gridpoints_f64(&self) -> Vec<Vec<GridPointF64>> {
+        (0..self.polyid_gridpoints.len()).map(|idx| self.entry_f64(idx)).collect()
     }
 
-    /// Returns a raw representation of gridpoints. 
-    pub fn get_raw_gridpoints(&self) -> Vec<(u32, u32, f32, f32, f32)> {
-        let mut ret = Vec::new();
+    /// Returns the points of a polyid with double-precision weights, as
+    /// `get_entry` does
+    pub fn get_entry_f64(&self, polyid: &str) -> Option<Vec<GridPointF64>> {
+        self.polyid_index(polyid).map(|idx| self.entry_f64(idx))
+    }
 
-        // loop over each gridpoint
-        for region in self.polyid_gridpoints.iter() {
-            for point in region.data.iter() {
-                ret.push((
-                    point.0,
-                    point.1,
-                    point.2,
-                    point.3,
-                    point.4
-                ))
-            }
+    fn entry_f64(&self, idx: usize) -> Vec<GridPointF64> {
+        let data = &self.polyid_gridpoints[idx];
+        match &self.f64_weights {
+            Some(weights) => data.iter().zip(weights[idx].iter()).map(|(p, &w)| (p.0, p.1, p.2, p.3, w)).collect(),
+            None => data.iter().map(|p| (p.0, p.1, p.2, p.3, p.4 as f64)).collect(),
         }
-
-        ret
     }
-}
 
+    /// Switches how the weights are stored. Going to `F32` rounds away the
+    /// double-precision values for good; going to `F64` widens the current
+    /// f32 weights
+    pub fn set_weight_dtype(&mut self, dtype: WeightDtype) {
+        self.f64_weights = match dtype {
+            WeightDtype::F32 => None,
+            WeightDtype::F64 => Some(self.f64_weights.take().unwrap_or_else(|| {
+                self.polyid_gridpoints.iter().map(|points| points.iter().map(|p| p.4 as f64).collect()).collect()
+            })),
+        };
+    }
 
-impl JsonData {
-    /// creates a new instance of `JsonData`
-    pub fn new() -> Self {
-        Self {
-            global_attrs: Vec::new(),
-            per_variable_attrs: HashMap::new(),
-            polyids: Vec::new()
-        }
+    /// Returns the index of a polyid in `get_polyids()`. If the file contains
+    /// the same polyid more than once, the first occurrence wins
+    pub fn polyid_index(&self, polyid: &str) -> Option<usize> {
+        let lookup = self.polyid_lookup.get_or_init(|| {
+            let mut lookup = HashMap::with_capacity(self.json_data.polyids.len());
+            for (idx, name) in self.json_data.polyids.iter().enumerate() {
+                lookup.entry(name.clone()).or_insert(idx);
+            }
+            lookup
+        });
+        lookup.get(polyid).copied()
     }
 
-    /// adds a global attribute to the structure
-    pub fn add_global_attr(&mut self, key: String, value: String) {
-        self.global_attrs.push((key, value));
+    /// Returns the grid points of the polyid with the given name. Duplicate
+    /// polyids resolve to the first occurrence, as in `polyid_index`. Like
+    /// `get_gridpoints`, this copies every entry out on first use; prefer
+    /// `entry_points` with `polyid_index`
+    pub fn get_entry(&self, polyid: &str) -> Option<&PolyidEntry> {
+        self.polyid_index(polyid).and_then(|idx| self.polyid_gridpoints.entries().get(idx))
     }
 
-    /// adds a new variable to the structure
-    pub fn add_variable(&mut self, variable_name: &String) {
-        self.per_variable_attrs.insert(variable_name.clone(), Vec::new());
+    /// Appends a new polyid and its entry to the end of the file, with an
+    /// empty entry in any other weight sets
+    pub fn add_polyid_entry(&mut self, name: String, entry: PolyidEntry) {
+        self.json_data.add_polyid(name);
+        if let Some(weights) = self.f64_weights.as_mut() {
+            weights.push(entry.data.iter().map(|p| p.4 as f64).collect());
+        }
+        for set in self.weight_sets.iter_mut() {
+            set.entries.push(PolyidEntry::new());
+        }
+        self.polyid_gridpoints.push(entry.data);
+        self.entries_changed();
     }
 
-    /// adds a new attribute for the associated variable. If the variable has
-    /// not yet been added, it is added
-    pub fn add_variable_attr(&mut self, var_name: &String, key: String, value: String) {
-        // check if it exists, if not add it
-        if !self.per_variable_attrs.contains_key(var_name) {
-            self.add_variable(var_name);
+    /// Removes a polyid and its entries in every weight set, along with its
+    /// metadata. If the name
+    /// appears more than once, the first occurrence is removed and the
+    /// metadata stays with the rest
+    pub fn remove_polyid(&mut self, name: &str) -> Result<(), NwtError> {
+        let idx = self.polyid_index(name)
+            .ok_or_else(|| NwtError::PolyidNotFound(name.to_string()))?;
+        self.json_data.polyids.remove(idx);
+        if !self.json_data.polyids.iter().any(|other| other == name) {
+            self.json_data.polyid_meta.remove(name);
         }
-        // add the values to the vector
-        let vec_ref = self.per_variable_attrs.get_mut(var_name).unwrap();
-        vec_ref.push((key, value));
+        self.polyid_gridpoints.remove(idx);
+        if let Some(weights) = self.f64_weights.as_mut() {
+            weights.remove(idx);
+        }
+        for set in self.weight_sets.iter_mut() {
+            set.entries.remove(idx);
+        }
+        self.entries_changed();
+        Ok(())
     }
 
-    /// adds a polyid to the list of polyids
-    pub fn add_polyid(&mut self, polyid: String) {
-        self.polyids.push(polyid)
+    /// Replaces the entry of an existing polyid, keeping its position. Same
+    /// as `update_polyid`
+    pub fn replace_entry(&mut self, name: &str, entry: PolyidEntry) -> Result<(), NwtError> {
+        self.update_polyid(name, entry)
     }
 
-    /// Retrieves a global arribute with the provided name
-    pub fn get_global_attr(&self, name: &String) -> Result<String, String> {
-        for v in self.global_attrs.iter() {
-            if v.0 == *name { return Ok(v.1.clone()) }
+    /// Splices a recomputed entry in place of an existing polyid's, shifting
+    /// the lookup table offsets of every polyid after it by the change in
+    /// point count. Only the structure in memory changes; serialize it again
+    /// to update a file
+    pub fn update_polyid(&mut self, name: &str, entry: PolyidEntry) -> Result<(), NwtError> {
+        let idx = self.polyid_index(name)
+            .ok_or_else(|| NwtError::PolyidNotFound(name.to_string()))?;
+        if let Some(weights) = self.f64_weights.as_mut() {
+            weights[idx] = entry.data.iter().map(|p| p.4 as f64).collect();
         }
-        Err(format!("Global attribute {} not found", name))
+        let (old, new) = (self.polyid_gridpoints[idx].len() as u64, entry.data.len() as u64);
+        self.polyid_gridpoints.replace(idx, entry.data);
+        if let Some(row) = self.lookup_table.get_mut(idx) {
+            row.count = new;
+        }
+        if new != old {
+            for row in self.lookup_table.iter_mut().skip(idx + 1) {
+                row.offset = row.offset - old + new;
+            }
+        }
+        Ok(())
     }
 
-    /// Retrieves all global attributes
-    pub fn get_global_attrs(&self) -> &Vec<(String, String)> {
-        &self.global_attrs
+    /// recomputes everything derived from the polyid list and entries
+    fn entries_changed(&mut self) {
+        self.lookup_table = self.polyid_gridpoints.lookup_table();
+        self.polyid_lookup = OnceLock::new();
     }
 
-    /// Retrieves a given variable's attribute of a provided name
-    pub fn get_var_attr(&self, variable_name: &String, attr_name: &String) -> Result<String, String> {
-        match self.per_variable_attrs.get(variable_name) {
-            Some(a) => {
-                for v in a.iter() {
-                    if v.0 == *attr_name { return Ok(v.1.clone()) }
-                }
-                Err(format!("Global attribute {} not found", attr_name)) 
-            },
-            None => return Err(format!("Variable {} not found in the weight file", variable_name))
+    /// Compares two weight files, allowing weights to differ by up to
+    /// `weight_tol`. Everything else, including grid indices and coordinates,
+    /// must match exactly
+    pub fn approx_eq(&self, other: &Self, weight_tol: f32) -> bool {
+        if self.json_data != other.json_data
+            || self.get_dimensions() != other.get_dimensions()
+            || self.lookup_table != other.lookup_table
+            || self.weight_set_names() != other.weight_set_names()
+        {
+            return false;
         }
-    }
-}
 
-impl PolyidEntry {
-    /// creates a new PolyidEntry
-    pub fn new() -> Self {
-        Self { data: Vec::new() }
+        let points_match = |a: &[GridPoint], b: &[GridPoint]| {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(p, q)| {
+                p.0 == q.0 && p.1 == q.1 && p.2 == q.2 && p.3 == q.3 && (p.4 - q.4).abs() <= weight_tol
+            })
+        };
+        let entries_match = |a: &[PolyidEntry], b: &[PolyidEntry]| {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| points_match(&a.data, &b.data))
+        };
+        self.polyid_gridpoints.len() == other.polyid_gridpoints.len()
+            && self.polyid_gridpoints.iter().zip(other.polyid_gridpoints.iter()).all(|(a, b)| points_match(a, b))
+            && self.weight_sets.iter().zip(other.weight_sets.iter()).all(|(a, b)| entries_match(&a.entries, &b.entries))
     }
 
-    /// adds a new point to the PolyidEntry
-    pub fn add_point(&mut self, lat_idx: u32, lon_idx: u32, lat: f32, lon: f32, value: f32) {
-        self.data.push((lat_idx, lon_idx, lat, lon, value))
+    /// Returns an iterator over (polyid, points) pairs in `get_polyids()`
+    /// order, borrowing the points without copying them
+    pub fn iter(&self) -> PolyidIter<'_> {
+        PolyidIter { polyids: self.json_data.polyids.iter(), gridpoints: &self.polyid_gridpoints, next: 0 }
     }
-}
 
+    /// Returns the number of polyids in the file
+    pub fn len(&self) -> usize {
+        self.json_data.polyids.len()
+    }
 
+    /// Returns true if the file contains no polyids
+    pub fn is_empty(&self) -> bool {
+        self.json_data.polyids.is_empty()
+    }
 
-#[cfg(test)]
+    /// Returns a reference to the data lookup table
+    pub fn get_lookup_table(&self) -> &Vec<LookupEntry> {
+        &self.lookup_table
+    }
+
+    /// Returns the number of gridpoints across all polyids, read from the
+    /// end of the lookup table
+    pub fn total_points(&self) -> u64 {
+        self.lookup_table.last().map_or(0, |e| e.offset + e.count)
+    }
+
+    /// Returns the number of gridpoints of the polyid at `polyid_idx` in
+    /// `get_polyids()` order, or `None` if it's out of range
+    pub fn point_count(&self, polyid_idx: usize) -> Option<u64> {
+        self.lookup_table.get(polyid_idx).map(|e| e.count)
+    }
+
+    /// Returns every polyid's number of gridpoints, in `get_polyids()` order
+    pub fn point_counts(&self) -> Vec<u64> {
+        self.lookup_table.iter().map(|e| e.count).collect()
+    }
+
+    /// Returns the latitude axis of the source grid, if it was recorded
+    pub fn get_latitudes(&self) -> Option<&[f32]> {
+        self.lat_axis.as_deref()
+    }
+
+    /// Returns the longitude axis of the source grid, if it was recorded
+    pub fn get_longitudes(&self) -> Option<&[f32]> {
+        self.lon_axis.as_deref()
+    }
+
+    /// Returns the (lower, upper) latitude bounds of every lat_idx's cells,
+    /// if the source file had them
+    pub fn lat_bounds(&self) -> Option<&[(f32, f32)]> {
+        self.lat_bounds.as_deref()
+    }
+
+    /// Returns the (lower, upper) longitude bounds of every lon_idx's cells,
+    /// if the source file had them
+    pub fn lon_bounds(&self) -> Option<&[(f32, f32)]> {
+        self.lon_bounds.as_deref()
+    }
+
+    /// Returns the dimensions of the weight file
+    pub fn get_dimensions(&self) -> (u64, u64) {
+        (self.lat_len, self.lon_len)
+    }
+
+    /// Returns a lazy iterator over every grid point, tagged with the index of
+    /// the polyid it belongs to. Points come in polyid order, then in the order
+    /// they were stored, and nothing is allocated
+    pub fn iter_points(&self) -> impl Iterator<Item = (usize, &GridPoint)> + '_ {
+        self.polyid_gridpoints.iter()
+            .enumerate()
+            .flat_map(|(idx, points)| points.iter().map(move |point| (idx, point)))
+    }
+
+    /// Returns a raw representation of gridpoints. This copies every point into
+    /// a freshly allocated `Vec`; prefer `iter_points` when you only need to walk them
+    pub fn get_raw_gridpoints(&self) -> Vec<GridPoint> {
+        self.polyid_gridpoints.points().to_vec()
+    }
+}
+
+impl PartialEq for NextWeightFile {
+    fn eq(&self, other: &Self) -> bool {
+        // the polyid lookup is just a cache, so it doesn't take part
+        self.json_data == other.json_data
+            && self.lat_len == other.lat_len
+            && self.lon_len == other.lon_len
+            && self.polyid_gridpoints == other.polyid_gridpoints
+            && self.lookup_table == other.lookup_table
+            && self.lat_axis == other.lat_axis
+            && self.lon_axis == other.lon_axis
+            && self.lat_bounds == other.lat_bounds
+            && self.lon_bounds == other.lon_bounds
+            && self.weight_set_name == other.weight_set_name
+            && self.weight_sets == other.weight_sets
+            && self.f64_weights == other.f64_weights
+    }
+}
+
+/// Iterator over the (polyid, points) pairs of a `NextWeightFile`
+pub struct PolyidIter<'a> {
+    polyids: std::slice::Iter<'a, String>,
+    gridpoints: &'a PointArena,
+    /// index of the entry the next polyid owns
+    next: usize,
+}
+
+impl<'a> Iterator for PolyidIter<'a> {
+    type Item = (&'a str, &'a [GridPoint]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let points = self.gridpoints.get(self.next)?;
+        let polyid = self.polyids.next()?;
+        self.next += 1;
+        Some((polyid.as_str(), points))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.polyids.len().min(self.gridpoints.len() - self.next);
+        (len, Some(len))
+    }
+}
+
+impl<'a> IntoIterator for &'a NextWeightFile {
+    type Item = (&'a str, &'a [GridPoint]);
+    type IntoIter = PolyidIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+
+impl JsonData {
+    /// creates a new instance of `JsonData`
+    pub fn new() -> Self {
+        Self {
+            global_attrs: Vec::new(),
+            per_variable_attrs: BTreeMap::new(),
+            polyids: Vec::new(),
+            polyid_meta: BTreeMap::new(),
+        }
+    }
+
+    /// adds a global attribute to the structure
+    pub fn add_global_attr(&mut self, key: impl Into<String>, value: impl Into<AttrVal>) {
+        self.global_attrs.push((key.into(), value.into()));
+    }
+
+    /// sets a global attribute, replacing any with the same key in place of
+    /// the first, or adding it at the end
+    pub fn set_global_attr(&mut self, key: impl Into<String>, value: impl Into<AttrVal>) {
+        set_attr(&mut self.global_attrs, key.into(), value.into());
+    }
+
+    /// removes every global attribute with the given key, returning whether
+    /// there were any
+    pub fn remove_global_attr(&mut self, key: &str) -> bool {
+        remove_attr(&mut self.global_attrs, key)
+    }
+
+    /// sets a variable's attribute like `set_global_attr`, adding the
+    /// variable if needed
+    pub fn set_var_attr(&mut self, var_name: &str, key: impl Into<String>, value: impl Into<AttrVal>) {
+        let attrs = self.per_variable_attrs.entry(var_name.to_string()).or_default();
+        set_attr(attrs, key.into(), value.into());
+    }
+
+    /// removes every attribute of a variable with the given key, returning
+    /// whether there were any
+    pub fn remove_var_attr(&mut self, var_name: &str, key: &str) -> bool {
+        self.per_variable_attrs.get_mut(var_name).is_some_and(|attrs| remove_attr(attrs, key))
+    }
+
+    /// removes a variable and all its attributes, returning whether it existed
+    pub fn remove_variable(&mut self, var_name: &str) -> bool {
+        self.per_variable_attrs.remove(var_name).is_some()
+    }
+
+    /// adds a new variable to the structure
+    pub fn add_variable(&mut self, variable_name: impl Into<String>) {
+        self.per_variable_attrs.insert(variable_name.into(), Vec::new());
+    }
+
+    /// adds a new attribute for the associated variable. If the variable has
+    /// not yet been added, it is added
+    pub fn add_variable_attr(&mut self, var_name: impl Into<String>, key: impl Into<String>, value: impl Into<AttrVal>) {
+        let attrs = self.per_variable_attrs.entry(var_name.into()).or_default();
+        attrs.push((key.into(), value.into()));
+    }
+
+    /// adds a polyid to the list of polyids. Crate-only, as a file's
+    /// polyids must stay in step with its entries; see
+    /// `NextWeightFile::add_polyid_entry`
+    pub(crate) fn add_polyid(&mut self, polyid: String) {
+        self.polyids.push(polyid)
+    }
+
+    /// Retrieves a string global attribute with the provided name. Numeric
+    /// and list attributes give `None`; see `get_global_attr_value`
+    pub fn get_global_attr(&self, name: &str) -> Option<&str> {
+        self.get_global_attr_value(name)?.as_str()
+    }
+
+    /// Retrieves the typed value of a global attribute
+    pub fn get_global_attr_value(&self, name: &str) -> Option<&AttrVal> {
+        self.global_attrs.iter().find(|(k, _)| k == name).map(|(_, v)| v)
+    }
+
+    /// Retrieves all global attributes
+    pub fn get_global_attrs(&self) -> &Vec<(String, AttrVal)> {
+        &self.global_attrs
+    }
+
+    /// Retrieves the typed value of a variable's attribute
+    pub fn get_var_attr_value(&self, variable_name: &str, attr_name: &str) -> Option<&AttrVal> {
+        self.per_variable_attrs.get(variable_name)?
+            .iter().find(|(k, _)| k == attr_name).map(|(_, v)| v)
+    }
+
+    /// Retrieves a given variable's string attribute of a provided name.
+    /// Numeric and list attributes give `None`; see `get_var_attr_value`
+    pub fn get_var_attr(&self, variable_name: &str, attr_name: &str) -> Option<&str> {
+        self.get_var_attr_value(variable_name, attr_name)?.as_str()
+    }
+
+    /// Iterates over the global attributes in file order. Values keep their
+    /// type; format them with `to_string` for text
+    pub fn global_attrs_iter(&self) -> impl Iterator<Item = (&str, &AttrVal)> {
+        self.global_attrs.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// Retrieves a global attribute ignoring the case of its name, for files
+    /// that spell `title` as `Title`. An exact match wins over the others
+    pub fn find_global_attr_ci(&self, name: &str) -> Option<&AttrVal> {
+        self.get_global_attr_value(name).or_else(|| {
+            self.global_attrs.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v)
+        })
+    }
+
+    /// Iterates over the names of variables with attributes, sorted
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.per_variable_attrs.keys().map(String::as_str)
+    }
+
+    /// Whether the file recorded a variable, even one without attributes
+    pub fn has_variable(&self, variable_name: &str) -> bool {
+        self.per_variable_attrs.contains_key(variable_name)
+    }
+
+    /// Iterates over a variable's attributes in file order, yielding nothing
+    /// for a variable the file doesn't have
+    pub fn var_attrs_iter(&self, variable_name: &str) -> impl Iterator<Item = (&str, &AttrVal)> {
+        self.per_variable_attrs.get(variable_name).into_iter().flatten().map(|(k, v)| (k.as_str(), v))
+    }
+}
+
+/// replaces the first attribute with `key`, dropping any later ones, or
+/// appends it
+fn set_attr(attrs: &mut Vec<(String, AttrVal)>, key: String, value: AttrVal) {
+    match attrs.iter().position(|(k, _)| *k == key) {
+        Some(first) => {
+            attrs[first].1 = value;
+            let mut rest = attrs.split_off(first + 1);
+            rest.retain(|(k, _)| *k != key);
+            attrs.append(&mut rest);
+        }
+        None => attrs.push((key, value)),
+    }
+}
+
+/// removes every attribute with `key`, returning whether there were any
+fn remove_attr(attrs: &mut Vec<(String, AttrVal)>, key: &str) -> bool {
+    let len = attrs.len();
+    attrs.retain(|(k, _)| k != key);
+    attrs.len() != len
+}
+
+impl PolyidEntry {
+    /// creates a new PolyidEntry
+    pub fn new() -> Self {
+        Self { data: Vec::new() }
+    }
+
+    /// adds a new point to the PolyidEntry
+    pub fn add_point(&mut self, lat_idx: u32, lon_idx: u32, lat: f32, lon: f32, value: f32) {
+        self.data.push((lat_idx, lon_idx, lat, lon, value))
+    }
+}
+
+
+
+/// builds the lookup table for a list of entries, where the offset is the
+/// number of points stored before each entry
+fn build_lookup_table(entries: &[PolyidEntry]) -> Vec<LookupEntry> {
+    let mut lookup_table: Vec<LookupEntry> = Vec::with_capacity(entries.len());
+    let mut running_total: u64 = 0;
+    for entry in entries.iter() {
+        let entry_size = entry.data.len() as u64;
+        lookup_table.push(LookupEntry::new(running_total, entry_size));
+        running_total += entry_size;
+    }
+    lookup_table
+}
+
+/// Everything in an NWT file that precedes the gridpoint data
+#[derive(Debug)]
+pub struct NwtHeader {
+    /// format version the file was written with
+    pub version: u16,
+    /// compression codec of the lookup table and gridpoint data (0 = none)
+    pub codec: u16,
+    /// how the weights are stored (always f32 before format version 8)
+    pub weight_dtype: WeightDtype,
+    /// number of polyids in the file
+    pub num_polyids: u64,
+    /// latitude dimension length
+    pub lat_len: u64,
+    /// longitude dimension length
+    pub lon_len: u64,
+    /// attributes and polyid names
+    pub json_data: JsonData,
+    /// (offset, count) of each polyid's points
+    pub lookup_table: Vec<LookupEntry>,
+    /// the lat/lon of every index, stored ahead of the gridpoint data from
+    /// format version 7 on. `None` for older files
+    pub coordinates: Option<Coordinates>,
+    /// the (lower, upper) cell bounds along each axis, from format version 9
+    /// on. `None` if the file doesn't have them
+    pub lat_bounds: Option<Vec<(f32, f32)>>,
+    pub lon_bounds: Option<Vec<(f32, f32)>>,
+    /// the (min, max) of each polyid's weights in files written with
+    /// `WriteOptions::quantize`, which store every weight as a u16 step
+    /// between the two. `None` for unquantized files
+    pub quantization: Option<Vec<(f32, f32)>>,
+}
+
+impl NwtHeader {
+    /// bytes per stored gridpoint
+    pub(crate) fn point_size(&self) -> usize {
+        Self::point_size_of(self.version, self.weight_dtype, self.quantization.is_some())
+    }
+
+    /// bytes per gridpoint stored in the given format version and dtype
+    pub(crate) fn point_size_of(version: u16, dtype: WeightDtype, quantized: bool) -> usize {
+        let coords = if version >= COMPACT_POINTS_VERSION { 8 } else { 16 };
+        let weight = if quantized { size_of::<u16>() } else { dtype.size() };
+        coords + weight
+    }
+}
+
+/// The fixed-size start of an NWT file, the header fields before the JSON
+#[derive(Debug, Clone, Copy)]
+struct FixedHeader {
+    version: u16,
+    codec: u16,
+    weight_dtype: WeightDtype,
+    quantized: bool,
+    json_len: u64,
+    num_polyids: u64,
+    lat_len: u64,
+    lon_len: u64,
+    json_offset: u64,
+    lookup_offset: u64,
+}
+
+/// The sections between the lookup table and the gridpoint data, as
+/// `NwtHeader` holds them
+struct GridSections {
+    coordinates: Option<Coordinates>,
+    lat_bounds: Option<Bounds>,
+    lon_bounds: Option<Bounds>,
+    quantization: Option<Vec<(f32, f32)>>,
+}
+
+/// The latitude of every lat_idx and longitude of every lon_idx, which the
+/// points of a format 7 file refer to instead of storing their own
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coordinates {
+    pub lats: Vec<f32>,
+    pub lons: Vec<f32>,
+    /// true if these are the file's coordinate axes, false if they were
+    /// rebuilt from the points when writing (NaN at indices no point uses)
+    pub are_axes: bool,
+}
+
+impl Coordinates {
+    /// the (lat, lon) of a cell, or `None` if it's outside the grid
+    pub fn get(&self, lat_idx: u32, lon_idx: u32) -> Option<(f32, f32)> {
+        Some((*self.lats.get(lat_idx as usize)?, *self.lons.get(lon_idx as usize)?))
+    }
+}
+
+/// Reads the header, JSON metadata and lookup table of an NWT file without
+/// loading any of the gridpoint data
+pub fn read_header(path: impl AsRef<Path>) -> Result<NwtHeader, NwtError> {
+    let mut cursor = NwtReader::open(path.as_ref(), false)?;
+    NextWeightFile::read_preamble(&mut cursor)
+}
+
+/// Checks an NWT file's integrity without building the full structure: the
+/// header, metadata and lookup table must be consistent, all gridpoint data
+/// must be present, and the checksum (for files new enough to have one) must
+/// match
+pub fn verify_file(path: impl AsRef<Path>) -> Result<(), NwtError> {
+    let mut cursor = NwtReader::open(path.as_ref(), true)?;
+    let header = NextWeightFile::read_preamble(&mut cursor)?;
+
+    let total_points: u64 = header.lookup_table.iter().map(|e| e.count).sum();
+    let data_len = total_points.checked_mul(header.point_size() as u64)
+        .ok_or_else(|| NwtError::Corrupt(format!("point count {} is too large", total_points)))?;
+    let data_end = cursor.position().checked_add(data_len)
+        .ok_or_else(|| NwtError::Corrupt(format!("point count {} is too large", total_points)))?;
+    cursor.skip_to(data_end, "gridpoint data")?;
+
+    if header.version >= AXES_VERSION && header.coordinates.is_none() {
+        read_axes(&mut cursor, header.lat_len, header.lon_len)?;
+    }
+    if let (Some(coords), true) = (&header.coordinates, header.version >= WEIGHT_SETS_VERSION) {
+        weight_sets::read_weight_sets(&mut cursor, header.num_polyids, coords)?;
+    }
+    if header.version >= CHECKSUM_VERSION {
+        cursor.finish_checksum()?;
+    }
+    Ok(())
+}
+
+/// writes the fixed-size header that precedes the JSON metadata, returning
+/// where the metadata starts
+#[allow(clippy::too_many_arguments)]
+fn write_header<W: Write>(
+    w: &mut W,
+    version: u16,
+    codec: u16,
+    dtype: u16,
+    json: &str,
+    num_polyids: u64,
+    lat_len: u64,
+    lon_len: u64,
+) -> std::io::Result<u64> {
+    // magic bytes and format version
+    w.write_all(MAGIC)?;
+    write_u16_le(w, version)?;
+    // u16: compression codec of the lookup table and gridpoint data
+    write_u16_le(w, codec)?;
+    // u16: weight dtype, 0 for f32, 1 for f64 and 2 for quantized u16
+    let dtype_len = if version >= WEIGHT_DTYPE_VERSION {
+        write_u16_le(w, dtype)?;
+        size_of::<u16>()
+    } else {
+        0
+    };
+    // u64: length of json string
+    write_u64_le(w, json.len() as u64)?;
+    // u64: number of polyids
+    write_u64_le(w, num_polyids)?;
+    // u64: latitude length
+    write_u64_le(w, lat_len)?;
+    // u64: longitude length
+    write_u64_le(w, lon_len)?;
+    // u64: beginning of json attributes string. Both offsets are u64
+    // whatever the platform's usize
+    let json_offset = (size_of::<u64>() * 6 + size_of::<u16>() * 2 + dtype_len + 4) as u64;
+    write_u64_le(w, json_offset)?;
+    // u64: beginning of lookup vector
+    let lookup_offset = json_offset + json.len() as u64;
+    write_u64_le(w, lookup_offset)?;
+    Ok(json_offset)
+}
+
+/// packs one gridpoint record: its indices, its lat/lon unless the file
+/// stores them once for the grid (`compact`), and its weight as `dtype`, or
+/// as a u16 step within `range` for quantized files
+fn encode_point<W: Write>(
+    w: &mut W,
+    p: &GridPoint,
+    weight: f64,
+    compact: bool,
+    dtype: WeightDtype,
+    range: Option<(f32, f32)>,
+) -> std::io::Result<()> {
+    write_u32_le(w, p.0)?;
+    write_u32_le(w, p.1)?;
+    if !compact {
+        write_f32_le(w, p.2)?;
+        write_f32_le(w, p.3)?;
+    }
+    match (range, dtype) {
+        (Some(range), _) => write_u16_le(w, quantize(weight, range)),
+        (None, WeightDtype::F32) => write_f32_le(w, weight as f32),
+        (None, WeightDtype::F64) => write_f64_le(w, weight),
+    }
+}
+
+/// writes the optional coordinate axes section that follows the gridpoint
+/// data before format 7, flagged by a single byte
+fn write_axes<W: Write>(w: &mut W, axes: Option<(&[f32], &[f32])>) -> std::io::Result<()> {
+    match axes {
+        Some((lats, lons)) => {
+            w.write_all(&[1])?;
+            for &v in lats.iter().chain(lons.iter()) {
+                write_f32_le(w, v)?;
+            }
+        }
+        None => w.write_all(&[0])?,
+    }
+    Ok(())
+}
+
+/// reads the optional (lat, lon) axes section that follows the gridpoint data
+fn read_axes<R: Read>(cursor: &mut NwtReader<R>, lat_len: u64, lon_len: u64) -> Result<Option<Axes>, NwtError> {
+    match cursor.read_bytes(1, "coordinate axes")?[0] {
+        0 => Ok(None),
+        1 => {
+            let lats = read_f32s(cursor, lat_len, "coordinate axes")?;
+            let lons = read_f32s(cursor, lon_len, "coordinate axes")?;
+            Ok(Some((lats, lons)))
+        }
+        flag => Err(NwtError::Corrupt(format!("invalid coordinate axes flag {}", flag))),
+    }
+}
+
+/// writes the cell bounds section: a byte flagging which axes have bounds
+/// (1 for latitude, 2 for longitude), then their (lower, upper) pairs
+fn write_bounds<W: Write>(w: &mut W, lat: Option<&[(f32, f32)]>, lon: Option<&[(f32, f32)]>) -> std::io::Result<()> {
+    w.write_all(&[lat.is_some() as u8 | (lon.is_some() as u8) << 1])?;
+    for &(lower, upper) in lat.into_iter().chain(lon).flatten() {
+        write_f32_le(w, lower)?;
+        write_f32_le(w, upper)?;
+    }
+    Ok(())
+}
+
+/// reads the cell bounds section `write_bounds` writes
+fn read_bounds<R: Read>(cursor: &mut NwtReader<R>, lat_len: u64, lon_len: u64) -> Result<(Option<Bounds>, Option<Bounds>), NwtError> {
+    let flags = cursor.read_bytes(1, "cell bounds")?[0];
+    if flags > 3 {
+        return Err(NwtError::Corrupt(format!("invalid cell bounds flags {}", flags)));
+    }
+    let mut read = |present: bool, len: u64| -> Result<Option<Bounds>, NwtError> {
+        if !present {
+            return Ok(None);
+        }
+        let count = len.checked_mul(2)
+            .ok_or_else(|| NwtError::Corrupt(format!("{} cell bounds are too many", len)))?;
+        let values = read_f32s(cursor, count, "cell bounds")?;
+        Ok(Some(values.chunks_exact(2).map(|pair| (pair[0], pair[1])).collect()))
+    };
+    Ok((read(flags & 1 != 0, lat_len)?, read(flags & 2 != 0, lon_len)?))
+}
+
+/// reads one polyid's `num_coords` gridpoint records onto the end of
+/// `points`, returning the f64 weights of double-precision files. A point off the grid is an error,
+/// unless `lenient`, when the rest are still read so the next polyid starts
+/// where it should, and the reason to drop this one is returned instead
+fn read_entry<R: Read>(
+    cursor: &mut NwtReader<R>,
+    points: &mut Vec<GridPoint>,
+    num_coords: u64,
+    coordinates: Option<&Coordinates>,
+    weight_dtype: WeightDtype,
+    range: Option<(f32, f32)>,
+    lenient: bool,
+) -> Result<(Vec<f64>, Option<String>), NwtError> {
+    let mut off_grid = None;
+    // plain f32 records are read for the whole entry and decoded together
+    if let (WeightDtype::F32, None) = (weight_dtype, range) {
+        let record_size = if coordinates.is_some() { CompactPoint::SIZE } else { OnDiskPoint::SIZE };
+        let len = to_usize(num_coords)?.checked_mul(record_size)
+            .ok_or_else(|| NwtError::Corrupt(format!("point count {} is too large", num_coords)))?;
+        let bytes = cursor.read_bytes(len, "gridpoint data")?;
+        match coordinates {
+            Some(coords) => {
+                points.reserve(to_usize(num_coords)?);
+                for p in records::<CompactPoint>(&bytes) {
+                    let (lat, lon) = coords.get(p.lat_idx, p.lon_idx).unwrap_or_else(|| {
+                        off_grid.get_or_insert_with(|| format!("point ({}, {}) is outside the {}x{} grid",
+                            { p.lat_idx }, { p.lon_idx }, coords.lats.len(), coords.lons.len()));
+                        (f32::NAN, f32::NAN)
+                    });
+                    points.push((p.lat_idx, p.lon_idx, lat, lon, p.weight));
+                }
+            }
+            None => points.extend(records::<OnDiskPoint>(&bytes).map(GridPoint::from)),
+        }
+        return match (off_grid, lenient) {
+            (Some(reason), false) => Err(NwtError::Corrupt(reason)),
+            (off_grid, _) => Ok((Vec::new(), off_grid)),
+        };
+    }
+
+    let mut f64_weights = Vec::new();
+    for _ in 0..num_coords {
+        let lat_idx = cursor.read_u32("gridpoint data")?;
+        let lon_idx = cursor.read_u32("gridpoint data")?;
+        let (lat, lon) = match coordinates {
+            Some(coords) => coords.get(lat_idx, lon_idx).unwrap_or_else(|| {
+                off_grid.get_or_insert_with(|| format!("point ({}, {}) is outside the {}x{} grid",
+                    lat_idx, lon_idx, coords.lats.len(), coords.lons.len()));
+                (f32::NAN, f32::NAN)
+            }),
+            None => (cursor.read_f32("gridpoint data")?, cursor.read_f32("gridpoint data")?),
+        };
+        if let (Some(reason), false) = (&off_grid, lenient) {
+            return Err(NwtError::Corrupt(reason.clone()));
+        }
+        let weight = match weight_dtype {
+            WeightDtype::F32 => match range {
+                Some(range) => dequantize(cursor.read_u16("gridpoint data")?, range),
+                None => cursor.read_f32("gridpoint data")?,
+            },
+            WeightDtype::F64 => {
+                let weight = cursor.read_f64("gridpoint data")?;
+                f64_weights.push(weight);
+                weight as f32
+            }
+        };
+        points.push((lat_idx, lon_idx, lat, lon, weight));
+    }
+    Ok((f64_weights, off_grid))
+}
+
+/// reads `len` f32s in a row
+fn read_f32s<R: Read>(cursor: &mut NwtReader<R>, len: u64, section: &'static str) -> Result<Vec<f32>, NwtError> {
+    (0..len).map(|_| cursor.read_f32(section)).collect()
+}
+
+/// Sequential reader over an NWT stream that reports truncation per section
+struct NwtReader<R: Read> {
+    inner: R,
+    pos: u64,
+    /// whether to check the file's checksum, if it has one
+    verify: bool,
+    /// running hash of the bytes read since `start_checksum`
+    hasher: Option<crc32fast::Hasher>,
+    /// decompressed section that reads are served from instead of `inner`
+    payload: Option<std::io::Cursor<Vec<u8>>>,
+    /// length of the stream, if known
+    len: Option<u64>,
+}
+
+impl NwtReader<BufReader<std::fs::File>> {
+    /// a buffered reader over a file, which knows its length
+    fn open(path: &Path, verify: bool) -> Result<Self, NwtError> {
+        let file = std::fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Self { verify, ..Self::new(BufReader::new(file)) }.sized(len))
+    }
+}
+
+impl<R: Read> NwtReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, pos: 0, verify: false, hasher: None, payload: None, len: None }
+    }
+
+    /// a reader that knows the stream holds `len` bytes
+    fn sized(self, len: u64) -> Self {
+        Self { len: Some(len), ..self }
+    }
+
+    /// a reader that checks the checksum of files that have one
+    fn verifying(inner: R) -> Self {
+        Self { verify: true, ..Self::new(inner) }
+    }
+
+    /// starts hashing everything read from here on, if verifying
+    fn start_checksum(&mut self) {
+        if self.verify {
+            self.hasher = Some(crc32fast::Hasher::new());
+        }
+    }
+
+    /// Serves further reads from a decompressed section, which starts at the
+    /// logical offset `pos` of the uncompressed layout. The raw bytes it came
+    /// from have already been checksummed
+    fn set_payload(&mut self, payload: Vec<u8>, pos: u64) {
+        self.payload = Some(std::io::Cursor::new(payload));
+        self.pos = pos;
+    }
+
+    /// reads the checksum trailer and compares it against the bytes hashed
+    /// since `start_checksum`, if verifying
+    fn finish_checksum(&mut self) -> Result<(), NwtError> {
+        if let Some(payload) = self.payload.take() {
+            let leftover = payload.get_ref().len() as u64 - payload.position();
+            if leftover != 0 {
+                return Err(NwtError::Corrupt(format!("{} unexpected bytes after the gridpoint data", leftover)));
+            }
+        }
+        let hasher = self.hasher.take();
+        let expected = self.read_u32("checksum")?;
+        if let Some(got) = hasher.map(crc32fast::Hasher::finalize) {
+            if expected != got {
+                return Err(NwtError::ChecksumMismatch { expected, got });
+            }
+        }
+        Ok(())
+    }
+
+    /// consumes the rest of the stream, returning how many bytes it held
+    fn remaining(&mut self) -> Result<u64, NwtError> {
+        Ok(std::io::copy(&mut self.inner, &mut std::io::sink())?)
+    }
+
+    /// number of bytes consumed so far
+    fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// whether `ensure_available` can tell how much is left
+    fn is_sized(&self) -> bool {
+        self.len.is_some() || self.payload.is_some()
+    }
+
+    /// Fails with `Truncated` if the stream, or the decompressed section
+    /// being read, is known to hold fewer than `len` more bytes. Lets a
+    /// corrupt size be caught before anything is allocated for it
+    fn ensure_available(&self, len: u64, section: &'static str) -> Result<(), NwtError> {
+        let left = match &self.payload {
+            Some(payload) => Some(payload.get_ref().len() as u64 - payload.position()),
+            None => self.len.map(|total| total.saturating_sub(self.pos)),
+        };
+        match left {
+            Some(left) if left < len => Err(NwtError::Truncated {
+                section,
+                expected: usize::try_from(len).unwrap_or(usize::MAX),
+                got: usize::try_from(left).unwrap_or(usize::MAX),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// fills `buf` completely, or reports how much of it the stream could supply
+    fn fill(&mut self, buf: &mut [u8], section: &'static str) -> Result<(), NwtError> {
+        let mut got = 0;
+        while got < buf.len() {
+            let read = match self.payload.as_mut() {
+                Some(payload) => payload.read(&mut buf[got..]),
+                None => self.inner.read(&mut buf[got..]),
+            };
+            match read {
+                Ok(0) => return Err(NwtError::Truncated { section, expected: buf.len(), got }),
+                Ok(n) => {
+                    if let (Some(hasher), None) = (self.hasher.as_mut(), &self.payload) {
+                        hasher.update(&buf[got..got + n]);
+                    }
+                    got += n;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.pos += got as u64;
+        Ok(())
+    }
+
+    /// discards bytes until the absolute offset `pos` is reached
+    fn skip_to(&mut self, pos: u64, section: &'static str) -> Result<(), NwtError> {
+        let mut scratch = [0u8; 256];
+        while self.pos < pos {
+            let len = (pos - self.pos).min(scratch.len() as u64) as usize;
+            self.fill(&mut scratch[..len], section)?;
+        }
+        Ok(())
+    }
+
+    /// consumes the next `len` bytes. The buffer grows as they arrive, so a
+    /// corrupt length can't allocate much more than the stream holds
+    fn read_bytes(&mut self, len: usize, section: &'static str) -> Result<Vec<u8>, NwtError> {
+        const FIRST_CHUNK: usize = 4096;
+        let mut buff = Vec::with_capacity(len.min(FIRST_CHUNK));
+        while buff.len() < len {
+            let start = buff.len();
+            buff.resize(start + (len - start).min(start.max(FIRST_CHUNK)), 0);
+            self.fill(&mut buff[start..], section).map_err(|e| match e {
+                NwtError::Truncated { got, .. } => NwtError::Truncated { section, expected: len, got: start + got },
+                other => other,
+            })?;
+        }
+        Ok(buff)
+    }
+
+    fn read_u64(&mut self, section: &'static str) -> Result<u64, NwtError> {
+        let mut buff = [0u8; size_of::<u64>()];
+        self.fill(&mut buff, section)?;
+        Ok(read_u64_le(&buff))
+    }
+
+    fn read_u16(&mut self, section: &'static str) -> Result<u16, NwtError> {
+        let mut buff = [0u8; size_of::<u16>()];
+        self.fill(&mut buff, section)?;
+        Ok(read_u16_le(&buff))
+    }
+
+    fn read_u32(&mut self, section: &'static str) -> Result<u32, NwtError> {
+        let mut buff = [0u8; size_of::<u32>()];
+        self.fill(&mut buff, section)?;
+        Ok(read_u32_le(&buff))
+    }
+
+    fn read_f32(&mut self, section: &'static str) -> Result<f32, NwtError> {
+        let mut buff = [0u8; size_of::<f32>()];
+        self.fill(&mut buff, section)?;
+        Ok(read_f32_le(&buff))
+    }
+
+    fn read_f64(&mut self, section: &'static str) -> Result<f64, NwtError> {
+        let mut buff = [0u8; size_of::<f64>()];
+        self.fill(&mut buff, section)?;
+        Ok(read_f64_le(&buff))
+    }
+}
+
+/// Writer that counts and checksums everything passing through it
+struct ChecksumWriter<'a, W: Write> {
+    inner: &'a mut W,
+    hasher: crc32fast::Hasher,
+    len: u64,
+}
+
+impl<'a, W: Write> ChecksumWriter<'a, W> {
+    fn new(inner: &'a mut W) -> Self {
+        Self { inner, hasher: crc32fast::Hasher::new(), len: 0 }
+    }
+
+    /// the number of bytes written and their CRC32
+    fn finish(self) -> (u64, u32) {
+        (self.len, self.hasher.finalize())
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// true if the file starts with either NWT magic
+fn has_nwt_magic(path: &Path) -> Result<bool, NwtError> {
+    let mut data = [0u8; 4];
+    let read_len = std::fs::File::open(path)?.read(&mut data)?;
+    Ok(is_nwt_magic(&data[..read_len]))
+}
+
+/// true if `data` is either NWT magic
+fn is_nwt_magic(data: &[u8]) -> bool {
+    data == LEGACY_MAGIC || data == MAGIC
+}
+
+/// converts a size read from a file header into a `usize`
+fn to_usize(v: u64) -> Result<usize, NwtError> {
+    usize::try_from(v).map_err(|_| NwtError::Corrupt(format!("size {} does not fit in memory", v)))
+}
+
+/// the number of cells of a `lat_len` x `lon_len` grid, making sure every
+/// index fits in the u32 points store it as and every flat cell index in a
+/// usize, so index math on the grid can't wrap
+fn grid_cells(lat_len: u64, lon_len: u64) -> Result<usize, NwtError> {
+    let too_large = || NwtError::GridTooLarge { lat_len, lon_len };
+    let max_len = u32::MAX as u64 + 1;
+    if lat_len > max_len || lon_len > max_len || usize::try_from(lat_len.max(lon_len)).is_err() {
+        return Err(too_large());
+    }
+    lat_len.checked_mul(lon_len).and_then(|cells| usize::try_from(cells).ok()).ok_or_else(too_large)
+}
+
+
+#[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
+    #[cfg(feature = "netcdf")]
     fn it_works() {
         // lets test this
-        let test_path = PathBuf::from_str("../rust_science/test_cases/wgts/cckp_aggregation_1x1").unwrap();
-        let new_path = PathBuf::from_str("test.nwt").unwrap();
+        let test_path = Path::new("../rust_science/test_cases/wgts/cckp_aggregation_1x1");
+        let new_path = Path::new("test.nwt");
         let new_weight = NextWeightFile::from_weight_file(test_path).unwrap();
-        println!("new_weight file weight examples: {:?}", &new_weight.lookup_table[..10]);
-        println!("new_weight file weights: {:?}", &new_weight.polyid_gridpoints[..10]);
-        new_weight.serialize_to_file(None).unwrap();
+        log::debug!("{}", new_weight);
+        new_weight.serialize_to_file_with(new_path, &WriteOptions { overwrite: true, ..Default::default() }).unwrap();
         let fresh_weight = NextWeightFile::from_nwt(new_path).unwrap();
 
-        for v in 0..new_weight.json_data.polyids.len() {
-            assert_eq!(new_weight.json_data.polyids[v], fresh_weight.json_data.polyids[v]); 
+        assert_eq!(new_weight, fresh_weight);
+    }
+
+    /// returns a unique path in the system temp directory for test artifacts
+    pub(crate) fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("nwt_test_{}_{}", std::process::id(), name))
+    }
+
+    /// builds a small weight file by hand so tests don't need a NetCDF fixture
+    pub(crate) fn sample_file() -> NextWeightFile {
+        let mut json_data = JsonData::new();
+        json_data.add_global_attr("title", "sample weights");
+        json_data.add_variable_attr("regridweights", "units", "1");
+
+        let mut polyid_gridpoints = Vec::new();
+        let mut a = PolyidEntry::new();
+        a.add_point(0, 0, -45.0, 0.0, 0.25);
+        a.add_point(0, 1, -45.0, 90.0, 0.75);
+        let mut b = PolyidEntry::new();
+        b.add_point(1, 1, 45.0, 90.0, 1.0);
+        let mut c = PolyidEntry::new();
+        c.add_point(1, 0, 45.0, 0.0, 0.5);
+        c.add_point(1, 2, 45.0, 180.0, 0.5);
+        c.add_point(0, 2, -45.0, 180.0, 0.0);
+        for (name, entry) in [("A", a), ("B", b), ("C", c)] {
+            json_data.add_polyid(name.to_string());
+            polyid_gridpoints.push(entry);
         }
 
-        for v in 0..new_weight.json_data.global_attrs.len() {
-            let attr_name = &new_weight.json_data.global_attrs[v].0;
-            let attr_value = &new_weight.json_data.global_attrs[v].1;
-            let new_name = &fresh_weight.json_data.global_attrs[v].0;
-            let new_value = &fresh_weight.json_data.global_attrs[v].1;
+        let mut lookup_table = Vec::new();
+        let mut running_total = 0;
+        for entry in polyid_gridpoints.iter() {
+            lookup_table.push(LookupEntry::new(running_total, entry.data.len() as u64));
+            running_total += entry.data.len() as u64;
+        }
 
-            assert_eq!(attr_name, new_name);
-            assert_eq!(attr_value, new_value);
+        NextWeightFile {
+            json_data,
+            lat_len: 2,
+            lon_len: 3,
+            polyid_gridpoints: polyid_gridpoints.into_iter().collect(),
+            lookup_table,
+            lat_axis: None,
+            lon_axis: None,
+            lat_bounds: None,
+            lon_bounds: None,
+            weight_set_name: DEFAULT_WEIGHT_SET.to_string(),
+            weight_sets: Vec::new(),
+            f64_weights: None,
+            polyid_lookup: OnceLock::new(),
         }
+    }
 
-        for key in new_weight.json_data.per_variable_attrs.keys() {
-            let curr_v_vec = new_weight.json_data.per_variable_attrs.get(key).unwrap();
-            let new_v_vec = fresh_weight.json_data.per_variable_attrs.get(key).unwrap();
-            for v in 0..curr_v_vec.len() {
-                let attr_name = &curr_v_vec[v].0;
-                let attr_value = &curr_v_vec[v].1;
-                let new_name = &new_v_vec[v].0;
-                let new_value = &new_v_vec[v].1;
+    #[test]
+    fn bad_magic_is_typed_error() {
+        let path = temp_path("bad_magic.nwt");
+        std::fs::write(&path, b"NOPE and some more bytes").unwrap();
+        let err = NextWeightFile::from_nwt(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(err, NwtError::InvalidMagic));
+        assert_eq!(err.to_string(), "Invalid file format");
+    }
 
-                assert_eq!(attr_name, new_name);
-                assert_eq!(attr_value, new_value);
+    #[test]
+    fn truncated_nwt_is_an_error() {
+        let path = temp_path("truncate_src.nwt");
+        sample_file().serialize_to_file(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // work out the section boundaries from the header (after the magic,
+        // version, codec and weight dtype)
+        let json_offset = read_u64_le(&bytes[42..]) as usize;
+        let lookup_offset = read_u64_le(&bytes[50..]) as usize;
+        let coordinates_offset = lookup_offset + 3 * 16;
+        let bounds_offset = coordinates_offset + 1 + (2 + 3) * 4;
+        let data_offset = bounds_offset + 1;
+        // the main set's name and a zero count of other sets, then the checksum
+        let weight_sets_offset = bytes.len() - 4 - (8 + DEFAULT_WEIGHT_SET.len() + 8);
+        let cases = [
+            (2, "magic"),
+            (20, "header"),
+            (json_offset, "json metadata"),
+            (json_offset + 5, "json metadata"),
+            (lookup_offset, "lookup table"),
+            (lookup_offset + 20, "lookup table"),
+            (coordinates_offset, "coordinates"),
+            (coordinates_offset + 7, "coordinates"),
+            (bounds_offset, "cell bounds"),
+            (data_offset, "gridpoint data"),
+            (weight_sets_offset - 5, "gridpoint data"),
+            (weight_sets_offset, "weight sets"),
+            (weight_sets_offset + 10, "weight sets"),
+            (bytes.len() - 1, "checksum"),
+        ];
+
+        let cut_path = temp_path("truncate_cut.nwt");
+        for (cut, expected_section) in cases {
+            std::fs::write(&cut_path, &bytes[..cut]).unwrap();
+            match NextWeightFile::from_nwt(&cut_path) {
+                Err(NwtError::Truncated { section, .. }) => {
+                    assert_eq!(section, expected_section, "cut at {}", cut);
+                }
+                other => panic!("cut at {} gave {:?}", cut, other.map(|_| ())),
             }
         }
+        std::fs::remove_file(&cut_path).unwrap();
+    }
+
+    #[test]
+    fn inconsistent_offsets_are_an_error() {
+        let path = temp_path("bad_offsets.nwt");
+        sample_file().serialize_to_file(&path).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+
+        // point the lookup table somewhere that doesn't follow the json block
+        let lookup_offset = read_u64_le(&bytes[50..]);
+        bytes[50..58].copy_from_slice(&(lookup_offset + 8).to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        let err = NextWeightFile::from_nwt(&path).unwrap_err();
+        assert!(matches!(err, NwtError::Corrupt(_)), "{:?}", err);
+
+        // restore it, then break the running offsets in the lookup table
+        bytes[50..58].copy_from_slice(&lookup_offset.to_le_bytes());
+        let second_entry = lookup_offset as usize + 16;
+        bytes[second_entry..second_entry + 8].copy_from_slice(&7u64.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        let err = NextWeightFile::from_nwt(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(err, NwtError::Corrupt(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn serialize_to_writer_round_trip() {
+        let original = sample_file();
+        let mut buffer: Vec<u8> = Vec::new();
+        let written = original.serialize_to_writer(&mut buffer).unwrap();
+        assert_eq!(written, buffer.len() as u64);
+
+        let fresh = NextWeightFile::from_reader(&buffer[..]).unwrap();
+        assert_eq!(original, fresh);
+    }
+
+    #[test]
+    fn from_reader_cursor_round_trip() {
+        let original = sample_file();
+        let mut buffer: Vec<u8> = Vec::new();
+        original.serialize_to_writer(&mut buffer).unwrap();
+
+        let fresh = NextWeightFile::from_reader(std::io::Cursor::new(buffer)).unwrap();
+        assert_eq!(original, fresh);
+    }
+
+    #[test]
+    fn buffered_file_output_matches_writer_output() {
+        let original = sample_file();
+        let mut buffer: Vec<u8> = Vec::new();
+        original.serialize_to_writer(&mut buffer).unwrap();
+
+        let path = temp_path("buffered.nwt");
+        original.serialize_to_file(&path).unwrap();
+        let file_bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(buffer, file_bytes);
+    }
+
+    #[test]
+    fn streamed_from_nwt_matches_in_memory_parse() {
+        let path = temp_path("streamed.nwt");
+        sample_file().serialize_to_file(&path).unwrap();
+
+        // the old behavior: slurp the whole file and parse the bytes
+        let bytes = std::fs::read(&path).unwrap();
+        let slurped = NextWeightFile::from_reader(&bytes[..]).unwrap();
+        let streamed = NextWeightFile::from_nwt(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(slurped, streamed);
+    }
+
+    #[test]
+    fn read_header_matches_full_load() {
+        let path = temp_path("header.nwt");
+        sample_file().serialize_to_file(&path).unwrap();
+        let header = read_header(&path).unwrap();
+        let full = NextWeightFile::from_nwt(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(header.num_polyids, full.get_polyids().len() as u64);
+        assert_eq!((header.lat_len, header.lon_len), full.get_dimensions());
+        assert_eq!(&header.json_data.polyids, full.get_polyids());
+        assert_eq!(header.json_data.get_global_attrs(), full.get_global_attrs());
+        assert_eq!(&header.lookup_table, full.get_lookup_table());
+    }
+
+    #[test]
+    fn lookup_entry_by_polyid() {
+        let nwt = sample_file();
+        assert_eq!(nwt.polyid_index("A"), Some(0));
+        assert_eq!(nwt.polyid_index("C"), Some(2));
+        assert_eq!(nwt.polyid_index("missing"), None);
+        assert_eq!(nwt.get_entry("B").unwrap().data, vec![(1, 1, 45.0, 90.0, 1.0)]);
+        assert!(nwt.get_entry("missing").is_none());
+    }
+
+    #[test]
+    fn duplicate_polyid_resolves_to_first() {
+        let mut nwt = sample_file();
+        // give the last region the same name as the first one
+        nwt.json_data.polyids[2] = "A".to_string();
+        assert_eq!(nwt.polyid_index("A"), Some(0));
+        assert_eq!(nwt.get_entry("A").unwrap().data.len(), 2);
+    }
+
+    #[test]
+    fn iterate_polyid_entries() {
+        let nwt = sample_file();
+        assert_eq!(nwt.len(), 3);
+        assert!(!nwt.is_empty());
+
+        let names: Vec<&str> = nwt.iter().map(|(id, _)| id).collect();
+        assert_eq!(names, nwt.get_polyids().iter().map(|s| s.as_str()).collect::<Vec<_>>());
+        for (idx, (_, points)) in (&nwt).into_iter().enumerate() {
+            assert_eq!(points, nwt.entry_points(idx));
+        }
+
+        let empty = NextWeightFile {
+            json_data: JsonData::new(),
+            lat_len: 0,
+            lon_len: 0,
+            polyid_gridpoints: PointArena::new(),
+            lookup_table: Vec::new(),
+            lat_axis: None,
+            lon_axis: None,
+            lat_bounds: None,
+            lon_bounds: None,
+            weight_set_name: DEFAULT_WEIGHT_SET.to_string(),
+            weight_sets: Vec::new(),
+            f64_weights: None,
+            polyid_lookup: OnceLock::new(),
+        };
+        assert_eq!(empty.len(), 0);
+        assert!(empty.is_empty());
+        assert_eq!((&empty).into_iter().count(), 0);
+    }
+
+    #[test]
+    fn iter_points_tags_owning_polyid() {
+        let nwt = sample_file();
+        let points: Vec<(usize, GridPoint)> = nwt.iter_points().map(|(idx, p)| (idx, *p)).collect();
+        assert_eq!(points.len(), 6);
+        assert_eq!(points.iter().map(|p| p.0).collect::<Vec<_>>(), vec![0, 0, 1, 2, 2, 2]);
+        // same order as the allocating accessor
+        assert_eq!(points.iter().map(|p| p.1).collect::<Vec<_>>(), nwt.get_raw_gridpoints());
+    }
+
+    #[test]
+    fn approx_eq_tolerates_weight_noise() {
+        let original = sample_file();
+        let mut noisy = sample_file();
+        assert_eq!(original, noisy);
+
+        noisy.polyid_gridpoints[0][1].4 += 1e-6;
+        assert_ne!(original, noisy);
+        assert!(original.approx_eq(&noisy, 1e-5));
+        assert!(!original.approx_eq(&noisy, 1e-7));
+
+        // indices have to match exactly no matter the tolerance
+        let mut moved = sample_file();
+        moved.polyid_gridpoints[1][0].0 = 0;
+        assert!(!original.approx_eq(&moved, 1.0));
+    }
+
+    #[test]
+    fn mutate_entries_and_reserialize() {
+        let mut nwt = sample_file();
+        let mut d = PolyidEntry::new();
+        d.add_point(0, 1, -45.0, 90.0, 1.0);
+        nwt.add_polyid_entry("D".to_string(), d);
+        nwt.remove_polyid("B").unwrap();
+        let mut c = PolyidEntry::new();
+        c.add_point(1, 2, 45.0, 180.0, 1.0);
+        nwt.replace_entry("C", c).unwrap();
+
+        assert_eq!(nwt.get_polyids(), &vec!["A".to_string(), "C".to_string(), "D".to_string()]);
+        assert_eq!(nwt.get_lookup_table(), &vec![(0, 2), (2, 1), (3, 1)]);
+        assert_eq!(nwt.polyid_index("D"), Some(2));
+        assert_eq!(nwt.polyid_index("B"), None);
+
+        let mut buffer: Vec<u8> = Vec::new();
+        nwt.serialize_to_writer(&mut buffer).unwrap();
+        assert_eq!(NextWeightFile::from_reader(&buffer[..]).unwrap(), nwt);
+    }
+
+    #[test]
+    fn update_middle_polyid_keeps_later_entries() {
+        let mut nwt = sample_file();
+        let b = PolyidEntry { data: vec![(1, 1, 45.0, 90.0, 0.5), (0, 1, -45.0, 90.0, 0.25), (0, 0, -45.0, 0.0, 0.25)] };
+        nwt.update_polyid("B", b.clone()).unwrap();
+        assert_eq!(nwt.get_lookup_table(), &vec![(0, 2), (2, 3), (5, 3)]);
+        assert_eq!(nwt.check_lookup_table(), Ok(()));
+
+        let reread = NextWeightFile::from_bytes(&nwt.to_bytes().unwrap()).unwrap();
+        assert_eq!(reread.get_entry("B"), Some(&b));
+        assert_eq!(reread.get_entry("C"), sample_file().get_entry("C"));
+
+        // shrinking it back moves the later offsets back too
+        nwt.update_polyid("B", sample_file().get_entry("B").unwrap().clone()).unwrap();
+        assert_eq!(nwt, sample_file());
+        assert!(matches!(nwt.update_polyid("nope", PolyidEntry::new()), Err(NwtError::PolyidNotFound(_))));
+    }
+
+    #[test]
+    fn remove_polyid_edge_cases() {
+        let mut nwt = sample_file();
+        assert!(matches!(nwt.remove_polyid("nope"), Err(NwtError::PolyidNotFound(_))));
+        assert!(matches!(nwt.replace_entry("nope", PolyidEntry::new()), Err(NwtError::PolyidNotFound(_))));
+        assert_eq!(nwt.len(), 3);
+
+        // removing everything leaves a valid, empty file
+        for name in ["A", "B", "C"] {
+            nwt.remove_polyid(name).unwrap();
+        }
+        assert!(nwt.is_empty());
+        assert!(nwt.get_lookup_table().is_empty());
+
+        let mut buffer: Vec<u8> = Vec::new();
+        nwt.serialize_to_writer(&mut buffer).unwrap();
+        let fresh = NextWeightFile::from_reader(&buffer[..]).unwrap();
+        assert!(fresh.is_empty());
+    }
+
+    #[test]
+    fn reads_legacy_and_versioned_fixtures() {
+        let v1 = NextWeightFile::from_reader(&include_bytes!("../tests/fixtures/sample_v1.nwt")[..]).unwrap();
+        let v2 = NextWeightFile::from_reader(&include_bytes!("../tests/fixtures/sample_v2.nwt")[..]).unwrap();
+        let v3 = NextWeightFile::from_reader(&include_bytes!("../tests/fixtures/sample_v3.nwt")[..]).unwrap();
+        let v4 = NextWeightFile::from_reader(&include_bytes!("../tests/fixtures/sample_v4.nwt")[..]).unwrap();
+        let v5 = NextWeightFile::from_reader(&include_bytes!("../tests/fixtures/sample_v5.nwt")[..]).unwrap();
+        let v6 = NextWeightFile::from_reader(&include_bytes!("../tests/fixtures/sample_v6.nwt")[..]).unwrap();
+        let v7 = NextWeightFile::from_reader(&include_bytes!("../tests/fixtures/sample_v7.nwt")[..]).unwrap();
+        let v8 = NextWeightFile::from_reader(&include_bytes!("../tests/fixtures/sample_v8.nwt")[..]).unwrap();
+        let v9 = NextWeightFile::from_reader(&include_bytes!("../tests/fixtures/sample_v9.nwt")[..]).unwrap();
+        let v10 = NextWeightFile::from_reader(&include_bytes!("../tests/fixtures/sample_v10.nwt")[..]).unwrap();
+        assert_eq!(v1, sample_file());
+        assert_eq!(v2, sample_file());
+        assert_eq!(v3, sample_file());
+        assert_eq!(v4, sample_file());
+        assert_eq!(v5, sample_file());
+        assert_eq!(v6, sample_file());
+        assert_eq!(v7, sample_file());
+        assert_eq!(v8, sample_file());
+        assert_eq!(v9, sample_file());
+        assert_eq!(v10, sample_file());
+
+        // new files are written in the current version unless asked otherwise
+        let mut buff = Vec::new();
+        sample_file().serialize_to_writer(&mut buff).unwrap();
+        assert_eq!(buff, include_bytes!("../tests/fixtures/sample_v10.nwt"));
+        let older: [(u16, &[u8]); 4] = [
+            (6, include_bytes!("../tests/fixtures/sample_v6.nwt")),
+            (7, include_bytes!("../tests/fixtures/sample_v7.nwt")),
+            (8, include_bytes!("../tests/fixtures/sample_v8.nwt")),
+            (9, include_bytes!("../tests/fixtures/sample_v9.nwt")),
+        ];
+        for (version, fixture) in older {
+            let mut buff = Vec::new();
+            let opts = WriteOptions { version, ..Default::default() };
+            sample_file().serialize_to_writer_with(&mut buff, &opts).unwrap();
+            assert_eq!(buff, fixture, "version {}", version);
+        }
+
+        let path = temp_path("legacy.nwt");
+        std::fs::write(&path, include_bytes!("../tests/fixtures/sample_v1.nwt")).unwrap();
+        assert_eq!(read_header(&path).unwrap().version, 1);
+        assert_eq!(NextWeightFile::open(&path).unwrap(), sample_file());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn shared_point_buffer_writes_the_same_bytes() {
+        let fixture = include_bytes!("../tests/fixtures/sample_v10.nwt");
+        let nwt = NextWeightFile::from_bytes(fixture).unwrap();
+        assert_eq!(nwt.to_bytes().unwrap(), fixture);
+        for (idx, entry) in nwt.get_gridpoints().iter().enumerate() {
+            assert_eq!(nwt.entry_points(idx), &entry.data[..]);
+        }
+
+        // edits that move the entries after them around the buffer leave
+        // it as if the file had been built that way
+        let mut edited = nwt.clone();
+        let mut grown = edited.get_entry("A").unwrap().clone();
+        grown.add_point(1, 2, 45.0, 180.0, 0.0);
+        edited.update_polyid("A", grown).unwrap();
+        edited.update_polyid("A", nwt.get_entry("A").unwrap().clone()).unwrap();
+        let b = edited.get_entry("B").unwrap().clone();
+        edited.remove_polyid("B").unwrap();
+        edited.add_polyid_entry("B".to_string(), b);
+        let mut reordered = NextWeightFileBuilder::new().dimensions(2, 3);
+        for name in ["A", "C", "B"] {
+            reordered = reordered.add_polyid(name, nwt.get_entry(name).unwrap().data.clone());
+        }
+        let mut reordered = reordered.build();
+        reordered.json_data = edited.json_data.clone();
+        assert_eq!(edited.to_bytes().unwrap(), reordered.to_bytes().unwrap());
+        assert_eq!(edited.entry_points(2), nwt.entry_points(1));
+    }
+
+    #[test]
+    fn header_offsets_are_u64_on_every_platform() {
+        // magic, three u16 fields and six u64 fields, then the JSON
+        const HEADER_LEN: usize = 4 + 3 * 2 + 6 * 8;
+        let bytes = sample_file().to_bytes().unwrap();
+        let json_len = read_u64_le(&bytes[10..]);
+        assert_eq!(read_u64_le(&bytes[42..]), HEADER_LEN as u64);
+        assert_eq!(read_u64_le(&bytes[50..]), HEADER_LEN as u64 + json_len);
+        assert_eq!(bytes[HEADER_LEN], b'{');
+
+        // version 6 files have no weight dtype
+        let mut v6 = Vec::new();
+        sample_file().serialize_to_writer_with(&mut v6, &WriteOptions { version: 6, ..Default::default() }).unwrap();
+        assert_eq!(read_u64_le(&v6[40..]), HEADER_LEN as u64 - 2);
+        assert_eq!(v6[HEADER_LEN - 2], b'{');
+    }
+
+    #[test]
+    fn lookup_rows_are_16_bytes() {
+        let mut row = Vec::new();
+        LookupEntry::new(3, 0x0102).write(&mut row).unwrap();
+        assert_eq!(row.len(), LookupEntry::SERIALIZED_SIZE);
+        assert_eq!(row, [[3, 0, 0, 0, 0, 0, 0, 0], [2, 1, 0, 0, 0, 0, 0, 0]].concat());
+        assert_eq!(LookupEntry::read(&mut NwtReader::new(&row[..]), "lookup table").unwrap(), (3, 0x0102));
+
+        // the table sits between the JSON and the coordinates flag
+        let bytes = sample_file().to_bytes().unwrap();
+        let lookup_offset = read_u64_le(&bytes[50..]) as usize;
+        let table = &bytes[lookup_offset..lookup_offset + 3 * LookupEntry::SERIALIZED_SIZE];
+        let rows: Vec<LookupEntry> = table.chunks_exact(LookupEntry::SERIALIZED_SIZE)
+            .map(|row| LookupEntry::new(read_u64_le(row), read_u64_le(&row[8..])))
+            .collect();
+        assert_eq!(&rows, sample_file().get_lookup_table());
+        assert_eq!(bytes[lookup_offset + table.len()], 0);
+    }
+
+    #[test]
+    fn strict_parsing_fails_where_lenient_salvages() {
+        let bytes = sample_file().to_bytes().unwrap();
+        let lookup_offset = read_u64_le(&bytes[50..]) as usize;
+        let data_offset = lookup_offset + 3 * 16 + 1 + (2 + 3) * 4 + 1;
+        let strict = ParseOptions::default();
+        let lenient = ParseOptions { mode: ParseMode::Lenient, ..Default::default() };
+        let path = temp_path("parse_modes.nwt");
+        let parse = |data: &[u8], opts: &ParseOptions| {
+            std::fs::write(&path, data).unwrap();
+            NextWeightFile::from_nwt_with(&path, opts)
+        };
+        // the checksum after an edit, for the damage it isn't about
+        let resum = |data: &mut Vec<u8>| {
+            let end = data.len() - 4;
+            let checksum = crc32fast::hash(&data[58..end]);
+            data[end..].copy_from_slice(&checksum.to_le_bytes());
+        };
+        let without_b = || {
+            let mut nwt = sample_file();
+            nwt.remove_polyid("B").unwrap();
+            nwt
+        };
+
+        assert_eq!(parse(&bytes, &strict).unwrap(), (sample_file(), vec![]));
+        assert_eq!(parse(&bytes, &lenient).unwrap(), (sample_file(), vec![]));
+
+        let mut trailing = bytes.clone();
+        trailing.extend_from_slice(b"junk");
+        assert!(matches!(parse(&trailing, &strict), Err(NwtError::Corrupt(_))));
+        assert_eq!(parse(&trailing, &lenient).unwrap(), (sample_file(), vec![ParseWarning::TrailingBytes(4)]));
+
+        // a field this version doesn't know
+        let mut unknown = bytes[..58].to_vec();
+        unknown.extend_from_slice(b"{\"extra\":1,");
+        unknown.extend_from_slice(&bytes[59..]);
+        let grow = |at: usize, data: &mut Vec<u8>| {
+            let value = read_u64_le(&data[at..]) + 10;
+            data[at..at + 8].copy_from_slice(&value.to_le_bytes());
+        };
+        grow(10, &mut unknown);
+        grow(50, &mut unknown);
+        resum(&mut unknown);
+        assert!(matches!(parse(&unknown, &strict), Err(NwtError::Corrupt(ref m)) if m == "unknown JSON field extra"));
+        assert_eq!(parse(&unknown, &lenient).unwrap(),
+            (sample_file(), vec![ParseWarning::UnknownJsonField("extra".to_string())]));
+
+        // a lookup offset that doesn't follow from the counts
+        let mut offsets = bytes.clone();
+        offsets[lookup_offset + 16..lookup_offset + 24].copy_from_slice(&7u64.to_le_bytes());
+        resum(&mut offsets);
+        assert!(matches!(parse(&offsets, &strict), Err(NwtError::Corrupt(_))));
+        assert_eq!(parse(&offsets, &lenient).unwrap(),
+            (sample_file(), vec![ParseWarning::LookupOffset { index: 1, expected: 2, got: 7 }]));
+
+        // B's only point moved off the grid, without fixing the checksum
+        let mut off_grid = bytes.clone();
+        off_grid[data_offset + 2 * 12..data_offset + 2 * 12 + 4].copy_from_slice(&5u32.to_le_bytes());
+        assert!(matches!(parse(&off_grid, &strict), Err(NwtError::Corrupt(_))));
+        let (nwt, warnings) = parse(&off_grid, &lenient).unwrap();
+        assert_eq!(nwt, without_b());
+        assert_eq!(warnings[0], ParseWarning::PolyidDropped {
+            index: 1,
+            polyid: Some("B".to_string()),
+            reason: "point (5, 1) is outside the 2x3 grid".to_string(),
+        });
+        assert!(matches!(warnings[1], ParseWarning::ChecksumMismatch { .. }));
+        assert_eq!(warnings.len(), 2);
+
+        // cut off within C's points
+        let cut = &bytes[..data_offset + 3 * 12 + 5];
+        assert!(matches!(parse(cut, &strict), Err(NwtError::Truncated { .. })));
+        let (nwt, warnings) = parse(cut, &lenient).unwrap();
+        let mut expected = sample_file();
+        expected.remove_polyid("C").unwrap();
+        assert_eq!(nwt, expected);
+        assert!(matches!(&warnings[..], [ParseWarning::Truncated { index: 2, dropped: 1, .. }]));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn polyid_count_must_match_the_json_names() {
+        // sample_v10.nwt with "B" cut from the JSON polyid list by hand,
+        // and the lengths and checksum fixed up to match
+        let doctored = include_bytes!("../tests/fixtures/doctored_polyids_v10.nwt");
+        let err = NextWeightFile::from_reader(&doctored[..]).unwrap_err();
+        assert!(matches!(&err, NwtError::Corrupt(m) if m == "header declares 3 polyids but the JSON metadata names 2"),
+            "{:?}", err);
+
+        // a lenient parse keeps the first two rows under the two names: the
+        // file can't say which name went missing
+        let path = temp_path("doctored_polyids.nwt");
+        std::fs::write(&path, doctored).unwrap();
+        let lenient = ParseOptions { mode: ParseMode::Lenient, ..Default::default() };
+        let (nwt, warnings) = NextWeightFile::from_nwt_with(&path, &lenient).unwrap();
+        assert_eq!(warnings, [ParseWarning::PolyidCountMismatch { header: 3, json: 2 }]);
+        assert_eq!(nwt.get_polyids(), &["A", "C"]);
+        assert_eq!(nwt.get_entry("C"), sample_file().get_entry("B"));
+        assert_eq!(nwt.lookup_table.len(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn oversized_grids_are_rejected() {
+        let bytes = sample_file().to_bytes().unwrap();
+        // a latitude index past u32, then a cell count past u64
+        for (lat_len, lon_len) in [(1u64 << 33, 3u64), (1 << 32, 1 << 32)] {
+            let mut forged = bytes.clone();
+            forged[26..34].copy_from_slice(&lat_len.to_le_bytes());
+            forged[34..42].copy_from_slice(&lon_len.to_le_bytes());
+            let err = NextWeightFile::from_reader(&forged[..]).unwrap_err();
+            assert!(matches!(err, NwtError::GridTooLarge { lat_len: l, lon_len: m } if (l, m) == (lat_len, lon_len)),
+                "{:?}", err);
+        }
+        assert_eq!(grid_cells(2, 3).unwrap(), 6);
+        // past what a 32-bit platform can address
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(grid_cells(1 << 16, 1 << 16).unwrap(), 1 << 32);
+        #[cfg(target_pointer_width = "32")]
+        assert!(matches!(grid_cells(1 << 16, 1 << 16), Err(NwtError::GridTooLarge { .. })));
+    }
+
+    #[test]
+    fn unknown_version_is_an_error() {
+        let mut buff = Vec::new();
+        sample_file().serialize_to_writer(&mut buff).unwrap();
+        buff[4..6].copy_from_slice(&99u16.to_le_bytes());
+        assert!(matches!(NextWeightFile::from_reader(&buff[..]), Err(NwtError::UnsupportedVersion(99))));
+    }
+
+    #[test]
+    fn checksum_catches_corruption() {
+        let path = temp_path("checksum.nwt");
+        sample_file().serialize_to_file(&path).unwrap();
+        verify_file(&path).unwrap();
+
+        // flip a bit in the last weight, which the structural checks can't
+        // see. It sits before the weight sets section and the checksum
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last_weight = bytes.len() - 4 - (8 + DEFAULT_WEIGHT_SET.len() + 8) - 2;
+        bytes[last_weight] ^= 0x40;
+        std::fs::write(&path, &bytes).unwrap();
+
+        assert!(matches!(NextWeightFile::from_nwt(&path), Err(NwtError::ChecksumMismatch { .. })));
+        assert!(matches!(verify_file(&path), Err(NwtError::ChecksumMismatch { .. })));
+        let unchecked = NextWeightFile::open_unchecked(&path).unwrap();
+        assert_ne!(unchecked, sample_file());
+        assert_eq!(unchecked.get_polyids(), sample_file().get_polyids());
+
+        // files from before checksums existed still verify structurally
+        std::fs::write(&path, include_bytes!("../tests/fixtures/sample_v2.nwt")).unwrap();
+        verify_file(&path).unwrap();
+        std::fs::write(&path, &include_bytes!("../tests/fixtures/sample_v2.nwt")[..100]).unwrap();
+        assert!(matches!(verify_file(&path), Err(NwtError::Truncated { .. })));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unsupported_codec_is_rejected() {
+        let mut buff = Vec::new();
+        sample_file().serialize_to_writer(&mut buff).unwrap();
+        buff[6..8].copy_from_slice(&7u16.to_le_bytes());
+        assert!(matches!(NextWeightFile::from_reader(&buff[..]), Err(NwtError::UnsupportedCodec(7))));
+
+        // zstd files can only be read with the compression feature
+        #[cfg(not(feature = "compression"))]
+        {
+            buff[6..8].copy_from_slice(&1u16.to_le_bytes());
+            assert!(matches!(NextWeightFile::from_reader(&buff[..]), Err(NwtError::UnsupportedCodec(1))));
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compressed_round_trip() {
+        let path = temp_path("compressed.nwt");
+        let opts = WriteOptions { compression: Compression::Zstd(3), ..Default::default() };
+        sample_file().serialize_to_file_with(&path, &opts).unwrap();
+
+        assert_eq!(read_header(&path).unwrap().codec, 1);
+        assert_eq!(NextWeightFile::from_nwt(&path).unwrap(), sample_file());
+        verify_file(&path).unwrap();
+
+        let mut buff = Vec::new();
+        let written = sample_file().serialize_to_writer_with(&mut buff, &opts).unwrap();
+        assert_eq!(written, buff.len() as u64);
+        assert_eq!(std::fs::read(&path).unwrap(), buff);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn coordinate_axes_round_trip() {
+        let mut nwt = sample_file();
+        assert_eq!(nwt.get_latitudes(), None);
+        nwt.lat_axis = Some(vec![-45.0, 45.0]);
+        nwt.lon_axis = Some(vec![0.0, 90.0, 180.0]);
+
+        let path = temp_path("axes.nwt");
+        nwt.serialize_to_file(&path).unwrap();
+        verify_file(&path).unwrap();
+        let loaded = NextWeightFile::from_nwt(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.get_latitudes(), Some(&[-45.0, 45.0][..]));
+        assert_eq!(loaded.get_longitudes(), Some(&[0.0, 90.0, 180.0][..]));
+        assert_eq!(loaded, nwt);
+
+        // files from before the axes section have none
+        let v4 = NextWeightFile::from_reader(&include_bytes!("../tests/fixtures/sample_v4.nwt")[..]).unwrap();
+        assert_eq!(v4.get_longitudes(), None);
+    }
 
-        for v in 0..new_weight.lookup_table.len() {
-            let curr_v = new_weight.lookup_table[v];
-            let new_v = fresh_weight.lookup_table[v];
-            assert_eq!(curr_v, new_v);
+    #[test]
+    fn bytes_round_trip() {
+        let mut with_axes = sample_file();
+        with_axes.lat_axis = Some(vec![-45.0, 45.0]);
+        with_axes.lon_axis = Some(vec![0.0, 90.0, 180.0]);
+        let cases = [
+            sample_file(),
+            with_axes,
+            NextWeightFileBuilder::new().build(),
+            NextWeightFileBuilder::new().dimensions(4, 1).add_polyid("empty", Vec::new()).build(),
+        ];
+        for nwt in cases {
+            let bytes = nwt.to_bytes().unwrap();
+            let mut streamed = Vec::new();
+            nwt.serialize_to_writer(&mut streamed).unwrap();
+            assert_eq!(bytes, streamed);
+            assert_eq!(NextWeightFile::from_bytes(&bytes).unwrap(), nwt);
         }
+        assert!(matches!(NextWeightFile::from_bytes(b"NEW"), Err(NwtError::Truncated { .. })));
+    }
 
-        for v in 0..new_weight.polyid_gridpoints.len() {
-            let curr_v_entry = &new_weight.polyid_gridpoints[v];
-            let new_v_entry = &fresh_weight.polyid_gridpoints[v];
-            for grid_idx in 0..curr_v_entry.data.len() {
-                let curr_pt = curr_v_entry.data[grid_idx];
-                let new_pt = new_v_entry.data[grid_idx];
+    #[test]
+    fn serialize_refuses_to_overwrite_unless_asked() {
+        let path = temp_path("overwrite.nwt");
+        assert_eq!(sample_file().serialize_to_file(&path).unwrap(), path);
+
+        let mut other = sample_file();
+        other.add_polyid_entry("D".to_string(), PolyidEntry { data: vec![(1, 2, 45.0, 180.0, 1.0)] });
+        let err = other.serialize_to_file(&path).unwrap_err();
+        assert!(matches!(err, NwtError::AlreadyExists(ref p) if p == &path));
+        assert_eq!(NextWeightFile::from_nwt(&path).unwrap(), sample_file());
+
+        let opts = WriteOptions { overwrite: true, ..Default::default() };
+        other.serialize_to_file_with(&path, &opts).unwrap();
+        let loaded = NextWeightFile::from_nwt(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, other);
+    }
 
-                let curr_lat = curr_pt.0;
-                let curr_lon = curr_pt.1;
-                let curr_wgt = curr_pt.2;
-                let new_lon = new_pt.1;
-                let new_lat = new_pt.0;
-                let new_wgt = new_pt.2;
+    #[test]
+    fn compact_points_share_coordinates() {
+        let v6_opts = WriteOptions { version: 6, ..Default::default() };
+        let v7_opts = WriteOptions { version: 7, ..Default::default() };
+        let mut with_axes = sample_file();
+        with_axes.lat_axis = Some(vec![-45.0, 45.0]);
+        with_axes.lon_axis = Some(vec![0.0, 90.0, 180.0]);
+        for nwt in [sample_file(), with_axes] {
+            let (mut v6, mut v7) = (Vec::new(), Vec::new());
+            nwt.serialize_to_writer_with(&mut v6, &v6_opts).unwrap();
+            nwt.serialize_to_writer_with(&mut v7, &v7_opts).unwrap();
+            // 8 bytes less per point, for 5 shared coordinates
+            assert_eq!(v6.len() - v7.len(), 6 * 8 - 5 * 4 + usize::from(nwt.lat_axis.is_some()) * 5 * 4);
+            assert_eq!(NextWeightFile::from_reader(&v7[..]).unwrap(), nwt);
+            assert_eq!(NextWeightFile::from_reader(&v6[..]).unwrap(), nwt);
+        }
 
-                assert_eq!(curr_lat, new_lat);
-                assert_eq!(curr_lon, new_lon);
-                assert_eq!(curr_wgt, new_wgt);
+        // a point whose lat/lon don't match its indices' can only be kept
+        // by the wide records of version 6
+        let mut odd = sample_file();
+        odd.add_polyid_entry("D".to_string(), PolyidEntry { data: vec![(1, 1, 44.0, 90.0, 1.0)] });
+        let err = odd.serialize_to_writer_with(&mut Vec::new(), &v7_opts).unwrap_err();
+        assert!(matches!(err, NwtError::InvalidInput(ref m) if m.contains("polyid D")));
+        let mut buff = Vec::new();
+        odd.serialize_to_writer_with(&mut buff, &v6_opts).unwrap();
+        assert_eq!(NextWeightFile::from_reader(&buff[..]).unwrap(), odd);
+
+        let old = WriteOptions { version: 5, ..Default::default() };
+        assert!(matches!(sample_file().serialize_to_writer_with(&mut Vec::new(), &old), Err(NwtError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn double_precision_weights_round_trip() {
+        let third = 1.0 / 3.0;
+        let nwt = NextWeightFileBuilder::new()
+            .dimensions(2, 3)
+            .add_polyid("A", vec![(0, 0, -45.0, 0.0, 0.25)])
+            .add_polyid_f64("B", vec![(1, 1, 45.0, 90.0, third), (1, 2, 45.0, 180.0, 1.0 - third)])
+            .build();
+        assert_eq!(nwt.weight_dtype(), WeightDtype::F64);
+
+        let bytes = nwt.to_bytes().unwrap();
+        let header = NextWeightFile::read_preamble(&mut NwtReader::new(&bytes[..])).unwrap();
+        assert_eq!(header.weight_dtype, WeightDtype::F64);
+        let loaded = NextWeightFile::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded, nwt);
+        assert_eq!(loaded.get_entry_f64("B").unwrap(), vec![(1, 1, 45.0, 90.0, third), (1, 2, 45.0, 180.0, 1.0 - third)]);
+        // the f32 API sees the weights rounded
+        assert_eq!(loaded.get_entry("B").unwrap().data[0], (1, 1, 45.0, 90.0, third as f32));
+        assert_eq!(loaded.get_gridpoints_f64()[0], vec![(0, 0, -45.0, 0.0, 0.25)]);
+
+        // older versions can't hold them until they're explicitly rounded
+        let v7 = WriteOptions { version: 7, ..Default::default() };
+        assert!(matches!(loaded.serialize_to_writer_with(&mut Vec::new(), &v7), Err(NwtError::InvalidInput(_))));
+        let mut rounded = loaded.clone();
+        rounded.set_weight_dtype(WeightDtype::F32);
+        let mut buff = Vec::new();
+        rounded.serialize_to_writer_with(&mut buff, &v7).unwrap();
+        let reread = NextWeightFile::from_bytes(&buff).unwrap();
+        assert_eq!(reread.weight_dtype(), WeightDtype::F32);
+        assert_eq!(reread.get_gridpoints(), loaded.get_gridpoints());
+        assert!(bytes.len() > rounded.to_bytes().unwrap().len());
+    }
+
+    #[test]
+    fn quantized_weights_stay_within_half_a_step() {
+        let fixture = NextWeightFile::from_reader(&include_bytes!("../tests/fixtures/sample_v8.nwt")[..]).unwrap();
+        let mut builder = NextWeightFileBuilder::new().dimensions(40, 50);
+        for p in 0..30u32 {
+            let points = (0..200u32)
+                .map(|i| (i % 40, (i * 7 + p) % 50, 0.0, 0.0, ((i * 37 + p * 11) % 101) as f32 / (p + 1) as f32 / 97.0))
+                .collect();
+            builder = builder.add_polyid(format!("P{}", p), points);
+        }
+
+        let quantized = WriteOptions { quantize: true, ..Default::default() };
+        // the fixture's few points don't make up for the range table and
+        // attributes, but any real file's do
+        for (nwt, shrinks) in [(fixture, false), (builder.build(), true)] {
+            let mut buff = Vec::new();
+            nwt.serialize_to_writer_with(&mut buff, &quantized).unwrap();
+            assert_eq!(buff.len() < nwt.to_bytes().unwrap().len(), shrinks);
+            let header = NextWeightFile::read_preamble(&mut NwtReader::new(&buff[..])).unwrap();
+            assert_eq!(header.point_size(), 10);
+
+            let loaded = NextWeightFile::from_bytes(&buff).unwrap();
+            assert_eq!(loaded.weight_dtype(), WeightDtype::F32);
+            assert_eq!(loaded.get_polyids(), nwt.get_polyids());
+            let mut max_error: f64 = 0.0;
+            for (entry, back) in nwt.get_gridpoints().iter().zip(loaded.get_gridpoints()) {
+                let (min, max) = entry.data.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), p| (lo.min(p.4), hi.max(p.4)));
+                let bound = (max - min) as f64 / 131070.0 + max.abs() as f64 * f32::EPSILON as f64;
+                for (p, q) in entry.data.iter().zip(back.data.iter()) {
+                    assert_eq!((p.0, p.1, p.2, p.3), (q.0, q.1, q.2, q.3));
+                    let error = (p.4 as f64 - q.4 as f64).abs();
+                    assert!(error <= bound, "{} is off by {}, more than {}", p.4, error, bound);
+                    if max > 0.0 {
+                        max_error = max_error.max(error / max as f64);
+                    }
+                }
+            }
+            assert!(max_error < 7.7e-6);
+            assert_eq!(loaded.json_data.get_global_attr_value("nwt_quantization"), Some(&AttrVal::from("u16 per-polyid min/max")));
+            match loaded.json_data.get_global_attr_value("nwt_quantization_max_error") {
+                Some(AttrVal::F64(recorded)) => assert!((recorded - max_error).abs() < 1e-9),
+                other => panic!("unexpected max error attribute {:?}", other),
             }
         }
 
+        let v7 = WriteOptions { version: 7, quantize: true, ..Default::default() };
+        assert!(matches!(sample_file().serialize_to_writer_with(&mut Vec::new(), &v7), Err(NwtError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn edited_attributes_round_trip() {
+        let mut nwt = sample_file();
+        {
+            let meta = nwt.metadata_mut();
+            meta.add_global_attr("history", "one");
+            meta.add_global_attr("title", "duplicate from an old file");
+            meta.add_global_attr("history", "two");
+            meta.set_global_attr("title", "corrected title");
+            assert!(meta.remove_global_attr("history"));
+            assert!(!meta.remove_global_attr("history"));
+
+            meta.set_var_attr("regridweights", "units", "m2");
+            meta.set_var_attr("lat", "units", "degrees_north");
+            meta.add_variable("stale");
+            assert!(meta.remove_variable("stale"));
+            assert!(!meta.remove_var_attr("stale", "units"));
+            meta.add_variable_attr("lon", "units", "degrees_east");
+            assert!(meta.remove_var_attr("lon", "units"));
+        }
+
+        let reread = NextWeightFile::from_bytes(&nwt.to_bytes().unwrap()).unwrap();
+        assert_eq!(reread.get_global_attrs(), &vec![("title".to_string(), AttrVal::from("corrected title"))]);
+        assert_eq!(reread.json_data.get_var_attr_value("regridweights", "units"), Some(&AttrVal::from("m2")));
+        assert_eq!(reread.json_data.get_var_attr_value("lat", "units"), Some(&AttrVal::from("degrees_north")));
+        assert_eq!(reread.get_var_attrs("lon"), Some(&vec![]));
+        assert!(reread.get_var_attrs("stale").is_none());
+    }
+
+    #[test]
+    fn string_getters_borrow_and_skip_other_types() {
+        let mut nwt = sample_file();
+        nwt.metadata_mut().add_global_attr("nwt_version", AttrVal::U64(8));
+        let meta = &nwt.json_data;
+        assert_eq!(meta.get_global_attr("title"), Some("sample weights"));
+        assert_eq!(meta.get_global_attr("nwt_version"), None);
+        assert_eq!(meta.get_global_attr("missing"), None);
+        assert_eq!(meta.get_var_attr("regridweights", "units"), Some("1"));
+        assert_eq!(meta.get_var_attr("regridweights", "long_name"), None);
+        assert_eq!(meta.get_var_attr("lat", "units"), None);
+    }
+
+    #[test]
+    fn metadata_iterators_and_case_insensitive_lookup() {
+        let mut nwt = sample_file();
+        {
+            let meta = nwt.metadata_mut();
+            meta.add_global_attr("Conventions", "CF-1.8");
+            meta.add_global_attr("Title", "capitalised");
+            meta.add_variable("mask");
+        }
+        let meta = nwt.metadata();
+        let globals: Vec<(&str, String)> = meta.global_attrs_iter().map(|(k, v)| (k, v.to_string())).collect();
+        assert_eq!(globals, vec![
+            ("title", "sample weights".to_string()),
+            ("Conventions", "CF-1.8".to_string()),
+            ("Title", "capitalised".to_string()),
+        ]);
+        assert_eq!(meta.find_global_attr_ci("Title"), Some(&AttrVal::from("capitalised")));
+        assert_eq!(meta.find_global_attr_ci("TITLE"), Some(&AttrVal::from("sample weights")));
+        assert_eq!(meta.find_global_attr_ci("conventions"), Some(&AttrVal::from("CF-1.8")));
+        assert_eq!(meta.find_global_attr_ci("history"), None);
+
+        assert_eq!(meta.variables().collect::<Vec<_>>(), vec!["mask", "regridweights"]);
+        assert!(meta.has_variable("mask"));
+        assert!(!meta.has_variable("lat"));
+        assert_eq!(meta.var_attrs_iter("mask").count(), 0);
+        assert_eq!(meta.var_attrs_iter("lat").count(), 0);
+        assert_eq!(meta.var_attrs_iter("regridweights").collect::<Vec<_>>(), vec![("units", &AttrVal::from("1"))]);
+    }
+
+    #[test]
+    fn point_counts_match_lookup_table_and_entries() {
+        let mut nwt = sample_file();
+        let check = |nwt: &NextWeightFile| {
+            let counts: Vec<u64> = nwt.get_gridpoints().iter().map(|e| e.data.len() as u64).collect();
+            assert_eq!(nwt.point_counts(), counts);
+            assert_eq!(nwt.total_points(), counts.iter().sum::<u64>());
+            for (idx, count) in counts.iter().enumerate() {
+                assert_eq!(nwt.point_count(idx), Some(*count));
+                assert_eq!(nwt.get_lookup_table()[idx].count, *count);
+            }
+            assert_eq!(nwt.point_count(counts.len()), None);
+            assert!(nwt.check_lookup_table().is_ok());
+        };
+        check(&nwt);
+        assert_eq!((nwt.total_points(), nwt.point_counts()), (6, vec![2, 1, 3]));
+
+        nwt.add_polyid_entry("D".to_string(), PolyidEntry { data: vec![(0, 1, -45.0, 90.0, 1.0)] });
+        nwt.remove_polyid("A").unwrap();
+        check(&nwt);
+        assert_eq!(nwt.total_points(), 5);
+        assert_eq!(NextWeightFileBuilder::new().dimensions(2, 3).build().total_points(), 0);
+    }
+
+    #[test]
+    fn polyid_metadata_round_trips_and_follows_removal() {
+        let mut nwt = sample_file();
+        let plain = nwt.to_bytes().unwrap();
+        assert!(!String::from_utf8_lossy(&plain).contains("polyid_meta"));
+
+        nwt.set_polyid_meta("A", "name", "Angola").unwrap();
+        nwt.set_polyid_meta("A", "area_km2", "1246700").unwrap();
+        nwt.set_polyid_meta("C", "name", "Chad").unwrap();
+        nwt.set_polyid_meta("C", "name", "Tchad").unwrap();
+        assert!(matches!(nwt.set_polyid_meta("Z", "name", "nowhere"), Err(NwtError::PolyidNotFound(_))));
+
+        let mut reread = NextWeightFile::from_bytes(&nwt.to_bytes().unwrap()).unwrap();
+        assert_eq!(reread, nwt);
+        assert_eq!(reread.get_polyid_meta("A", "area_km2"), Some("1246700"));
+        assert_eq!(reread.get_polyid_meta("C", "name"), Some("Tchad"));
+        assert_eq!(reread.get_polyid_meta("B", "name"), None);
+
+        reread.remove_polyid("A").unwrap();
+        assert_eq!(reread.get_polyid_meta("A", "name"), None);
+        assert!(!String::from_utf8_lossy(&reread.to_bytes().unwrap()).contains("Angola"));
+
+        // files from before the field existed simply have none
+        assert_eq!(NextWeightFile::from_bytes(&plain).unwrap().get_polyid_meta("A", "name"), None);
+    }
+
+    #[test]
+    fn cell_bounds_round_trip_from_version_9() {
+        let lat_bounds = vec![(-90.0, 0.0), (0.0, 90.0)];
+        let lon_bounds = vec![(-45.0, 45.0), (45.0, 135.0), (135.0, 225.0)];
+        let nwt = NextWeightFileBuilder::new()
+            .dimensions(2, 3)
+            .axes(vec![-45.0, 45.0], vec![0.0, 90.0, 180.0])
+            .bounds(lat_bounds.clone(), lon_bounds.clone())
+            .add_polyid("A", vec![(0, 0, -45.0, 0.0, 1.0)])
+            .build();
+        let reread = NextWeightFile::from_bytes(&nwt.to_bytes().unwrap()).unwrap();
+        assert_eq!(reread, nwt);
+        assert_eq!(reread.lat_bounds(), Some(&lat_bounds[..]));
+        assert_eq!(reread.lon_bounds(), Some(&lon_bounds[..]));
+
+        let path = temp_path("bounds_header.nwt");
+        nwt.serialize_to_file(&path).unwrap();
+        let header = read_header(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((header.lat_bounds, header.lon_bounds), (Some(lat_bounds.clone()), Some(lon_bounds)));
+
+        // only one axis, too old a version, or the wrong length
+        let mut lat_only = nwt.clone();
+        lat_only.lon_bounds = None;
+        let reread = NextWeightFile::from_bytes(&lat_only.to_bytes().unwrap()).unwrap();
+        assert_eq!((reread.lat_bounds(), reread.lon_bounds()), (Some(&lat_bounds[..]), None));
+        let v8 = WriteOptions { version: 8, ..Default::default() };
+        assert!(matches!(nwt.serialize_to_writer_with(&mut Vec::new(), &v8), Err(NwtError::InvalidInput(_))));
+        lat_only.lat_bounds.as_mut().unwrap().pop();
+        assert!(matches!(lat_only.to_bytes(), Err(NwtError::InvalidInput(ref m)) if m == "latitude bounds have length 1 but the grid has 2"));
+        assert_eq!((sample_file().lat_bounds(), sample_file().lon_bounds()), (None, None));
+    }
+
+    #[test]
+    fn variable_attributes_serialize_in_a_stable_order() {
+        let build = |vars: &[&str]| {
+            let mut builder = NextWeightFileBuilder::new().dimensions(2, 3);
+            for var in vars {
+                builder = builder.variable_attr(*var, "units", "1");
+            }
+            builder.add_polyid("A", vec![(0, 0, -45.0, 0.0, 1.0)]).build().to_bytes().unwrap()
+        };
+        let vars = ["regridweights", "lon", "polyid", "lat", "area", "mask"];
+        let mut reversed = vars;
+        reversed.reverse();
+        assert_eq!(build(&vars), build(&reversed));
     }
 }
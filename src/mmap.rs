@@ -0,0 +1,170 @@
+use std::ops::Range;
+use std::path::Path;
+
+use crate::codec::{read_f32_le, read_f64_le, read_u16_le, read_u32_le};
+use crate::{dequantize, records, AttrVal, CompactPoint, Coordinates, GridPoint, JsonData, LookupEntry, NextWeightFile, NwtError, NwtHeader, NwtReader, OnDiskPoint, PolyidEntry, WeightDtype};
+
+/// A memory-mapped NWT file that decodes `PolyidEntry`s on demand.
+///
+/// Only the header, JSON metadata and lookup table are parsed when the file is
+/// opened; gridpoint data stays in the mapping until `get_entry` is called.
+/// Records are packed tuples of 12 bytes or more. With the `bytemuck`
+/// feature on a little-endian target, plain f32 records are reinterpreted in
+/// place; every other field is decoded byte-wise. In format 7 files, a point
+/// whose index is outside the grid gets a NaN lat/lon, since only a full
+/// load checks that. The file's checksum is not verified,
+/// since that would mean reading every byte up front; call `verify_file` first
+/// if that matters.
+#[derive(Debug)]
+pub struct NextWeightFileMmap {
+    mmap: memmap2::Mmap,
+    json_data: JsonData,
+    lat_len: u64,
+    lon_len: u64,
+    lookup_table: Vec<LookupEntry>,
+    coordinates: Option<Coordinates>,
+    weight_dtype: WeightDtype,
+    quantization: Option<Vec<(f32, f32)>>,
+    point_size: usize,
+    data_offset: usize,
+}
+
+impl NextWeightFileMmap {
+    /// memory-maps an NWT file and parses its metadata
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, NwtError> {
+        let file = std::fs::File::open(path)?;
+        // safety: the mapping is read-only. As with any mmap, another process
+        // truncating the file underneath us is outside of what we can guard
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+
+        let mut cursor = NwtReader::new(&mmap[..]).sized(mmap.len() as u64);
+        let header = NextWeightFile::read_preamble(&mut cursor)?;
+        let point_size = header.point_size();
+        let NwtHeader { codec, weight_dtype, json_data, lat_len, lon_len, lookup_table, coordinates, quantization, .. } = header;
+        if codec != 0 {
+            return Err(NwtError::InvalidInput("compressed NWT files can't be memory-mapped".to_string()));
+        }
+        let data_offset = cursor.position() as usize;
+
+        // make sure every entry the lookup table promises is actually in the file,
+        // so decoding later can never run off the end of the mapping
+        let total_points: u64 = lookup_table.iter().map(|e| e.count).sum();
+        let needed = (total_points as usize).checked_mul(point_size)
+            .ok_or_else(|| NwtError::Corrupt(format!("point count {} is too large", total_points)))?;
+        let remaining = mmap.len() - data_offset;
+        if needed > remaining {
+            return Err(NwtError::Truncated { section: "gridpoint data", expected: needed, got: remaining });
+        }
+
+        Ok(Self { mmap, json_data, lat_len, lon_len, lookup_table, coordinates, weight_dtype, quantization, point_size, data_offset })
+    }
+
+    /// decodes the entry for the polyid at `idx`, rounding double-precision
+    /// weights to f32. Panics if `idx` is out of range
+    pub fn get_entry(&self, idx: usize) -> PolyidEntry {
+        let bytes = &self.mmap[self.entry_range(idx)];
+        let mut entry = PolyidEntry::new();
+        if let (WeightDtype::F32, None) = (self.weight_dtype, &self.quantization) {
+            entry.data = match &self.coordinates {
+                Some(coords) => records::<CompactPoint>(bytes).map(|p| {
+                    let (lat, lon) = coords.get(p.lat_idx, p.lon_idx).unwrap_or((f32::NAN, f32::NAN));
+                    (p.lat_idx, p.lon_idx, lat, lon, p.weight)
+                }).collect(),
+                None => records::<OnDiskPoint>(bytes).map(GridPoint::from).collect(),
+            };
+            return entry;
+        }
+        for record in bytes.chunks_exact(self.point_size) {
+            let (lat_idx, lon_idx) = (read_u32_le(record), read_u32_le(&record[4..]));
+            let (lat, lon) = match &self.coordinates {
+                Some(coords) => coords.get(lat_idx, lon_idx).unwrap_or((f32::NAN, f32::NAN)),
+                None => (read_f32_le(&record[8..]), read_f32_le(&record[12..])),
+            };
+            let weight = match self.weight_dtype {
+                WeightDtype::F32 => match &self.quantization {
+                    Some(ranges) => {
+                        dequantize(read_u16_le(&record[self.point_size - 2..]), ranges[idx])
+                    }
+                    None => read_f32_le(&record[self.point_size - 4..]),
+                },
+                WeightDtype::F64 => read_f64_le(&record[self.point_size - 8..]) as f32,
+            };
+            entry.add_point(lat_idx, lon_idx, lat, lon, weight);
+        }
+        entry
+    }
+
+    /// byte range of an entry within the mapping
+    fn entry_range(&self, idx: usize) -> Range<usize> {
+        let LookupEntry { offset, count } = self.lookup_table[idx];
+        let start = self.data_offset + offset as usize * self.point_size;
+        start..start + count as usize * self.point_size
+    }
+
+    /// Returns the number of polyids in the file
+    pub fn len(&self) -> usize {
+        self.lookup_table.len()
+    }
+
+    /// Returns true if the file contains no polyids
+    pub fn is_empty(&self) -> bool {
+        self.lookup_table.is_empty()
+    }
+
+    /// Returns all global attributes in the file
+    pub fn get_global_attrs(&self) -> &Vec<(String, AttrVal)> {
+        &self.json_data.global_attrs
+    }
+
+    /// Returns a list of polyids
+    pub fn get_polyids(&self) -> &Vec<String> {
+        &self.json_data.polyids
+    }
+
+    /// Returns a reference to the data lookup table
+    pub fn get_lookup_table(&self) -> &Vec<LookupEntry> {
+        &self.lookup_table
+    }
+
+    /// Returns the dimensions of the weight file
+    pub fn get_dimensions(&self) -> (u64, u64) {
+        (self.lat_len, self.lon_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{sample_file, temp_path};
+
+    #[test]
+    fn lazy_entries_match_eager_load() {
+        let path = temp_path("lazy.nwt");
+        sample_file().serialize_to_file(&path).unwrap();
+        let eager = NextWeightFile::from_nwt(&path).unwrap();
+        let lazy = NextWeightFileMmap::open(&path).unwrap();
+
+        assert_eq!(lazy.len(), eager.get_polyids().len());
+        assert_eq!(lazy.get_dimensions(), eager.get_dimensions());
+        for idx in 0..lazy.len() {
+            assert_eq!(lazy.get_entry(idx), eager.get_gridpoints()[idx]);
+        }
+        drop(lazy);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn quantized_entries_match_eager_load() {
+        let path = temp_path("lazy_quantized.nwt");
+        let opts = crate::WriteOptions { quantize: true, ..Default::default() };
+        sample_file().serialize_to_file_with(&path, &opts).unwrap();
+        let eager = NextWeightFile::from_nwt(&path).unwrap();
+        let lazy = NextWeightFileMmap::open(&path).unwrap();
+
+        for idx in 0..lazy.len() {
+            assert_eq!(lazy.get_entry(idx), eager.get_gridpoints()[idx]);
+        }
+        drop(lazy);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
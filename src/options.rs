@@ -0,0 +1,331 @@
+//! Options controlling how NWT files are read, converted and written.
+
+use std::fmt;
+#[cfg(feature = "netcdf")]
+use std::path::{Path, PathBuf};
+
+use crate::NwtError;
+
+/// codec id stored in the header for uncompressed data
+pub(crate) const CODEC_NONE: u16 = 0;
+/// codec id stored in the header for zstd-compressed data
+#[cfg(feature = "compression")]
+pub(crate) const CODEC_ZSTD: u16 = 1;
+
+/// How the lookup table and gridpoint data are stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// stored as-is
+    #[default]
+    None,
+    /// compressed with zstd at the given level. Readers need the
+    /// `compression` feature
+    #[cfg(feature = "compression")]
+    Zstd(i32),
+}
+
+impl Compression {
+    /// the codec id recorded in the file header
+    pub(crate) fn codec(&self) -> u16 {
+        match self {
+            Compression::None => CODEC_NONE,
+            #[cfg(feature = "compression")]
+            Compression::Zstd(_) => CODEC_ZSTD,
+        }
+    }
+}
+
+/// Options for `NextWeightFile::serialize_to_file_with`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteOptions {
+    pub compression: Compression,
+    /// replace the target if it already exists, instead of failing with
+    /// `AlreadyExists`
+    pub overwrite: bool,
+    /// format version to write: `FORMAT_VERSION`, or 6 for readers that
+    /// predate version 7's shared coordinates and expect every point to
+    /// carry its own lat/lon
+    pub version: u16,
+    /// Store each weight as a u16 step between its polyid's smallest and
+    /// largest weight, for smaller files. Reading it back is off by at most
+    /// half a step, `(max - min) / 131070`, plus f32 rounding: under 7.7e-6
+    /// of the largest weight when weights are non-negative. The mode and
+    /// actual largest error, relative to each polyid's largest weight, are
+    /// recorded in the `nwt_quantization` and `nwt_quantization_max_error`
+    /// global attributes
+    pub quantize: bool,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self { compression: Compression::default(), overwrite: false, version: crate::FORMAT_VERSION, quantize: false }
+    }
+}
+
+/// How `NextWeightFile::from_nwt_with` treats anomalies in a file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Fail on the first anomaly: inconsistent offsets, points off the grid,
+    /// a checksum mismatch, JSON fields this version doesn't know or bytes
+    /// after the end of the file
+    #[default]
+    Strict,
+    /// Salvage what can be read, recording each anomaly as a `ParseWarning`.
+    /// Lookup offsets are recomputed from the counts, polyids with points
+    /// off the grid are dropped, only as many polyids as both the header
+    /// and the JSON metadata have are kept, and the file is cut short at the
+    /// first point that can't be read. Damage to the header, metadata or lookup
+    /// table is still an error
+    Lenient,
+}
+
+/// `ParseOptions::max_polyids` unless set otherwise
+pub const DEFAULT_MAX_POLYIDS: u64 = 10_000_000;
+/// `ParseOptions::max_total_points` unless set otherwise
+pub const DEFAULT_MAX_TOTAL_POINTS: u64 = 1_000_000_000;
+
+/// Options for `NextWeightFile::from_nwt_with` and `recover_with`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseOptions {
+    pub mode: ParseMode,
+    /// Refuse files whose header declares more polyids than this, before
+    /// reading their lookup table. Raise it to read larger files
+    pub max_polyids: u64,
+    /// Refuse files whose lookup table adds up to more gridpoints than
+    /// this, before reading any. Raise it to read larger files. A
+    /// compressed section may only inflate to this many points' worth of
+    /// data, weight sets included, past its lookup table and grid sections
+    pub max_total_points: u64,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self { mode: ParseMode::default(), max_polyids: DEFAULT_MAX_POLYIDS, max_total_points: DEFAULT_MAX_TOTAL_POINTS }
+    }
+}
+
+/// An anomaly `ParseMode::Lenient` read past. Polyid-specific warnings
+/// carry the polyid's index in the file and, when it has one, its name
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseWarning {
+    /// The JSON metadata has a field this version doesn't know, which was
+    /// ignored
+    UnknownJsonField(String),
+    /// The header declares a different number of polyids than the JSON
+    /// metadata names. Only the first of however many both have were kept
+    PolyidCountMismatch { header: u64, json: usize },
+    /// A lookup table offset isn't the sum of the counts before it, and was
+    /// recomputed from them
+    LookupOffset { index: usize, expected: u64, got: u64 },
+    /// A polyid has a point off the grid, and was dropped
+    PolyidDropped { index: usize, polyid: Option<String>, reason: String },
+    /// The gridpoint data became unreadable within a polyid, which was
+    /// dropped along with the `dropped - 1` after it
+    Truncated { index: usize, dropped: usize, reason: String },
+    /// A section after the gridpoint data couldn't be read and was left out
+    SectionSkipped { section: &'static str, reason: String },
+    /// The data doesn't match the file's checksum, so some weights may be
+    /// damaged
+    ChecksumMismatch { expected: u32, got: u32 },
+    /// This many bytes follow the end of the file's data
+    TrailingBytes(u64),
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseWarning::UnknownJsonField(name) => write!(f, "unknown JSON field {} ignored", name),
+            ParseWarning::PolyidCountMismatch { header, json } => {
+                write!(f, "header declares {} polyids but the JSON metadata names {}; kept the first {}",
+                    header, json, (*header).min(*json as u64))
+            }
+            ParseWarning::LookupOffset { index, expected, got } => {
+                write!(f, "lookup table entry {} has offset {}, recomputed as {}", index, got, expected)
+            }
+            ParseWarning::PolyidDropped { index, polyid, reason } => {
+                write!(f, "polyid {} ({}) dropped: {}", index, polyid.as_deref().unwrap_or("<unnamed>"), reason)
+            }
+            ParseWarning::Truncated { index, dropped, reason } => {
+                write!(f, "{} polyids from index {} on dropped: {}", dropped, index, reason)
+            }
+            ParseWarning::SectionSkipped { section, reason } => write!(f, "{} skipped: {}", section, reason),
+            ParseWarning::ChecksumMismatch { expected, got } => {
+                write!(f, "checksum mismatch: file says {:08x} but the data hashes to {:08x}", expected, got)
+            }
+            ParseWarning::TrailingBytes(len) => write!(f, "{} unexpected bytes after the end of the file", len),
+        }
+    }
+}
+
+/// Options for `NextWeightFile::from_weight_file_with`
+#[cfg(feature = "netcdf")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConvertOptions {
+    /// Record the source file, conversion time and converter version as
+    /// `nwt_` global attributes and in `history`. Turn off for byte-stable,
+    /// reproducible output
+    pub provenance: bool,
+    /// Treat this value as the fill instead of the `regridweights`
+    /// `_FillValue`, e.g. for dense files that use zeros for empty cells.
+    /// Like the attribute, it's compared before packed weights are unpacked
+    pub fill_value: Option<f32>,
+    /// treat values within this distance of the fill as fill too, e.g. ones
+    /// rounded off it by an earlier f64 to f32 conversion. 0 only matches
+    /// the fill exactly
+    pub fill_tolerance: f32,
+    /// drop weights that are exactly zero, which contribute nothing
+    pub skip_zero_weights: bool,
+    /// name of the variable holding the polyid strings
+    pub polyid_var: String,
+    /// name of the (polyid, lat, lon) weights variable
+    pub weights_var: String,
+    /// name of the latitude coordinate variable
+    pub lat_var: String,
+    /// name of the longitude coordinate variable
+    pub lon_var: String,
+    /// name of the latitude dimension
+    pub lat_dim: String,
+    /// name of the longitude dimension
+    pub lon_dim: String,
+    /// how many latitude rows of a polyid's weights are read at a time.
+    /// Bounds peak memory to `chunk_rows x lon` values however large the
+    /// grid; the result doesn't depend on it
+    pub chunk_rows: usize,
+    /// Store the weights as f32 even if the source's are double precision,
+    /// which are otherwise kept as they are
+    pub force_f32: bool,
+    /// name of the weight set the converted weights become, e.g.
+    /// `conservative`. Convert other sources for the same polyids and add
+    /// their weights with `NextWeightFile::add_weight_set`
+    pub weight_set: String,
+}
+
+/// `ConvertOptions::chunk_rows` unless set otherwise
+#[cfg(feature = "netcdf")]
+pub const DEFAULT_CHUNK_ROWS: usize = 64;
+
+#[cfg(feature = "netcdf")]
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            provenance: true,
+            fill_value: None,
+            fill_tolerance: 0.0,
+            skip_zero_weights: false,
+            polyid_var: "polyid".to_string(),
+            weights_var: "regridweights".to_string(),
+            lat_var: "lat".to_string(),
+            lon_var: "lon".to_string(),
+            lat_dim: "lat".to_string(),
+            lon_dim: "lon".to_string(),
+            chunk_rows: DEFAULT_CHUNK_ROWS,
+            force_f32: false,
+            weight_set: crate::DEFAULT_WEIGHT_SET.to_string(),
+        }
+    }
+}
+
+/// Where `NextWeightFile::open` caches the NWT file it converts a NetCDF
+/// file to
+#[cfg(feature = "netcdf")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// don't write anything
+    Never,
+    /// write `<source>.nwt` next to the source file
+    #[default]
+    Alongside,
+    /// write `<source file name>.nwt` into this directory
+    Directory(PathBuf),
+}
+
+#[cfg(feature = "netcdf")]
+impl CachePolicy {
+    /// where the converted `source` goes, if anywhere
+    pub(crate) fn path_for(&self, source: &Path) -> Option<PathBuf> {
+        let with_ext = |path: &Path| {
+            let mut path = path.as_os_str().to_owned();
+            path.push(".nwt");
+            PathBuf::from(path)
+        };
+        match self {
+            CachePolicy::Never => None,
+            CachePolicy::Alongside => Some(with_ext(source)),
+            CachePolicy::Directory(dir) => source.file_name().map(|name| with_ext(&dir.join(name))),
+        }
+    }
+}
+
+/// Options for `NextWeightFile::open_with`
+#[cfg(feature = "netcdf")]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpenOptions {
+    /// where to cache a converted NetCDF file. Failing to write the cache
+    /// only logs a warning
+    pub cache: CachePolicy,
+    /// how to convert a NetCDF file, with its names used as-is. Without
+    /// them, the defaults are used and missing names are looked up under
+    /// common aliases
+    pub convert: Option<ConvertOptions>,
+}
+
+/// Options for `NextWeightFile::from_scrip`
+#[cfg(feature = "netcdf")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScripOptions {
+    /// names for the destination cells, in order. Without them each polyid
+    /// is named after its 0-based destination index
+    pub polyids: Option<Vec<String>>,
+}
+
+#[cfg(feature = "netcdf")]
+impl ConvertOptions {
+    /// the names actually used, as recorded in the `nwt_source_names`
+    /// provenance attribute
+    pub(crate) fn names_summary(&self) -> String {
+        format!("polyid={} weights={} lat={} lon={} lat_dim={} lon_dim={}",
+            self.polyid_var, self.weights_var, self.lat_var, self.lon_var, self.lat_dim, self.lon_dim)
+    }
+}
+
+/// true if this build can decode data stored with `codec`
+pub(crate) fn codec_supported(codec: u16) -> bool {
+    match codec {
+        CODEC_NONE => true,
+        #[cfg(feature = "compression")]
+        CODEC_ZSTD => true,
+        _ => false,
+    }
+}
+
+/// compresses the lookup table and gridpoint section
+pub(crate) fn compress(compression: Compression, body: Vec<u8>) -> Result<Vec<u8>, NwtError> {
+    match compression {
+        Compression::None => Ok(body),
+        #[cfg(feature = "compression")]
+        Compression::Zstd(level) => Ok(zstd::stream::encode_all(&body[..], level)?),
+    }
+}
+
+/// undoes `compress` for a section stored with `codec`, failing instead of
+/// inflating it past `limit` bytes
+pub(crate) fn decompress(codec: u16, bytes: &[u8], limit: u64) -> Result<Vec<u8>, NwtError> {
+    let body = match codec {
+        CODEC_NONE => bytes.to_vec(),
+        #[cfg(feature = "compression")]
+        CODEC_ZSTD => {
+            use std::io::Read;
+            let mut body = Vec::new();
+            zstd::stream::Decoder::new(bytes)
+                .and_then(|decoder| decoder.take(limit.saturating_add(1)).read_to_end(&mut body))
+                .map_err(|e| NwtError::Corrupt(format!("compressed section could not be decoded: {}", e)))?;
+            body
+        }
+        other => return Err(NwtError::UnsupportedCodec(other)),
+    };
+    if body.len() as u64 > limit {
+        return Err(NwtError::Corrupt(format!(
+            "compressed section inflates past the {} bytes the header and parse limits allow", limit)));
+    }
+    Ok(body)
+}
@@ -0,0 +1,66 @@
+//! Provenance attributes recorded when converting from NetCDF.
+
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{AttrVal, ConvertOptions, JsonData, NwtError};
+
+/// Records where a converted file came from, and which of its variables were
+/// read, in `nwt_`-prefixed global attributes, and appends a line to the CF
+/// `history` attribute
+pub(crate) fn record(json_data: &mut JsonData, source: &Path, names: &ConvertOptions) -> Result<(), NwtError> {
+    let created = rfc3339(SystemTime::now());
+    let converter = format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    let size = std::fs::metadata(source)?.len();
+
+    json_data.set_global_attr("nwt_source", source.display().to_string());
+    json_data.set_global_attr("nwt_source_size", AttrVal::U64(size));
+    json_data.set_global_attr("nwt_created", created.clone());
+    json_data.set_global_attr("nwt_converter_version", converter.clone());
+    json_data.set_global_attr("nwt_source_names", names.names_summary());
+
+    let line = format!("{}: converted to NWT by {}", created, converter);
+    let history = match json_data.get_global_attr_value("history") {
+        Some(AttrVal::Str(existing)) if !existing.is_empty() => AttrVal::Str(format!("{}\n{}", existing, line)),
+        Some(AttrVal::Strs(existing)) => {
+            let mut lines = existing.clone();
+            lines.push(line);
+            AttrVal::Strs(lines)
+        }
+        _ => AttrVal::Str(line),
+    };
+    json_data.set_global_attr("history", history);
+    Ok(())
+}
+
+/// formats a time as an RFC 3339 UTC timestamp with second precision
+fn rfc3339(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, rem) = (secs / 86400, secs % 86400);
+
+    // days since the epoch to a civil date (Howard Hinnant's algorithm)
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn rfc3339_timestamps() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(UNIX_EPOCH + Duration::from_secs(951_782_400 + 3661)), "2000-02-29T01:01:01Z");
+        assert_eq!(rfc3339(UNIX_EPOCH + Duration::from_secs(1_791_459_199)), "2026-10-08T11:33:19Z");
+    }
+}
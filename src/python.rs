@@ -0,0 +1,80 @@
+//! Python bindings, built with maturin: `maturin develop --features python`.
+
+use std::path::PathBuf;
+
+use numpy::{IntoPyArray, PyArray1, PyReadonlyArray2};
+use pyo3::exceptions::{PyFileExistsError, PyIOError, PyKeyError, PyValueError};
+use pyo3::prelude::*;
+
+use crate::{NextWeightFile, NwtError, WriteOptions};
+
+fn to_py(e: NwtError) -> PyErr {
+    match e {
+        NwtError::Io(e) => PyIOError::new_err(e.to_string()),
+        NwtError::PolyidNotFound(name) => PyKeyError::new_err(name),
+        NwtError::AlreadyExists(path) => PyFileExistsError::new_err(path),
+        other => PyValueError::new_err(other.to_string()),
+    }
+}
+
+/// A weight file, opened from NWT or converted from NetCDF
+#[pyclass(name = "NextWeightFile", module = "nextgen_weightfile")]
+struct PyNextWeightFile {
+    inner: NextWeightFile,
+}
+
+#[pymethods]
+impl PyNextWeightFile {
+    /// Opens an NWT file, or converts a NetCDF weight file
+    #[staticmethod]
+    fn open(path: PathBuf) -> PyResult<Self> {
+        Ok(Self { inner: NextWeightFile::open(path).map_err(to_py)? })
+    }
+
+    /// The polyid names, in file order
+    #[getter]
+    fn polyids(&self) -> Vec<String> {
+        self.inner.get_polyids().clone()
+    }
+
+    /// The polyid's points as five parallel lists:
+    /// `(lat_idx, lon_idx, lat, lon, weight)`
+    #[allow(clippy::type_complexity)]
+    fn get_entry(&self, name: &str) -> PyResult<(Vec<u32>, Vec<u32>, Vec<f32>, Vec<f32>, Vec<f32>)> {
+        let idx = self.inner.polyid_index(name).ok_or_else(|| PyKeyError::new_err(name.to_string()))?;
+        let mut columns = (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for p in self.inner.entry_points(idx) {
+            columns.0.push(p.0);
+            columns.1.push(p.1);
+            columns.2.push(p.2);
+            columns.3.push(p.3);
+            columns.4.push(p.4);
+        }
+        Ok(columns)
+    }
+
+    /// Applies the weights to a 2-D float32 `(lat, lon)` array, returning one
+    /// value per polyid. The array is read in place, not copied
+    fn apply<'py>(&self, py: Python<'py>, field: PyReadonlyArray2<'py, f32>) -> PyResult<Bound<'py, PyArray1<f32>>> {
+        let out = self.inner.apply_array(field.as_array()).map_err(to_py)?;
+        Ok(out.into_pyarray_bound(py))
+    }
+
+    /// Writes the weights to an NWT file. An existing file is only replaced
+    /// with `overwrite=True`
+    #[pyo3(signature = (path, overwrite = false))]
+    fn save(&self, path: PathBuf, overwrite: bool) -> PyResult<()> {
+        let opts = WriteOptions { overwrite, ..Default::default() };
+        self.inner.serialize_to_file_with(path, &opts).map(|_| ()).map_err(to_py)
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.len()
+    }
+}
+
+#[pymodule]
+fn nextgen_weightfile(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyNextWeightFile>()?;
+    Ok(())
+}
@@ -0,0 +1,155 @@
+//! The on-disk layouts of f32 gridpoint records, so a whole entry can be
+//! decoded from its bytes at once.
+//!
+//! With the `bytemuck` feature on a little-endian target an entry's bytes
+//! are reinterpreted as records in place; everywhere else each record is
+//! decoded field by field, which gives the same points.
+
+use std::mem::size_of;
+
+use crate::codec::{read_f32_le, read_u32_le};
+use crate::GridPoint;
+
+/// A layout of gridpoint record. Packed, so a run of them can be read from
+/// any offset
+pub(crate) trait Record: Copy + 'static {
+    /// bytes per record
+    const SIZE: usize;
+
+    /// decodes a record from its `SIZE` little-endian bytes
+    fn from_le_bytes(bytes: &[u8]) -> Self;
+}
+
+/// A point as stored before format 7, which keeps its own lat/lon
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "bytemuck", derive(bytemuck::Pod, bytemuck::Zeroable))]
+pub(crate) struct OnDiskPoint {
+    pub(crate) lat_idx: u32,
+    pub(crate) lon_idx: u32,
+    pub(crate) lat: f32,
+    pub(crate) lon: f32,
+    pub(crate) weight: f32,
+}
+
+/// A point as stored from format 7 on, whose lat/lon come from the file's
+/// coordinates
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "bytemuck", derive(bytemuck::Pod, bytemuck::Zeroable))]
+pub(crate) struct CompactPoint {
+    pub(crate) lat_idx: u32,
+    pub(crate) lon_idx: u32,
+    pub(crate) weight: f32,
+}
+
+const _: () = assert!(size_of::<OnDiskPoint>() == OnDiskPoint::SIZE && size_of::<CompactPoint>() == CompactPoint::SIZE);
+
+impl Record for OnDiskPoint {
+    const SIZE: usize = 20;
+
+    fn from_le_bytes(bytes: &[u8]) -> Self {
+        Self {
+            lat_idx: read_u32_le(bytes),
+            lon_idx: read_u32_le(&bytes[4..]),
+            lat: read_f32_le(&bytes[8..]),
+            lon: read_f32_le(&bytes[12..]),
+            weight: read_f32_le(&bytes[16..]),
+        }
+    }
+}
+
+impl Record for CompactPoint {
+    const SIZE: usize = 12;
+
+    fn from_le_bytes(bytes: &[u8]) -> Self {
+        Self {
+            lat_idx: read_u32_le(bytes),
+            lon_idx: read_u32_le(&bytes[4..]),
+            weight: read_f32_le(&bytes[8..]),
+        }
+    }
+}
+
+impl From<OnDiskPoint> for GridPoint {
+    fn from(p: OnDiskPoint) -> Self {
+        (p.lat_idx, p.lon_idx, p.lat, p.lon, p.weight)
+    }
+}
+
+/// The records held by `bytes`, a whole number of them, reinterpreted in
+/// place
+#[cfg(all(feature = "bytemuck", target_endian = "little"))]
+pub(crate) fn records<T: Record + bytemuck::Pod>(bytes: &[u8]) -> impl Iterator<Item = T> + '_ {
+    bytemuck::cast_slice::<u8, T>(bytes).iter().copied()
+}
+
+/// The records held by `bytes`, a whole number of them, decoded one by one
+#[cfg(not(all(feature = "bytemuck", target_endian = "little")))]
+pub(crate) fn records<T: Record>(bytes: &[u8]) -> impl Iterator<Item = T> + '_ {
+    scalar_records(bytes)
+}
+
+/// the portable decoding `records` falls back to
+#[cfg_attr(all(feature = "bytemuck", target_endian = "little", not(test)), allow(dead_code))]
+fn scalar_records<T: Record>(bytes: &[u8]) -> impl Iterator<Item = T> + '_ {
+    debug_assert!(bytes.len().is_multiple_of(T::SIZE));
+    bytes.chunks_exact(T::SIZE).map(T::from_le_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encode_point, WeightDtype};
+
+    /// points covering the awkward corners of f32 and u32
+    fn points() -> Vec<GridPoint> {
+        vec![
+            (0, 0, -45.0, 0.0, 0.25),
+            (u32::MAX, 7, -0.0, f32::MIN_POSITIVE / 2.0, f32::MAX),
+            (1 << 24, u32::MAX - 1, f32::NEG_INFINITY, 179.999, f32::NAN),
+        ]
+    }
+
+    fn encode(compact: bool) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for p in points() {
+            encode_point(&mut bytes, &p, p.4 as f64, compact, WeightDtype::F32, None).unwrap();
+        }
+        bytes
+    }
+
+    /// a point's fields as bits, so NaNs compare equal
+    fn bits(p: GridPoint) -> (u32, u32, u32, u32, u32) {
+        (p.0, p.1, p.2.to_bits(), p.3.to_bits(), p.4.to_bits())
+    }
+
+    fn compact_bits(p: CompactPoint) -> (u32, u32, u32) {
+        (p.lat_idx, p.lon_idx, p.weight.to_bits())
+    }
+
+    #[test]
+    fn records_decode_what_was_encoded() {
+        let bytes = encode(false);
+        let full: Vec<_> = records::<OnDiskPoint>(&bytes).map(|p| bits(p.into())).collect();
+        assert_eq!(full, points().into_iter().map(bits).collect::<Vec<_>>());
+
+        // one byte in, as records sit wherever the metadata before them ends
+        let shifted = [&[0xff][..], &encode(true)].concat();
+        let compact: Vec<_> = records::<CompactPoint>(&shifted[1..]).map(compact_bits).collect();
+        let expected: Vec<_> = points().into_iter().map(|p| (p.0, p.1, p.4.to_bits())).collect();
+        assert_eq!(compact, expected);
+    }
+
+    #[cfg(all(feature = "bytemuck", target_endian = "little"))]
+    #[test]
+    fn in_place_and_scalar_decoding_agree() {
+        let (full, compact) = (encode(false), [&[0][..], &encode(true)].concat());
+        let cast: Vec<_> = records::<OnDiskPoint>(&full).map(|p| bits(p.into())).collect();
+        let scalar: Vec<_> = scalar_records::<OnDiskPoint>(&full).map(|p| bits(p.into())).collect();
+        assert_eq!(cast, scalar);
+        let cast: Vec<_> = records::<CompactPoint>(&compact[1..]).map(compact_bits).collect();
+        let scalar: Vec<_> = scalar_records::<CompactPoint>(&compact[1..]).map(compact_bits).collect();
+        assert_eq!(cast, scalar);
+    }
+}
@@ -0,0 +1,382 @@
+//! Salvaging the readable polyids of a damaged NWT file.
+
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+
+use crate::{
+    read_axes, read_entry, weight_sets, Coordinates, FixedHeader, GridSections, LookupEntry, NextWeightFile,
+    NextWeightFileBuilder, NwtError, NwtHeader, NwtReader, ParseOptions, PointArena, ValidationIssue, WeightDtype,
+    AXES_VERSION, CHECKSUM_VERSION, WEIGHT_SETS_VERSION,
+};
+
+/// A polyid `NextWeightFile::recover` had to leave out
+#[derive(Debug, Clone, PartialEq)]
+pub struct LostPolyid {
+    /// its position in the damaged file
+    pub index: usize,
+    /// its name, if the metadata has one for it
+    pub polyid: Option<String>,
+    pub reason: String,
+}
+
+impl fmt::Display for LostPolyid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "polyid {} ({}): {}", self.index, self.polyid.as_deref().unwrap_or("<unnamed>"), self.reason)
+    }
+}
+
+/// What `NextWeightFile::recover` salvaged from a file, and what it couldn't
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecoveryReport {
+    /// format version the file was written with
+    pub version: u16,
+    /// number of polyids the header declares
+    pub declared_polyids: u64,
+    pub lat_len: u64,
+    pub lon_len: u64,
+    /// number of polyids recovered
+    pub recovered: usize,
+    /// every polyid left out, in file order. Empty if the metadata naming
+    /// them couldn't be read
+    pub lost: Vec<LostPolyid>,
+    /// each section that couldn't be read and why, in file order
+    pub damaged: Vec<(&'static str, String)>,
+}
+
+impl RecoveryReport {
+    /// true if nothing was lost or damaged
+    pub fn is_complete(&self) -> bool {
+        self.lost.is_empty() && self.damaged.is_empty()
+    }
+}
+
+impl NextWeightFile {
+    /// Reads as much of a damaged NWT file, say a truncated download, as it
+    /// can. Polyids whose points lie past the end of the file, whose lookup
+    /// table count runs into the next polyid's points, or that have points
+    /// off the grid or weights that aren't finite and non-negative are left
+    /// out, and the report says which and why. If the metadata can't be
+    /// read nothing is recovered, but the report still has what the header
+    /// says.
+    ///
+    /// What is recovered serializes to an undamaged file, and passes
+    /// `validate()` as long as the weights kept summed to one in the
+    /// original. Only a damaged fixed header is an error, as is one
+    /// declaring more than the default `ParseOptions::max_polyids`
+    pub fn recover(path: impl AsRef<Path>) -> Result<(Self, RecoveryReport), NwtError> {
+        Self::recover_with(path, &ParseOptions::default())
+    }
+
+    /// Same as `recover`, within `opts`' limits rather than the defaults:
+    /// `max_polyids` caps the header, and `max_total_points` how far a
+    /// compressed section may inflate, so raise them to salvage larger
+    /// files. `opts.mode` is ignored, as recovery always reads past what
+    /// it can
+    pub fn recover_with(path: impl AsRef<Path>, opts: &ParseOptions) -> Result<(Self, RecoveryReport), NwtError> {
+        let mut cursor = NwtReader::open(path.as_ref(), true)?;
+        let fixed = Self::read_fixed_header(&mut cursor)?;
+        if fixed.num_polyids > opts.max_polyids {
+            return Err(NwtError::LimitExceeded { what: "polyids", declared: fixed.num_polyids, limit: opts.max_polyids });
+        }
+        let mut report = RecoveryReport {
+            version: fixed.version,
+            declared_polyids: fixed.num_polyids,
+            lat_len: fixed.lat_len,
+            lon_len: fixed.lon_len,
+            recovered: 0,
+            lost: Vec::new(),
+            damaged: Vec::new(),
+        };
+        let mut nwt = NextWeightFileBuilder::new().dimensions(fixed.lat_len, fixed.lon_len).build();
+        match Self::read_json_metadata(&mut cursor, &fixed, true, &mut Vec::new()) {
+            Ok(json_data) => nwt.json_data = json_data,
+            Err(e) => {
+                report.damaged.push(("json metadata", e.to_string()));
+                return Ok((nwt, report));
+            }
+        }
+
+        // why each row of the lookup table was left out, if it was
+        let num_polyids = fixed.num_polyids as usize;
+        let mut reasons: Vec<Option<String>> = vec![None; num_polyids];
+        let sections = Self::read_compressed(&mut cursor, &fixed, opts)
+            .map_err(|e| ("compressed data", e))
+            .and_then(|()| {
+                let rows = (0..num_polyids)
+                    .map(|_| LookupEntry::read(&mut cursor, "lookup table"))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| ("lookup table", e))?;
+                Ok((rows, Self::read_grid(&mut cursor, &fixed).map_err(|e| ("grid sections", e))?))
+            });
+        let mut f64_weights = (fixed.weight_dtype == WeightDtype::F64).then(Vec::new);
+        match sections {
+            Ok((rows, grid)) => {
+                let mut reader = Salvage { cursor, fixed, reasons: &mut reasons, report: &mut report };
+                reader.read_points(&mut nwt, &rows, grid, &mut f64_weights);
+            }
+            Err((section, e)) => {
+                reasons.fill(Some(format!("the {} is unreadable", section)));
+                report.damaged.push((section, e.to_string()));
+            }
+        }
+        nwt.polyid_gridpoints.pad_to(num_polyids);
+        if let Some(weights) = f64_weights.as_mut() {
+            weights.resize_with(num_polyids, Vec::new);
+        }
+        nwt.f64_weights = f64_weights;
+        nwt.entries_changed();
+
+        // a row the metadata has no name for can't be kept, nor a name
+        // without a row
+        let names = std::mem::take(&mut nwt.json_data.polyids);
+        for reason in reasons.iter_mut().skip(names.len()).filter(|reason| reason.is_none()) {
+            *reason = Some("it isn't named in the metadata".to_string());
+        }
+        for (index, name) in names.iter().enumerate().skip(num_polyids) {
+            report.lost.push(LostPolyid { index, polyid: Some(name.clone()), reason: "it has no lookup table entry".to_string() });
+        }
+        nwt.json_data.polyids = names.iter().take(num_polyids).cloned().collect();
+        for name in names.iter().skip(num_polyids) {
+            if !nwt.json_data.polyids.contains(name) {
+                nwt.json_data.polyid_meta.remove(name);
+            }
+        }
+        nwt.drop_all(&reasons);
+
+        // and what validate() would still flag, which the file's own
+        // records can hold however intact it is
+        let kept: Vec<usize> = (0..num_polyids).filter(|&idx| reasons[idx].is_none()).collect();
+        let mut invalid = vec![None; kept.len()];
+        for issue in nwt.validate().issues {
+            let (index, problem) = match issue {
+                ValidationIssue::IndexOutOfBounds { index, .. } => (index, "points outside the grid"),
+                ValidationIssue::NonFiniteWeight { index, .. } => (index, "non-finite weights"),
+                ValidationIssue::NegativeWeight { index, .. } => (index, "negative weights"),
+                _ => continue,
+            };
+            invalid[index].get_or_insert_with(|| format!("it has {}", problem));
+        }
+        for (&idx, reason) in kept.iter().zip(&invalid) {
+            reasons[idx] = reason.clone();
+        }
+        nwt.drop_all(&invalid);
+
+        report.lost.extend(reasons.into_iter().enumerate().filter_map(|(index, reason)| {
+            reason.map(|reason| LostPolyid { index, polyid: names.get(index).cloned(), reason })
+        }));
+        report.lost.sort_by_key(|lost| lost.index);
+        report.recovered = nwt.json_data.polyids.len();
+        Ok((nwt, report))
+    }
+
+    /// `drop_unreadable`, for the polyids with a reason to be dropped
+    fn drop_all(&mut self, reasons: &[Option<String>]) {
+        let dropped: Vec<bool> = reasons.iter().map(Option::is_some).collect();
+        if dropped.contains(&true) {
+            self.drop_unreadable(&dropped);
+        }
+    }
+}
+
+/// The state `recover` reads the points and the sections after them with
+struct Salvage<'a, R: Read> {
+    cursor: NwtReader<R>,
+    fixed: FixedHeader,
+    reasons: &'a mut [Option<String>],
+    report: &'a mut RecoveryReport,
+}
+
+impl<R: Read> Salvage<'_, R> {
+    /// Reads each row's points from where its offset says they start, so a
+    /// bad count costs only that polyid, then the sections after the points
+    /// if the file goes that far
+    fn read_points(&mut self, nwt: &mut NextWeightFile, rows: &[LookupEntry], grid: GridSections, f64_weights: &mut Option<Vec<Vec<f64>>>) {
+        let GridSections { coordinates, lat_bounds, lon_bounds, quantization } = grid;
+        (nwt.lat_bounds, nwt.lon_bounds) = (lat_bounds, lon_bounds);
+        if let Some(coords) = coordinates.as_ref().filter(|coords| coords.are_axes) {
+            (nwt.lat_axis, nwt.lon_axis) = (Some(coords.lats.clone()), Some(coords.lons.clone()));
+        }
+        let FixedHeader { version, weight_dtype, quantized, .. } = self.fixed;
+        let data_start = self.cursor.position();
+        let point_size = NwtHeader::point_size_of(version, weight_dtype, quantized) as u64;
+        let start_of = |offset: u64| offset.checked_mul(point_size).and_then(|at| at.checked_add(data_start));
+
+        let mut gridpoints = PointArena::with_capacity(rows.len(), 0);
+        let mut ended = None;
+        for (idx, &LookupEntry { offset, count }) in rows.iter().enumerate() {
+            let at = match (start_of(offset), offset.checked_add(count), rows.get(idx + 1)) {
+                (None, ..) | (_, None, _) => Err(format!("its offset {} and count {} overflow", offset, count)),
+                (_, Some(end), Some(next)) if end != next.offset => Err(format!(
+                    "its {} points from offset {} run to {}, but the next polyid's start at {}", count, offset, end, next.offset)),
+                (Some(at), ..) if at < self.cursor.position() => {
+                    Err(format!("its points at offset {} overlap the polyid before it", offset))
+                }
+                (Some(at), ..) => Ok(at),
+            };
+            let cursor = &mut self.cursor;
+            let read = at.and_then(|at| {
+                if ended.is_some() {
+                    return Err("its points are past the end of the file".to_string());
+                }
+                let range = quantization.as_ref().map(|ranges| ranges[idx]);
+                let read = gridpoints.push_with(|points| {
+                    cursor.skip_to(at, "gridpoint data")?;
+                    read_entry(cursor, points, count, coordinates.as_ref(), weight_dtype, range, true)
+                });
+                match read {
+                    Ok((weights, None)) => Ok(weights),
+                    Ok((_, Some(off_grid))) => Err(off_grid),
+                    Err(e @ NwtError::Truncated { .. }) => {
+                        let reason = format!("its points are past the end of the file: {}", e);
+                        ended = Some(e);
+                        Err(reason)
+                    }
+                    Err(e) => Err(format!("its points are unreadable: {}", e)),
+                }
+            });
+            gridpoints.pad_to(idx + 1);
+            let weights = read.unwrap_or_else(|reason| {
+                gridpoints.replace(idx, []);
+                self.reasons[idx] = Some(reason);
+                Vec::new()
+            });
+            if let Some(f64_weights) = f64_weights.as_mut() {
+                f64_weights.push(weights);
+            }
+        }
+        nwt.polyid_gridpoints = gridpoints;
+
+        // the sections after the points are only where they should be if
+        // the last polyid's are
+        let data_end = rows.last().map_or(Some(0), |row| row.offset.checked_add(row.count)).and_then(start_of);
+        let trailing = match (ended, data_end) {
+            (Some(e), _) => Err(("gridpoint data", e)),
+            (None, None) => Err(("gridpoint data", NwtError::Corrupt("the last polyid's points overflow".to_string()))),
+            (None, Some(end)) => self.read_trailing(nwt, end, rows.len() as u64, coordinates.as_ref()),
+        };
+        if let Err((section, e)) = trailing {
+            self.report.damaged.push((section, e.to_string()));
+        }
+    }
+
+    /// reads the weight sets, coordinate axes and checksum that follow the
+    /// points ending at `data_end`, as far as the file's version has them
+    fn read_trailing(
+        &mut self,
+        nwt: &mut NextWeightFile,
+        data_end: u64,
+        num_polyids: u64,
+        coordinates: Option<&Coordinates>,
+    ) -> Result<(), (&'static str, NwtError)> {
+        let version = self.fixed.version;
+        let cursor = &mut self.cursor;
+        cursor.skip_to(data_end, "gridpoint data").map_err(|e| ("gridpoint data", e))?;
+        if let (Some(coords), true) = (coordinates, version >= WEIGHT_SETS_VERSION) {
+            (nwt.weight_set_name, nwt.weight_sets) = weight_sets::read_weight_sets(cursor, num_polyids, coords)
+                .map_err(|e| ("weight sets", e))?;
+        }
+        if coordinates.is_none() && version >= AXES_VERSION {
+            let axes = read_axes(cursor, nwt.lat_len, nwt.lon_len).map_err(|e| ("coordinate axes", e))?;
+            (nwt.lat_axis, nwt.lon_axis) = axes.unzip();
+        }
+        if version >= CHECKSUM_VERSION {
+            cursor.finish_checksum().map_err(|e| ("checksum", e))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::read_u64_le;
+    use crate::tests::{sample_file, temp_path};
+
+    /// writes `bytes` to a file of its own and recovers it
+    fn recover_bytes(name: &str, bytes: &[u8]) -> (NextWeightFile, RecoveryReport) {
+        let path = temp_path(name);
+        std::fs::write(&path, bytes).unwrap();
+        let recovered = NextWeightFile::recover(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        recovered
+    }
+
+    fn lost(report: &RecoveryReport) -> Vec<(usize, Option<&str>)> {
+        report.lost.iter().map(|lost| (lost.index, lost.polyid.as_deref())).collect()
+    }
+
+    /// the recovered file is valid and survives a round trip
+    fn assert_clean(nwt: &NextWeightFile) {
+        assert!(nwt.validate().is_ok(), "{:?}", nwt.validate());
+        assert_eq!(&NextWeightFile::from_bytes(&nwt.to_bytes().unwrap()).unwrap(), nwt);
+    }
+
+    #[test]
+    fn an_intact_file_is_recovered_whole() {
+        let (nwt, report) = recover_bytes("recover_intact.nwt", &sample_file().to_bytes().unwrap());
+        assert_eq!(nwt, sample_file());
+        assert!(report.is_complete(), "{:?}", report);
+        assert_eq!(report.recovered, 3);
+    }
+
+    #[test]
+    fn recovery_keeps_to_the_given_limits() {
+        let path = temp_path("recover_limits.nwt");
+        sample_file().serialize_to_file(&path).unwrap();
+        let tight = ParseOptions { max_polyids: 2, ..Default::default() };
+        let refused = NextWeightFile::recover_with(&path, &tight);
+        let (nwt, _) = NextWeightFile::recover_with(&path, &ParseOptions { max_polyids: 3, ..tight }).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(refused, Err(NwtError::LimitExceeded { what: "polyids", declared: 3, limit: 2 })));
+        assert_eq!(nwt, sample_file());
+    }
+
+    #[test]
+    fn truncation_costs_only_the_polyids_it_reaches() {
+        let bytes = sample_file().to_bytes().unwrap();
+        let lookup_offset = read_u64_le(&bytes[50..]) as usize;
+        let data_offset = lookup_offset + 3 * 16 + 1 + (2 + 3) * 4 + 1;
+
+        // mid-entry: C's points are cut short
+        let (nwt, report) = recover_bytes("recover_mid_entry.nwt", &bytes[..data_offset + 3 * 12 + 5]);
+        assert_eq!(nwt.get_polyids(), &["A", "B"]);
+        assert_eq!(nwt.get_entry("A"), sample_file().get_entry("A"));
+        assert_eq!(lost(&report), [(2, Some("C"))]);
+        assert!(report.lost[0].reason.contains("past the end of the file"), "{}", report.lost[0]);
+        assert_eq!(report.damaged.iter().map(|(section, _)| *section).collect::<Vec<_>>(), ["gridpoint data"]);
+        assert_clean(&nwt);
+
+        // mid-lookup-table: nothing can be located, but the metadata stays
+        let (nwt, report) = recover_bytes("recover_mid_lookup.nwt", &bytes[..lookup_offset + 20]);
+        assert!(nwt.get_polyids().is_empty());
+        assert_eq!(nwt.get_dimensions(), (2, 3));
+        assert_eq!(lost(&report), [(0, Some("A")), (1, Some("B")), (2, Some("C"))]);
+        assert_eq!(report.damaged[0].0, "lookup table");
+        assert_clean(&nwt);
+
+        // mid-JSON: the polyids aren't even known, only the header
+        let (nwt, report) = recover_bytes("recover_mid_json.nwt", &bytes[..lookup_offset - 3]);
+        assert!(nwt.get_polyids().is_empty());
+        assert_eq!((report.version, report.declared_polyids, report.lat_len, report.lon_len), (10, 3, 2, 3));
+        assert!(report.lost.is_empty());
+        assert_eq!(report.damaged[0].0, "json metadata");
+        assert_clean(&nwt);
+    }
+
+    #[test]
+    fn an_inconsistent_count_costs_only_its_polyid() {
+        let mut bytes = sample_file().to_bytes().unwrap();
+        let lookup_offset = read_u64_le(&bytes[50..]) as usize;
+        // B's count, which should be 1
+        bytes[lookup_offset + 16 + 8] = 5;
+        let (nwt, report) = recover_bytes("recover_bad_count.nwt", &bytes);
+        assert_eq!(nwt.get_polyids(), &["A", "C"]);
+        assert_eq!(nwt.get_entry("C"), sample_file().get_entry("C"));
+        assert_eq!(lost(&report), [(1, Some("B"))]);
+        assert!(report.lost[0].reason.contains("next polyid"), "{}", report.lost[0]);
+        // the edit shows up in the checksum, which is all that's damaged
+        assert_eq!(report.damaged.iter().map(|(section, _)| *section).collect::<Vec<_>>(), ["checksum"]);
+        assert_clean(&nwt);
+    }
+}
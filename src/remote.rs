@@ -0,0 +1,222 @@
+//! Reading NWT files straight from object storage (S3, GCS, Azure, HTTP or
+//! the local filesystem), addressed by URL. Credentials come from each
+//! service's standard environment variables (`AWS_*`, `GOOGLE_*`,
+//! `AZURE_*`) and the default providers behind them.
+
+use object_store::aws::AmazonS3Builder;
+use object_store::azure::MicrosoftAzureBuilder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::http::HttpBuilder;
+use object_store::local::LocalFileSystem;
+use object_store::path::Path as ObjectPath;
+use object_store::{ObjectStore, ObjectStoreScheme};
+use url::Url;
+
+use crate::{dequantize, to_usize, AttrVal, Coordinates, JsonData, LookupEntry, NextWeightFile, NwtError, NwtHeader, NwtReader, PolyidEntry, WeightDtype};
+/// bytes fetched for the metadata at first, doubled until the header, JSON
+/// metadata and lookup table fit
+const INITIAL_PREFIX: usize = 64 * 1024;
+
+impl NextWeightFile {
+    /// Fetches a whole NWT file from an `s3://`, `gs://`, `az://`,
+    /// `http(s)://` or `file://` URL and parses it as `from_bytes` does,
+    /// verifying its checksum. Use `NextWeightFileRemote` to fetch only the
+    /// polyids you need
+    pub async fn open_url(url: &str) -> Result<Self, NwtError> {
+        let (store, path) = store_for(url)?;
+        let bytes = store.get(&path).await?.bytes().await?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+/// An NWT file in object storage that fetches `PolyidEntry`s on demand.
+///
+/// Opening it reads only the header, JSON metadata and lookup table; each
+/// `get_entry` is then a single ranged request for that polyid's points. As
+/// with `NextWeightFileMmap`, the checksum is not verified, and compressed
+/// files can't be read this way.
+#[derive(Debug)]
+pub struct NextWeightFileRemote {
+    store: Box<dyn ObjectStore>,
+    path: ObjectPath,
+    json_data: JsonData,
+    lat_len: u64,
+    lon_len: u64,
+    lookup_table: Vec<LookupEntry>,
+    coordinates: Option<Coordinates>,
+    weight_dtype: WeightDtype,
+    quantization: Option<Vec<(f32, f32)>>,
+    point_size: u64,
+    data_offset: u64,
+}
+
+impl NextWeightFileRemote {
+    /// fetches and parses the metadata of the NWT file at `url`, which takes
+    /// the same schemes as `NextWeightFile::open_url`
+    pub async fn open(url: &str) -> Result<Self, NwtError> {
+        let (store, path) = store_for(url)?;
+        let size = store.head(&path).await?.size;
+
+        // the metadata's length is only known once it's parsed, so fetch a
+        // prefix and retry with a longer one for as long as it runs short
+        let mut prefix_len = INITIAL_PREFIX.min(size);
+        let (header, data_offset) = loop {
+            let prefix = store.get_range(&path, 0..prefix_len).await?;
+            let mut cursor = NwtReader::new(&prefix[..]);
+            match NextWeightFile::read_preamble(&mut cursor) {
+                Ok(header) => break (header, cursor.position()),
+                Err(NwtError::Truncated { .. }) if prefix_len < size => prefix_len = (prefix_len * 2).min(size),
+                Err(e) => return Err(e),
+            }
+        };
+        let point_size = header.point_size() as u64;
+        let NwtHeader { codec, weight_dtype, json_data, lat_len, lon_len, lookup_table, coordinates, quantization, .. } = header;
+        if codec != 0 {
+            return Err(NwtError::InvalidInput("compressed NWT files can't be read with ranged requests".to_string()));
+        }
+
+        // make sure every entry the lookup table promises is in the object
+        let total_points: u64 = lookup_table.iter().map(|e| e.count).sum();
+        let needed = total_points.checked_mul(point_size)
+            .ok_or_else(|| NwtError::Corrupt(format!("point count {} is too large", total_points)))?;
+        let remaining = size as u64 - data_offset;
+        if needed > remaining {
+            return Err(NwtError::Truncated { section: "gridpoint data", expected: to_usize(needed)?, got: to_usize(remaining)? });
+        }
+
+        Ok(Self { store, path, json_data, lat_len, lon_len, lookup_table, coordinates, weight_dtype, quantization, point_size, data_offset })
+    }
+
+    /// fetches the entry for the polyid at `idx`, rounding double-precision
+    /// weights to f32
+    pub async fn get_entry(&self, idx: usize) -> Result<PolyidEntry, NwtError> {
+        let &LookupEntry { offset, count } = self.lookup_table.get(idx)
+            .ok_or_else(|| NwtError::InvalidInput(format!("polyid index {} is out of range", idx)))?;
+        let start = self.data_offset + offset * self.point_size;
+        let range = to_usize(start)?..to_usize(start + count * self.point_size)?;
+        let bytes = self.store.get_range(&self.path, range).await?;
+
+        let mut cursor = NwtReader::new(&bytes[..]);
+        let mut entry = PolyidEntry::new();
+        for _ in 0..count {
+            let lat_idx = cursor.read_u32("gridpoint data")?;
+            let lon_idx = cursor.read_u32("gridpoint data")?;
+            let (lat, lon) = match &self.coordinates {
+                Some(coords) => coords.get(lat_idx, lon_idx).ok_or_else(|| NwtError::Corrupt(format!(
+                    "point ({}, {}) is outside the {}x{} grid", lat_idx, lon_idx, self.lat_len, self.lon_len)))?,
+                None => (cursor.read_f32("gridpoint data")?, cursor.read_f32("gridpoint data")?),
+            };
+            let weight = match self.weight_dtype {
+                WeightDtype::F32 => match &self.quantization {
+                    Some(ranges) => dequantize(cursor.read_u16("gridpoint data")?, ranges[idx]),
+                    None => cursor.read_f32("gridpoint data")?,
+                },
+                WeightDtype::F64 => cursor.read_f64("gridpoint data")? as f32,
+            };
+            entry.add_point(lat_idx, lon_idx, lat, lon, weight);
+        }
+        Ok(entry)
+    }
+
+    /// fetches the entry of the polyid with the given name. Duplicate
+    /// polyids resolve to the first occurrence
+    pub async fn get_entry_by_name(&self, polyid: &str) -> Result<PolyidEntry, NwtError> {
+        let idx = self.json_data.polyids.iter().position(|name| name == polyid)
+            .ok_or_else(|| NwtError::PolyidNotFound(polyid.to_string()))?;
+        self.get_entry(idx).await
+    }
+
+    /// Returns the number of polyids in the file
+    pub fn len(&self) -> usize {
+        self.lookup_table.len()
+    }
+
+    /// Returns true if the file contains no polyids
+    pub fn is_empty(&self) -> bool {
+        self.lookup_table.is_empty()
+    }
+
+    /// Returns all global attributes in the file
+    pub fn get_global_attrs(&self) -> &Vec<(String, AttrVal)> {
+        &self.json_data.global_attrs
+    }
+
+    /// Returns a list of polyids
+    pub fn get_polyids(&self) -> &Vec<String> {
+        &self.json_data.polyids
+    }
+
+    /// Returns a reference to the data lookup table
+    pub fn get_lookup_table(&self) -> &Vec<LookupEntry> {
+        &self.lookup_table
+    }
+
+    /// Returns the dimensions of the weight file
+    pub fn get_dimensions(&self) -> (u64, u64) {
+        (self.lat_len, self.lon_len)
+    }
+}
+
+/// the store serving `url`, and the object's path within it
+fn store_for(url: &str) -> Result<(Box<dyn ObjectStore>, ObjectPath), NwtError> {
+    let url = Url::parse(url).map_err(|e| NwtError::InvalidInput(format!("invalid URL {}: {}", url, e)))?;
+    let (scheme, path) = ObjectStoreScheme::parse(&url)?;
+    let store: Box<dyn ObjectStore> = match scheme {
+        ObjectStoreScheme::Local => Box::new(LocalFileSystem::new()),
+        ObjectStoreScheme::AmazonS3 => Box::new(AmazonS3Builder::from_env().with_url(url.as_str()).build()?),
+        ObjectStoreScheme::GoogleCloudStorage => Box::new(GoogleCloudStorageBuilder::from_env().with_url(url.as_str()).build()?),
+        ObjectStoreScheme::MicrosoftAzure => Box::new(MicrosoftAzureBuilder::from_env().with_url(url.as_str()).build()?),
+        // the HTTP store is rooted at the server, and the path is relative to it
+        ObjectStoreScheme::Http => Box::new(HttpBuilder::new().with_url(&url[..url::Position::BeforePath]).build()?),
+        _ => return Err(NwtError::InvalidInput(format!("unsupported URL scheme {}", url.scheme()))),
+    };
+    Ok((store, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::task::{Context, Poll, Waker};
+
+    use super::*;
+    use crate::tests::{sample_file, temp_path};
+    use crate::NextWeightFileBuilder;
+
+    /// the local store finishes without a runtime, so polling is enough
+    fn block_on<F: Future>(f: F) -> F::Output {
+        let mut f = std::pin::pin!(f);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(v) = f.as_mut().poll(&mut cx) {
+                return v;
+            }
+        }
+    }
+
+    #[test]
+    fn file_urls_open_whole_and_ranged() {
+        // enough polyids that the metadata outgrows the first prefix
+        let mut builder = NextWeightFileBuilder::new().dimensions(2, 3);
+        for idx in 0..4000u32 {
+            builder = builder.add_polyid(format!("polyid_{:05}", idx), vec![(idx % 2, idx % 3, 0.0, 0.0, idx as f32)]);
+        }
+        for nwt in [sample_file(), builder.build()] {
+            let path = temp_path("remote.nwt");
+            nwt.serialize_to_file(&path).unwrap();
+            let url = format!("file://{}", path.display());
+
+            assert_eq!(block_on(NextWeightFile::open_url(&url)).unwrap(), nwt);
+            let remote = block_on(NextWeightFileRemote::open(&url)).unwrap();
+            assert_eq!(remote.get_polyids(), nwt.get_polyids());
+            assert_eq!(remote.get_lookup_table(), nwt.get_lookup_table());
+            let last = nwt.len() - 1;
+            assert_eq!(&block_on(remote.get_entry(last)).unwrap(), &nwt.get_gridpoints()[last]);
+            let name = &nwt.get_polyids()[1];
+            assert_eq!(block_on(remote.get_entry_by_name(name)).unwrap(), *nwt.get_entry(name).unwrap());
+            assert!(block_on(remote.get_entry(nwt.len())).is_err());
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        assert!(matches!(block_on(NextWeightFile::open_url("not a url")), Err(NwtError::InvalidInput(_))));
+    }
+}
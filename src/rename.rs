@@ -0,0 +1,221 @@
+//! Renaming polyids, one at a time or through a mapping.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+
+use crate::{NextWeightFile, NwtError};
+
+/// What `NextWeightFile::remap_polyids` did besides renaming
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RemapReport {
+    /// polyids the mapping had no new name for, which kept theirs, in file
+    /// order
+    pub unmapped: Vec<String>,
+    /// names in the mapping that aren't polyids of the file, sorted
+    pub missing: Vec<String>,
+    /// each merged polyid's new name and the old names whose entries were
+    /// concatenated into it, in file order
+    pub merged: Vec<(String, Vec<String>)>,
+}
+
+impl NextWeightFile {
+    /// Renames a polyid, leaving its weights and position alone and taking
+    /// its metadata along. Fails if `from` isn't a polyid or `to` already is
+    /// one
+    pub fn rename_polyid(&mut self, from: &str, to: &str) -> Result<(), NwtError> {
+        let idx = self.polyid_index(from)
+            .ok_or_else(|| NwtError::PolyidNotFound(from.to_string()))?;
+        if from == to {
+            return Ok(());
+        }
+        if self.polyid_index(to).is_some() {
+            return Err(NwtError::InvalidInput(format!("polyid {} already exists", to)));
+        }
+        self.json_data.polyids[idx] = to.to_string();
+        let meta = &mut self.json_data.polyid_meta;
+        let moved = match self.json_data.polyids.iter().any(|name| name == from) {
+            true => meta.get(from).cloned(),
+            false => meta.remove(from),
+        };
+        if let Some(moved) = moved {
+            meta.insert(to.to_string(), moved);
+        }
+        self.polyid_lookup = OnceLock::new();
+        Ok(())
+    }
+
+    /// Renames every polyid found in `mapping` (old name -> new name).
+    /// Fails, changing nothing, if a new name would be shared with another
+    /// polyid; see `remap_polyids_merging` to combine them instead
+    pub fn remap_polyids(&mut self, mapping: &HashMap<String, String>) -> Result<RemapReport, NwtError> {
+        self.remap(mapping, false)
+    }
+
+    /// Same as `remap_polyids`, but polyids that end up with the same name
+    /// are merged into one at the first one's position, their points
+    /// concatenated in file order, in every weight set. Their metadata is
+    /// combined, the first polyid's value winning where keys clash
+    pub fn remap_polyids_merging(&mut self, mapping: &HashMap<String, String>) -> RemapReport {
+        self.remap(mapping, true).expect("merging remaps can't collide")
+    }
+
+    fn remap(&mut self, mapping: &HashMap<String, String>, merge: bool) -> Result<RemapReport, NwtError> {
+        let polyids = &self.json_data.polyids;
+        let mut report = RemapReport {
+            unmapped: polyids.iter().filter(|name| !mapping.contains_key(*name)).cloned().collect(),
+            missing: mapping.keys().filter(|name| self.polyid_index(name).is_none()).cloned().collect(),
+            merged: Vec::new(),
+        };
+        report.missing.sort();
+
+        // the polyids that will share each new name, in file order. A file's
+        // existing duplicates that the mapping doesn't touch are left alone
+        let new_names: Vec<String> = polyids.iter().map(|name| mapping.get(name).unwrap_or(name).clone()).collect();
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        let mut group_of: HashMap<&str, usize> = HashMap::new();
+        for (idx, name) in new_names.iter().enumerate() {
+            let group = *group_of.entry(name).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[group].push(idx);
+        }
+        let collisions: Vec<&Vec<usize>> = groups.iter()
+            .filter(|group| group.len() > 1 && group.iter().any(|&idx| mapping.contains_key(&polyids[idx])))
+            .collect();
+        if let (Some(group), false) = (collisions.first(), merge) {
+            let olds: Vec<&str> = group.iter().map(|&idx| polyids[idx].as_str()).collect();
+            return Err(NwtError::InvalidInput(format!(
+                "polyids {} would all be named {}", olds.join(", "), new_names[group[0]])));
+        }
+
+        let mut meta = std::mem::take(&mut self.json_data.polyid_meta);
+        let mut new_meta = BTreeMap::new();
+        for (old, new) in polyids.iter().zip(new_names.iter()) {
+            if let Some(values) = meta.remove(old) {
+                let merged: &mut BTreeMap<String, String> = new_meta.entry(new.clone()).or_default();
+                for (key, value) in values {
+                    merged.entry(key).or_insert(value);
+                }
+            }
+        }
+        new_meta.append(&mut meta);
+        self.json_data.polyid_meta = new_meta;
+
+        let mut absorbed = vec![false; new_names.len()];
+        for group in collisions {
+            let (first, rest) = (group[0], &group[1..]);
+            report.merged.push((new_names[first].clone(), group.iter().map(|&idx| polyids[idx].clone()).collect()));
+            for &idx in rest {
+                let merged: Vec<_> = self.polyid_gridpoints[first].iter().chain(&self.polyid_gridpoints[idx]).copied().collect();
+                self.polyid_gridpoints.replace(first, merged);
+                if let Some(weights) = self.f64_weights.as_mut() {
+                    let extra = std::mem::take(&mut weights[idx]);
+                    weights[first].extend(extra);
+                }
+                for set in self.weight_sets.iter_mut() {
+                    let points = std::mem::take(&mut set.entries[idx].data);
+                    set.entries[first].data.extend(points);
+                }
+                absorbed[idx] = true;
+            }
+        }
+
+        self.json_data.polyids = without(new_names, &absorbed);
+        self.polyid_gridpoints = self.polyid_gridpoints.without(&absorbed);
+        if let Some(weights) = self.f64_weights.take() {
+            self.f64_weights = Some(without(weights, &absorbed));
+        }
+        for set in self.weight_sets.iter_mut() {
+            set.entries = without(std::mem::take(&mut set.entries), &absorbed);
+        }
+        self.entries_changed();
+        Ok(report)
+    }
+}
+
+/// the items whose `dropped` flag isn't set
+pub(crate) fn without<T>(items: Vec<T>, dropped: &[bool]) -> Vec<T> {
+    items.into_iter().zip(dropped.iter()).filter(|(_, &gone)| !gone).map(|(item, _)| item).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::sample_file;
+    use crate::NextWeightFileBuilder;
+
+    fn mapping(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(a, b)| (a.to_string(), b.to_string())).collect()
+    }
+
+    #[test]
+    fn rename_keeps_weights_and_index() {
+        let mut nwt = sample_file();
+        nwt.get_entry("A").unwrap();
+        nwt.rename_polyid("A", "USA").unwrap();
+        assert_eq!(nwt.get_polyids()[0], "USA");
+        assert_eq!(nwt.get_entry("USA"), sample_file().get_entry("A"));
+        assert!(nwt.get_entry("A").is_none());
+
+        assert!(matches!(nwt.rename_polyid("A", "X"), Err(NwtError::PolyidNotFound(_))));
+        assert!(matches!(nwt.rename_polyid("USA", "B"), Err(NwtError::InvalidInput(_))));
+        nwt.rename_polyid("B", "B").unwrap();
+    }
+
+    #[test]
+    fn remap_reports_and_refuses_collisions() {
+        let mut nwt = sample_file();
+        let report = nwt.remap_polyids(&mapping(&[("A", "1"), ("C", "3"), ("Z", "26")])).unwrap();
+        assert_eq!(nwt.get_polyids(), &vec!["1".to_string(), "B".to_string(), "3".to_string()]);
+        assert_eq!(report, RemapReport { unmapped: vec!["B".to_string()], missing: vec!["Z".to_string()], merged: vec![] });
+        assert_eq!(nwt.get_entry("3"), sample_file().get_entry("C"));
+
+        // onto a name another polyid keeps, or two onto one
+        let before = nwt.clone();
+        assert!(matches!(nwt.remap_polyids(&mapping(&[("1", "B")])), Err(NwtError::InvalidInput(_))));
+        assert!(matches!(nwt.remap_polyids(&mapping(&[("1", "X"), ("3", "X")])), Err(NwtError::InvalidInput(ref m))
+            if m == "polyids 1, 3 would all be named X"));
+        assert_eq!(nwt, before);
+    }
+
+    #[test]
+    fn merging_remap_concatenates_entries() {
+        let mut nwt = sample_file();
+        let report = nwt.remap_polyids_merging(&mapping(&[("A", "X"), ("C", "X")]));
+        assert_eq!(report.merged, vec![("X".to_string(), vec!["A".to_string(), "C".to_string()])]);
+        assert_eq!(nwt.get_polyids(), &vec!["X".to_string(), "B".to_string()]);
+        let mut expected = sample_file().get_entry("A").unwrap().data.clone();
+        expected.extend(sample_file().get_entry("C").unwrap().data.iter().copied());
+        assert_eq!(nwt.get_entry("X").unwrap().data, expected);
+        assert_eq!(nwt.get_lookup_table(), &vec![(0, 5), (5, 1)]);
+
+        let mut wide = NextWeightFileBuilder::new()
+            .dimensions(2, 3)
+            .add_polyid_f64("A", vec![(0, 0, -45.0, 0.0, 0.1)])
+            .add_polyid_f64("B", vec![(1, 1, 45.0, 90.0, 0.2)])
+            .build();
+        wide.remap_polyids_merging(&mapping(&[("B", "A")]));
+        assert_eq!(wide.get_entry_f64("A").unwrap(), vec![(0, 0, -45.0, 0.0, 0.1), (1, 1, 45.0, 90.0, 0.2)]);
+    }
+
+    #[test]
+    fn metadata_follows_renames_and_merges() {
+        let mut nwt = sample_file();
+        nwt.set_polyid_meta("A", "name", "first").unwrap();
+        nwt.set_polyid_meta("C", "name", "third").unwrap();
+        nwt.set_polyid_meta("C", "area_km2", "12").unwrap();
+        nwt.rename_polyid("A", "USA").unwrap();
+        assert_eq!(nwt.get_polyid_meta("USA", "name"), Some("first"));
+        assert_eq!(nwt.get_polyid_meta("A", "name"), None);
+
+        let mut remapped = nwt.clone();
+        remapped.remap_polyids(&mapping(&[("C", "3")])).unwrap();
+        assert_eq!(remapped.get_polyid_meta("3", "area_km2"), Some("12"));
+        assert_eq!(remapped.get_polyid_meta("C", "area_km2"), None);
+
+        nwt.remap_polyids_merging(&mapping(&[("USA", "X"), ("C", "X")]));
+        assert_eq!(nwt.get_polyid_meta("X", "name"), Some("first"));
+        assert_eq!(nwt.get_polyid_meta("X", "area_km2"), Some("12"));
+    }
+}
@@ -0,0 +1,193 @@
+//! Sparse matrix views of the weights.
+//!
+//! The weights form a `polyid x gridcell` matrix, where a gridcell's column is
+//! its row-major flat index `lat_idx * lon_len + lon_idx`.
+
+#[cfg(feature = "sprs")]
+use crate::{grid_cells, GridPoint, NextWeightFileBuilder};
+use crate::{NextWeightFile, NwtError};
+
+impl NextWeightFile {
+    /// Exports the weights as a CSR matrix with one row per polyid (in
+    /// `get_polyids()` order) and `lat_len * lon_len` columns. Returns
+    /// `(row_ptr, col_idx, values)`; column indices are sorted within each row
+    /// and repeated gridcells are summed into a single entry. A point off the
+    /// grid, which has no column, is an error
+    #[allow(clippy::type_complexity)]
+    pub fn to_csr(&self) -> Result<(Vec<usize>, Vec<usize>, Vec<f32>), NwtError> {
+        let nnz = self.total_points() as usize;
+        let mut row_ptr = Vec::with_capacity(self.polyid_gridpoints.len() + 1);
+        let mut col_idx = Vec::with_capacity(nnz);
+        let mut values = Vec::with_capacity(nnz);
+        let mut row: Vec<(usize, f32)> = Vec::new();
+
+        row_ptr.push(0);
+        for entry in self.polyid_gridpoints.iter() {
+            row.clear();
+            for p in entry.iter() {
+                row.push((self.flat_index(p.0, p.1)?, p.4));
+            }
+            row.sort_by_key(|&(col, _)| col);
+
+            let mut i = 0;
+            while i < row.len() {
+                let col = row[i].0;
+                let mut total = 0.0f64;
+                while i < row.len() && row[i].0 == col {
+                    total += row[i].1 as f64;
+                    i += 1;
+                }
+                col_idx.push(col);
+                values.push(total as f32);
+            }
+            row_ptr.push(col_idx.len());
+        }
+        Ok((row_ptr, col_idx, values))
+    }
+
+    /// Exports the weights as a `sprs` CSR matrix, laid out as in `to_csr`.
+    /// A point off the grid is an error, as in `to_csr`
+    #[cfg(feature = "sprs")]
+    pub fn to_sprs(&self) -> Result<sprs::CsMat<f32>, NwtError> {
+        let (row_ptr, col_idx, values) = self.to_csr()?;
+        let shape = (self.polyid_gridpoints.len(), grid_cells(self.lat_len, self.lon_len)?);
+        sprs::CsMat::try_new(shape, row_ptr, col_idx, values)
+            .map_err(|(.., e)| NwtError::Corrupt(format!("weights don't form a valid CSR matrix: {}", e)))
+    }
+
+    /// Builds a weight file from a `polyid x gridcell` matrix laid out as in
+    /// `to_csr`, recovering each point's lat/lon indices from its column.
+    /// `coords` optionally supplies the `(lats, lons)` axis values, which are
+    /// also kept as the file's coordinate axes; without it the point
+    /// coordinates are left as NaN
+    #[cfg(feature = "sprs")]
+    pub fn from_csr(
+        lat_len: u64,
+        lon_len: u64,
+        polyids: Vec<String>,
+        mat: &sprs::CsMat<f32>,
+        coords: Option<(&[f32], &[f32])>,
+    ) -> Result<NextWeightFile, NwtError> {
+        if mat.rows() != polyids.len() {
+            return Err(NwtError::InvalidInput(format!(
+                "matrix has {} rows but {} polyids were given", mat.rows(), polyids.len())));
+        }
+        let ncells = grid_cells(lat_len, lon_len)?;
+        if mat.cols() != ncells {
+            return Err(NwtError::InvalidInput(format!(
+                "matrix has {} columns but the {}x{} grid has {} cells", mat.cols(), lat_len, lon_len, ncells)));
+        }
+        if let Some((lats, lons)) = coords {
+            if lats.len() as u64 != lat_len || lons.len() as u64 != lon_len {
+                return Err(NwtError::InvalidInput(format!(
+                    "coordinate axes have lengths {}x{} but the grid is {}x{}", lats.len(), lons.len(), lat_len, lon_len)));
+            }
+        }
+
+        let csr;
+        let mat = if mat.is_csr() { mat } else { csr = mat.to_csr(); &csr };
+
+        let mut builder = NextWeightFileBuilder::new().dimensions(lat_len, lon_len);
+        if let Some((lats, lons)) = coords {
+            builder = builder.axes(lats.to_vec(), lons.to_vec());
+        }
+        for (name, row) in polyids.into_iter().zip(mat.outer_iterator()) {
+            let points = row.iter().map(|(col, &weight)| {
+                let lat_idx = col / lon_len as usize;
+                let lon_idx = col % lon_len as usize;
+                let (lat, lon) = match coords {
+                    Some((lats, lons)) => (lats[lat_idx], lons[lon_idx]),
+                    None => (f32::NAN, f32::NAN),
+                };
+                (lat_idx as u32, lon_idx as u32, lat, lon, weight)
+            }).collect::<Vec<GridPoint>>();
+            builder = builder.add_polyid(name, points);
+        }
+        Ok(builder.build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::sample_file;
+    use crate::{NwtError, PolyidEntry};
+
+    #[test]
+    fn csr_export_matches_entries() {
+        let nwt = sample_file();
+        let (row_ptr, col_idx, values) = nwt.to_csr().unwrap();
+        assert_eq!(row_ptr.len(), nwt.len() + 1);
+        assert_eq!(*row_ptr.last().unwrap(), col_idx.len());
+        assert_eq!(col_idx.len(), values.len());
+
+        for (row, (_, points)) in nwt.iter().enumerate() {
+            let cols = &col_idx[row_ptr[row]..row_ptr[row + 1]];
+            assert!(cols.windows(2).all(|w| w[0] < w[1]));
+            for p in points {
+                let col = p.0 as usize * 3 + p.1 as usize;
+                let k = row_ptr[row] + cols.iter().position(|&c| c == col).unwrap();
+                assert_eq!(values[k], p.4);
+            }
+        }
+    }
+
+    #[test]
+    fn csr_export_sums_duplicates() {
+        let mut nwt = sample_file();
+        let mut entry = PolyidEntry::new();
+        entry.add_point(1, 2, 0.0, 0.0, 0.5);
+        entry.add_point(0, 1, 0.0, 0.0, 0.25);
+        entry.add_point(1, 2, 0.0, 0.0, 0.125);
+        nwt.add_polyid_entry("D".to_string(), entry);
+
+        let (row_ptr, col_idx, values) = nwt.to_csr().unwrap();
+        let last = row_ptr[row_ptr.len() - 2]..row_ptr[row_ptr.len() - 1];
+        assert_eq!(&col_idx[last.clone()], &[1, 5]);
+        assert_eq!(&values[last], &[0.25, 0.625]);
+    }
+
+    #[test]
+    fn csr_export_rejects_points_off_the_grid() {
+        // (0, 3) would otherwise land in (1, 0)'s column
+        let mut nwt = sample_file();
+        let mut entry = PolyidEntry::new();
+        entry.add_point(0, 3, 0.0, 0.0, 1.0);
+        nwt.add_polyid_entry("D".to_string(), entry);
+        assert!(matches!(nwt.to_csr(), Err(NwtError::Corrupt(_))));
+    }
+
+    #[cfg(feature = "sprs")]
+    #[test]
+    fn sprs_export_rejects_points_off_the_grid() {
+        let mut nwt = sample_file();
+        let mut entry = PolyidEntry::new();
+        entry.add_point(2, 0, 0.0, 0.0, 1.0);
+        nwt.add_polyid_entry("D".to_string(), entry);
+        assert!(matches!(nwt.to_sprs(), Err(NwtError::Corrupt(_))));
+    }
+
+    #[cfg(feature = "sprs")]
+    #[test]
+    fn sprs_round_trip() {
+        let nwt = sample_file();
+        let mat = nwt.to_sprs().unwrap();
+        assert_eq!((mat.rows(), mat.cols()), (3, 6));
+
+        let polyids = nwt.get_polyids().clone();
+        let rebuilt = crate::NextWeightFile::from_csr(2, 3, polyids.clone(), &mat, None).unwrap();
+        assert_eq!(rebuilt.to_sprs().unwrap(), mat);
+        assert_eq!(rebuilt.get_polyids(), &polyids);
+        assert!(rebuilt.get_entry("B").unwrap().data[0].2.is_nan());
+
+        let lats = [-45.0, 45.0];
+        let lons = [0.0, 120.0, 240.0];
+        let rebuilt = crate::NextWeightFile::from_csr(2, 3, polyids.clone(), &mat, Some((&lats, &lons))).unwrap();
+        assert_eq!(rebuilt.get_entry("B").unwrap().data, vec![(1, 1, 45.0, 120.0, 1.0)]);
+        assert_eq!(rebuilt.get_longitudes(), Some(&lons[..]));
+
+        assert!(matches!(crate::NextWeightFile::from_csr(2, 3, vec!["A".into()], &mat, None),
+            Err(crate::NwtError::InvalidInput(_))));
+        assert!(matches!(crate::NextWeightFile::from_csr(1 << 33, 3, polyids.clone(), &mat, None),
+            Err(crate::NwtError::GridTooLarge { .. })));
+    }
+}
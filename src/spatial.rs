@@ -0,0 +1,377 @@
+//! Lookups from grid cells and coordinates back to the polyids that use
+//! them, geographic subsetting, and longitude conventions.
+
+use crate::{GridPoint, NextWeightFile, NwtError};
+
+/// The range stored longitudes are expressed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LonConvention {
+    /// `[0, 360)`
+    ZeroTo360,
+    /// `[-180, 180)`
+    Neg180To180,
+}
+
+impl LonConvention {
+    /// the value recorded in the `nwt_lon_convention` global attribute
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LonConvention::ZeroTo360 => "0_360",
+            LonConvention::Neg180To180 => "-180_180",
+        }
+    }
+
+    /// Rewrites a longitude into this convention. Values within `tol` of the
+    /// seam are snapped onto it, so f32 noise like `179.99998` doesn't end up
+    /// on the wrong side
+    fn wrap(&self, lon: f64, tol: f64) -> f64 {
+        let start = match self {
+            LonConvention::ZeroTo360 => 0.0,
+            LonConvention::Neg180To180 => -180.0,
+        };
+        let wrapped = (lon - start).rem_euclid(360.0);
+        if wrapped > 360.0 - tol || wrapped < tol && wrapped > 0.0 {
+            start
+        } else {
+            wrapped + start
+        }
+    }
+}
+
+/// Maps each grid cell to the `(polyid index, weight)` pairs that reference
+/// it, built by `NextWeightFile::build_reverse_index`.
+///
+/// The pairs for all cells live in one flat array, with a per-cell offset
+/// table into it. That costs `(lat_len * lon_len + 1) * 8` bytes for the
+/// offsets plus 16 bytes per stored point on 64-bit targets, independent of
+/// how the points are spread over the grid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReverseIndex {
+    lat_len: u64,
+    lon_len: u64,
+    /// `cell_offsets[c]..cell_offsets[c + 1]` is cell `c`'s range in `pairs`
+    cell_offsets: Vec<usize>,
+    pairs: Vec<(usize, f32)>,
+}
+
+impl ReverseIndex {
+    /// Returns the `(polyid index, weight)` pairs referencing the cell, in
+    /// polyid order. Cells outside the grid have none
+    pub fn lookup(&self, lat_idx: u32, lon_idx: u32) -> &[(usize, f32)] {
+        if lat_idx as u64 >= self.lat_len || lon_idx as u64 >= self.lon_len {
+            return &[];
+        }
+        let cell = lat_idx as usize * self.lon_len as usize + lon_idx as usize;
+        &self.pairs[self.cell_offsets[cell]..self.cell_offsets[cell + 1]]
+    }
+}
+
+impl NextWeightFile {
+    /// Builds a `ReverseIndex` for answering "which polyids use this cell"
+    /// queries. Points outside the grid are left out
+    pub fn build_reverse_index(&self) -> ReverseIndex {
+        let lon_len = self.lon_len as usize;
+        let ncells = self.lat_len as usize * lon_len;
+        let cell_of = |p: &GridPoint| {
+            let inside = (p.0 as u64) < self.lat_len && (p.1 as u64) < self.lon_len;
+            inside.then(|| p.0 as usize * lon_len + p.1 as usize)
+        };
+
+        // count the points per cell, then turn the counts into offsets
+        let mut cell_offsets = vec![0usize; ncells + 1];
+        for (_, point) in self.iter_points() {
+            if let Some(cell) = cell_of(point) {
+                cell_offsets[cell + 1] += 1;
+            }
+        }
+        for cell in 0..ncells {
+            cell_offsets[cell + 1] += cell_offsets[cell];
+        }
+
+        let mut next = cell_offsets.clone();
+        let mut pairs = vec![(0usize, 0.0f32); cell_offsets[ncells]];
+        for (idx, point) in self.iter_points() {
+            if let Some(cell) = cell_of(point) {
+                pairs[next[cell]] = (idx, point.4);
+                next[cell] += 1;
+            }
+        }
+
+        ReverseIndex { lat_len: self.lat_len, lon_len: self.lon_len, cell_offsets, pairs }
+    }
+
+    /// Snaps `(lat, lon)` to the nearest grid cell and returns the
+    /// `(polyid index, weight)` pairs referencing that cell. Cell positions
+    /// come from the file's coordinate axes if it has them, or else the lat/lon
+    /// values stored with the points, and longitudes are
+    /// compared modulo 360 so either convention can be queried against either
+    /// kind of file. A coordinate more than half a cell away from every stored
+    /// cell gives an empty result
+    pub fn query_point(&self, lat: f32, lon: f32) -> Vec<(usize, f32)> {
+        let (lats, lons) = self.axis_values();
+        let lat_idx = match snap(&lats, lat, |a, b| (a - b).abs()) {
+            Some(idx) => idx,
+            None => return Vec::new(),
+        };
+        let lon_idx = match snap(&lons, lon, lon_distance) {
+            Some(idx) => idx,
+            None => return Vec::new(),
+        };
+
+        self.iter_points()
+            .filter(|(_, p)| p.0 as usize == lat_idx && p.1 as usize == lon_idx)
+            .map(|(idx, p)| (idx, p.4))
+            .collect()
+    }
+
+    /// Returns a copy of the file keeping only points whose stored lat/lon
+    /// falls inside the box. `lat_len`/`lon_len` are unchanged, so indices stay
+    /// valid. If `lon_min > lon_max` the box crosses the dateline, e.g.
+    /// `170.0..-170.0`; longitudes are compared modulo 360 either way
+    pub fn crop(&self, lat_min: f32, lat_max: f32, lon_min: f32, lon_max: f32) -> NextWeightFile {
+        self.crop_with(lat_min, lat_max, lon_min, lon_max, false).0
+    }
+
+    /// Same as `crop`, optionally rescaling each cropped polyid's remaining
+    /// weights so its weight sum is unchanged. Also returns the indices of
+    /// polyids that had points before cropping but none after
+    pub fn crop_with(
+        &self,
+        lat_min: f32,
+        lat_max: f32,
+        lon_min: f32,
+        lon_max: f32,
+        renormalize: bool,
+    ) -> (NextWeightFile, Vec<usize>) {
+        let lon_width = lon_max - lon_min;
+        let lon_inside = |lon: f32| {
+            lon_width >= 360.0 || (lon - lon_min).rem_euclid(360.0) <= lon_width.rem_euclid(360.0)
+        };
+
+        let mut copy = self.clone();
+        let mut emptied = Vec::new();
+        for idx in 0..copy.polyid_gridpoints.len() {
+            let data = &copy.polyid_gridpoints[idx];
+            let len = data.len();
+            let before = copy.weight_sum(idx);
+            let keep: Vec<bool> = data.iter().map(|p| p.2 >= lat_min && p.2 <= lat_max && lon_inside(p.3)).collect();
+            let dropped = copy.retain_points(idx, &keep);
+
+            if len > 0 && dropped == len {
+                emptied.push(idx);
+            } else if renormalize && dropped > 0 {
+                copy.rescale_to(idx, before);
+            }
+        }
+        copy.entries_changed();
+        (copy, emptied)
+    }
+
+    /// Infers the longitude convention from the stored lon values. Returns
+    /// `None` if they fit both conventions (all within `[0, 180]`) or neither
+    pub fn lon_convention(&self) -> Option<LonConvention> {
+        let (mut below_zero, mut above_180) = (false, false);
+        for (_, p) in self.iter_points() {
+            below_zero |= p.3 < 0.0;
+            above_180 |= p.3 > 180.0;
+        }
+        match (below_zero, above_180) {
+            (true, false) => Some(LonConvention::Neg180To180),
+            (false, true) => Some(LonConvention::ZeroTo360),
+            _ => None,
+        }
+    }
+
+    /// Rewrites every stored lon value into `target` and reorders `lon_idx` so
+    /// the axis stays ascending, recording the result in the
+    /// `nwt_lon_convention` global attribute. Assumes a regular longitude
+    /// axis; fails if the converted axis isn't one, e.g. a regional grid that
+    /// would be split across the new seam
+    pub fn convert_longitudes(&mut self, target: LonConvention) -> Result<(), NwtError> {
+        let lon_len = self.lon_len as usize;
+        let (_, lons) = self.axis_values();
+        let known: Vec<(usize, f64)> = lons.iter().enumerate()
+            .filter_map(|(idx, v)| v.map(|v| (idx, v as f64)))
+            .collect();
+
+        // reconstruct the full regular axis from the outermost stored values
+        let step = match (known.first(), known.last()) {
+            (Some(&(i, a)), Some(&(j, b))) if j > i => (b - a) / (j - i) as f64,
+            _ => 360.0 / lon_len.max(1) as f64,
+        };
+        let start = known.first().map(|&(i, v)| v - i as f64 * step).unwrap_or(0.0);
+        let tol = step.abs() * 1e-3;
+        let converted: Vec<f64> = (0..lon_len)
+            .map(|idx| target.wrap(start + idx as f64 * step, tol))
+            .collect();
+
+        let mut order: Vec<usize> = (0..lon_len).collect();
+        order.sort_by(|&a, &b| converted[a].total_cmp(&converted[b]));
+        if order.windows(2).any(|w| ((converted[w[1]] - converted[w[0]]) - step.abs()).abs() > tol) {
+            return Err(NwtError::InvalidInput(format!(
+                "longitude axis is not regular once converted to {}", target.as_str())));
+        }
+        let mut new_idx = vec![0u32; lon_len];
+        for (rank, &old) in order.iter().enumerate() {
+            new_idx[old] = rank as u32;
+        }
+
+        for point in self.polyid_gridpoints.points_mut() {
+            point.3 = target.wrap(point.3 as f64, tol) as f32;
+            if let Some(&idx) = new_idx.get(point.1 as usize) {
+                point.1 = idx;
+            }
+        }
+        if let Some(lon_axis) = self.lon_axis.as_mut() {
+            *lon_axis = order.iter().map(|&old| converted[old] as f32).collect();
+        }
+        self.json_data.set_global_attr("nwt_lon_convention", target.as_str());
+        Ok(())
+    }
+
+    /// the lat value of each lat_idx and lon value of each lon_idx, from the
+    /// coordinate axes if the file has them or else the first point seen at
+    /// that index, in the main weight set before any others
+    pub(crate) fn axis_values(&self) -> (Vec<Option<f32>>, Vec<Option<f32>>) {
+        if let (Some(lats), Some(lons)) = (&self.lat_axis, &self.lon_axis) {
+            return (lats.iter().copied().map(Some).collect(), lons.iter().copied().map(Some).collect());
+        }
+        let mut lats = vec![None; self.lat_len as usize];
+        let mut lons = vec![None; self.lon_len as usize];
+        let others = self.weight_sets.iter().flat_map(|set| set.entries.iter()).flat_map(|e| e.data.iter());
+        for p in self.iter_points().map(|(_, p)| p).chain(others) {
+            if let Some(slot @ None) = lats.get_mut(p.0 as usize) {
+                *slot = Some(p.2);
+            }
+            if let Some(slot @ None) = lons.get_mut(p.1 as usize) {
+                *slot = Some(p.3);
+            }
+        }
+        (lats, lons)
+    }
+}
+
+/// angular distance between two longitudes, in degrees
+fn lon_distance(a: f32, b: f32) -> f32 {
+    let d = (a - b).rem_euclid(360.0);
+    d.min(360.0 - d)
+}
+
+/// Finds the index of the axis value nearest `target`, or `None` if it's
+/// further than half the axis' smallest spacing from every known value
+fn snap(axis: &[Option<f32>], target: f32, dist: impl Fn(f32, f32) -> f32) -> Option<usize> {
+    let known: Vec<(usize, f32)> = axis.iter().enumerate()
+        .filter_map(|(idx, v)| v.map(|v| (idx, v)))
+        .collect();
+
+    // spacing is per index step, so gaps where no point was stored don't inflate it
+    let spacing = known.windows(2)
+        .map(|w| dist(w[1].1, w[0].1) / (w[1].0 - w[0].0) as f32)
+        .filter(|s| *s > 0.0)
+        .fold(f32::INFINITY, f32::min);
+
+    let (idx, d) = known.iter()
+        .map(|&(idx, v)| (idx, dist(v, target)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))?;
+    (d <= spacing / 2.0 + 1e-4).then_some(idx)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::sample_file;
+    use crate::{AttrVal, LonConvention, NextWeightFileBuilder, NwtError, PolyidEntry};
+
+    #[test]
+    fn reverse_index_lists_sharing_polyids() {
+        let mut nwt = sample_file();
+        let mut d = PolyidEntry::new();
+        d.add_point(1, 1, 45.0, 90.0, 0.5);
+        nwt.add_polyid_entry("D".to_string(), d);
+
+        let index = nwt.build_reverse_index();
+        assert_eq!(index.lookup(1, 1), &[(1, 1.0), (3, 0.5)]);
+        assert_eq!(index.lookup(0, 0), &[(0, 0.25)]);
+        assert_eq!(index.lookup(0, 2), &[(2, 0.0)]);
+        assert!(index.lookup(2, 0).is_empty());
+        assert!(index.lookup(0, 3).is_empty());
+    }
+
+    #[test]
+    fn query_point_snaps_to_nearest_cell() {
+        let nwt = sample_file();
+        // cells sit at lat -45/45 and lon 0/90/180
+        assert_eq!(nwt.query_point(40.0, 95.0), vec![(1, 1.0)]);
+        assert_eq!(nwt.query_point(-50.0, 10.0), vec![(0, 0.25)]);
+        // 0..360 file queried with a -180..180 longitude
+        assert_eq!(nwt.query_point(-45.0, -170.0), vec![(2, 0.0)]);
+        assert_eq!(nwt.query_point(-45.0, -100.0), vec![]);
+        assert_eq!(nwt.query_point(100.0, 0.0), vec![]);
+    }
+
+    #[test]
+    fn crop_to_box() {
+        let nwt = sample_file();
+        let (cropped, emptied) = nwt.crop_with(0.0, 90.0, -10.0, 100.0, true);
+        assert_eq!(emptied, vec![0]);
+        assert_eq!(cropped.get_dimensions(), (2, 3));
+        assert_eq!(cropped.get_entry("B"), nwt.get_entry("B"));
+        assert_eq!(cropped.get_entry("C").unwrap().data, vec![(1, 0, 45.0, 0.0, 1.0)]);
+        assert_eq!(cropped.get_lookup_table(), &vec![(0, 0), (0, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn crop_across_dateline() {
+        let nwt = sample_file();
+        // only the 180 degree column survives, whichever convention the box uses
+        for (lon_min, lon_max) in [(170.0, -170.0), (170.0, 190.0)] {
+            let cropped = nwt.crop(-90.0, 90.0, lon_min, lon_max);
+            assert_eq!(cropped.iter_points().count(), 2);
+            assert!(cropped.iter_points().all(|(_, p)| p.3 == 180.0));
+        }
+        assert_eq!(nwt.crop(-90.0, 90.0, 0.0, 360.0), nwt);
+    }
+
+    #[test]
+    fn convert_longitudes_across_the_seam() {
+        let points = (0..4).map(|i| (0, i, 0.0, i as f32 * 90.0, 0.25)).collect();
+        let mut nwt = NextWeightFileBuilder::new().dimensions(1, 4).add_polyid("R", points).build();
+        assert_eq!(nwt.lon_convention(), Some(LonConvention::ZeroTo360));
+        let field = [1.0, 2.0, 3.0, 4.0];
+        let before = nwt.apply(&field).unwrap();
+
+        nwt.convert_longitudes(LonConvention::Neg180To180).unwrap();
+        assert_eq!(nwt.lon_convention(), Some(LonConvention::Neg180To180));
+        // the cell at 180 lands on -180, the start of the new axis
+        let data = &nwt.get_entry("R").unwrap().data;
+        assert_eq!(data.iter().map(|p| (p.1, p.3)).collect::<Vec<_>>(),
+            vec![(2, 0.0), (3, 90.0), (0, -180.0), (1, -90.0)]);
+        assert_eq!(nwt.apply(&[3.0, 4.0, 1.0, 2.0]).unwrap(), before);
+        assert_eq!(nwt.get_global_attrs().last().unwrap(), &("nwt_lon_convention".to_string(), AttrVal::from("-180_180")));
+
+        nwt.convert_longitudes(LonConvention::ZeroTo360).unwrap();
+        assert_eq!(nwt.apply(&field).unwrap(), before);
+        assert_eq!(nwt.get_longitudes(), None);
+        assert_eq!(nwt.get_entry("R").unwrap().data[2].3, 180.0);
+    }
+
+    #[test]
+    fn convert_longitudes_snaps_seam_noise() {
+        let points = vec![(0, 0, 0.0, 179.99998, 0.5), (0, 1, 0.0, 270.0, 0.5)];
+        let mut nwt = NextWeightFileBuilder::new().dimensions(1, 2).add_polyid("R", points).build();
+        nwt.convert_longitudes(LonConvention::Neg180To180).unwrap();
+        assert_eq!(nwt.get_entry("R").unwrap().data, vec![(0, 0, 0.0, -180.0, 0.5), (0, 1, 0.0, -90.0, 0.5)]);
+
+        // a stored axis is converted along with the points
+        let points = vec![(0, 2, 0.0, 180.0, 1.0)];
+        let mut nwt = NextWeightFileBuilder::new().dimensions(1, 4).axes(vec![0.0], vec![0.0, 90.0, 180.0, 270.0])
+            .add_polyid("R", points).build();
+        nwt.convert_longitudes(LonConvention::Neg180To180).unwrap();
+        assert_eq!(nwt.get_longitudes(), Some(&[-180.0, -90.0, 0.0, 90.0][..]));
+        assert_eq!(nwt.get_entry("R").unwrap().data, vec![(0, 0, 0.0, -180.0, 1.0)]);
+
+        // a regional 0..360 grid spanning the dateline can't stay regular
+        let points = (0..3).map(|i| (0, i, 0.0, 170.0 + i as f32 * 10.0, 0.5)).collect();
+        let mut nwt = NextWeightFileBuilder::new().dimensions(1, 3).add_polyid("R", points).build();
+        assert!(matches!(nwt.convert_longitudes(LonConvention::Neg180To180), Err(NwtError::InvalidInput(_))));
+    }
+}
@@ -0,0 +1,221 @@
+//! Per-polyid summary statistics and grid coverage, computed on demand.
+
+use std::fmt;
+
+use crate::NextWeightFile;
+
+/// Summary of one polyid's weights and the cells they cover. For a polyid
+/// with no points every floating point field is NaN
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PolyidStats {
+    pub point_count: usize,
+    pub weight_sum: f64,
+    pub min_weight: f32,
+    pub max_weight: f32,
+    pub mean_weight: f64,
+    /// bounding box of the contributing cells' stored lat/lon values
+    pub min_lat: f32,
+    pub max_lat: f32,
+    pub min_lon: f32,
+    pub max_lon: f32,
+}
+
+/// The shape of a whole file at a glance, as `NextWeightFile`'s `Display`
+/// prints it. The points-per-polyid figures are all 0 for a file without
+/// polyids
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileSummary {
+    pub lat_len: u64,
+    pub lon_len: u64,
+    pub num_polyids: usize,
+    pub total_points: usize,
+    pub num_global_attrs: usize,
+    /// attributes across all variables
+    pub num_variable_attrs: usize,
+    pub min_points: usize,
+    pub max_points: usize,
+    pub mean_points: f64,
+}
+
+impl fmt::Display for FileSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "dimensions: {} lat x {} lon", self.lat_len, self.lon_len)?;
+        writeln!(f, "polyids: {}", self.num_polyids)?;
+        writeln!(f, "points: {}", self.total_points)?;
+        writeln!(f, "points per polyid: min {}, max {}, mean {:.2}", self.min_points, self.max_points, self.mean_points)?;
+        writeln!(f, "variable attributes: {}", self.num_variable_attrs)?;
+        write!(f, "global attributes: {}", self.num_global_attrs)
+    }
+}
+
+/// A summary rather than every point, which for real files would be millions
+impl fmt::Display for NextWeightFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.summary().fmt(f)
+    }
+}
+
+impl NextWeightFile {
+    /// Summarizes the file's dimensions, size and attributes
+    pub fn summary(&self) -> FileSummary {
+        let counts = self.polyid_gridpoints.counts();
+        let total_points: usize = counts.clone().sum();
+        let num_polyids = self.polyid_gridpoints.len();
+        FileSummary {
+            lat_len: self.lat_len,
+            lon_len: self.lon_len,
+            num_polyids,
+            total_points,
+            num_global_attrs: self.json_data.global_attrs.len(),
+            num_variable_attrs: self.json_data.per_variable_attrs.values().map(Vec::len).sum(),
+            min_points: counts.clone().min().unwrap_or(0),
+            max_points: counts.max().unwrap_or(0),
+            mean_points: if num_polyids == 0 { 0.0 } else { total_points as f64 / num_polyids as f64 },
+        }
+    }
+
+    /// Computes statistics for the polyid at `idx` in `get_polyids()` order.
+    /// Panics if `idx` is out of range
+    pub fn polyid_stats(&self, idx: usize) -> PolyidStats {
+        let data = &self.polyid_gridpoints[idx];
+        if data.is_empty() {
+            return PolyidStats {
+                point_count: 0,
+                weight_sum: f64::NAN,
+                min_weight: f32::NAN,
+                max_weight: f32::NAN,
+                mean_weight: f64::NAN,
+                min_lat: f32::NAN,
+                max_lat: f32::NAN,
+                min_lon: f32::NAN,
+                max_lon: f32::NAN,
+            };
+        }
+
+        let mut stats = PolyidStats {
+            point_count: data.len(),
+            weight_sum: 0.0,
+            min_weight: f32::INFINITY,
+            max_weight: f32::NEG_INFINITY,
+            mean_weight: 0.0,
+            min_lat: f32::INFINITY,
+            max_lat: f32::NEG_INFINITY,
+            min_lon: f32::INFINITY,
+            max_lon: f32::NEG_INFINITY,
+        };
+        for point in data.iter() {
+            stats.weight_sum += point.4 as f64;
+            stats.min_weight = stats.min_weight.min(point.4);
+            stats.max_weight = stats.max_weight.max(point.4);
+            stats.min_lat = stats.min_lat.min(point.2);
+            stats.max_lat = stats.max_lat.max(point.2);
+            stats.min_lon = stats.min_lon.min(point.3);
+            stats.max_lon = stats.max_lon.max(point.3);
+        }
+        stats.mean_weight = stats.weight_sum / data.len() as f64;
+        stats
+    }
+
+    /// Computes `polyid_stats` for every polyid, in `get_polyids()` order
+    pub fn all_stats(&self) -> Vec<PolyidStats> {
+        (0..self.polyid_gridpoints.len()).map(|idx| self.polyid_stats(idx)).collect()
+    }
+
+    /// Returns a row-major `lat_len * lon_len` mask holding, for each grid
+    /// cell, the number of polyids that reference it (0 = uncovered). A polyid
+    /// listing the same cell twice counts once, and points outside the grid
+    /// are ignored
+    pub fn coverage_mask(&self) -> Vec<u32> {
+        let lon_len = self.lon_len as usize;
+        let mut mask = vec![0u32; self.lat_len as usize * lon_len];
+        // the last polyid to touch each cell, so repeats within one entry count once
+        let mut last_seen = vec![usize::MAX; mask.len()];
+        for (idx, entry) in self.polyid_gridpoints.iter().enumerate() {
+            for point in entry.iter() {
+                if point.0 as u64 >= self.lat_len || point.1 as u64 >= self.lon_len {
+                    continue;
+                }
+                let cell = point.0 as usize * lon_len + point.1 as usize;
+                if last_seen[cell] != idx {
+                    last_seen[cell] = idx;
+                    mask[cell] += 1;
+                }
+            }
+        }
+        mask
+    }
+
+    /// Returns the polyids whose entries have no points
+    pub fn uncovered_polyids(&self) -> Vec<&str> {
+        self.iter()
+            .filter(|(_, points)| points.is_empty())
+            .map(|(polyid, _)| polyid)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileSummary;
+    use crate::tests::sample_file;
+    use crate::{NextWeightFileBuilder, PolyidEntry};
+
+    #[test]
+    fn stats_for_sample_polyids() {
+        let mut nwt = sample_file();
+        nwt.add_polyid_entry("D".to_string(), PolyidEntry::new());
+        let stats = nwt.all_stats();
+        assert_eq!(stats.len(), 4);
+
+        let c = stats[2];
+        assert_eq!(c.point_count, 3);
+        assert_eq!(c.weight_sum, 1.0);
+        assert_eq!((c.min_weight, c.max_weight), (0.0, 0.5));
+        assert!((c.mean_weight - 1.0 / 3.0).abs() < 1e-12);
+        assert_eq!((c.min_lat, c.max_lat, c.min_lon, c.max_lon), (-45.0, 45.0, 0.0, 180.0));
+
+        assert_eq!(stats[3].point_count, 0);
+        assert!(stats[3].weight_sum.is_nan() && stats[3].min_lat.is_nan());
+    }
+
+    #[test]
+    fn coverage_counts_polyids_per_cell() {
+        let mut nwt = sample_file();
+        let mut d = PolyidEntry::new();
+        d.add_point(1, 1, 45.0, 90.0, 0.5);
+        d.add_point(1, 1, 45.0, 90.0, 0.5);
+        nwt.add_polyid_entry("D".to_string(), d);
+        nwt.add_polyid_entry("E".to_string(), PolyidEntry::new());
+
+        assert_eq!(nwt.coverage_mask(), vec![1, 1, 1, 1, 2, 1]);
+        assert_eq!(nwt.uncovered_polyids(), vec!["E"]);
+        assert!(sample_file().uncovered_polyids().is_empty());
+    }
+
+    #[test]
+    fn summary_describes_the_file() {
+        let nwt = sample_file();
+        assert_eq!(nwt.summary(), FileSummary {
+            lat_len: 2,
+            lon_len: 3,
+            num_polyids: 3,
+            total_points: 6,
+            num_global_attrs: 1,
+            num_variable_attrs: 1,
+            min_points: 1,
+            max_points: 3,
+            mean_points: 2.0,
+        });
+        assert_eq!(nwt.to_string(), "dimensions: 2 lat x 3 lon\npolyids: 3\npoints: 6\n\
+            points per polyid: min 1, max 3, mean 2.00\nvariable attributes: 1\nglobal attributes: 1");
+
+        let empty = NextWeightFileBuilder::new().build().summary();
+        assert_eq!((empty.min_points, empty.max_points, empty.mean_points), (0, 0, 0.0));
+
+        // entries only show their first few points
+        assert_eq!(format!("{:?}", nwt.get_gridpoints()[1]), "PolyidEntry { len: 1, data: [(1, 1, 45.0, 90.0, 1.0)] }");
+        let long = PolyidEntry { data: (0..10).map(|i| (i, 0, 0.0, 0.0, 0.5)).collect() };
+        assert_eq!(format!("{:?}", long),
+            "PolyidEntry { len: 10, data: [(0, 0, 0.0, 0.0, 0.5), (1, 0, 0.0, 0.0, 0.5), (2, 0, 0.0, 0.0, 0.5), ..] }");
+    }
+}
@@ -0,0 +1,268 @@
+//! Structural and numerical sanity checks for loaded weight files.
+
+use std::fmt;
+
+use crate::{LookupEntry, NextWeightFile};
+
+/// How far a polyid's weight sum may stray from 1.0 before `is_valid` flags it
+pub const DEFAULT_SUM_TOLERANCE: f64 = 1e-4;
+
+/// A single problem found by `NextWeightFile::validate`. Polyid-specific
+/// findings carry the polyid's index and, when the file has one for that
+/// index, its name
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationIssue {
+    /// The JSON polyid list and the gridpoint entries have different lengths
+    PolyidCountMismatch { polyids: usize, entries: usize },
+    /// The lookup table has a different number of rows than there are entries
+    LookupLengthMismatch { expected: usize, got: usize },
+    /// A lookup table row doesn't match the running offset and entry length
+    LookupMismatch { index: usize, polyid: Option<String>, expected: LookupEntry, got: LookupEntry },
+    /// A point's lat/lon index lies outside the grid
+    IndexOutOfBounds { index: usize, polyid: Option<String>, point: usize, lat_idx: u32, lon_idx: u32 },
+    /// A weight is NaN or infinite
+    NonFiniteWeight { index: usize, polyid: Option<String>, point: usize, weight: f32 },
+    /// A weight is negative
+    NegativeWeight { index: usize, polyid: Option<String>, point: usize, weight: f32 },
+    /// A polyid's weights don't sum to 1.0 within the tolerance
+    WeightSum { index: usize, polyid: Option<String>, sum: f64 },
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn name(polyid: &Option<String>) -> &str {
+            polyid.as_deref().unwrap_or("<unnamed>")
+        }
+        match self {
+            ValidationIssue::PolyidCountMismatch { polyids, entries } => {
+                write!(f, "{} polyids listed but {} gridpoint entries stored", polyids, entries)
+            }
+            ValidationIssue::LookupLengthMismatch { expected, got } => {
+                write!(f, "lookup table has {} rows, expected {}", got, expected)
+            }
+            ValidationIssue::LookupMismatch { index, polyid, expected, got } => {
+                write!(f, "polyid {} ({}): lookup row {} should be {}", index, name(polyid), got, expected)
+            }
+            ValidationIssue::IndexOutOfBounds { index, polyid, point, lat_idx, lon_idx } => {
+                write!(f, "polyid {} ({}): point {} at ({}, {}) is outside the grid", index, name(polyid), point, lat_idx, lon_idx)
+            }
+            ValidationIssue::NonFiniteWeight { index, polyid, point, weight } => {
+                write!(f, "polyid {} ({}): point {} has non-finite weight {}", index, name(polyid), point, weight)
+            }
+            ValidationIssue::NegativeWeight { index, polyid, point, weight } => {
+                write!(f, "polyid {} ({}): point {} has negative weight {}", index, name(polyid), point, weight)
+            }
+            ValidationIssue::WeightSum { index, polyid, sum } => {
+                write!(f, "polyid {} ({}): weights sum to {}", index, name(polyid), sum)
+            }
+        }
+    }
+}
+
+/// A way the lookup table disagrees with the entries it indexes, as found
+/// by `NextWeightFile::check_lookup_table`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LookupError {
+    /// The table has a different number of rows than there are entries
+    LengthMismatch { expected: usize, got: usize },
+    /// A row's offset isn't the sum of the counts before it
+    OffsetMismatch { index: usize, expected: u64, got: u64 },
+    /// A row's count isn't the number of points in its entry
+    CountMismatch { index: usize, expected: u64, got: u64 },
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LookupError::LengthMismatch { expected, got } => {
+                write!(f, "lookup table has {} rows, expected {}", got, expected)
+            }
+            LookupError::OffsetMismatch { index, expected, got } => {
+                write!(f, "lookup table entry {} has offset {}, expected {}", index, got, expected)
+            }
+            LookupError::CountMismatch { index, expected, got } => {
+                write!(f, "lookup table entry {} has count {}, but the entry has {} points", index, got, expected)
+            }
+        }
+    }
+}
+
+/// Everything `NextWeightFile::validate` found wrong with a file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// true if no problems were found
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl NextWeightFile {
+    /// Checks the file for structural and numerical problems, collecting every
+    /// finding rather than stopping at the first, using `DEFAULT_SUM_TOLERANCE`
+    /// for the per-polyid weight sums
+    pub fn validate(&self) -> ValidationReport {
+        self.validate_with_tolerance(DEFAULT_SUM_TOLERANCE)
+    }
+
+    /// Same as `validate`, allowing each polyid's weight sum to differ from
+    /// 1.0 by up to `sum_tol`
+    pub fn validate_with_tolerance(&self, sum_tol: f64) -> ValidationReport {
+        let mut issues = Vec::new();
+        let polyids = &self.json_data.polyids;
+        let name = |idx: usize| polyids.get(idx).cloned();
+
+        if polyids.len() != self.polyid_gridpoints.len() {
+            issues.push(ValidationIssue::PolyidCountMismatch {
+                polyids: polyids.len(),
+                entries: self.polyid_gridpoints.len(),
+            });
+        }
+
+        let expected = self.polyid_gridpoints.lookup_table();
+        if expected.len() != self.lookup_table.len() {
+            issues.push(ValidationIssue::LookupLengthMismatch { expected: expected.len(), got: self.lookup_table.len() });
+        }
+        for (index, (want, got)) in expected.iter().zip(self.lookup_table.iter()).enumerate() {
+            if want != got {
+                issues.push(ValidationIssue::LookupMismatch { index, polyid: name(index), expected: *want, got: *got });
+            }
+        }
+
+        for (index, entry) in self.polyid_gridpoints.iter().enumerate() {
+            let mut sum = 0.0f64;
+            let mut finite = true;
+            for (point, p) in entry.iter().enumerate() {
+                if p.0 as u64 >= self.lat_len || p.1 as u64 >= self.lon_len {
+                    issues.push(ValidationIssue::IndexOutOfBounds {
+                        index, polyid: name(index), point, lat_idx: p.0, lon_idx: p.1,
+                    });
+                }
+                if !p.4.is_finite() {
+                    finite = false;
+                    issues.push(ValidationIssue::NonFiniteWeight { index, polyid: name(index), point, weight: p.4 });
+                } else if p.4 < 0.0 {
+                    issues.push(ValidationIssue::NegativeWeight { index, polyid: name(index), point, weight: p.4 });
+                }
+                sum += p.4 as f64;
+            }
+            // a non-finite weight has already been reported, and poisons the sum
+            if finite && (sum - 1.0).abs() > sum_tol {
+                issues.push(ValidationIssue::WeightSum { index, polyid: name(index), sum });
+            }
+        }
+
+        ValidationReport { issues }
+    }
+
+    /// true if `validate` finds nothing wrong with the file
+    pub fn is_valid(&self) -> bool {
+        self.validate().is_ok()
+    }
+
+    /// Checks that every lookup table offset is the running sum of the
+    /// counts before it and every count is its entry's number of points,
+    /// returning all the rows that aren't
+    pub fn check_lookup_table(&self) -> Result<(), Vec<LookupError>> {
+        let mut errors = Vec::new();
+        if self.lookup_table.len() != self.polyid_gridpoints.len() {
+            errors.push(LookupError::LengthMismatch { expected: self.polyid_gridpoints.len(), got: self.lookup_table.len() });
+        }
+        let mut running_total: u64 = 0;
+        for (index, &LookupEntry { offset, count }) in self.lookup_table.iter().enumerate() {
+            if offset != running_total {
+                errors.push(LookupError::OffsetMismatch { index, expected: running_total, got: offset });
+            }
+            if let Some(entry) = self.polyid_gridpoints.get(index) {
+                if count != entry.len() as u64 {
+                    errors.push(LookupError::CountMismatch { index, expected: entry.len() as u64, got: count });
+                }
+            }
+            running_total = running_total.saturating_add(count);
+        }
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Regenerates the lookup table from the entries, fixing anything
+    /// `check_lookup_table` finds
+    pub fn rebuild_lookup_table(&mut self) {
+        self.lookup_table = self.polyid_gridpoints.lookup_table();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::read_u64_le;
+    use crate::tests::sample_file;
+    use crate::NwtError;
+
+    #[test]
+    fn sample_file_is_valid() {
+        assert!(sample_file().is_valid());
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let mut nwt = sample_file();
+        nwt.polyid_gridpoints[0][0].4 = f32::INFINITY;
+        nwt.polyid_gridpoints[1][0] = (2, 1, 0.0, 0.0, 1.0);
+        nwt.polyid_gridpoints[2][2].4 = -0.5;
+        nwt.lookup_table[2] = LookupEntry::new(3, 4);
+        nwt.json_data.polyids.pop();
+
+        let report = nwt.validate();
+        assert!(!report.is_ok());
+        assert_eq!(report.issues, vec![
+            ValidationIssue::PolyidCountMismatch { polyids: 2, entries: 3 },
+            ValidationIssue::LookupMismatch { index: 2, polyid: None, expected: LookupEntry::new(3, 3), got: LookupEntry::new(3, 4) },
+            ValidationIssue::NonFiniteWeight { index: 0, polyid: Some("A".to_string()), point: 0, weight: f32::INFINITY },
+            ValidationIssue::IndexOutOfBounds { index: 1, polyid: Some("B".to_string()), point: 0, lat_idx: 2, lon_idx: 1 },
+            ValidationIssue::NegativeWeight { index: 2, polyid: None, point: 2, weight: -0.5 },
+            ValidationIssue::WeightSum { index: 2, polyid: None, sum: 0.5 },
+        ]);
+    }
+
+    #[test]
+    fn weight_sum_tolerance() {
+        let mut nwt = sample_file();
+        nwt.polyid_gridpoints[1][0].4 = 1.01;
+        assert!(!nwt.is_valid());
+        assert!(nwt.validate_with_tolerance(0.05).is_ok());
+    }
+
+    #[test]
+    fn lookup_table_check_and_rebuild() {
+        let mut nwt = sample_file();
+        assert_eq!(nwt.check_lookup_table(), Ok(()));
+
+        nwt.lookup_table[1] = LookupEntry::new(3, 1);
+        nwt.lookup_table.push(LookupEntry::new(4, 0));
+        assert_eq!(nwt.check_lookup_table(), Err(vec![
+            LookupError::LengthMismatch { expected: 3, got: 4 },
+            LookupError::OffsetMismatch { index: 1, expected: 2, got: 3 },
+            LookupError::OffsetMismatch { index: 3, expected: 6, got: 4 },
+        ]));
+
+        nwt.rebuild_lookup_table();
+        assert_eq!(nwt.check_lookup_table(), Ok(()));
+        assert_eq!(nwt.get_lookup_table(), sample_file().get_lookup_table());
+
+        nwt.lookup_table[0] = LookupEntry::new(0, 5);
+        assert_eq!(nwt.check_lookup_table(), Err(vec![
+            LookupError::CountMismatch { index: 0, expected: 2, got: 5 },
+            LookupError::OffsetMismatch { index: 1, expected: 5, got: 2 },
+            LookupError::OffsetMismatch { index: 2, expected: 6, got: 3 },
+        ]));
+
+        // a file whose offsets don't add up is refused rather than misread
+        let mut bytes = sample_file().to_bytes().unwrap();
+        let lookup_offset = read_u64_le(&bytes[50..]) as usize;
+        bytes[lookup_offset + 16] = 3;
+        assert!(matches!(NextWeightFile::from_bytes(&bytes),
+            Err(NwtError::Corrupt(ref m)) if m == "lookup table entry 1 has offset 3, expected 2"));
+    }
+}
@@ -0,0 +1,238 @@
+//! Additional named weight sets sharing a file's polyids and grid, e.g.
+//! bilinear weights alongside conservative ones.
+
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::codec::{write_f32_le, write_u32_le, write_u64_le};
+use crate::{build_lookup_table, to_usize, Coordinates, GridPoint, LookupEntry, NextWeightFile, NwtError, NwtReader, PolyidEntry};
+
+/// Name of the weight set of files that don't name theirs, including every
+/// file from before format version 10
+pub const DEFAULT_WEIGHT_SET: &str = "default";
+
+/// A weight set besides a file's main one, with an entry per polyid
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct WeightSet {
+    pub(crate) name: String,
+    pub(crate) entries: Vec<PolyidEntry>,
+}
+
+impl NextWeightFile {
+    /// Returns the names of the file's weight sets, the main one first. The
+    /// main set is the one `get_gridpoints`, `get_entry` and every other
+    /// single-set accessor read
+    pub fn weight_set_names(&self) -> Vec<&str> {
+        std::iter::once(self.weight_set_name.as_str())
+            .chain(self.weight_sets.iter().map(|set| set.name.as_str()))
+            .collect()
+    }
+
+    /// Returns the points of each polyid in a weight set, in `get_polyids()`
+    /// order, or `None` if the file has no set by that name. The points are
+    /// borrowed from where they're stored, so none are copied
+    pub fn weight_set(&self, name: &str) -> Option<Vec<&[GridPoint]>> {
+        if name == self.weight_set_name {
+            return Some(self.polyid_gridpoints.iter().collect());
+        }
+        self.weight_sets.iter().find(|set| set.name == name).map(|set| set.entries.iter().map(|e| &e.data[..]).collect())
+    }
+
+    /// Adds another set of weights for the same polyids, one entry per
+    /// polyid in `get_polyids()` order. Its points are stored with f32
+    /// weights whatever the main set's dtype, and need format version 10.
+    /// Polyids added to the file later get an empty entry in it
+    pub fn add_weight_set(&mut self, name: impl Into<String>, entries: Vec<PolyidEntry>) -> Result<(), NwtError> {
+        let name = name.into();
+        if self.weight_set_names().contains(&name.as_str()) {
+            return Err(NwtError::InvalidInput(format!("weight set {} already exists", name)));
+        }
+        if entries.len() != self.len() {
+            return Err(NwtError::InvalidInput(format!(
+                "weight set {} has {} entries for {} polyids", name, entries.len(), self.len())));
+        }
+        for (polyid, entry) in self.json_data.polyids.iter().zip(entries.iter()) {
+            if let Some(p) = entry.data.iter().find(|p| p.0 as u64 >= self.lat_len || p.1 as u64 >= self.lon_len) {
+                return Err(NwtError::InvalidInput(format!(
+                    "weight set {} has a point of polyid {} at ({}, {}), outside the {}x{} grid",
+                    name, polyid, p.0, p.1, self.lat_len, self.lon_len)));
+            }
+        }
+        self.weight_sets.push(WeightSet { name, entries });
+        Ok(())
+    }
+
+    /// Removes an additional weight set, returning whether there was one.
+    /// The main set can't be removed
+    pub fn remove_weight_set(&mut self, name: &str) -> bool {
+        let before = self.weight_sets.len();
+        self.weight_sets.retain(|set| set.name != name);
+        self.weight_sets.len() != before
+    }
+}
+
+/// writes the weight sets section of format 10 on: the main set's name,
+/// then the number of additional sets and, for each, its name, lookup table
+/// and (lat_idx, lon_idx, f32 weight) points
+pub(crate) fn write_weight_sets<W: Write>(w: &mut W, main_name: &str, sets: &[WeightSet]) -> std::io::Result<()> {
+    write_name(w, main_name)?;
+    write_u64_le(w, sets.len() as u64)?;
+    for set in sets.iter() {
+        write_name(w, &set.name)?;
+        for entry in build_lookup_table(&set.entries) {
+            entry.write(w)?;
+        }
+        for p in set.entries.iter().flat_map(|entry| entry.data.iter()) {
+            write_u32_le(w, p.0)?;
+            write_u32_le(w, p.1)?;
+            write_f32_le(w, p.4)?;
+        }
+    }
+    Ok(())
+}
+
+/// the size in bytes of what `write_weight_sets` writes
+pub(crate) fn weight_sets_size(main_name: &str, sets: &[WeightSet], num_polyids: usize) -> u64 {
+    let sets_size: u64 = sets.iter()
+        .map(|set| {
+            let points: u64 = set.entries.iter().map(|e| e.data.len() as u64).sum();
+            8 + set.name.len() as u64 + (num_polyids * LookupEntry::SERIALIZED_SIZE) as u64 + points * 12
+        })
+        .sum();
+    8 + main_name.len() as u64 + 8 + sets_size
+}
+
+/// reads the section `write_weight_sets` writes, looking the points' lat/lon
+/// up in the file's coordinates
+pub(crate) fn read_weight_sets<R: Read>(
+    cursor: &mut NwtReader<R>,
+    num_polyids: u64,
+    coordinates: &Coordinates,
+) -> Result<(String, Vec<WeightSet>), NwtError> {
+    let main_name = read_name(cursor)?;
+    let num_sets = cursor.read_u64("weight sets")?;
+    let mut sets = Vec::new();
+    for _ in 0..num_sets {
+        let name = read_name(cursor)?;
+        let mut counts = Vec::new();
+        let mut running_total: u64 = 0;
+        for idx in 0..num_polyids {
+            let LookupEntry { offset, count } = LookupEntry::read(cursor, "weight sets")?;
+            if offset != running_total {
+                return Err(NwtError::Corrupt(format!(
+                    "weight set {} lookup table entry {} has offset {}, expected {}", name, idx, offset, running_total)));
+            }
+            running_total = running_total.checked_add(count)
+                .ok_or_else(|| NwtError::Corrupt(format!("weight set {} point counts overflow", name)))?;
+            counts.push(count);
+        }
+        let mut entries = Vec::with_capacity(counts.len());
+        for count in counts {
+            let mut entry = PolyidEntry::new();
+            for _ in 0..count {
+                let lat_idx = cursor.read_u32("weight sets")?;
+                let lon_idx = cursor.read_u32("weight sets")?;
+                let weight = cursor.read_f32("weight sets")?;
+                let (lat, lon) = coordinates.get(lat_idx, lon_idx).ok_or_else(|| NwtError::Corrupt(format!(
+                    "weight set {} has a point at ({}, {}), outside the grid", name, lat_idx, lon_idx)))?;
+                entry.add_point(lat_idx, lon_idx, lat, lon, weight);
+            }
+            entries.push(entry);
+        }
+        sets.push(WeightSet { name, entries });
+    }
+    Ok((main_name, sets))
+}
+
+fn write_name<W: Write>(w: &mut W, name: &str) -> std::io::Result<()> {
+    write_u64_le(w, name.len() as u64)?;
+    w.write_all(name.as_bytes())
+}
+
+fn read_name<R: Read>(cursor: &mut NwtReader<R>) -> Result<String, NwtError> {
+    let len = cursor.read_u64("weight sets")?;
+    let bytes = cursor.read_bytes(to_usize(len)?, "weight sets")?;
+    String::from_utf8(bytes).map_err(|_| NwtError::Corrupt("weight set name is not UTF-8".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{sample_file, temp_path};
+    use crate::{NwtAppender, WeightDtype, WriteOptions};
+
+    /// the sample file's weights halved, standing in for a second method
+    fn halved() -> Vec<PolyidEntry> {
+        sample_file().get_gridpoints().iter()
+            .map(|e| PolyidEntry { data: e.data.iter().map(|p| (p.0, p.1, p.2, p.3, p.4 / 2.0)).collect() })
+            .collect()
+    }
+
+    fn slices(entries: &[PolyidEntry]) -> Vec<&[GridPoint]> {
+        entries.iter().map(|e| &e.data[..]).collect()
+    }
+
+    #[test]
+    fn weight_sets_round_trip() {
+        let mut nwt = sample_file();
+        assert_eq!(nwt.weight_set_names(), vec![DEFAULT_WEIGHT_SET]);
+        assert_eq!(nwt.weight_set("default"), Some(slices(nwt.get_gridpoints())));
+        nwt.add_weight_set("bilinear", halved()).unwrap();
+        assert_eq!(nwt.weight_set_names(), vec!["default", "bilinear"]);
+
+        let reread = NextWeightFile::from_bytes(&nwt.to_bytes().unwrap()).unwrap();
+        assert_eq!(reread, nwt);
+        assert_eq!(reread.weight_set("bilinear"), Some(slices(&halved())));
+        assert_eq!(reread.get_gridpoints(), sample_file().get_gridpoints());
+        assert_eq!(reread.weight_set("conservative"), None);
+        assert_eq!(nwt.estimated_file_size(), nwt.to_bytes().unwrap().len() as u64);
+
+        // a double-precision main set leaves the others f32
+        let mut wide = nwt.clone();
+        wide.set_weight_dtype(WeightDtype::F64);
+        let reread = NextWeightFile::from_bytes(&wide.to_bytes().unwrap()).unwrap();
+        assert_eq!(reread.weight_set("bilinear"), Some(slices(&halved())));
+
+        let v9 = WriteOptions { version: 9, ..Default::default() };
+        assert!(matches!(nwt.serialize_to_writer_with(&mut Vec::new(), &v9), Err(NwtError::InvalidInput(_))));
+        assert!(nwt.remove_weight_set("bilinear"));
+        assert!(!nwt.remove_weight_set("default"));
+        nwt.serialize_to_writer_with(&mut Vec::new(), &v9).unwrap();
+    }
+
+    #[test]
+    fn bad_weight_sets_are_refused() {
+        let mut nwt = sample_file();
+        assert!(matches!(nwt.add_weight_set("default", halved()), Err(NwtError::InvalidInput(_))));
+        assert!(matches!(nwt.add_weight_set("short", halved()[..2].to_vec()), Err(NwtError::InvalidInput(_))));
+        let mut off_grid = halved();
+        off_grid[1].data.push((2, 0, 0.0, 0.0, 1.0));
+        assert!(matches!(nwt.add_weight_set("off_grid", off_grid), Err(NwtError::InvalidInput(ref m))
+            if m == "weight set off_grid has a point of polyid B at (2, 0), outside the 2x3 grid"));
+        assert_eq!(nwt.weight_set_names(), vec!["default"]);
+    }
+
+    #[test]
+    fn weight_sets_follow_polyid_changes() {
+        let mut nwt = sample_file();
+        nwt.add_weight_set("bilinear", halved()).unwrap();
+        nwt.remove_polyid("A").unwrap();
+        nwt.add_polyid_entry("D".to_string(), PolyidEntry { data: vec![(0, 0, -45.0, 0.0, 1.0)] });
+        let set = nwt.weight_set("bilinear").unwrap();
+        assert_eq!(set.len(), 3);
+        assert_eq!(set[..2], slices(&halved()[1..]));
+        assert!(set[2].is_empty());
+
+        let path = temp_path("weight_sets_append.nwt");
+        nwt.serialize_to_file(&path).unwrap();
+        let mut appender = NwtAppender::open(&path).unwrap();
+        appender.append("E", PolyidEntry { data: vec![(1, 2, 45.0, 180.0, 1.0)] }).unwrap();
+        appender.finish().unwrap();
+        let appended = NextWeightFile::from_nwt(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let set = appended.weight_set("bilinear").unwrap();
+        assert_eq!(set.len(), 4);
+        assert!(set[3].is_empty());
+    }
+}
@@ -0,0 +1,231 @@
+//! In-place adjustments to the stored weights.
+
+use crate::NextWeightFile;
+
+impl NextWeightFile {
+    /// Rescales every polyid's weights so they sum to one, turning area
+    /// weights into fractional weights. Entries whose weights don't sum to a
+    /// positive, finite total are left untouched and their indices returned.
+    /// Records the change with a `nwt_normalized` global attribute
+    pub fn normalize_weights(&mut self) -> Vec<usize> {
+        let mut skipped = Vec::new();
+        for idx in 0..self.polyid_gridpoints.len() {
+            let total = self.weight_sum(idx);
+            if !(total.is_finite() && total > 0.0) {
+                skipped.push(idx);
+                continue;
+            }
+            self.map_weights(idx, |w| w / total);
+        }
+
+        if self.json_data.get_global_attr_value("nwt_normalized").is_none() {
+            self.json_data.add_global_attr("nwt_normalized", "true");
+        }
+        skipped
+    }
+
+    /// Removes every point whose weight is below `min_weight`, returning how
+    /// many were dropped. With `renormalize`, each polyid's remaining weights
+    /// are rescaled so its weight sum is unchanged. A polyid that loses all of
+    /// its points stays in the file as an empty entry
+    pub fn filter_weights(&mut self, min_weight: f32, renormalize: bool) -> usize {
+        let mut removed = 0;
+        for idx in 0..self.polyid_gridpoints.len() {
+            let before = self.weight_sum(idx);
+            let keep: Vec<bool> = self.polyid_gridpoints[idx].iter().map(|p| p.4 >= min_weight).collect();
+            let dropped = self.retain_points(idx, &keep);
+            removed += dropped;
+
+            if renormalize && dropped > 0 {
+                self.rescale_to(idx, before);
+            }
+        }
+        self.entries_changed();
+        removed
+    }
+
+    /// Returns a copy of the file where each polyid keeps only its `k`
+    /// largest-weight points, preferring earlier points on ties and keeping
+    /// them in their stored order. With `renormalize`, each truncated polyid's
+    /// weights are rescaled so its weight sum is unchanged
+    pub fn truncate_top_k(&self, k: usize, renormalize: bool) -> NextWeightFile {
+        let mut copy = self.clone();
+        for idx in 0..copy.polyid_gridpoints.len() {
+            let data = &copy.polyid_gridpoints[idx];
+            if data.len() <= k {
+                continue;
+            }
+            let before = copy.weight_sum(idx);
+
+            let mut order: Vec<usize> = (0..data.len()).collect();
+            // stable sort, so ties keep their stored order
+            order.sort_by(|&a, &b| data[b].4.total_cmp(&data[a].4));
+            let mut keep = vec![false; data.len()];
+            for &i in order.iter().take(k) {
+                keep[i] = true;
+            }
+            copy.retain_points(idx, &keep);
+
+            if renormalize {
+                copy.rescale_to(idx, before);
+            }
+        }
+        copy.entries_changed();
+        copy
+    }
+
+    /// Returns a copy of the file with `normalize_weights` applied
+    pub fn normalized(&self) -> NextWeightFile {
+        let mut copy = self.clone();
+        copy.normalize_weights();
+        copy
+    }
+}
+
+impl NextWeightFile {
+    /// the sum of entry `idx`'s weights, at full precision if the file has it
+    pub(crate) fn weight_sum(&self, idx: usize) -> f64 {
+        match &self.f64_weights {
+            Some(weights) => weights[idx].iter().sum(),
+            None => self.polyid_gridpoints[idx].iter().map(|p| p.4 as f64).sum(),
+        }
+    }
+
+    /// replaces each of entry `idx`'s weights `w` with `f(w)`, computed on
+    /// the double-precision weights if the file has them
+    fn map_weights(&mut self, idx: usize, f: impl Fn(f64) -> f64) {
+        let data = &mut self.polyid_gridpoints[idx];
+        match self.f64_weights.as_mut() {
+            Some(weights) => {
+                for (point, w) in data.iter_mut().zip(weights[idx].iter_mut()) {
+                    *w = f(*w);
+                    point.4 = *w as f32;
+                }
+            }
+            None => {
+                for point in data.iter_mut() {
+                    point.4 = f(point.4 as f64) as f32;
+                }
+            }
+        }
+    }
+
+    /// keeps the points of entry `idx` whose `keep` flag is set, along with
+    /// their double-precision weights, returning how many were dropped
+    pub(crate) fn retain_points(&mut self, idx: usize, keep: &[bool]) -> usize {
+        let dropped = self.polyid_gridpoints.retain_in(idx, keep);
+        if let Some(weights) = self.f64_weights.as_mut() {
+            let mut flags = keep.iter();
+            weights[idx].retain(|_| *flags.next().unwrap());
+        }
+        dropped
+    }
+
+    /// scales entry `idx`'s weights so they sum to `target`, unless the
+    /// current sum is zero or not finite
+    pub(crate) fn rescale_to(&mut self, idx: usize, target: f64) {
+        let total = self.weight_sum(idx);
+        if total.is_finite() && total > 0.0 {
+            let scale = target / total;
+            self.map_weights(idx, |w| w * scale);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::sample_file;
+    use crate::{NextWeightFileBuilder, PolyidEntry};
+
+    #[test]
+    fn normalize_area_weights() {
+        let mut nwt = sample_file();
+        let mut areas = PolyidEntry::new();
+        areas.add_point(0, 0, 0.0, 0.0, 30.0);
+        areas.add_point(0, 1, 0.0, 0.0, 10.0);
+        nwt.add_polyid_entry("D".to_string(), areas);
+        let mut empty = PolyidEntry::new();
+        empty.add_point(1, 1, 0.0, 0.0, 0.0);
+        nwt.add_polyid_entry("E".to_string(), empty);
+
+        let copy = nwt.normalized();
+        let skipped = nwt.normalize_weights();
+        assert_eq!(copy, nwt);
+        assert_eq!(skipped, vec![4]);
+
+        let weights: Vec<f32> = nwt.get_entry("D").unwrap().data.iter().map(|p| p.4).collect();
+        assert_eq!(weights, vec![0.75, 0.25]);
+        assert_eq!(nwt.get_entry("E").unwrap().data[0].4, 0.0);
+        assert_eq!(nwt.get_entry("A"), sample_file().get_entry("A"));
+
+        nwt.normalize_weights();
+        let flags = nwt.get_global_attrs().iter().filter(|(k, v)| k == "nwt_normalized" && v.as_str() == Some("true")).count();
+        assert_eq!(flags, 1);
+    }
+
+    #[test]
+    fn filter_small_weights() {
+        let mut nwt = sample_file();
+        let removed = nwt.filter_weights(0.3, true);
+        assert_eq!(removed, 2);
+        assert_eq!(nwt.get_entry("A").unwrap().data, vec![(0, 1, -45.0, 90.0, 1.0)]);
+        assert_eq!(nwt.get_entry("C").unwrap().data.len(), 2);
+        assert!(nwt.is_valid());
+
+        let mut nwt = sample_file();
+        assert_eq!(nwt.filter_weights(2.0, false), 6);
+        assert_eq!(nwt.len(), 3);
+        assert!(nwt.iter().all(|(_, points)| points.is_empty()));
+        assert_eq!(nwt.get_lookup_table(), &vec![(0, 0); 3]);
+    }
+
+    #[test]
+    fn top_k_keeps_largest_weights() {
+        let nwt = sample_file();
+        let top = nwt.truncate_top_k(1, false);
+        assert_eq!(top.get_entry("A").unwrap().data, vec![(0, 1, -45.0, 90.0, 0.75)]);
+        // tie between the two 0.5 weights goes to the first one stored
+        assert_eq!(top.get_entry("C").unwrap().data, vec![(1, 0, 45.0, 0.0, 0.5)]);
+        assert_eq!(top.get_entry("B"), nwt.get_entry("B"));
+        assert_eq!(top.get_lookup_table(), &vec![(0, 1), (1, 1), (2, 1)]);
+
+        let top = nwt.truncate_top_k(2, true);
+        assert_eq!(top.get_entry("C").unwrap().data.len(), 2);
+        assert!(top.is_valid());
+    }
+
+    #[test]
+    fn top_k_error_shrinks_with_k() {
+        let points = (0..12u32)
+            .map(|i| (i / 4, i % 4, 0.0, 0.0, ((i * 7) % 12 + 1) as f32 / 78.0))
+            .collect();
+        let nwt = NextWeightFileBuilder::new().dimensions(3, 4).add_polyid("R", points).build();
+        let field: Vec<f32> = (0..12).map(|i| 1.0 + (i as f32 * 0.37).sin().abs()).collect();
+        let exact = nwt.apply(&field).unwrap()[0];
+
+        let errors: Vec<f32> = (0..=12)
+            .map(|k| (nwt.truncate_top_k(k, false).apply(&field).unwrap()[0] - exact).abs())
+            .collect();
+        assert!(errors.windows(2).all(|w| w[1] <= w[0]));
+        assert_eq!(errors[12], 0.0);
+    }
+
+    #[test]
+    fn double_precision_weights_follow_the_points() {
+        let third = 1.0 / 3.0;
+        let mut nwt = NextWeightFileBuilder::new()
+            .dimensions(1, 3)
+            .add_polyid_f64("R", vec![(0, 0, 0.0, 0.0, third), (0, 1, 0.0, 1.0, third), (0, 2, 0.0, 2.0, 0.01)])
+            .build();
+        assert_eq!(nwt.filter_weights(0.1, true), 1);
+        let weights: Vec<f64> = nwt.get_entry_f64("R").unwrap().iter().map(|p| p.4).collect();
+        // rescaled in double precision, well beyond what f32 could hold
+        assert_eq!(weights[0], weights[1]);
+        assert!((weights[0] + weights[1] - (2.0 * third + 0.01)).abs() < 1e-15);
+        assert_eq!(nwt.get_entry("R").unwrap().data[1].4, weights[1] as f32);
+
+        nwt.normalize_weights();
+        let weights: Vec<f64> = nwt.get_entry_f64("R").unwrap().iter().map(|p| p.4).collect();
+        assert_eq!(weights, vec![0.5, 0.5]);
+    }
+}
@@ -0,0 +1,82 @@
+//! Drives the `nwt` binary against the test fixtures.
+#![cfg(feature = "cli")]
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/sample_v6.nwt");
+
+fn nwt(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_nwt")).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("nwt_cli_{}_{}", std::process::id(), name))
+}
+
+#[test]
+fn info_lists_dimensions_and_attributes() {
+    let out = nwt(&["info", FIXTURE]);
+    assert!(out.status.success());
+    let text = stdout(&out);
+    assert!(text.contains("dimensions: 2 lat x 3 lon"));
+    assert!(text.contains("polyids: 3"));
+    assert!(text.contains("  title = sample weights"));
+}
+
+#[test]
+fn list_polyids_prints_one_per_line() {
+    let out = nwt(&["list-polyids", FIXTURE]);
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "A\nB\nC\n");
+}
+
+#[test]
+fn dump_prints_csv_for_one_polyid() {
+    let out = nwt(&["dump", FIXTURE, "--polyid", "B"]);
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "polyid,lat_idx,lon_idx,lat,lon,weight\nB,1,1,45,90,1\n");
+
+    let missing = nwt(&["dump", FIXTURE, "--polyid", "nope"]);
+    assert!(!missing.status.success());
+    assert!(String::from_utf8_lossy(&missing.stderr).contains("nope"));
+}
+
+#[test]
+fn validate_reports_ok() {
+    let out = nwt(&["validate", FIXTURE]);
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "ok\n");
+
+    let missing = nwt(&["validate", "/nonexistent/weights.nwt"]);
+    assert!(!missing.status.success());
+}
+
+#[test]
+fn convert_writes_nwt() {
+    let src = temp_path("convert.nc");
+    let dst = temp_path("convert.nwt");
+    {
+        let mut file = netcdf::create(&src).unwrap();
+        file.add_dimension("polyid", 1).unwrap();
+        file.add_dimension("lat", 1).unwrap();
+        file.add_dimension("lon", 2).unwrap();
+        file.add_string_variable("polyid", &["polyid"]).unwrap().put_string("only", 0).unwrap();
+        file.add_variable::<f32>("lat", &["lat"]).unwrap().put_values(&[10.0f32], ..).unwrap();
+        file.add_variable::<f32>("lon", &["lon"]).unwrap().put_values(&[20.0f32, 30.0], ..).unwrap();
+        let mut weights = file.add_variable::<f32>("regridweights", &["polyid", "lat", "lon"]).unwrap();
+        weights.set_fill_value(-1.0f32).unwrap();
+        weights.put_values(&[1.0f32, -1.0], ..).unwrap();
+    }
+    let out = nwt(&["convert", src.to_str().unwrap(), dst.to_str().unwrap()]);
+    let listed = nwt(&["list-polyids", dst.to_str().unwrap()]);
+    std::fs::remove_file(&src).unwrap();
+    std::fs::remove_file(&dst).unwrap();
+
+    assert!(out.status.success());
+    assert_eq!(stdout(&listed), "only\n");
+}
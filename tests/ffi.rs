@@ -0,0 +1,81 @@
+//! Compiles and runs a small C program against `include/nwt.h` and the
+//! cdylib, reading the sample fixture.
+#![cfg(feature = "ffi")]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const CONSUMER: &str = r#"
+#include <stdio.h>
+#include <string.h>
+#include "nwt.h"
+
+int main(int argc, char **argv) {
+    NwtHandle *h = nwt_open(argv[1]);
+    if (!h) { fprintf(stderr, "%s\n", nwt_last_error_message()); return 1; }
+    if (nwt_num_polyids(h) != 3) return 2;
+
+    char name[16];
+    if (nwt_get_polyid_name(h, 1, name, sizeof name) != NWT_OK || strcmp(name, "B") != 0) return 3;
+
+    const NwtPoint *points;
+    size_t len;
+    if (nwt_get_entry(h, 1, &points, &len) != NWT_OK || len != 1) return 4;
+    if (sizeof(NwtPoint) != 20 || points[0].lat_idx != 1 || points[0].weight != 1.0f) return 5;
+    if (nwt_get_entry(h, 9, &points, &len) != NWT_ERR_INVALID_ARGUMENT) return 6;
+
+    nwt_close(h);
+    printf("ok\n");
+    return 0;
+}
+"#;
+
+/// the directory holding the test binary's copy of the cdylib
+fn lib_dir() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    let deps = exe.parent().unwrap();
+    let lib = format!("{}nextgen_weightfile{}", std::env::consts::DLL_PREFIX, std::env::consts::DLL_SUFFIX);
+    let dir = [deps, deps.parent().unwrap()].into_iter()
+        .find(|dir| dir.join(&lib).exists())
+        .expect("cdylib not built");
+    dir.to_path_buf()
+}
+
+#[test]
+fn header_declares_every_export() {
+    let header = std::fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("include/nwt.h")).unwrap();
+    for decl in ["nwt_open(", "nwt_num_polyids(", "nwt_get_polyid_name(", "nwt_get_entry(", "nwt_close(",
+                 "nwt_last_error_message(", "typedef struct NwtPoint"] {
+        assert!(header.contains(decl), "nwt.h is missing {}", decl);
+    }
+}
+
+#[test]
+fn c_consumer_reads_fixture() {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let work = std::env::temp_dir().join(format!("nwt_ffi_{}", std::process::id()));
+    std::fs::create_dir_all(&work).unwrap();
+    let source = work.join("consumer.c");
+    let binary = work.join("consumer");
+    std::fs::write(&source, CONSUMER).unwrap();
+
+    let lib_dir = lib_dir();
+    let status = Command::new(std::env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .arg(&source)
+        .arg("-I").arg(manifest.join("include"))
+        .arg("-L").arg(&lib_dir)
+        .arg("-lnextgen_weightfile")
+        .arg("-o").arg(&binary)
+        .status()
+        .expect("a C compiler is needed to run the FFI test");
+    assert!(status.success());
+
+    let output = Command::new(&binary)
+        .arg(manifest.join("tests/fixtures/sample_v6.nwt"))
+        .env("LD_LIBRARY_PATH", &lib_dir)
+        .env("DYLD_LIBRARY_PATH", &lib_dir)
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&work).unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n", "consumer exited with {:?}", output.status);
+}
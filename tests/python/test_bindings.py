@@ -0,0 +1,33 @@
+"""Run with `maturin develop --features python && pytest tests/python`."""
+from pathlib import Path
+
+import numpy as np
+import pytest
+
+from nextgen_weightfile import NextWeightFile
+
+FIXTURE = Path(__file__).parent.parent / "fixtures" / "sample_v6.nwt"
+
+
+def test_open_and_inspect():
+    nwt = NextWeightFile.open(str(FIXTURE))
+    assert nwt.polyids == ["A", "B", "C"]
+    assert len(nwt) == 3
+    assert nwt.get_entry("B") == ([1], [1], [45.0], [90.0], [1.0])
+    with pytest.raises(KeyError):
+        nwt.get_entry("nope")
+
+
+def test_apply_and_save(tmp_path):
+    nwt = NextWeightFile.open(str(FIXTURE))
+    field = np.arange(6, dtype=np.float32).reshape(2, 3)
+    np.testing.assert_allclose(nwt.apply(field), [0.75, 4.0, 4.0])
+    with pytest.raises(ValueError):
+        nwt.apply(np.zeros((3, 2), dtype=np.float32))
+
+    out = tmp_path / "copy.nwt"
+    nwt.save(str(out))
+    assert NextWeightFile.open(str(out)).polyids == nwt.polyids
+    with pytest.raises(FileExistsError):
+        nwt.save(str(out))
+    nwt.save(str(out), overwrite=True)
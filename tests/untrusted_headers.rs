@@ -0,0 +1,165 @@
+//! Feeds random headers to the parser, which must reject them without
+//! panicking or allocating for sizes the input can't back.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use nextgen_weightfile::{NextWeightFile, NwtError, ParseOptions};
+
+/// records the largest single allocation made on a thread while it's
+/// watching
+struct PeakAlloc;
+
+thread_local! {
+    static WATCHING: Cell<bool> = const { Cell::new(false) };
+    static PEAK: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        note(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        note(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+fn note(size: usize) {
+    // `try_with` as allocations can happen while the thread is torn down
+    let _ = WATCHING.try_with(|watching| {
+        if watching.get() {
+            PEAK.with(|peak| peak.set(peak.get().max(size)));
+        }
+    });
+}
+
+#[global_allocator]
+static ALLOC: PeakAlloc = PeakAlloc;
+
+/// runs `f`, returning the largest single allocation it made
+fn peak_allocation(f: impl FnOnce()) -> usize {
+    PEAK.with(|peak| peak.set(0));
+    WATCHING.with(|watching| watching.set(true));
+    f();
+    WATCHING.with(|watching| watching.set(false));
+    PEAK.with(Cell::get)
+}
+
+/// xorshift64, so the inputs are the same on every run
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// 64 bytes opening like a current file: magic, version 10, no compression
+/// and f32 weights, then random sizes and offsets
+fn random_header(rng: &mut Rng) -> Vec<u8> {
+    let mut bytes = b"NEWV".to_vec();
+    bytes.extend_from_slice(&10u16.to_le_bytes());
+    bytes.extend_from_slice(&[0; 4]);
+    while bytes.len() < 64 {
+        bytes.extend_from_slice(&rng.next().to_le_bytes());
+    }
+    bytes.truncate(64);
+    bytes
+}
+
+#[test]
+fn random_headers_are_rejected_cheaply() {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..5000 {
+        let mut bytes = random_header(&mut rng);
+        // small sizes get past the first checks more often
+        if rng.next().is_multiple_of(2) {
+            for field in (10..42).step_by(8) {
+                let small = rng.next() % 64;
+                bytes[field..field + 8].copy_from_slice(&small.to_le_bytes());
+            }
+        }
+        let peak = peak_allocation(|| {
+            assert!(NextWeightFile::from_bytes(&bytes).is_err());
+            // without a known length, reads fail once the input runs out
+            assert!(NextWeightFile::from_reader(&bytes[..]).is_err());
+        });
+        assert!(peak <= 16 * 1024, "allocated {} bytes for header {:02x?}", peak, bytes);
+    }
+}
+
+#[test]
+fn declared_sizes_are_checked_against_the_input() {
+    let mut bytes = random_header(&mut Rng(1));
+    // a terabyte of metadata right after the header, on a 2x3 grid
+    for (field, value) in [(10, 1u64 << 40), (18, 1), (26, 2), (34, 3), (42, 58), (50, 58 + (1 << 40))] {
+        bytes[field..field + 8].copy_from_slice(&u64::to_le_bytes(value));
+    }
+    let peak = peak_allocation(|| {
+        assert!(matches!(NextWeightFile::from_bytes(&bytes),
+            Err(NwtError::Truncated { section: "json metadata", got: 6, .. })));
+    });
+    assert!(peak <= 16 * 1024);
+}
+
+#[test]
+fn caps_refuse_files_before_reading_them() {
+    let path = std::env::temp_dir().join(format!("untrusted_headers_caps_{}.nwt", std::process::id()));
+    std::fs::write(&path, std::fs::read("tests/fixtures/sample_v10.nwt").unwrap()).unwrap();
+    let few_polyids = ParseOptions { max_polyids: 2, ..Default::default() };
+    let few_points = ParseOptions { max_total_points: 5, ..Default::default() };
+    let polyids = NextWeightFile::from_nwt_with(&path, &few_polyids);
+    let points = NextWeightFile::from_nwt_with(&path, &few_points);
+    let defaults = NextWeightFile::from_nwt_with(&path, &ParseOptions::default());
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(matches!(polyids, Err(NwtError::LimitExceeded { what: "polyids", declared: 3, limit: 2 })));
+    assert!(matches!(points, Err(NwtError::LimitExceeded { what: "gridpoints", declared: 6, limit: 5 })));
+    assert_eq!(defaults.unwrap().0.len(), 3);
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn compressed_sections_inflate_only_as_far_as_the_caps_allow() {
+    use nextgen_weightfile::{Compression, WriteOptions};
+
+    let sample = NextWeightFile::from_nwt("tests/fixtures/sample_v10.nwt").unwrap();
+    let path = std::env::temp_dir().join(format!("untrusted_headers_bomb_{}.nwt", std::process::id()));
+    let opts = WriteOptions { compression: Compression::Zstd(3), overwrite: true, ..Default::default() };
+    sample.serialize_to_file_with(&path, &opts).unwrap();
+    let bytes = std::fs::read(&path).unwrap();
+
+    // the compressed section swapped for 256 MiB of zeros, which compress
+    // to almost nothing
+    let lookup_offset = u64::from_le_bytes(bytes[50..58].try_into().unwrap()) as usize;
+    let bomb = zstd::stream::encode_all(&vec![0u8; 256 << 20][..], 19).unwrap();
+    let mut forged = bytes[..lookup_offset].to_vec();
+    forged.extend_from_slice(&(bomb.len() as u64).to_le_bytes());
+    forged.extend_from_slice(&bomb);
+    forged.extend_from_slice(&[0; 4]);
+    let bomb_len = bomb.len();
+    drop(bomb);
+    std::fs::write(&path, &forged).unwrap();
+
+    let few_points = ParseOptions { max_total_points: 1000, ..Default::default() };
+    let peak = peak_allocation(|| {
+        let err = NextWeightFile::from_nwt_with(&path, &few_points).unwrap_err();
+        assert!(matches!(&err, NwtError::Corrupt(m) if m.contains("inflates past")), "{:?}", err);
+    });
+    std::fs::remove_file(&path).unwrap();
+    // reading the compressed bytes themselves is all that's allowed for
+    assert!(peak <= 2 * bomb_len + (1 << 20), "allocated {} bytes for a {} byte bomb", peak, bomb_len);
+
+    // while the genuine compressed file still reads
+    assert_eq!(NextWeightFile::from_bytes(&bytes).unwrap(), sample);
+}
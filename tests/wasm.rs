@@ -0,0 +1,26 @@
+//! Checks that the NWT core builds for `wasm32-unknown-unknown` once the
+//! NetCDF conversion is turned off. Skipped when that target isn't installed.
+
+use std::path::Path;
+use std::process::Command;
+
+const TARGET: &str = "wasm32-unknown-unknown";
+
+#[test]
+fn core_builds_for_wasm32() {
+    let libdir = Command::new("rustc").args(["--print", "target-libdir", "--target", TARGET]).output().unwrap();
+    let libdir = String::from_utf8(libdir.stdout).unwrap();
+    if !Path::new(libdir.trim()).exists() {
+        eprintln!("skipping: the {} target is not installed", TARGET);
+        return;
+    }
+
+    let status = Command::new(env!("CARGO"))
+        .args(["check", "--lib", "--no-default-features", "--target", TARGET, "--manifest-path"])
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"))
+        // a separate target directory, as the outer build still holds its lock
+        .env("CARGO_TARGET_DIR", Path::new(env!("CARGO_TARGET_TMPDIR")).join("wasm"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+}